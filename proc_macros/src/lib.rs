@@ -1,6 +1,6 @@
 extern crate proc_macro;
 
-use proc_macro::{Ident, TokenStream};
+use proc_macro::TokenStream;
 use quote::quote;
 use syn::{parse_macro_input, Data, DeriveInput};
 
@@ -17,15 +17,15 @@ pub fn derive_storable(input: TokenStream) -> TokenStream {
             for v in variants.clone().into_iter().filter(|v| v.ident != "Null") {
                 let name = v.ident;
                 let quote = quote! {
-                    Data::#name(d) => d.id(),
+                    Self::#name(d) => d.id(),
                 };
                 enum_id.push(quote);
             }
             let quote = quote! {
-                fn id(&self) -> u16 {
+                fn id(&self) -> Uid {
                     match self{
                         #(#enum_id)*
-                        _ => 0u16
+                        _ => Uid::default()
                     }
                 }
             };
@@ -35,12 +35,12 @@ pub fn derive_storable(input: TokenStream) -> TokenStream {
             for v in variants.clone().into_iter().filter(|v| v.ident != "Null") {
                 let name = v.ident;
                 let quote = quote! {
-                    Data::#name(d) => d.set_uid(uid),
+                    Self::#name(d) => d.set_uid(uid),
                 };
                 enum_uid.push(quote);
             }
             let quote = quote! {
-                fn set_uid(&mut self, uid : u16){
+                fn set_uid(&mut self, uid : Uid){
                     match self{
                         #(#enum_uid)*
                         _ => ()
@@ -49,37 +49,19 @@ pub fn derive_storable(input: TokenStream) -> TokenStream {
             };
             enum_token.push(quote);
 
-            let mut enum_insert = vec![];
+            let mut enum_record = vec![];
             for v in variants.clone().into_iter().filter(|v| v.ident != "Null") {
                 let name = v.ident;
                 let quote = quote! {
-                    Data::#name(d) => d.insert_statement(place),
+                    Self::#name(d) => d.to_record(),
                 };
-                enum_insert.push(quote);
+                enum_record.push(quote);
             }
             let quote = quote! {
-                fn insert_statement(&self, place : String) -> String {
+                fn to_record(&self) -> Record {
                     match self{
-                        #(#enum_insert)*
-                        _ => String::from("")
-                    }
-                }
-            };
-            enum_token.push(quote);
-
-            let mut enum_value = vec![];
-            for v in variants.clone().into_iter().filter(|v| v.ident != "Null") {
-                let name = v.ident;
-                let quote = quote! {
-                    Data::#name(d) => d.value()
-                };
-                enum_value.push(quote);
-            }
-            let quote = quote! {
-                fn value(&self) -> mysql::params::Params{
-                    match self{
-                        #(#enum_value,)*
-                        _ => params::Params::Empty,
+                        #(#enum_record)*
+                        _ => Record::new(),
                     }
                 }
             };
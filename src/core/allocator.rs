@@ -0,0 +1,918 @@
+//! Dynamic allocation of IPv4 addresses.
+//!
+//! An [`AddressPool`] hands out the addresses of the dynamic ranges
+//! of a subnet. An address is first offered to a client, and held
+//! for it during a short time, then committed once the client
+//! requests it, until it is released. Offers which are not
+//! committed in time are reclaimed.
+//!
+//! An address declined by its client, because it is already used
+//! on the network, is abandoned: it is not handed out again until
+//! the abandon period elapsed.
+//!
+//! Clients are identified by their [`ClientId`], so that a client
+//! sending a client identifier keeps its address when it changes
+//! network interface.
+//!
+//! An [`Allocator`] holds the pool of every subnet.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{
+    client_id::ClientId,
+    errors::AllocationError,
+    history::LeaseHistory,
+    reservations::{Reservation, Reservations},
+};
+
+/// Filter of the addresses an [`Allocator`] can offer
+pub type Filter = dyn Fn(Ipv4Addr) -> bool + Send + Sync;
+
+/// Time during which declined addresses are not handed out
+pub const DEFAULT_ABANDON_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Inclusive range of IPv4 addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    start: Ipv4Addr,
+    end: Ipv4Addr,
+}
+
+impl AddressRange {
+    /// Creates the range from `start` to `end`, both included
+    pub fn new(start: Ipv4Addr, end: Ipv4Addr) -> Result<Self, AllocationError> {
+        match start <= end {
+            true => Ok(Self { start, end }),
+            false => Err(AllocationError::InvalidRange(start, end)),
+        }
+    }
+
+    pub fn start(&self) -> Ipv4Addr {
+        self.start
+    }
+
+    pub fn end(&self) -> Ipv4Addr {
+        self.end
+    }
+
+    /// Returns whether `address` belongs to the range
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        self.start <= address && address <= self.end
+    }
+
+    /// Returns the number of addresses of the range
+    pub fn len(&self) -> usize {
+        (u32::from(self.end) - u32::from(self.start)) as usize + 1
+    }
+
+    /// Always false, a range holds at least one address
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+/// Use of an address which is not free
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Binding {
+    /// Offered to a client, until the given time
+    Offered(ClientId, Instant),
+    /// Committed to a client
+    Leased(ClientId),
+    /// Declined by a client, until the given time
+    Abandoned(Instant),
+}
+
+impl Binding {
+    fn client(&self) -> Option<&ClientId> {
+        match self {
+            Self::Offered(client, _) | Self::Leased(client) => Some(client),
+            Self::Abandoned(_) => None,
+        }
+    }
+
+    /// Returns the time the binding expires at, if it does
+    fn expiration(&self) -> Option<Instant> {
+        match self {
+            Self::Offered(_, until) | Self::Abandoned(until) => Some(*until),
+            Self::Leased(_) => None,
+        }
+    }
+}
+
+/// Free addresses, as disjoint inclusive ranges keyed by their start
+///
+/// Keeps large pools cheap: a pool holds as many ranges
+/// as there are holes between its bound addresses.
+#[derive(Default)]
+struct FreeRanges {
+    ranges: BTreeMap<u32, u32>,
+    len: usize,
+}
+
+impl FreeRanges {
+    fn contains(&self, address: u32) -> bool {
+        self.ranges
+            .range(..=address)
+            .next_back()
+            .is_some_and(|(_, &end)| address <= end)
+    }
+
+    /// Frees the addresses from `start` to `end`, merging them
+    /// with the adjacent free ranges
+    fn insert(&mut self, mut start: u32, mut end: u32) {
+        if let Some((&previous, &previous_end)) = self.ranges.range(..=start).next_back() {
+            if previous_end.saturating_add(1) >= start {
+                self.take(previous);
+                start = previous;
+                end = end.max(previous_end);
+            }
+        }
+        while let Some((&next, &next_end)) = self.ranges.range(start..=end.saturating_add(1)).next()
+        {
+            self.take(next);
+            end = end.max(next_end);
+        }
+        self.ranges.insert(start, end);
+        self.len += span(start, end);
+    }
+
+    /// Takes the addresses from `start` to `end` out of the free ranges
+    fn remove(&mut self, start: u32, end: u32) {
+        let overlapping: Vec<u32> = self
+            .ranges
+            .range(..=end)
+            .rev()
+            .take_while(|(_, &range_end)| range_end >= start)
+            .map(|(&range_start, _)| range_start)
+            .collect();
+        for range_start in overlapping {
+            let range_end = self.take(range_start);
+            if range_start < start {
+                self.ranges.insert(range_start, start - 1);
+                self.len += span(range_start, start - 1);
+            }
+            if range_end > end {
+                self.ranges.insert(end + 1, range_end);
+                self.len += span(end + 1, range_end);
+            }
+        }
+    }
+
+    /// Removes the range starting at `start`, returning its end
+    fn take(&mut self, start: u32) -> u32 {
+        let end = self.ranges.remove(&start).unwrap();
+        self.len -= span(start, end);
+        end
+    }
+
+    /// Returns the free addresses, lowest first
+    fn addresses(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.ranges
+            .iter()
+            .flat_map(|(&start, &end)| (start..=end).map(Ipv4Addr::from))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Returns the number of addresses from `start` to `end`
+fn span(start: u32, end: u32) -> usize {
+    (end - start) as usize + 1
+}
+
+struct Bindings {
+    free: FreeRanges,
+    bound: HashMap<Ipv4Addr, Binding>,
+    /// Offered and abandoned addresses, by expiration time
+    expirations: BTreeSet<(Instant, Ipv4Addr)>,
+    clients: HashMap<ClientId, Ipv4Addr>,
+    excluded: Vec<AddressRange>,
+    leases: usize,
+}
+
+impl Bindings {
+    /// Frees the addresses whose offer or abandon period expired before `now`
+    fn reclaim(&mut self, now: Instant) {
+        while let Some(&(until, address)) = self.expirations.first() {
+            if until > now {
+                break;
+            }
+            self.unbind(address);
+        }
+    }
+
+    fn bind(&mut self, address: Ipv4Addr, binding: Binding) {
+        self.free.remove(u32::from(address), u32::from(address));
+        if let Some(client) = binding.client() {
+            self.clients.insert(client.clone(), address);
+        }
+        if let Binding::Leased(_) = binding {
+            self.leases += 1;
+        }
+        let expiration = binding.expiration();
+        if let Some(previous) = self.bound.insert(address, binding) {
+            if let Binding::Leased(_) = previous {
+                self.leases -= 1;
+            }
+            if let Some(until) = previous.expiration() {
+                self.expirations.remove(&(until, address));
+            }
+        }
+        if let Some(until) = expiration {
+            self.expirations.insert((until, address));
+        }
+    }
+
+    fn unbind(&mut self, address: Ipv4Addr) -> Option<Binding> {
+        let binding = self.bound.remove(&address)?;
+        if let Binding::Leased(_) = binding {
+            self.leases -= 1;
+        }
+        if let Some(until) = binding.expiration() {
+            self.expirations.remove(&(until, address));
+        }
+        if let Some(client) = binding.client() {
+            if self.clients.get(client) == Some(&address) {
+                self.clients.remove(client);
+            }
+        }
+        if !self.is_excluded(address) {
+            self.free.insert(u32::from(address), u32::from(address));
+        }
+        Some(binding)
+    }
+
+    fn is_excluded(&self, address: Ipv4Addr) -> bool {
+        self.excluded.iter().any(|range| range.contains(address))
+    }
+
+    /// Returns whether `client` holds a lease
+    fn is_leased(&self, client: &ClientId) -> bool {
+        let address = self.clients.get(client);
+        matches!(
+            address.and_then(|address| self.bound.get(address)),
+            Some(Binding::Leased(_))
+        )
+    }
+}
+
+/// Addresses of the dynamic ranges of a subnet
+pub struct AddressPool {
+    ranges: Vec<AddressRange>,
+    hold: Duration,
+    abandon: Duration,
+    limit: Option<usize>,
+    bindings: Mutex<Bindings>,
+}
+
+impl AddressPool {
+    /// Creates a pool of the addresses of `ranges`, holding
+    /// offered addresses during `hold`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let range = AddressRange::new(Ipv4Addr::new(10, 0, 0, 100), Ipv4Addr::new(10, 0, 0, 200))?;
+    /// let pool = AddressPool::new(vec![range], Duration::from_secs(30));
+    /// ```
+    pub fn new(ranges: Vec<AddressRange>, hold: Duration) -> Self {
+        let mut free = FreeRanges::default();
+        for range in &ranges {
+            free.insert(u32::from(range.start), u32::from(range.end));
+        }
+        Self {
+            ranges,
+            hold,
+            abandon: DEFAULT_ABANDON_PERIOD,
+            limit: None,
+            bindings: Mutex::new(Bindings {
+                free,
+                bound: HashMap::new(),
+                expirations: BTreeSet::new(),
+                clients: HashMap::new(),
+                excluded: vec![],
+                leases: 0,
+            }),
+        }
+    }
+
+    /// Sets the time during which declined addresses are not handed out
+    pub fn set_abandon_period(&mut self, abandon: Duration) {
+        self.abandon = abandon;
+    }
+
+    /// Caps the number of concurrent leases of the pool,
+    /// refusing new clients once `limit` is reached
+    pub fn set_lease_limit(&mut self, limit: usize) {
+        self.limit = Some(limit);
+    }
+
+    /// Returns the number of committed addresses
+    pub fn leases(&self) -> usize {
+        self.bindings.lock().unwrap().leases
+    }
+
+    /// Returns the number of leases which can still be
+    /// granted before the limit is reached, if any
+    pub fn remaining(&self) -> Option<usize> {
+        let leases = self.leases();
+        self.limit.map(|limit| limit.saturating_sub(leases))
+    }
+
+    /// Never hands out the addresses of `range`, such as infrastructure
+    /// addresses inside a dynamic range
+    ///
+    /// Addresses already bound stay bound until released.
+    pub fn exclude(&self, range: AddressRange) {
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings
+            .free
+            .remove(u32::from(range.start), u32::from(range.end));
+        bindings.excluded.push(range);
+    }
+
+    /// Returns whether `address` is excluded from the pool
+    pub fn is_excluded(&self, address: Ipv4Addr) -> bool {
+        self.bindings.lock().unwrap().is_excluded(address)
+    }
+
+    /// Returns the ranges of the pool
+    pub fn ranges(&self) -> &[AddressRange] {
+        &self.ranges
+    }
+
+    /// Returns whether `address` belongs to the ranges of the pool
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        self.ranges.iter().any(|range| range.contains(address))
+    }
+
+    /// Offers an address to `client`, and holds it until it is
+    /// committed or the offer expires
+    ///
+    /// The address already bound to the client is offered again if any,
+    /// then `hint` if it is free, then the lowest free address.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let offered = pool.allocate(packet.chaddr, requested_address)?;
+    /// ```
+    pub fn allocate(
+        &self,
+        client: impl Into<ClientId>,
+        hint: Option<Ipv4Addr>,
+    ) -> Result<Ipv4Addr, AllocationError> {
+        self.allocate_unreserved(client.into(), hint, |_| false)
+    }
+
+    /// Offers an address to `client`, preferring the first free
+    /// address of `candidates` and skipping the addresses
+    /// for which `reserved` returns true
+    fn allocate_unreserved(
+        &self,
+        client: ClientId,
+        candidates: impl IntoIterator<Item = Ipv4Addr>,
+        reserved: impl Fn(Ipv4Addr) -> bool,
+    ) -> Result<Ipv4Addr, AllocationError> {
+        let now = Instant::now();
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings.reclaim(now);
+        if let Some(&address) = bindings.clients.get(&client) {
+            if let Some(Binding::Offered(..)) = bindings.bound.get(&address) {
+                bindings.bind(address, Binding::Offered(client, now + self.hold));
+            }
+            return Ok(address);
+        }
+        if self.limit.is_some_and(|limit| bindings.leases >= limit) {
+            return Err(AllocationError::LimitReached);
+        }
+        let address = candidates
+            .into_iter()
+            .find(|candidate| {
+                bindings.free.contains(u32::from(*candidate)) && !reserved(*candidate)
+            })
+            .or_else(|| {
+                bindings
+                    .free
+                    .addresses()
+                    .find(|address| !reserved(*address))
+            })
+            .ok_or(AllocationError::Exhausted)?;
+        bindings.bind(address, Binding::Offered(client, now + self.hold));
+        Ok(address)
+    }
+
+    /// Commits `address` to `client`, once requested by the client
+    ///
+    /// The address must be free, or bound to the client.
+    pub fn commit(
+        &self,
+        address: Ipv4Addr,
+        client: impl Into<ClientId>,
+    ) -> Result<(), AllocationError> {
+        let client = client.into();
+        if !self.contains(address) {
+            return Err(AllocationError::OutOfRange(address));
+        }
+        let mut bindings = self.bindings.lock().unwrap();
+        if bindings.is_excluded(address) {
+            return Err(AllocationError::Excluded(address));
+        }
+        bindings.reclaim(Instant::now());
+        match bindings.bound.get(&address) {
+            Some(Binding::Abandoned(_)) => Err(AllocationError::Abandoned(address)),
+            Some(binding) if binding.client() != Some(&client) => {
+                Err(AllocationError::InUse(address))
+            }
+            _ if !bindings.is_leased(&client)
+                && self.limit.is_some_and(|limit| bindings.leases >= limit) =>
+            {
+                Err(AllocationError::LimitReached)
+            }
+            _ => {
+                //A client holds a single address
+                if let Some(&previous) = bindings.clients.get(&client) {
+                    bindings.unbind(previous);
+                }
+                bindings.bind(address, Binding::Leased(client));
+                Ok(())
+            }
+        }
+    }
+
+    /// Frees `address`, once released by its client or expired
+    pub fn release(&self, address: Ipv4Addr) -> Result<(), AllocationError> {
+        self.bindings
+            .lock()
+            .unwrap()
+            .unbind(address)
+            .map(|_| ())
+            .ok_or(AllocationError::NotAllocated(address))
+    }
+
+    /// Abandons `address`, declined by `client` because it is already
+    /// used on the network, until the abandon period elapsed
+    pub fn decline(
+        &self,
+        address: Ipv4Addr,
+        client: impl Into<ClientId>,
+    ) -> Result<(), AllocationError> {
+        let client = client.into();
+        let now = Instant::now();
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings.reclaim(now);
+        match bindings.bound.get(&address).and_then(Binding::client) {
+            Some(holder) if *holder == client => {
+                bindings.unbind(address);
+                bindings.bind(address, Binding::Abandoned(now + self.abandon));
+                log::warn!(
+                    "Address {} was declined by {}, abandoning it",
+                    address,
+                    client
+                );
+                Ok(())
+            }
+            Some(_) => Err(AllocationError::InUse(address)),
+            None => Err(AllocationError::NotAllocated(address)),
+        }
+    }
+
+    /// Returns whether `address` was declined and is still abandoned
+    pub fn is_abandoned(&self, address: Ipv4Addr) -> bool {
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings.reclaim(Instant::now());
+        matches!(bindings.bound.get(&address), Some(Binding::Abandoned(_)))
+    }
+
+    /// Returns the client `address` is bound to, if any
+    pub fn client(&self, address: Ipv4Addr) -> Option<ClientId> {
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings.reclaim(Instant::now());
+        bindings
+            .bound
+            .get(&address)
+            .and_then(Binding::client)
+            .cloned()
+    }
+
+    /// Returns every committed address, along with its client
+    pub fn leased(&self) -> Vec<(Ipv4Addr, ClientId)> {
+        let bindings = self.bindings.lock().unwrap();
+        bindings
+            .bound
+            .iter()
+            .filter_map(|(address, binding)| match binding {
+                Binding::Leased(client) => Some((*address, client.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the number of free addresses
+    pub fn available(&self) -> usize {
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings.reclaim(Instant::now());
+        bindings.free.len()
+    }
+}
+
+/// Address pools of every subnet, keyed by network address
+///
+/// Once [reservations](Allocator::set_reservations) are set, hosts
+/// with a reservation are always given their reserved address, and
+/// reserved addresses are never given to other hosts. Reservations
+/// are made by hardware address, so they only apply to the clients
+/// whose identifier is made of their Ethernet address.
+///
+/// Once a [history](Allocator::set_history) is set, returning
+/// clients are offered their previous address again if it is
+/// still free, so that their address stays stable across reboots.
+#[derive(Default)]
+pub struct Allocator {
+    pools: HashMap<Ipv4Addr, AddressPool>,
+    reservations: Option<Arc<Reservations>>,
+    history: Option<Arc<LeaseHistory>>,
+    filter: Option<Arc<Filter>>,
+}
+
+impl Allocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the pool of the subnet with the given network address
+    pub fn add_pool(&mut self, network: Ipv4Addr, pool: AddressPool) {
+        self.pools.insert(network, pool);
+    }
+
+    /// Consults `reservations` before any dynamic assignment
+    pub fn set_reservations(&mut self, reservations: Arc<Reservations>) {
+        self.reservations = Some(reservations);
+    }
+
+    /// Consults `history` for the previous address of returning clients
+    pub fn set_history(&mut self, history: Arc<LeaseHistory>) {
+        self.history = Some(history);
+    }
+
+    /// Only offers the addresses for which `filter` returns true, such
+    /// as the addresses owned by this server when its pools are split
+    /// with a failover peer
+    ///
+    /// Addresses already bound can still be committed.
+    pub fn set_filter(&mut self, filter: impl Fn(Ipv4Addr) -> bool + Send + Sync + 'static) {
+        self.filter = Some(Arc::new(filter));
+    }
+
+    /// Returns the pool of the subnet with the given network address
+    pub fn pool(&self, network: Ipv4Addr) -> Option<&AddressPool> {
+        self.pools.get(&network)
+    }
+
+    /// Offers an address of the subnet with the given network address to `client`,
+    /// see [`AddressPool::allocate`]
+    ///
+    /// As described in RFC 2131, the previous address of the client
+    /// is preferred over `hint`, the address it requested.
+    pub fn allocate(
+        &self,
+        network: Ipv4Addr,
+        client: impl Into<ClientId>,
+        hint: Option<Ipv4Addr>,
+    ) -> Result<Ipv4Addr, AllocationError> {
+        let client = client.into();
+        let pool = self
+            .pool(network)
+            .ok_or(AllocationError::UnknownSubnet(network))?;
+        let reservations = self.reservations.as_ref();
+        if let Some(reservation) =
+            reservations.and_then(|reservations| reservations.get(client.hardware_address()?))
+        {
+            return Ok(reservation.address);
+        }
+        let previous = self.history.as_ref().and_then(|history| {
+            history
+                .last_address(client.hardware_address()?)
+                .filter(|address| pool.contains(*address))
+        });
+        pool.allocate_unreserved(client, previous.into_iter().chain(hint), |address| {
+            reservations.is_some_and(|reservations| reservations.by_address(address).is_some())
+                || self.filter.as_ref().is_some_and(|filter| !filter(address))
+        })
+    }
+
+    /// Returns every committed address of every pool, along with its client
+    pub fn leased(&self) -> Vec<(Ipv4Addr, ClientId)> {
+        self.pools.values().flat_map(AddressPool::leased).collect()
+    }
+
+    /// Commits `address` to `client`, in the pool it belongs to
+    pub fn commit(
+        &self,
+        address: Ipv4Addr,
+        client: impl Into<ClientId>,
+    ) -> Result<(), AllocationError> {
+        let client = client.into();
+        match self.reservation(address) {
+            Some(reservation)
+                if client.hardware_address() == Some(reservation.hardware_address) =>
+            {
+                Ok(())
+            }
+            Some(_) => Err(AllocationError::InUse(address)),
+            None => self.pool_of(address)?.commit(address, client),
+        }
+    }
+
+    /// Frees `address`, in the pool it belongs to
+    ///
+    /// Reserved addresses stay reserved.
+    pub fn release(&self, address: Ipv4Addr) -> Result<(), AllocationError> {
+        match self.reservation(address) {
+            Some(_) => Ok(()),
+            None => self.pool_of(address)?.release(address),
+        }
+    }
+
+    /// Abandons `address`, declined by `client`, in the pool it belongs to
+    ///
+    /// Reserved addresses are never abandoned.
+    pub fn decline(
+        &self,
+        address: Ipv4Addr,
+        client: impl Into<ClientId>,
+    ) -> Result<(), AllocationError> {
+        let client = client.into();
+        match self.reservation(address) {
+            Some(_) => {
+                log::warn!("Reserved address {} was declined by {}", address, client);
+                Ok(())
+            }
+            None => self.pool_of(address)?.decline(address, client),
+        }
+    }
+
+    fn reservation(&self, address: Ipv4Addr) -> Option<Reservation> {
+        self.reservations.as_ref()?.by_address(address)
+    }
+
+    fn pool_of(&self, address: Ipv4Addr) -> Result<&AddressPool, AllocationError> {
+        self.pools
+            .values()
+            .find(|pool| pool.contains(address))
+            .ok_or(AllocationError::OutOfRange(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mac_address::MacAddress;
+
+    use super::*;
+    use crate::{
+        core::history::{LeaseEvent, LeaseEventKind},
+        storage::memory_backend::MemoryBackend,
+    };
+
+    fn client(last: u8) -> MacAddress {
+        MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, last])
+    }
+
+    #[test]
+    fn test_allocation() {
+        let range =
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 12)).unwrap();
+        let pool = AddressPool::new(vec![range], Duration::from_millis(50));
+        let hint = Ipv4Addr::new(10, 0, 0, 12);
+
+        assert_eq!(pool.allocate(client(1), Some(hint)), Ok(hint));
+        //The same client is offered the same address
+        assert_eq!(pool.allocate(client(1), None), Ok(hint));
+        assert_eq!(
+            pool.allocate(client(2), Some(hint)),
+            Ok(Ipv4Addr::new(10, 0, 0, 10))
+        );
+        pool.commit(hint, client(1)).unwrap();
+        assert_eq!(
+            pool.commit(hint, client(3)),
+            Err(AllocationError::InUse(hint))
+        );
+        assert_eq!(
+            pool.allocate(client(3), None),
+            Ok(Ipv4Addr::new(10, 0, 0, 11))
+        );
+        assert_eq!(
+            pool.allocate(client(4), None),
+            Err(AllocationError::Exhausted)
+        );
+
+        //Offers which are not committed are reclaimed
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(pool.available(), 2);
+        assert_eq!(pool.client(hint), Some(client(1).into()));
+        pool.release(hint).unwrap();
+        assert_eq!(pool.release(hint), Err(AllocationError::NotAllocated(hint)));
+        assert_eq!(pool.available(), 3);
+
+        pool.exclude(AddressRange::new(hint, hint).unwrap());
+        assert_eq!(
+            pool.allocate(client(1), Some(hint)),
+            Ok(Ipv4Addr::new(10, 0, 0, 10))
+        );
+        assert_eq!(
+            pool.commit(hint, client(1)),
+            Err(AllocationError::Excluded(hint))
+        );
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn test_large_pool() {
+        let range = AddressRange::new(Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 255, 255, 255))
+            .unwrap();
+        let pool = AddressPool::new(vec![range], Duration::from_millis(50));
+        assert_eq!(pool.available(), 1 << 24);
+
+        pool.exclude(
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 0, 255, 255)).unwrap(),
+        );
+        assert_eq!(pool.available(), (1 << 24) - (1 << 16));
+        assert_eq!(
+            pool.allocate(client(1), None),
+            Ok(Ipv4Addr::new(10, 1, 0, 0))
+        );
+        let hint = Ipv4Addr::new(10, 200, 0, 1);
+        assert_eq!(pool.allocate(client(2), Some(hint)), Ok(hint));
+        pool.commit(hint, client(2)).unwrap();
+        assert_eq!(pool.available(), (1 << 24) - (1 << 16) - 2);
+
+        //The offer expires, the lease does not
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(pool.available(), (1 << 24) - (1 << 16) - 1);
+        assert_eq!(
+            pool.allocate(client(3), None),
+            Ok(Ipv4Addr::new(10, 1, 0, 0))
+        );
+        pool.release(hint).unwrap();
+        assert_eq!(pool.available(), (1 << 24) - (1 << 16) - 1);
+    }
+
+    #[test]
+    fn test_lease_limit() {
+        let range =
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 12)).unwrap();
+        let mut pool = AddressPool::new(vec![range], Duration::from_secs(30));
+        pool.set_lease_limit(1);
+        let leased = pool.allocate(client(1), None).unwrap();
+        let offered = pool.allocate(client(2), None).unwrap();
+        pool.commit(leased, client(1)).unwrap();
+        assert_eq!(pool.remaining(), Some(0));
+        assert_eq!(
+            pool.commit(offered, client(2)),
+            Err(AllocationError::LimitReached)
+        );
+        assert_eq!(
+            pool.allocate(client(3), None),
+            Err(AllocationError::LimitReached)
+        );
+        //Renewals are still granted
+        pool.commit(leased, client(1)).unwrap();
+        pool.release(leased).unwrap();
+        assert_eq!(pool.leases(), 0);
+        pool.commit(offered, client(2)).unwrap();
+    }
+
+    #[test]
+    fn test_client_identifier() {
+        let range =
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 20)).unwrap();
+        let pool = AddressPool::new(vec![range], Duration::from_secs(30));
+        let client_id = Some(&b"\xffhost"[..]);
+
+        let address = pool
+            .allocate(ClientId::of(client(1), client_id), None)
+            .unwrap();
+        pool.commit(address, ClientId::of(client(1), client_id))
+            .unwrap();
+        //The client keeps its address on another interface
+        assert_eq!(
+            pool.allocate(ClientId::of(client(2), client_id), None),
+            Ok(address)
+        );
+        assert_eq!(
+            pool.commit(address, client(1)),
+            Err(AllocationError::InUse(address))
+        );
+        assert_eq!(
+            pool.client(address),
+            Some(ClientId::new(b"\xffhost".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_decline() {
+        let range =
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 11)).unwrap();
+        let mut pool = AddressPool::new(vec![range], Duration::from_secs(30));
+        pool.set_abandon_period(Duration::from_millis(50));
+        let declined = pool.allocate(client(1), None).unwrap();
+        assert_eq!(
+            pool.decline(declined, client(2)),
+            Err(AllocationError::InUse(declined))
+        );
+        pool.decline(declined, client(1)).unwrap();
+        assert!(pool.is_abandoned(declined));
+
+        //The declined address is not offered again
+        assert_eq!(
+            pool.allocate(client(1), None),
+            Ok(Ipv4Addr::new(10, 0, 0, 11))
+        );
+        assert_eq!(
+            pool.commit(declined, client(2)),
+            Err(AllocationError::Abandoned(declined))
+        );
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!pool.is_abandoned(declined));
+        assert_eq!(pool.allocate(client(2), None), Ok(declined));
+    }
+
+    #[test]
+    fn test_reservations_first() {
+        let range =
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 11)).unwrap();
+        let network = Ipv4Addr::new(10, 0, 0, 0);
+        let reservations = Arc::new(Reservations::new(MemoryBackend::new()).unwrap());
+        let reserved = Ipv4Addr::new(10, 0, 0, 10);
+        reservations
+            .set(Reservation::new(client(1), reserved))
+            .unwrap();
+        let mut allocator = Allocator::new();
+        allocator.add_pool(
+            network,
+            AddressPool::new(vec![range], Duration::from_secs(30)),
+        );
+        allocator.set_reservations(reservations);
+
+        assert_eq!(allocator.allocate(network, client(1), None), Ok(reserved));
+        assert_eq!(
+            allocator.allocate(network, client(2), Some(reserved)),
+            Ok(Ipv4Addr::new(10, 0, 0, 11))
+        );
+        assert_eq!(
+            allocator.allocate(network, client(3), None),
+            Err(AllocationError::Exhausted)
+        );
+        assert_eq!(
+            allocator.commit(reserved, client(2)),
+            Err(AllocationError::InUse(reserved))
+        );
+        assert_eq!(allocator.commit(reserved, client(1)), Ok(()));
+    }
+
+    #[test]
+    fn test_affinity() {
+        let range =
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 20)).unwrap();
+        let network = Ipv4Addr::new(10, 0, 0, 0);
+        let history = Arc::new(LeaseHistory::new(MemoryBackend::new()).unwrap());
+        let previous = Ipv4Addr::new(10, 0, 0, 15);
+        history
+            .record(LeaseEvent::new(LeaseEventKind::Acked, client(1), previous))
+            .unwrap();
+        history
+            .record(LeaseEvent::new(
+                LeaseEventKind::Expired,
+                client(1),
+                previous,
+            ))
+            .unwrap();
+        let mut allocator = Allocator::new();
+        allocator.add_pool(
+            network,
+            AddressPool::new(vec![range], Duration::from_secs(30)),
+        );
+        allocator.set_history(history);
+
+        //The previous address is preferred over the requested one
+        assert_eq!(
+            allocator.allocate(network, client(1), Some(Ipv4Addr::new(10, 0, 0, 12))),
+            Ok(previous)
+        );
+        allocator.release(previous).unwrap();
+        allocator.commit(previous, client(2)).unwrap();
+        assert_eq!(
+            allocator.allocate(network, client(1), Some(Ipv4Addr::new(10, 0, 0, 12))),
+            Ok(Ipv4Addr::new(10, 0, 0, 12))
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Optional batching layer for outputs able to send
+//! several packets at once (e.g. through `sendmmsg`).
+//!
+//! A [`BatchingOutput`] implements [`Output`] so it can be
+//! plugged into a [`StateSwitcher`] as-is: it buffers the
+//! packets it receives and flushes them to a [`BatchOutput`]
+//! once the batch is full, or once the oldest buffered
+//! packet waited long enough.
+//!
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+use super::{packet::PacketType, state_switcher::Output};
+
+/// An output able to dispatch a whole batch of packets at once
+#[async_trait]
+pub trait BatchOutput<T: PacketType>: Send + Sync {
+    /// Sends every packet of the batch to its destination, if any,
+    /// returning one result per packet, in the same order as the batch.
+    ///
+    /// Packets without a matching result are considered
+    /// as not sent.
+    async fn send_batch(
+        &self,
+        packets: Vec<(T, Option<SocketAddr>)>,
+    ) -> Vec<Result<usize, io::Error>>;
+}
+
+type Pending<T> = (
+    (T, Option<SocketAddr>),
+    oneshot::Sender<Result<usize, io::Error>>,
+);
+
+/// [`Output`] buffering packets and flushing them
+/// to a [`BatchOutput`] on size or time thresholds
+pub struct BatchingOutput<T: PacketType> {
+    sender: mpsc::Sender<Pending<T>>,
+}
+
+impl<T: PacketType + Send + 'static> BatchingOutput<T> {
+    /// Creates a new `BatchingOutput` flushing batches of at most
+    /// `max_batch` packets to `output`, without holding any packet
+    /// longer than `max_delay`.
+    ///
+    /// This spawns the flushing task, and thus must be
+    /// called from within a tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_batch` is 0
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let output = BatchingOutput::new(mmsg_output, 32, Duration::from_millis(1));
+    /// ```
+    pub fn new(
+        output: impl BatchOutput<T> + 'static,
+        max_batch: usize,
+        max_delay: Duration,
+    ) -> Self {
+        assert!(max_batch > 0, "Batch size must be greater than 0");
+        let (sender, receiver) = mpsc::channel(max_batch);
+        tokio::spawn(Self::flush_loop(output, receiver, max_batch, max_delay));
+
+        Self { sender }
+    }
+
+    async fn flush_loop(
+        output: impl BatchOutput<T>,
+        mut receiver: mpsc::Receiver<Pending<T>>,
+        max_batch: usize,
+        max_delay: Duration,
+    ) {
+        while let Some(first) = receiver.recv().await {
+            let flush_at = Instant::now() + max_delay;
+            let mut batch = vec![first];
+
+            while batch.len() < max_batch {
+                match tokio::time::timeout_at(flush_at, receiver.recv()).await {
+                    Ok(Some(pending)) => batch.push(pending),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            let (packets, waiters): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+            let mut results = output.send_batch(packets).await.into_iter();
+            for waiter in waiters {
+                let result = results.next().unwrap_or_else(|| {
+                    Err(io::Error::other("Packet was not sent as part of its batch"))
+                });
+                let _ = waiter.send(result);
+            }
+        }
+    }
+
+    async fn buffer(&self, packet: T, destination: Option<SocketAddr>) -> Result<usize, io::Error> {
+        let (waiter, result) = oneshot::channel();
+        self.sender
+            .send(((packet, destination), waiter))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Batch flusher stopped"))?;
+
+        result
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Batch flusher stopped"))?
+    }
+}
+
+#[async_trait]
+impl<T: PacketType + Send + 'static> Output<T> for BatchingOutput<T> {
+    /// Buffers the packet, and waits until the batch
+    /// containing it is flushed
+    async fn send(&self, packet: T) -> Result<usize, io::Error> {
+        self.buffer(packet, None).await
+    }
+
+    /// Buffers the packet along with its destination, and
+    /// waits until the batch containing it is flushed
+    async fn send_to(&self, packet: T, destination: SocketAddr) -> Result<usize, io::Error> {
+        self.buffer(packet, Some(destination)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct A {
+        raw: Vec<u8>,
+    }
+    impl PacketType for A {
+        fn empty() -> Self {
+            Self { raw: vec![] }
+        }
+        fn from_raw_bytes(raw: &[u8]) -> Self {
+            Self { raw: raw.to_vec() }
+        }
+
+        fn to_raw_bytes(&self) -> &[u8] {
+            &self.raw
+        }
+    }
+
+    struct RecordingBatchOutput {
+        batches: Arc<Mutex<Vec<usize>>>,
+    }
+
+    #[async_trait]
+    impl BatchOutput<A> for RecordingBatchOutput {
+        async fn send_batch(
+            &self,
+            packets: Vec<(A, Option<SocketAddr>)>,
+        ) -> Vec<Result<usize, io::Error>> {
+            self.batches.lock().unwrap().push(packets.len());
+            packets
+                .iter()
+                .map(|(packet, _)| Ok(packet.raw.len()))
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_size() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let output = Arc::new(BatchingOutput::new(
+            RecordingBatchOutput {
+                batches: batches.clone(),
+            },
+            2,
+            Duration::from_secs(60),
+        ));
+
+        let sends: Vec<_> = (1..=4)
+            .map(|len| {
+                let output = output.clone();
+                tokio::spawn(async move { output.send(A::from_raw_bytes(&vec![0; len])).await })
+            })
+            .collect();
+        let mut sent = vec![];
+        for send in sends {
+            sent.push(send.await.unwrap().unwrap());
+        }
+
+        assert_eq!(sent, vec![1, 2, 3, 4]);
+        assert_eq!(*batches.lock().unwrap(), vec![2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_delay() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let output = BatchingOutput::new(
+            RecordingBatchOutput {
+                batches: batches.clone(),
+            },
+            16,
+            Duration::from_millis(10),
+        );
+
+        assert_eq!(output.send(A::from_raw_bytes(&[1, 2, 3])).await.unwrap(), 3);
+        assert_eq!(*batches.lock().unwrap(), vec![1]);
+    }
+}
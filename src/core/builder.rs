@@ -0,0 +1,202 @@
+//! Builder for the [`StateSwitcher`], taking care of
+//! boxing the [`Input`] and [`Output`] and of creating
+//! the kill switch, while providing sane defaults for
+//! every optional setting.
+
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+
+use crate::hooks::hook_registry::HookRegistry;
+
+use super::{
+    counters::Counters,
+    dedup::Deduplicator,
+    errors::BuilderError,
+    handle::PipelineHandle,
+    packet::PacketType,
+    queue::OverflowPolicy,
+    retry::RetryPolicy,
+    state_switcher::{Input, Output, StateSwitcher, DEFAULT_QUEUE_CAPACITY},
+};
+
+/// Step by step configuration of a [`StateSwitcher`]
+///
+/// Only the [`Input`] and the [`Output`] are mandatory,
+/// an empty [`HookRegistry`] is used if none is provided.
+pub struct StateSwitcherBuilder<T: PacketType + Send + 'static, U: PacketType + Send + 'static> {
+    input: Option<Box<dyn Input<T>>>,
+    output: Option<Box<dyn Output<U>>>,
+    registry: Option<Arc<HookRegistry<T, U>>>,
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    retry_policy: RetryPolicy,
+    concurrency_limit: Option<usize>,
+    deadline: Option<Duration>,
+    metrics: Option<Arc<Counters>>,
+    dedup: Option<Deduplicator<T>>,
+    dry_run: Option<Box<dyn Output<U>>>,
+}
+
+impl<T: PacketType + Send, U: PacketType + Send> Default for StateSwitcherBuilder<T, U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PacketType + Send, U: PacketType + Send> StateSwitcherBuilder<T, U> {
+    /// Creates a new `StateSwitcherBuilder` with
+    /// default settings
+    pub fn new() -> Self {
+        Self {
+            input: None,
+            output: None,
+            registry: None,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            concurrency_limit: None,
+            deadline: None,
+            metrics: None,
+            dedup: None,
+            dry_run: None,
+        }
+    }
+
+    /// Sets the [`Input`] packets are read from
+    pub fn input(mut self, input: impl Input<T> + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    /// Sets the [`Output`] packets are dispatched to
+    pub fn output(mut self, output: impl Output<U> + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Sets the [`HookRegistry`] executed on every packet
+    pub fn registry(mut self, registry: HookRegistry<T, U>) -> Self {
+        self.registry = Some(Arc::new(registry));
+        self
+    }
+
+    /// Sets a [`HookRegistry`] shared with other
+    /// `StateSwitcher`, along with its services
+    pub fn shared_registry(mut self, registry: Arc<HookRegistry<T, U>>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Configures the queue between the [`Input`] and the
+    /// processing tasks, see [`StateSwitcher::set_backpressure`]
+    pub fn queue(mut self, capacity: usize, policy: OverflowPolicy) -> Self {
+        self.queue_capacity = capacity;
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Sets the [`RetryPolicy`] applied when dispatching packets
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Limits the number of packets processed concurrently,
+    /// see [`StateSwitcher::set_concurrency_limit`]
+    pub fn concurrency_limit(mut self, limit: usize) -> Self {
+        self.concurrency_limit = Some(limit);
+        self
+    }
+
+    /// Sets the maximum time a packet can spend in the
+    /// pipeline, see [`StateSwitcher::set_deadline`]
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Shares the given [`Counters`] with the
+    /// resulting [`StateSwitcher`]
+    pub fn metrics(mut self, counters: Arc<Counters>) -> Self {
+        self.metrics = Some(counters);
+        self
+    }
+
+    /// Drops duplicate packets, see [`StateSwitcher::set_dedup`]
+    pub fn dedup(
+        mut self,
+        ttl: Duration,
+        fingerprint: impl Fn(&T) -> Vec<u8> + Send + Sync + 'static,
+    ) -> Self {
+        self.dedup = Some(Deduplicator::new(ttl, fingerprint));
+        self
+    }
+
+    /// Enables the dry-run mode, handing output packets
+    /// over to `sink`, see [`StateSwitcher::set_dry_run`]
+    pub fn dry_run(mut self, sink: impl Output<U> + 'static) -> Self {
+        self.dry_run = Some(Box::new(sink));
+        self
+    }
+
+    /// Creates the configured [`StateSwitcher`], along with
+    /// a [`PipelineHandle`] controlling its execution
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BuilderError`] if no [`Input`] or
+    /// no [`Output`] was provided
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue capacity or the concurrency limit is 0
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let (state_switcher, handle) = StateSwitcherBuilder::new()
+    ///     .input(UdpInput::start("0.0.0.0:67").await?)
+    ///     .output(output)
+    ///     .registry(registry)
+    ///     .concurrency_limit(64)
+    ///     .build()?;
+    /// ```
+    pub fn build(self) -> Result<(StateSwitcher<T, U>, PipelineHandle), BuilderError> {
+        let input = self
+            .input
+            .ok_or(BuilderError::new("No input was provided"))?;
+        let output = self
+            .output
+            .ok_or(BuilderError::new("No output was provided"))?;
+
+        let mut state_switcher = StateSwitcher::new(
+            input,
+            output,
+            HookRegistry::new(),
+            Arc::new(AtomicBool::new(true)),
+        );
+        if let Some(registry) = self.registry {
+            state_switcher.set_registry(registry);
+        }
+        state_switcher.set_backpressure(self.queue_capacity, self.overflow_policy);
+        state_switcher.set_retry_policy(self.retry_policy);
+        state_switcher.set_dry_run(self.dry_run);
+        if let Some(limit) = self.concurrency_limit {
+            state_switcher.set_concurrency_limit(limit);
+        }
+        if let Some(deadline) = self.deadline {
+            state_switcher.set_deadline(deadline);
+        }
+        if let Some(dedup) = self.dedup {
+            state_switcher.set_deduplicator(dedup);
+        }
+        if let Some(counters) = self.metrics {
+            state_switcher.set_metrics(counters);
+        }
+
+        let handle = state_switcher.handle();
+        Ok((state_switcher, handle))
+    }
+}
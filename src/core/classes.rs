@@ -0,0 +1,390 @@
+//! Classification of clients.
+//!
+//! A [`Class`] groups the clients whose request matches its
+//! [`Match`] expression, evaluated over the hardware address and
+//! the options sent by the client. Classes can then give their
+//! clients a different pool, lease time or set of options.
+//!
+//! Requests are described by [`ClientAttributes`], so that the
+//! classifier doesn't depend on a packet type.
+//!
+//! A [`ClassLimiter`] caps the number of concurrent leases
+//! held by the clients of each class.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::Ipv4Addr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use mac_address::MacAddress;
+
+use super::{
+    client_id::{ClientId, CLIENT_ID_OPTION},
+    errors::AllocationError,
+    options::{VendorOptions, VENDOR_SPECIFIC_OPTION},
+};
+
+/// Code of the vendor class identifier option
+pub const VENDOR_CLASS_OPTION: u8 = 60;
+/// Code of the user class option
+pub const USER_CLASS_OPTION: u8 = 77;
+/// Code of the relay agent information option
+pub const RELAY_AGENT_OPTION: u8 = 82;
+
+/// Attributes of a request used to classify its client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientAttributes {
+    pub chaddr: MacAddress,
+    /// Raw value of the options of the request, by option code
+    pub options: BTreeMap<u8, Vec<u8>>,
+}
+
+impl ClientAttributes {
+    pub fn new(chaddr: MacAddress) -> Self {
+        Self {
+            chaddr,
+            options: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_option(&mut self, code: u8, value: Vec<u8>) {
+        self.options.insert(code, value);
+    }
+
+    /// Returns the raw value of option `code`, if sent
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options.get(&code).map(Vec::as_slice)
+    }
+
+    /// Returns the identifier of the client, see [`ClientId::of`]
+    pub fn client_id(&self) -> ClientId {
+        ClientId::of(self.chaddr, self.option(CLIENT_ID_OPTION))
+    }
+
+    /// Returns the vendor class identifier, if sent and valid UTF-8
+    pub fn vendor_class(&self) -> Option<&str> {
+        std::str::from_utf8(self.option(VENDOR_CLASS_OPTION)?).ok()
+    }
+
+    /// Returns the raw value of sub-option `code` of the
+    /// relay agent information option, if sent
+    pub fn relay_agent_option(&self, code: u8) -> Option<&[u8]> {
+        let mut data = self.option(RELAY_AGENT_OPTION)?;
+        while let [sub_code, len, rest @ ..] = data {
+            let value = rest.get(..*len as usize)?;
+            if *sub_code == code {
+                return Some(value);
+            }
+            data = &rest[*len as usize..];
+        }
+        None
+    }
+}
+
+/// Expression matching the attributes of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Match {
+    /// The vendor class identifier starts with the given string
+    VendorClass(String),
+    /// The user class is equal to the given value
+    UserClass(Vec<u8>),
+    /// The given relay agent sub-option is equal to the given value
+    RelayAgent(u8, Vec<u8>),
+    /// The hardware address starts with the given bytes
+    MacPrefix(Vec<u8>),
+    /// The given option is equal to the given value
+    Option(u8, Vec<u8>),
+    /// Every expression matches
+    All(Vec<Match>),
+    /// At least one expression matches
+    Any(Vec<Match>),
+    /// The expression doesn't match
+    Not(Box<Match>),
+}
+
+impl Match {
+    /// Returns whether `attributes` match the expression
+    pub fn matches(&self, attributes: &ClientAttributes) -> bool {
+        match self {
+            Self::VendorClass(prefix) => attributes
+                .option(VENDOR_CLASS_OPTION)
+                .is_some_and(|vendor| vendor.starts_with(prefix.as_bytes())),
+            Self::UserClass(value) => attributes.option(USER_CLASS_OPTION) == Some(value),
+            Self::RelayAgent(code, value) => attributes.relay_agent_option(*code) == Some(value),
+            Self::MacPrefix(prefix) => attributes.chaddr.bytes().starts_with(prefix),
+            Self::Option(code, value) => attributes.option(*code) == Some(value),
+            Self::All(expressions) => expressions.iter().all(|expr| expr.matches(attributes)),
+            Self::Any(expressions) => expressions.iter().any(|expr| expr.matches(attributes)),
+            Self::Not(expression) => !expression.matches(attributes),
+        }
+    }
+}
+
+/// Named group of clients, with its own settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Class {
+    name: String,
+    expression: Match,
+    pool: Option<Ipv4Addr>,
+    lease_time: Option<Duration>,
+    lease_limit: Option<usize>,
+    options: BTreeMap<u8, Vec<u8>>,
+}
+
+impl Class {
+    /// Creates the class of the clients matching `expression`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut phones = Class::new(String::from("phones"), Match::VendorClass(String::from("Polycom")));
+    /// phones.set_pool(Ipv4Addr::new(10, 0, 1, 0));
+    /// phones.set_option(66, b"tftp.lan".to_vec());
+    /// ```
+    pub fn new(name: String, expression: Match) -> Self {
+        Self {
+            name,
+            expression,
+            pool: None,
+            lease_time: None,
+            lease_limit: None,
+            options: BTreeMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn expression(&self) -> &Match {
+        &self.expression
+    }
+
+    /// Returns the network address of the pool serving the class, if any
+    pub fn pool(&self) -> Option<Ipv4Addr> {
+        self.pool
+    }
+
+    /// Serves the class from the pool of the subnet with the given network address
+    pub fn set_pool(&mut self, network: Ipv4Addr) {
+        self.pool = Some(network);
+    }
+
+    pub fn lease_time(&self) -> Option<Duration> {
+        self.lease_time
+    }
+
+    pub fn set_lease_time(&mut self, lease_time: Duration) {
+        self.lease_time = Some(lease_time);
+    }
+
+    pub fn lease_limit(&self) -> Option<usize> {
+        self.lease_limit
+    }
+
+    /// Caps the number of concurrent leases held by the clients of the class
+    pub fn set_lease_limit(&mut self, lease_limit: usize) {
+        self.lease_limit = Some(lease_limit);
+    }
+
+    pub fn options(&self) -> &BTreeMap<u8, Vec<u8>> {
+        &self.options
+    }
+
+    /// Sends option `code` with the given raw value to the clients of the class
+    pub fn set_option(&mut self, code: u8, value: Vec<u8>) {
+        self.options.insert(code, value);
+    }
+
+    /// Sends the given vendor-specific information to the clients of the class
+    pub fn set_vendor_options(&mut self, vendor: &VendorOptions) {
+        self.set_option(VENDOR_SPECIFIC_OPTION, vendor.encode());
+    }
+}
+
+/// Set of [`Class`], evaluated in the order they were added
+#[derive(Debug, Clone, Default)]
+pub struct Classifier {
+    classes: Vec<Class>,
+}
+
+impl Classifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_class(&mut self, class: Class) {
+        self.classes.push(class);
+    }
+
+    pub fn classes(&self) -> &[Class] {
+        &self.classes
+    }
+
+    /// Returns the classes of the client with the given attributes
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let classes = classifier.classify(&attributes);
+    /// let network = classes.iter().find_map(|class| class.pool()).unwrap_or(subnet.network());
+    /// ```
+    pub fn classify(&self, attributes: &ClientAttributes) -> Vec<&Class> {
+        self.classes
+            .iter()
+            .filter(|class| class.expression.matches(attributes))
+            .collect()
+    }
+}
+
+/// Enforces the lease limits of the classes of a [`Classifier`]
+pub struct ClassLimiter {
+    limits: HashMap<String, usize>,
+    leases: Mutex<HashMap<String, HashSet<MacAddress>>>,
+}
+
+impl ClassLimiter {
+    /// Creates a limiter enforcing the lease limit of every class of `classifier`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let classes = classifier.classify(&attributes);
+    /// limiter.acquire(&classes, attributes.chaddr)?;
+    /// allocator.commit(address, attributes.chaddr)?;
+    /// ```
+    pub fn new(classifier: &Classifier) -> Self {
+        Self {
+            limits: classifier
+                .classes
+                .iter()
+                .filter_map(|class| Some((class.name.clone(), class.lease_limit?)))
+                .collect(),
+            leases: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a lease of `chaddr` against the limit of each of
+    /// its classes, failing if any of them is reached
+    ///
+    /// Clients already holding a lease are counted once.
+    pub fn acquire(&self, classes: &[&Class], chaddr: MacAddress) -> Result<(), AllocationError> {
+        let mut leases = self.leases.lock().unwrap();
+        let full = classes.iter().any(|class| {
+            let Some(&limit) = self.limits.get(&class.name) else {
+                return false;
+            };
+            leases
+                .get(&class.name)
+                .is_some_and(|clients| clients.len() >= limit && !clients.contains(&chaddr))
+        });
+        if full {
+            return Err(AllocationError::LimitReached);
+        }
+        for class in classes {
+            if self.limits.contains_key(&class.name) {
+                leases.entry(class.name.clone()).or_default().insert(chaddr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops counting the lease of `chaddr`, once released or expired
+    pub fn release(&self, chaddr: MacAddress) {
+        for clients in self.leases.lock().unwrap().values_mut() {
+            clients.remove(&chaddr);
+        }
+    }
+
+    /// Returns the number of leases which can still be granted
+    /// to the clients of the class, if it has a limit
+    pub fn remaining(&self, class: &str) -> Option<usize> {
+        let limit = *self.limits.get(class)?;
+        let leases = self.leases.lock().unwrap();
+        Some(limit.saturating_sub(leases.get(class).map_or(0, HashSet::len)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        let mut classifier = Classifier::new();
+        let mut phones = Class::new(
+            String::from("phones"),
+            Match::All(vec![
+                Match::VendorClass(String::from("Polycom")),
+                Match::Not(Box::new(Match::UserClass(b"lab".to_vec()))),
+            ]),
+        );
+        phones.set_lease_time(Duration::from_secs(600));
+        let mut vendor = VendorOptions::new();
+        vendor.set_suboption(2, b"sip.lan".to_vec());
+        phones.set_vendor_options(&vendor);
+        classifier.add_class(phones);
+        classifier.add_class(Class::new(
+            String::from("vendor"),
+            Match::Any(vec![
+                Match::MacPrefix(vec![0x00, 0x04, 0xf2]),
+                Match::RelayAgent(1, b"port7".to_vec()),
+            ]),
+        ));
+
+        let mut phone = ClientAttributes::new(MacAddress::new([0x00, 0x04, 0xf2, 1, 2, 3]));
+        phone.set_option(VENDOR_CLASS_OPTION, b"Polycom-VVX".to_vec());
+        let names = |attributes: &ClientAttributes| {
+            classifier
+                .classify(attributes)
+                .iter()
+                .map(|class| class.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&phone), vec!["phones", "vendor"]);
+        assert_eq!(phone.vendor_class(), Some("Polycom-VVX"));
+        let phones = classifier.classify(&phone)[0];
+        assert_eq!(
+            phones.options().get(&VENDOR_SPECIFIC_OPTION),
+            Some(&b"\x02\x07sip.lan".to_vec())
+        );
+        phone.set_option(USER_CLASS_OPTION, b"lab".to_vec());
+        assert_eq!(names(&phone), vec!["vendor"]);
+
+        let mut relayed = ClientAttributes::new(MacAddress::new([0xaa, 0, 0, 0, 0, 1]));
+        assert!(names(&relayed).is_empty());
+        relayed.set_option(RELAY_AGENT_OPTION, b"\x02\x01x\x01\x05port7".to_vec());
+        assert_eq!(relayed.relay_agent_option(2), Some(&b"x"[..]));
+        assert_eq!(names(&relayed), vec!["vendor"]);
+    }
+
+    #[test]
+    fn test_class_limits() {
+        let mut classifier = Classifier::new();
+        let mut guests = Class::new(String::from("guests"), Match::MacPrefix(vec![0xaa]));
+        guests.set_lease_limit(1);
+        classifier.add_class(guests);
+        let limiter = ClassLimiter::new(&classifier);
+
+        let guest = |n| ClientAttributes::new(MacAddress::new([0xaa, 0, 0, 0, 0, n]));
+        let (first, second) = (guest(1), guest(2));
+        limiter
+            .acquire(&classifier.classify(&first), first.chaddr)
+            .unwrap();
+        //Renewals don't count twice
+        limiter
+            .acquire(&classifier.classify(&first), first.chaddr)
+            .unwrap();
+        assert_eq!(limiter.remaining("guests"), Some(0));
+        assert_eq!(
+            limiter.acquire(&classifier.classify(&second), second.chaddr),
+            Err(AllocationError::LimitReached)
+        );
+        limiter.release(first.chaddr);
+        assert_eq!(limiter.remaining("guests"), Some(1));
+        assert_eq!(limiter.remaining("phones"), None);
+    }
+}
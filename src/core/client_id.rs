@@ -0,0 +1,101 @@
+//! Identification of clients.
+//!
+//! As described in RFC 2131, a client is identified by its
+//! client identifier option when it sends one, and by its
+//! hardware address otherwise. A [`ClientId`] holds the canonical
+//! form of both: the value of the option, or the hardware type
+//! followed by the hardware address, which is what most clients
+//! send as their client identifier anyway.
+
+use std::fmt::Display;
+
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+
+/// Code of the client identifier option
+pub const CLIENT_ID_OPTION: u8 = 61;
+/// Hardware type of Ethernet addresses
+pub const ETHERNET: u8 = 1;
+
+/// Canonical identifier of a client
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ClientId(Vec<u8>);
+
+impl ClientId {
+    /// Creates the identifier with the given raw value,
+    /// as sent in the client identifier option
+    pub fn new(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+
+    /// Returns the identifier of the client of a request, preferring
+    /// its client identifier option over its hardware address
+    ///
+    /// Empty client identifiers are ignored.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let client = ClientId::of(packet.chaddr, options.get(&CLIENT_ID_OPTION).map(Vec::as_slice));
+    /// let offered = allocator.allocate(subnet.network(), client, requested)?;
+    /// ```
+    pub fn of(chaddr: MacAddress, client_id: Option<&[u8]>) -> Self {
+        match client_id {
+            Some(value) if !value.is_empty() => Self(value.to_vec()),
+            _ => Self::from(chaddr),
+        }
+    }
+
+    /// Returns the raw value of the identifier
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the Ethernet address the identifier is made of, if any
+    pub fn hardware_address(&self) -> Option<MacAddress> {
+        match self.0.as_slice() {
+            [ETHERNET, address @ ..] => Some(MacAddress::new(address.try_into().ok()?)),
+            _ => None,
+        }
+    }
+}
+
+impl From<MacAddress> for ClientId {
+    fn from(chaddr: MacAddress) -> Self {
+        Self([&[ETHERNET][..], &chaddr.bytes()].concat())
+    }
+}
+
+/// Formats the identifier as colon-separated hexadecimal bytes
+impl Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_client_id() {
+        let chaddr = MacAddress::new([0xaa, 0, 0, 0, 0, 1]);
+        let client = ClientId::of(chaddr, None);
+        assert_eq!(client.to_string(), "01:aa:00:00:00:00:01");
+        assert_eq!(client.hardware_address(), Some(chaddr));
+        assert_eq!(ClientId::of(chaddr, Some(&[])), client);
+        //Clients sending their hardware address are the same client
+        assert_eq!(ClientId::of(chaddr, Some(client.as_bytes())), client);
+
+        let duid = ClientId::of(chaddr, Some(b"\xff\x00\x00\x00\x01duid"));
+        assert_ne!(duid, client);
+        assert_eq!(duid.hardware_address(), None);
+    }
+}
@@ -0,0 +1,621 @@
+//! Configuration of the subnets served.
+//!
+//! A [`Subnet`] describes a scope: its network, the dynamic
+//! ranges handed out by the [`Allocator`], and the default
+//! options and lease time sent to its clients. The
+//! [`SubnetSelector`] picks the subnet of a request, based on
+//! the relay agent address or on the receiving interface.
+//!
+//! An authoritative subnet answers requests it can't
+//! satisfy with a NAK, so that misconfigured clients
+//! restart the configuration process, instead of
+//! ignoring them.
+//!
+//! A subnet allowing rapid commit commits the address offered
+//! to a DISCOVER carrying the rapid commit option, answering
+//! it with an ACK instead of an OFFER.
+//!
+//! Legacy BOOTP clients, which don't send a message type, are
+//! only served by subnets allowing BOOTP. They are given an
+//! address for good, and answered without DHCP-specific options,
+//! see [`bootp_options`](super::options::bootp_options).
+
+use std::{collections::BTreeMap, net::Ipv4Addr, time::Duration};
+
+use super::{
+    allocator::{AddressPool, AddressRange, Allocator},
+    classes::ClientAttributes,
+    client_id::ClientId,
+    errors::{AllocationError, ConfigError},
+    reservations::Reservations,
+};
+
+/// Lease time of subnets which don't set one
+pub const DEFAULT_LEASE_TIME: Duration = Duration::from_secs(86400);
+
+/// Code of the IP address lease time option
+pub const LEASE_TIME_OPTION: u8 = 51;
+/// Code of the renewal (T1) time option
+pub const RENEWAL_TIME_OPTION: u8 = 58;
+/// Code of the rebinding (T2) time option
+pub const REBINDING_TIME_OPTION: u8 = 59;
+/// Code of the rapid commit option
+pub const RAPID_COMMIT_OPTION: u8 = 80;
+
+/// Answer to a REQUEST for an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestAnswer {
+    /// The address was committed to the client, answer with an ACK
+    Ack,
+    /// The address can't be given to the client, answer with a NAK
+    Nak,
+    /// The request must be ignored
+    Ignore,
+}
+
+/// Answer to a DISCOVER
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscoverAnswer {
+    /// The address was offered, answer with an OFFER
+    Offer(Ipv4Addr),
+    /// The address was committed through rapid commit, answer
+    /// with an ACK carrying the rapid commit option
+    Ack(Ipv4Addr),
+}
+
+/// Scope of addresses served to a network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subnet {
+    network: Ipv4Addr,
+    prefix: u8,
+    ranges: Vec<AddressRange>,
+    exclusions: Vec<AddressRange>,
+    routers: Vec<Ipv4Addr>,
+    dns_servers: Vec<Ipv4Addr>,
+    domain_name: Option<String>,
+    lease_time: Duration,
+    renewal_time: Option<Duration>,
+    rebinding_time: Option<Duration>,
+    interfaces: Vec<String>,
+    authoritative: bool,
+    rapid_commit: bool,
+    bootp: bool,
+}
+
+impl Subnet {
+    /// Creates the subnet `network`/`prefix`, without any range
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut subnet = Subnet::new(Ipv4Addr::new(10, 0, 0, 0), 24)?;
+    /// subnet.add_range(AddressRange::new(Ipv4Addr::new(10, 0, 0, 100), Ipv4Addr::new(10, 0, 0, 200))?)?;
+    /// subnet.set_routers(vec![Ipv4Addr::new(10, 0, 0, 1)]);
+    /// ```
+    pub fn new(network: Ipv4Addr, prefix: u8) -> Result<Self, ConfigError> {
+        if prefix > 32 {
+            return Err(ConfigError::InvalidPrefix(prefix));
+        }
+        let subnet = Self {
+            network,
+            prefix,
+            ranges: vec![],
+            exclusions: vec![],
+            routers: vec![],
+            dns_servers: vec![],
+            domain_name: None,
+            lease_time: DEFAULT_LEASE_TIME,
+            renewal_time: None,
+            rebinding_time: None,
+            interfaces: vec![],
+            authoritative: false,
+            rapid_commit: false,
+            bootp: false,
+        };
+        match subnet.network == Ipv4Addr::from(u32::from(network) & u32::from(subnet.mask())) {
+            true => Ok(subnet),
+            false => Err(ConfigError::InvalidNetwork(network, prefix)),
+        }
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        self.network
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Returns the subnet mask
+    pub fn mask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0))
+    }
+
+    /// Returns the broadcast address of the subnet
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.network) | !u32::from(self.mask()))
+    }
+
+    /// Returns whether `address` belongs to the subnet
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        u32::from(address) & u32::from(self.mask()) == u32::from(self.network)
+    }
+
+    /// Returns whether the subnet shares addresses with `other`
+    pub fn overlaps(&self, other: &Subnet) -> bool {
+        self.contains(other.network) || other.contains(self.network)
+    }
+
+    pub fn ranges(&self) -> &[AddressRange] {
+        &self.ranges
+    }
+
+    /// Adds a range of addresses dynamically allocated to clients
+    pub fn add_range(&mut self, range: AddressRange) -> Result<(), ConfigError> {
+        if !self.contains(range.start()) || !self.contains(range.end()) {
+            return Err(ConfigError::RangeOutsideSubnet(range.start(), range.end()));
+        }
+        self.ranges.push(range);
+        Ok(())
+    }
+
+    pub fn exclusions(&self) -> &[AddressRange] {
+        &self.exclusions
+    }
+
+    /// Excludes a range of addresses, such as infrastructure
+    /// addresses, from the dynamic ranges
+    pub fn add_exclusion(&mut self, range: AddressRange) -> Result<(), ConfigError> {
+        if !self.contains(range.start()) || !self.contains(range.end()) {
+            return Err(ConfigError::RangeOutsideSubnet(range.start(), range.end()));
+        }
+        self.exclusions.push(range);
+        Ok(())
+    }
+
+    /// Returns whether `address` is excluded from the dynamic ranges
+    pub fn is_excluded(&self, address: Ipv4Addr) -> bool {
+        self.exclusions.iter().any(|range| range.contains(address))
+    }
+
+    pub fn routers(&self) -> &[Ipv4Addr] {
+        &self.routers
+    }
+
+    pub fn set_routers(&mut self, routers: Vec<Ipv4Addr>) {
+        self.routers = routers;
+    }
+
+    pub fn dns_servers(&self) -> &[Ipv4Addr] {
+        &self.dns_servers
+    }
+
+    pub fn set_dns_servers(&mut self, dns_servers: Vec<Ipv4Addr>) {
+        self.dns_servers = dns_servers;
+    }
+
+    pub fn domain_name(&self) -> Option<&str> {
+        self.domain_name.as_deref()
+    }
+
+    pub fn set_domain_name(&mut self, domain_name: String) {
+        self.domain_name = Some(domain_name);
+    }
+
+    pub fn lease_time(&self) -> Duration {
+        self.lease_time
+    }
+
+    pub fn set_lease_time(&mut self, lease_time: Duration) {
+        self.lease_time = lease_time;
+    }
+
+    /// Sends the given renewal (T1) time instead of half of the lease
+    pub fn set_renewal_time(&mut self, renewal_time: Duration) {
+        self.renewal_time = Some(renewal_time);
+    }
+
+    /// Sends the given rebinding (T2) time instead of 87.5% of the lease
+    pub fn set_rebinding_time(&mut self, rebinding_time: Duration) {
+        self.rebinding_time = Some(rebinding_time);
+    }
+
+    /// Returns the lease time, renewal time and rebinding time
+    /// options of a lease granted for `lease_time`, by option code
+    ///
+    /// Renewal and rebinding times default to 50% and 87.5% of the
+    /// lease, and are capped so that T1 <= T2 <= lease time.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// for (code, value) in subnet.lease_time_options(lease_time) {
+    ///     response.options.insert(code, value);
+    /// }
+    /// ```
+    pub fn lease_time_options(&self, lease_time: Duration) -> BTreeMap<u8, Vec<u8>> {
+        let rebinding = self
+            .rebinding_time
+            .unwrap_or(lease_time * 7 / 8)
+            .min(lease_time);
+        let renewal = self.renewal_time.unwrap_or(lease_time / 2).min(rebinding);
+        let seconds = |time: Duration| {
+            u32::try_from(time.as_secs())
+                .unwrap_or(u32::MAX)
+                .to_be_bytes()
+                .to_vec()
+        };
+        BTreeMap::from([
+            (LEASE_TIME_OPTION, seconds(lease_time)),
+            (RENEWAL_TIME_OPTION, seconds(renewal)),
+            (REBINDING_TIME_OPTION, seconds(rebinding)),
+        ])
+    }
+
+    /// Serves the subnet to clients directly connected
+    /// to the interface with the given name
+    pub fn add_interface(&mut self, interface: String) {
+        self.interfaces.push(interface);
+    }
+
+    pub fn interfaces(&self) -> &[String] {
+        &self.interfaces
+    }
+
+    pub fn authoritative(&self) -> bool {
+        self.authoritative
+    }
+
+    /// Answers requests which can't be satisfied
+    /// with a NAK instead of ignoring them
+    pub fn set_authoritative(&mut self, authoritative: bool) {
+        self.authoritative = authoritative;
+    }
+
+    pub fn rapid_commit(&self) -> bool {
+        self.rapid_commit
+    }
+
+    /// Allows clients to skip the OFFER/REQUEST
+    /// exchange with the rapid commit option
+    pub fn set_rapid_commit(&mut self, rapid_commit: bool) {
+        self.rapid_commit = rapid_commit;
+    }
+
+    pub fn bootp(&self) -> bool {
+        self.bootp
+    }
+
+    /// Serves legacy BOOTP clients from the ranges of the subnet
+    pub fn set_bootp(&mut self, bootp: bool) {
+        self.bootp = bootp;
+    }
+
+    /// Commits an address to the BOOTP client with the given
+    /// attributes, returning it, or `None` if the subnet
+    /// doesn't serve BOOTP clients
+    ///
+    /// BOOTP clients never renew nor release their address, so it
+    /// stays committed until it is released by an operator.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// if is_bootp(&attributes.options) {
+    ///     let Some(address) = subnet.answer_bootp(&allocator, &attributes)? else {
+    ///         return Ok(HookState::Drop);
+    ///     };
+    ///     let options = bootp_options(&subnet_options);
+    /// }
+    /// ```
+    pub fn answer_bootp(
+        &self,
+        allocator: &Allocator,
+        attributes: &ClientAttributes,
+    ) -> Result<Option<Ipv4Addr>, AllocationError> {
+        if !self.bootp {
+            return Ok(None);
+        }
+        let client = attributes.client_id();
+        let address = allocator.allocate(self.network, client.clone(), None)?;
+        allocator.commit(address, client)?;
+        Ok(Some(address))
+    }
+
+    /// Allocates an address to the client of a DISCOVER,
+    /// returning how the DISCOVER must be answered
+    ///
+    /// The address is committed right away if the subnet allows
+    /// rapid commit and the client sent the rapid commit option.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// match subnet.answer_discover(&allocator, &attributes, requested)? {
+    ///     DiscoverAnswer::Offer(address) => ...,
+    ///     DiscoverAnswer::Ack(address) => response.options.insert(RAPID_COMMIT_OPTION, vec![]),
+    /// }
+    /// ```
+    pub fn answer_discover(
+        &self,
+        allocator: &Allocator,
+        attributes: &ClientAttributes,
+        hint: Option<Ipv4Addr>,
+    ) -> Result<DiscoverAnswer, AllocationError> {
+        let client = attributes.client_id();
+        let address = allocator.allocate(self.network, client.clone(), hint)?;
+        if !self.rapid_commit || attributes.option(RAPID_COMMIT_OPTION).is_none() {
+            return Ok(DiscoverAnswer::Offer(address));
+        }
+        allocator.commit(address, client)?;
+        Ok(DiscoverAnswer::Ack(address))
+    }
+
+    /// Commits `address`, requested by `client`, returning
+    /// how the request must be answered
+    ///
+    /// Requests for addresses outside of the subnet, or which can't
+    /// be committed to the client, are only NAKed by authoritative
+    /// subnets.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// match subnet.answer_request(&allocator, requested, attributes.client_id()) {
+    ///     RequestAnswer::Ack => ...,
+    ///     RequestAnswer::Nak => ...,
+    ///     RequestAnswer::Ignore => return Ok(HookState::Drop),
+    /// }
+    /// ```
+    pub fn answer_request(
+        &self,
+        allocator: &Allocator,
+        address: Ipv4Addr,
+        client: impl Into<ClientId>,
+    ) -> RequestAnswer {
+        let committed = self.contains(address) && allocator.commit(address, client).is_ok();
+        match (committed, self.authoritative) {
+            (true, _) => RequestAnswer::Ack,
+            (false, true) => RequestAnswer::Nak,
+            (false, false) => RequestAnswer::Ignore,
+        }
+    }
+}
+
+/// Picks the [`Subnet`] serving a request
+#[derive(Debug, Clone, Default)]
+pub struct SubnetSelector {
+    subnets: Vec<Subnet>,
+}
+
+impl SubnetSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `subnet`, which must not overlap any other subnet
+    pub fn add_subnet(&mut self, subnet: Subnet) -> Result<(), ConfigError> {
+        if let Some(other) = self.subnets.iter().find(|other| other.overlaps(&subnet)) {
+            return Err(ConfigError::Overlap(subnet.network, other.network));
+        }
+        self.subnets.push(subnet);
+        Ok(())
+    }
+
+    pub fn subnets(&self) -> &[Subnet] {
+        &self.subnets
+    }
+
+    /// Returns the subnet of a request relayed by `giaddr`, or
+    /// received on `interface` if it was not relayed
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let subnet = selector.select(packet.giaddr, Some("eth0")).ok_or(HookError::new("No subnet"))?;
+    /// let address = allocator.allocate(subnet.network(), packet.chaddr, requested)?;
+    /// ```
+    pub fn select(&self, giaddr: Ipv4Addr, interface: Option<&str>) -> Option<&Subnet> {
+        if !giaddr.is_unspecified() {
+            return self.subnets.iter().find(|subnet| subnet.contains(giaddr));
+        }
+        let interface = interface?;
+        self.subnets
+            .iter()
+            .find(|subnet| subnet.interfaces.iter().any(|name| name == interface))
+    }
+
+    /// Creates an [`Allocator`] with a pool for the ranges of every
+    /// subnet, without their exclusions, holding offered addresses
+    /// during `hold`
+    pub fn allocator(&self, hold: Duration) -> Allocator {
+        let mut allocator = Allocator::new();
+        for subnet in &self.subnets {
+            let pool = AddressPool::new(subnet.ranges.clone(), hold);
+            for exclusion in &subnet.exclusions {
+                pool.exclude(*exclusion);
+            }
+            allocator.add_pool(subnet.network, pool);
+        }
+        allocator
+    }
+
+    /// Checks that every reservation belongs to a subnet,
+    /// outside of its exclusions
+    pub fn validate(&self, reservations: &Reservations) -> Result<(), ConfigError> {
+        for reservation in reservations.all() {
+            let address = reservation.address;
+            match self.subnets.iter().find(|subnet| subnet.contains(address)) {
+                Some(subnet) if subnet.is_excluded(address) => {
+                    return Err(ConfigError::ReservationExcluded(address))
+                }
+                Some(_) => (),
+                None => return Err(ConfigError::ReservationOutsideSubnets(address)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mac_address::MacAddress;
+
+    use super::*;
+    use crate::{core::reservations::Reservation, storage::memory_backend::MemoryBackend};
+
+    #[test]
+    fn test_subnet_selection() {
+        let mut lan = Subnet::new(Ipv4Addr::new(10, 0, 0, 0), 24).unwrap();
+        lan.add_range(
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 100), Ipv4Addr::new(10, 0, 0, 200)).unwrap(),
+        )
+        .unwrap();
+        lan.add_exclusion(
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 100), Ipv4Addr::new(10, 0, 0, 109)).unwrap(),
+        )
+        .unwrap();
+        lan.add_interface(String::from("eth0"));
+        assert_eq!(lan.mask(), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(lan.broadcast(), Ipv4Addr::new(10, 0, 0, 255));
+        let options = lan.lease_time_options(Duration::from_secs(3600));
+        assert_eq!(options[&RENEWAL_TIME_OPTION], 1800u32.to_be_bytes());
+        assert_eq!(options[&REBINDING_TIME_OPTION], 3150u32.to_be_bytes());
+        lan.set_renewal_time(Duration::from_secs(4000));
+        let options = lan.lease_time_options(Duration::from_secs(3600));
+        assert_eq!(options[&RENEWAL_TIME_OPTION], 3150u32.to_be_bytes());
+        assert_eq!(options[&LEASE_TIME_OPTION], 3600u32.to_be_bytes());
+        assert!(lan
+            .add_range(
+                AddressRange::new(Ipv4Addr::new(10, 0, 1, 1), Ipv4Addr::new(10, 0, 1, 9)).unwrap()
+            )
+            .is_err());
+        assert!(Subnet::new(Ipv4Addr::new(10, 0, 0, 1), 24).is_err());
+
+        let relayed = Subnet::new(Ipv4Addr::new(10, 1, 0, 0), 16).unwrap();
+        let mut selector = SubnetSelector::new();
+        selector.add_subnet(lan).unwrap();
+        selector.add_subnet(relayed).unwrap();
+        assert_eq!(
+            selector.add_subnet(Subnet::new(Ipv4Addr::new(10, 1, 2, 0), 24).unwrap()),
+            Err(ConfigError::Overlap(
+                Ipv4Addr::new(10, 1, 2, 0),
+                Ipv4Addr::new(10, 1, 0, 0)
+            ))
+        );
+
+        let select = |giaddr, interface| {
+            selector
+                .select(giaddr, interface)
+                .map(|subnet| subnet.network())
+        };
+        assert_eq!(
+            select(Ipv4Addr::UNSPECIFIED, Some("eth0")),
+            Some(Ipv4Addr::new(10, 0, 0, 0))
+        );
+        assert_eq!(
+            select(Ipv4Addr::new(10, 1, 3, 1), Some("eth0")),
+            Some(Ipv4Addr::new(10, 1, 0, 0))
+        );
+        assert_eq!(select(Ipv4Addr::UNSPECIFIED, Some("eth1")), None);
+
+        let allocator = selector.allocator(Duration::from_secs(30));
+        assert_eq!(
+            allocator
+                .pool(Ipv4Addr::new(10, 0, 0, 0))
+                .map(AddressPool::available),
+            Some(91)
+        );
+
+        //Only authoritative subnets NAK requests
+        let mut lan = selector.subnets()[0].clone();
+        let host = MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]);
+        let other = MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x02]);
+        let address = Ipv4Addr::new(10, 0, 0, 150);
+        assert_eq!(
+            lan.answer_request(&allocator, address, host),
+            RequestAnswer::Ack
+        );
+        assert_eq!(
+            lan.answer_request(&allocator, address, other),
+            RequestAnswer::Ignore
+        );
+        lan.set_authoritative(true);
+        assert_eq!(
+            lan.answer_request(&allocator, address, other),
+            RequestAnswer::Nak
+        );
+        assert_eq!(
+            lan.answer_request(&allocator, Ipv4Addr::new(10, 1, 0, 5), host),
+            RequestAnswer::Nak
+        );
+
+        //Rapid commit is only used when allowed and requested
+        let mut attributes = ClientAttributes::new(other);
+        attributes.set_option(RAPID_COMMIT_OPTION, vec![]);
+        assert_eq!(
+            lan.answer_discover(&allocator, &attributes, None),
+            Ok(DiscoverAnswer::Offer(Ipv4Addr::new(10, 0, 0, 110)))
+        );
+        lan.set_rapid_commit(true);
+        assert_eq!(
+            lan.answer_discover(&allocator, &attributes, None),
+            Ok(DiscoverAnswer::Ack(Ipv4Addr::new(10, 0, 0, 110)))
+        );
+        assert_eq!(
+            allocator
+                .pool(lan.network())
+                .and_then(|pool| pool.client(Ipv4Addr::new(10, 0, 0, 110))),
+            Some(other.into())
+        );
+
+        //Reservations must be served and not excluded
+        let reservations = Reservations::new(MemoryBackend::new()).unwrap();
+        reservations
+            .set(Reservation::new(host, Ipv4Addr::new(10, 1, 0, 5)))
+            .unwrap();
+        assert_eq!(selector.validate(&reservations), Ok(()));
+        reservations
+            .set(Reservation::new(host, Ipv4Addr::new(10, 0, 0, 105)))
+            .unwrap();
+        assert_eq!(
+            selector.validate(&reservations),
+            Err(ConfigError::ReservationExcluded(Ipv4Addr::new(
+                10, 0, 0, 105
+            )))
+        );
+        reservations
+            .set(Reservation::new(host, Ipv4Addr::new(192, 168, 0, 5)))
+            .unwrap();
+        assert_eq!(
+            selector.validate(&reservations),
+            Err(ConfigError::ReservationOutsideSubnets(Ipv4Addr::new(
+                192, 168, 0, 5
+            )))
+        );
+    }
+
+    #[test]
+    fn test_bootp() {
+        let mut subnet = Subnet::new(Ipv4Addr::new(10, 0, 0, 0), 24).unwrap();
+        subnet
+            .add_range(
+                AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 20))
+                    .unwrap(),
+            )
+            .unwrap();
+        let mut selector = SubnetSelector::new();
+        selector.add_subnet(subnet.clone()).unwrap();
+        let allocator = selector.allocator(Duration::from_secs(30));
+        let client = ClientAttributes::new(MacAddress::new([0xaa, 0, 0, 0, 0, 1]));
+
+        assert_eq!(subnet.answer_bootp(&allocator, &client), Ok(None));
+        subnet.set_bootp(true);
+        let address = Ipv4Addr::new(10, 0, 0, 10);
+        assert_eq!(subnet.answer_bootp(&allocator, &client), Ok(Some(address)));
+        assert_eq!(subnet.answer_bootp(&allocator, &client), Ok(Some(address)));
+        let pool = allocator.pool(subnet.network()).unwrap();
+        assert_eq!(pool.leases(), 1);
+    }
+}
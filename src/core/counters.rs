@@ -0,0 +1,202 @@
+//! Counters tracking the packets going
+//! through a [`StateSwitcher`].
+//!
+//! A single [`Counters`] instance is shared through an [`Arc`]
+//! between the [`StateSwitcher`], its processing tasks and
+//! every [`PipelineHandle`], and can also be shared across
+//! several `StateSwitcher` to aggregate their statistics.
+//!
+//! [`Arc`]: std::sync::Arc
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+//! [`PipelineHandle`]: super::handle::PipelineHandle
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicUsize, Ordering::SeqCst},
+};
+
+/// Point-in-time copy of [`Counters`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CountersSnapshot {
+    /// Packets read from the [`Input`]
+    ///
+    /// [`Input`]: super::state_switcher::Input
+    pub received: usize,
+    /// Packets successfully dispatched to the [`Output`]
+    ///
+    /// [`Output`]: super::state_switcher::Output
+    pub sent: usize,
+    /// Packets dropped, either because the queue was full,
+    /// through unsuccessful fatal [`Hook`] execution,
+    /// because their deadline expired, as duplicates,
+    /// or at the output
+    ///
+    /// [`Hook`]: crate::hooks::hook_registry::Hook
+    pub dropped: usize,
+    /// Packets currently going through the pipeline
+    pub in_flight: usize,
+    /// Packets which couldn't be parsed by the [`Input`],
+    /// and were dropped before being received
+    ///
+    /// [`Input`]: super::state_switcher::Input
+    pub malformed: usize,
+    /// Packets received and sent, by [`kind`], for
+    /// the kinds counted at least once
+    ///
+    /// [`kind`]: super::packet::PacketType::kind
+    pub kinds: BTreeMap<u8, usize>,
+}
+
+/// Lock-free pipeline counters
+#[derive(Debug)]
+pub struct Counters {
+    received: AtomicUsize,
+    sent: AtomicUsize,
+    dropped: AtomicUsize,
+    in_flight: AtomicUsize,
+    malformed: AtomicUsize,
+    kinds: [AtomicUsize; 256],
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            received: AtomicUsize::default(),
+            sent: AtomicUsize::default(),
+            dropped: AtomicUsize::default(),
+            in_flight: AtomicUsize::default(),
+            malformed: AtomicUsize::default(),
+            kinds: std::array::from_fn(|_| AtomicUsize::default()),
+        }
+    }
+}
+
+impl Counters {
+    /// Creates a new set of `Counters`, all set to 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_received(&self) {
+        self.received.fetch_add(1, SeqCst);
+    }
+
+    pub fn record_sent(&self) {
+        self.sent.fetch_add(1, SeqCst);
+    }
+
+    pub fn record_dropped(&self) {
+        self.dropped.fetch_add(1, SeqCst);
+    }
+
+    pub fn record_malformed(&self) {
+        self.malformed.fetch_add(1, SeqCst);
+    }
+
+    /// Counts a packet of the given [`kind`]
+    ///
+    /// [`kind`]: super::packet::PacketType::kind
+    pub fn record_kind(&self, kind: u8) {
+        self.kinds[kind as usize].fetch_add(1, SeqCst);
+    }
+
+    /// Increments the number of packets in flight,
+    /// returning the previous value
+    pub(crate) fn enter_flight(&self) -> usize {
+        self.in_flight.fetch_add(1, SeqCst)
+    }
+
+    /// Decrements the number of packets in flight,
+    /// returning the previous value
+    pub(crate) fn leave_flight(&self) -> usize {
+        self.in_flight.fetch_sub(1, SeqCst)
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(SeqCst)
+    }
+
+    pub fn malformed(&self) -> usize {
+        self.malformed.load(SeqCst)
+    }
+
+    /// Returns the number of packets of the given kind
+    pub fn kind(&self, kind: u8) -> usize {
+        self.kinds[kind as usize].load(SeqCst)
+    }
+
+    /// Returns a [`CountersSnapshot`] of every counter
+    ///
+    /// A packet is always counted as received before being
+    /// counted as sent or dropped, so outcomes are read first:
+    /// the snapshot never reports more packets sent or dropped
+    /// than received, even while the pipeline is running.
+    pub fn snapshot(&self) -> CountersSnapshot {
+        let in_flight = self.in_flight.load(SeqCst);
+        let sent = self.sent.load(SeqCst);
+        let dropped = self.dropped.load(SeqCst);
+        let received = self.received.load(SeqCst);
+
+        CountersSnapshot {
+            received,
+            sent,
+            dropped,
+            in_flight,
+            malformed: self.malformed.load(SeqCst),
+            kinds: (0..=u8::MAX)
+                .map(|kind| (kind, self.kind(kind)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_increments() {
+        let counters = Arc::new(Counters::new());
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let counters = counters.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counters.record_received();
+                        counters.record_dropped();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot.received, 8000);
+        assert_eq!(snapshot.dropped, 8000);
+        assert_eq!(snapshot.sent, 0);
+    }
+
+    #[test]
+    fn test_kind_counts() {
+        let counters = Counters::new();
+        for kind in [1, 2, 3, 5, 3, 6, 255] {
+            counters.record_kind(kind);
+        }
+        let kinds = counters.snapshot().kinds;
+        assert_eq!(kinds.len(), 6);
+        assert_eq!(kinds[&3], 2);
+        assert_eq!(kinds[&255], 1);
+        assert_eq!(kinds.get(&7), None);
+        assert_eq!(counters.kind(5), 1);
+    }
+}
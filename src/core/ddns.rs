@@ -0,0 +1,603 @@
+//! Dynamic DNS updates.
+//!
+//! A [`DnsUpdater`] registers the A and PTR records of the
+//! clients through RFC 2136 updates, optionally signed with a
+//! TSIG key (RFC 8945). It is meant to be registered as a
+//! service, and called by hooks when leases are granted, and
+//! when they are released or expire.
+//!
+//! Updates are sent with blocking sockets, and may wait for the
+//! DNS server for a while. Hooks, which run on the tokio workers,
+//! thus queue them to a [`DnsUpdateQueue`] instead, whose blocking
+//! worker sends them in the background.
+//!
+//! Responses must come from the server, echo the update and, once
+//! a key is set, be signed with it.
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+
+use super::{errors::DdnsError, options::encode_name};
+
+/// Time to wait for the response of the DNS server
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of updates waiting to be sent by a [`DnsUpdateQueue`]
+pub const UPDATE_QUEUE_CAPACITY: usize = 1024;
+
+const OPCODE_UPDATE: u16 = 5 << 11;
+const FLAG_RESPONSE: u8 = 0x80;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SOA: u16 = 6;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const TSIG_ALGORITHM: &str = "hmac-sha256";
+/// Allowed difference between the clocks of the server and the DNS server
+const TSIG_FUDGE: u16 = 300;
+
+/// Transport used to send updates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    /// Messages are prefixed by their length, as in RFC 1035
+    Tcp,
+}
+
+/// Shared secret signing updates with HMAC-SHA256
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TsigKey {
+    name: String,
+    secret: Vec<u8>,
+}
+
+impl TsigKey {
+    pub fn new(name: String, secret: Vec<u8>) -> Self {
+        Self { name, secret }
+    }
+}
+
+/// Change to a record set of an update
+enum Change {
+    /// Adds the record with the given TTL and data
+    Add(String, u16, u32, Vec<u8>),
+    /// Deletes every record of the name with the given type
+    DeleteSet(String, u16),
+    /// Deletes the record of the name with the given type and data
+    Delete(String, u16, Vec<u8>),
+}
+
+/// Client of a DNS server accepting dynamic updates
+#[derive(Debug, Clone)]
+pub struct DnsUpdater {
+    server: SocketAddr,
+    zone: String,
+    reverse_zone: Option<String>,
+    key: Option<TsigKey>,
+    transport: Transport,
+    timeout: Duration,
+}
+
+impl DnsUpdater {
+    /// Creates a client updating the forward `zone` of `server`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut ddns = DnsUpdater::new("10.0.0.2:53".parse()?, String::from("lan"));
+    /// ddns.set_key(TsigKey::new(String::from("dhcp-key"), secret));
+    /// registry.register_service(ddns.start());
+    /// ```
+    pub fn new(server: SocketAddr, zone: String) -> Self {
+        Self {
+            server,
+            zone,
+            reverse_zone: None,
+            key: None,
+            transport: Transport::Udp,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Signs the updates with `key`
+    pub fn set_key(&mut self, key: TsigKey) {
+        self.key = Some(key);
+    }
+
+    /// Updates PTR records in `reverse_zone`, instead of the
+    /// /24 reverse zone of the address
+    pub fn set_reverse_zone(&mut self, reverse_zone: String) {
+        self.reverse_zone = Some(reverse_zone);
+    }
+
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Spawns a blocking worker sending the updates
+    /// queued to the returned [`DnsUpdateQueue`]
+    ///
+    /// The worker stops once every queue is dropped.
+    /// Must be called from within a tokio runtime.
+    pub fn start(self) -> DnsUpdateQueue {
+        let (sender, mut receiver) = mpsc::channel(UPDATE_QUEUE_CAPACITY);
+        tokio::task::spawn_blocking(move || {
+            while let Some(update) = receiver.blocking_recv() {
+                let (fqdn, sent) = match update {
+                    Update::Register(fqdn, address, ttl) => {
+                        let sent = self.register(&fqdn, address, ttl);
+                        (fqdn, sent)
+                    }
+                    Update::Unregister(fqdn, address) => {
+                        let sent = self.unregister(&fqdn, address);
+                        (fqdn, sent)
+                    }
+                };
+                if let Err(e) = sent {
+                    log::warn!("Failed to update the records of {} : {}", fqdn, e);
+                }
+            }
+        });
+        DnsUpdateQueue { sender }
+    }
+
+    /// Registers the A record of `fqdn` and the PTR record of
+    /// `address`, replacing their previous records
+    ///
+    /// This call is blocking, queue the update to
+    /// a [`DnsUpdateQueue`] in an async context.
+    pub fn register(&self, fqdn: &str, address: Ipv4Addr, ttl: u32) -> Result<(), DdnsError> {
+        let fqdn = self.qualify(fqdn);
+        self.send(
+            &self.zone,
+            &[
+                Change::DeleteSet(fqdn.clone(), TYPE_A),
+                Change::Add(fqdn.clone(), TYPE_A, ttl, address.octets().to_vec()),
+            ],
+        )?;
+        let reverse = reverse_name(address);
+        self.send(
+            &self.reverse_zone(address),
+            &[
+                Change::DeleteSet(reverse.clone(), TYPE_PTR),
+                Change::Add(reverse, TYPE_PTR, ttl, encode_name(&fqdn)?),
+            ],
+        )
+    }
+
+    /// Removes the A record of `fqdn` pointing to
+    /// `address`, and the PTR record of `address`
+    ///
+    /// This call is blocking, queue the update to
+    /// a [`DnsUpdateQueue`] in an async context.
+    pub fn unregister(&self, fqdn: &str, address: Ipv4Addr) -> Result<(), DdnsError> {
+        let fqdn = self.qualify(fqdn);
+        self.send(
+            &self.zone,
+            &[Change::Delete(fqdn, TYPE_A, address.octets().to_vec())],
+        )?;
+        self.send(
+            &self.reverse_zone(address),
+            &[Change::DeleteSet(reverse_name(address), TYPE_PTR)],
+        )
+    }
+
+    /// Appends the zone to single label names
+    fn qualify(&self, fqdn: &str) -> String {
+        match fqdn.contains('.') {
+            true => fqdn.to_string(),
+            false => format!("{}.{}", fqdn, self.zone),
+        }
+    }
+
+    fn reverse_zone(&self, address: Ipv4Addr) -> String {
+        self.reverse_zone.clone().unwrap_or_else(|| {
+            let [a, b, c, _] = address.octets();
+            format!("{}.{}.{}.in-addr.arpa", c, b, a)
+        })
+    }
+
+    fn send(&self, zone: &str, changes: &[Change]) -> Result<(), DdnsError> {
+        let id = rand::random::<u16>();
+        let (message, mac) = self.message(id, zone, changes)?;
+        let response = match self.transport {
+            Transport::Udp => {
+                let socket = UdpSocket::bind(match self.server {
+                    SocketAddr::V4(_) => "0.0.0.0:0",
+                    SocketAddr::V6(_) => "[::]:0",
+                })?;
+                //Datagrams from other addresses are discarded
+                socket.connect(self.server)?;
+                socket.set_read_timeout(Some(self.timeout))?;
+                socket.send(&message)?;
+                let mut buffer = [0; 512];
+                let len = socket.recv(&mut buffer)?;
+                buffer[..len].to_vec()
+            }
+            Transport::Tcp => {
+                let mut stream = TcpStream::connect_timeout(&self.server, self.timeout)?;
+                stream.set_read_timeout(Some(self.timeout))?;
+                stream.write_all(&(message.len() as u16).to_be_bytes())?;
+                stream.write_all(&message)?;
+                let mut len = [0; 2];
+                stream.read_exact(&mut len)?;
+                let mut response = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut response)?;
+                response
+            }
+        };
+        let rcode = match response.as_slice() {
+            [high, low, flags, rcode, ..]
+                if u16::from_be_bytes([*high, *low]) == id && flags & FLAG_RESPONSE != 0 =>
+            {
+                rcode & 0x0f
+            }
+            _ => return Err(DdnsError::InvalidResponse),
+        };
+        //Servers failing to verify the update answer unsigned errors
+        if rcode != 0 {
+            return Err(DdnsError::Rcode(rcode));
+        }
+        match (&self.key, mac) {
+            (Some(key), Some(mac)) => self.verify(&response, key, &mac),
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds the update message of `changes` to `zone`, signed if a key is set,
+    /// returning it along with its MAC
+    fn message(
+        &self,
+        id: u16,
+        zone: &str,
+        changes: &[Change],
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>), DdnsError> {
+        let mut message = vec![];
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(&OPCODE_UPDATE.to_be_bytes());
+        //Zone, prerequisite, update and additional counts
+        for count in [1, 0, changes.len() as u16, 0] {
+            message.extend_from_slice(&count.to_be_bytes());
+        }
+        message.extend(encode_name(zone)?);
+        message.extend_from_slice(&TYPE_SOA.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+        for change in changes {
+            let (name, rtype, class, ttl, data) = match change {
+                Change::Add(name, rtype, ttl, data) => (name, *rtype, CLASS_IN, *ttl, &data[..]),
+                Change::DeleteSet(name, rtype) => (name, *rtype, CLASS_ANY, 0, &[][..]),
+                Change::Delete(name, rtype, data) => (name, *rtype, CLASS_NONE, 0, &data[..]),
+            };
+            write_record(&mut message, &encode_name(name)?, rtype, class, ttl, data);
+        }
+        let mac = match &self.key {
+            Some(key) => Some(sign(&mut message, id, key, None)?),
+            None => None,
+        };
+        Ok((message, mac))
+    }
+
+    /// Verifies the TSIG record of the response to the update signed with `request_mac`
+    fn verify(&self, response: &[u8], key: &TsigKey, request_mac: &[u8]) -> Result<(), DdnsError> {
+        let tsig = Tsig::parse(response).ok_or(DdnsError::InvalidSignature)?;
+        if !tsig.key_name.eq_ignore_ascii_case(&encode_name(&key.name)?)
+            || !tsig
+                .algorithm
+                .eq_ignore_ascii_case(&encode_name(TSIG_ALGORITHM)?)
+        {
+            return Err(DdnsError::InvalidSignature);
+        }
+        let mut time = [0; 8];
+        time[2..].copy_from_slice(&tsig.timers[..6]);
+        let fudge = u16::from_be_bytes([tsig.timers[6], tsig.timers[7]]);
+        if unix_time().abs_diff(u64::from_be_bytes(time)) > fudge as u64 {
+            return Err(DdnsError::InvalidSignature);
+        }
+
+        //The MAC covers the response without its TSIG record
+        let mut unsigned = response[..tsig.start].to_vec();
+        unsigned[..2].copy_from_slice(&tsig.original_id);
+        let additional = u16::from_be_bytes([unsigned[10], unsigned[11]]) - 1;
+        unsigned[10..12].copy_from_slice(&additional.to_be_bytes());
+        tsig_hmac(key, Some(request_mac), &unsigned, tsig.timers, tsig.error)?
+            .verify_slice(tsig.mac)
+            .map_err(|_| DdnsError::InvalidSignature)
+    }
+}
+
+/// Queue of the updates sent by the worker of a [`DnsUpdater`]
+///
+/// Queuing never blocks, so that hooks can
+/// update the records of their clients.
+#[derive(Debug, Clone)]
+pub struct DnsUpdateQueue {
+    sender: mpsc::Sender<Update>,
+}
+
+/// Update waiting in a [`DnsUpdateQueue`]
+#[derive(Debug)]
+enum Update {
+    Register(String, Ipv4Addr, u32),
+    Unregister(String, Ipv4Addr),
+}
+
+impl DnsUpdateQueue {
+    /// Queues the registration of the A record of `fqdn`
+    /// and the PTR record of `address`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DdnsError::QueueFull`] if the update cannot be queued.
+    /// Failures to send it are only logged.
+    pub fn register(&self, fqdn: &str, address: Ipv4Addr, ttl: u32) -> Result<(), DdnsError> {
+        self.queue(Update::Register(fqdn.to_string(), address, ttl))
+    }
+
+    /// Queues the removal of the A record of `fqdn`
+    /// pointing to `address`, and the PTR record of `address`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DdnsError::QueueFull`] if the update cannot be queued.
+    /// Failures to send it are only logged.
+    pub fn unregister(&self, fqdn: &str, address: Ipv4Addr) -> Result<(), DdnsError> {
+        self.queue(Update::Unregister(fqdn.to_string(), address))
+    }
+
+    fn queue(&self, update: Update) -> Result<(), DdnsError> {
+        self.sender
+            .try_send(update)
+            .map_err(|_| DdnsError::QueueFull)
+    }
+}
+
+/// TSIG record ending a message
+struct Tsig<'a> {
+    /// Offset of the record in the message
+    start: usize,
+    key_name: &'a [u8],
+    algorithm: &'a [u8],
+    /// Time signed and fudge
+    timers: &'a [u8],
+    mac: &'a [u8],
+    original_id: [u8; 2],
+    /// Error, other data length and other data
+    error: &'a [u8],
+}
+
+impl<'a> Tsig<'a> {
+    /// Reads the TSIG record ending `message`, if any
+    fn parse(message: &'a [u8]) -> Option<Self> {
+        let count = |offset: usize| {
+            Some(u16::from_be_bytes(
+                message.get(offset..offset + 2)?.try_into().ok()?,
+            ))
+        };
+        let additional = count(10)?.checked_sub(1)?;
+        let mut offset = 12;
+        for _ in 0..count(4)? {
+            offset = skip_name(message, offset)? + 4;
+        }
+        for _ in 0..count(6)? as usize + count(8)? as usize + additional as usize {
+            offset = skip_name(message, offset)? + 8;
+            offset += count(offset)? as usize + 2;
+        }
+
+        let start = offset;
+        let name_end = skip_name(message, start)?;
+        if count(name_end)? != TYPE_TSIG {
+            return None;
+        }
+        let data_start = name_end + 10;
+        let data = message.get(data_start..data_start + count(name_end + 8)? as usize)?;
+        if data_start + data.len() != message.len() {
+            return None;
+        }
+        let algorithm_end = skip_name(data, 0)?;
+        let timers = data.get(algorithm_end..algorithm_end + 8)?;
+        let mac_start = algorithm_end + 10;
+        let mac_len = u16::from_be_bytes(data.get(mac_start - 2..mac_start)?.try_into().ok()?);
+        let mac = data.get(mac_start..mac_start + mac_len as usize)?;
+        let id_start = mac_start + mac.len();
+        let original_id = data.get(id_start..id_start + 2)?.try_into().ok()?;
+        let error = data.get(id_start + 2..)?;
+        if error.len() < 4 {
+            return None;
+        }
+        Some(Self {
+            start,
+            key_name: &message[start..name_end],
+            algorithm: &data[..algorithm_end],
+            timers,
+            mac,
+            original_id,
+            error,
+        })
+    }
+}
+
+/// Returns the offset following the name at `offset`
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        match *message.get(offset)? {
+            0 => return Some(offset + 1),
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            len if len < 64 => offset += len as usize + 1,
+            _ => return None,
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Returns the HMAC of `message` along with its TSIG variables,
+/// preceded by the MAC of the request if `message` is a response
+fn tsig_hmac(
+    key: &TsigKey,
+    request_mac: Option<&[u8]>,
+    message: &[u8],
+    timers: &[u8],
+    error: &[u8],
+) -> Result<Hmac<Sha256>, DdnsError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key.secret)
+        .map_err(|_| DdnsError::Io(std::io::ErrorKind::InvalidInput))?;
+    if let Some(request_mac) = request_mac {
+        mac.update(&(request_mac.len() as u16).to_be_bytes());
+        mac.update(request_mac);
+    }
+    mac.update(message);
+    mac.update(&encode_name(&key.name.to_ascii_lowercase())?);
+    mac.update(&CLASS_ANY.to_be_bytes());
+    mac.update(&0u32.to_be_bytes());
+    mac.update(&encode_name(TSIG_ALGORITHM)?);
+    mac.update(timers);
+    mac.update(error);
+    Ok(mac)
+}
+
+/// Appends the TSIG record of `message`, returning its MAC
+///
+/// Responses are signed along with the MAC of their request.
+fn sign(
+    message: &mut Vec<u8>,
+    id: u16,
+    key: &TsigKey,
+    request_mac: Option<&[u8]>,
+) -> Result<Vec<u8>, DdnsError> {
+    let timers = [&unix_time().to_be_bytes()[2..], &TSIG_FUDGE.to_be_bytes()].concat();
+    //Error and other data length
+    let error = [0; 4];
+    let mac = tsig_hmac(key, request_mac, message, &timers, &error)?
+        .finalize()
+        .into_bytes()
+        .to_vec();
+
+    let mut data = encode_name(TSIG_ALGORITHM)?;
+    data.extend_from_slice(&timers);
+    data.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    data.extend_from_slice(&mac);
+    data.extend_from_slice(&id.to_be_bytes());
+    data.extend_from_slice(&error);
+    let key_name = encode_name(&key.name.to_ascii_lowercase())?;
+    write_record(message, &key_name, TYPE_TSIG, CLASS_ANY, 0, &data);
+    message[11] += 1;
+    Ok(mac)
+}
+
+fn write_record(message: &mut Vec<u8>, name: &[u8], rtype: u16, class: u16, ttl: u32, data: &[u8]) {
+    message.extend_from_slice(name);
+    message.extend_from_slice(&rtype.to_be_bytes());
+    message.extend_from_slice(&class.to_be_bytes());
+    message.extend_from_slice(&ttl.to_be_bytes());
+    message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    message.extend_from_slice(data);
+}
+
+/// Returns the name of the PTR record of `address`
+fn reverse_name(address: Ipv4Addr) -> String {
+    let [a, b, c, d] = address.octets();
+    format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_update() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let key = TsigKey::new(String::from("dhcp-key"), b"secret".to_vec());
+        let mut ddns = DnsUpdater::new(server.local_addr().unwrap(), String::from("lan"));
+        ddns.set_key(key.clone());
+        let responder = std::thread::spawn(move || {
+            let mut updates = vec![];
+            for (flags, rcode, signed) in [
+                (0xa8, 0, true),
+                (0xa8, 0, true),
+                (0xa8, 5, false),
+                (0xa8, 0, false),
+                (0x28, 0, true),
+            ] {
+                let mut buffer = [0; 512];
+                let (len, client) = server.recv_from(&mut buffer).unwrap();
+                let mac = Tsig::parse(&buffer[..len]).unwrap().mac.to_vec();
+                updates.push(buffer[..len].to_vec());
+                let mut response = vec![buffer[0], buffer[1], flags, rcode];
+                response.extend_from_slice(&[0; 8]);
+                if signed {
+                    let id = u16::from_be_bytes([buffer[0], buffer[1]]);
+                    sign(&mut response, id, &key, Some(&mac)).unwrap();
+                }
+                server.send_to(&response, client).unwrap();
+            }
+            updates
+        });
+
+        let address = Ipv4Addr::new(10, 0, 0, 42);
+        ddns.register("host", address, 3600).unwrap();
+        assert_eq!(
+            ddns.unregister("host.lan", address),
+            Err(DdnsError::Rcode(5))
+        );
+        //Unsigned responses, and queries, are refused
+        assert_eq!(
+            ddns.unregister("host.lan", address),
+            Err(DdnsError::InvalidSignature)
+        );
+        assert_eq!(
+            ddns.unregister("host.lan", address),
+            Err(DdnsError::InvalidResponse)
+        );
+        let updates = responder.join().unwrap();
+
+        let forward = &updates[0];
+        assert_eq!(&forward[2..4], &OPCODE_UPDATE.to_be_bytes());
+        //One zone, two updates and the TSIG record
+        assert_eq!(&forward[4..12], &[0, 1, 0, 0, 0, 2, 0, 1]);
+        assert_eq!(&forward[12..17], b"\x03lan\x00");
+        let a_record = [&encode_name("host.lan").unwrap()[..], &[0, 1, 0, 1]].concat();
+        assert!(forward.windows(a_record.len()).any(|w| w == a_record));
+        assert!(forward.windows(8).any(|w| w == b"dhcp-key"));
+        let reverse = &updates[1];
+        assert_eq!(&reverse[12..33], b"\x010\x010\x0210\x07in-addr\x04arpa\x00");
+    }
+
+    #[tokio::test]
+    async fn test_update_queue() {
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let ddns = DnsUpdater::new(server.local_addr().unwrap(), String::from("lan")).start();
+
+        ddns.register("host", Ipv4Addr::new(10, 0, 0, 42), 3600)
+            .unwrap();
+        let mut zones = vec![];
+        for _ in 0..2 {
+            let mut buffer = [0; 512];
+            let (len, client) = server.recv_from(&mut buffer).await.unwrap();
+            zones.push(buffer[12..len].to_vec());
+            server
+                .send_to(&[buffer[0], buffer[1], 0xa8, 0], client)
+                .await
+                .unwrap();
+        }
+        assert!(zones[0].starts_with(b"\x03lan\x00"));
+        assert!(zones[1].starts_with(b"\x010\x010\x0210\x07in-addr"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Duplicate packet suppression for the [`StateSwitcher`].
+//!
+//! A [`Deduplicator`] remembers the fingerprint of every packet
+//! processed during the last `ttl`, so that retransmissions
+//! received while the original packet is still being processed
+//! (or right after) are dropped instead of being processed twice.
+//!
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub type Fingerprint<T> = dyn Fn(&T) -> Vec<u8> + Send + Sync;
+
+/// TTL cache of packet fingerprints
+pub struct Deduplicator<T> {
+    ttl: Duration,
+    fingerprint: Box<Fingerprint<T>>,
+    seen: Mutex<SeenCache>,
+}
+
+struct SeenCache {
+    entries: HashMap<Vec<u8>, Instant>,
+    last_purge: Instant,
+}
+
+impl<T> Deduplicator<T> {
+    /// Creates a new `Deduplicator` identifying packets
+    /// through `fingerprint`, and considering two packets
+    /// with the same fingerprint received less than `ttl`
+    /// apart as duplicates
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let dedup = Deduplicator::new(Duration::from_secs(2), |packet: &DhcpPacket| {
+    ///     [&packet.xid.to_be_bytes()[..], &packet.chaddr[..]].concat()
+    /// });
+    /// ```
+    pub fn new(ttl: Duration, fingerprint: impl Fn(&T) -> Vec<u8> + Send + Sync + 'static) -> Self {
+        Self {
+            ttl,
+            fingerprint: Box::new(fingerprint),
+            seen: Mutex::new(SeenCache {
+                entries: HashMap::new(),
+                last_purge: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns the time during which a fingerprint is remembered
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Records the fingerprint of `packet`, returning whether
+    /// an identical fingerprint was already recorded less
+    /// than `ttl` ago
+    pub fn is_duplicate(&self, packet: &T) -> bool {
+        let key = (self.fingerprint)(packet);
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+
+        if now.duration_since(seen.last_purge) >= self.ttl {
+            let ttl = self.ttl;
+            seen.entries
+                .retain(|_, recorded| now.duration_since(*recorded) < ttl);
+            seen.last_purge = now;
+        }
+
+        match seen.entries.get(&key) {
+            Some(recorded) if now.duration_since(*recorded) < self.ttl => true,
+            _ => {
+                seen.entries.insert(key, now);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_duplicates_expire() {
+        let dedup = Deduplicator::new(Duration::from_millis(50), |packet: &(u8, u8)| {
+            vec![packet.0]
+        });
+
+        assert!(!dedup.is_duplicate(&(1, 0)));
+        assert!(dedup.is_duplicate(&(1, 1)));
+        assert!(!dedup.is_duplicate(&(2, 0)));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!dedup.is_duplicate(&(1, 2)));
+        assert!(dedup.is_duplicate(&(1, 3)));
+    }
+}
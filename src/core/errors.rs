@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{fmt::Display, net::Ipv4Addr};
 
 /// Generic error type for [`Hook`] and [`HookRegistry`]
 ///
@@ -16,3 +16,216 @@ impl Display for HookError {
         write!(f, "{}", self.0)
     }
 }
+
+/// Error returned by [`StateSwitcherBuilder`] when
+/// a mandatory component was not provided
+///
+/// [`StateSwitcherBuilder`]: crate::core::builder::StateSwitcherBuilder
+#[derive(Debug, Clone, Copy)]
+pub struct BuilderError(&'static str);
+impl BuilderError {
+    pub fn new(code: &'static str) -> Self {
+        Self(code)
+    }
+}
+impl Display for BuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Error returned by the [`Allocator`] and its [`AddressPool`]
+///
+/// [`Allocator`]: crate::core::allocator::Allocator
+/// [`AddressPool`]: crate::core::allocator::AddressPool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationError {
+    /// Every address of the pool is bound
+    Exhausted,
+    /// The address doesn't belong to any pool
+    OutOfRange(Ipv4Addr),
+    /// The address is bound to another client
+    InUse(Ipv4Addr),
+    /// The address is excluded from its pool
+    Excluded(Ipv4Addr),
+    /// The address was declined, and is not handed out
+    Abandoned(Ipv4Addr),
+    /// The maximum number of leases of the pool or class is reached
+    LimitReached,
+    /// The address is not bound to any client
+    NotAllocated(Ipv4Addr),
+    /// No pool for the subnet with the given network address
+    UnknownSubnet(Ipv4Addr),
+    /// The start of the range is greater than its end
+    InvalidRange(Ipv4Addr, Ipv4Addr),
+}
+
+impl Display for AllocationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exhausted => write!(f, "No address left"),
+            Self::OutOfRange(address) => write!(f, "Address {} is not in any pool", address),
+            Self::InUse(address) => write!(f, "Address {} is bound to another client", address),
+            Self::Excluded(address) => write!(f, "Address {} is excluded", address),
+            Self::Abandoned(address) => write!(f, "Address {} is abandoned", address),
+            Self::LimitReached => write!(f, "Lease limit reached"),
+            Self::NotAllocated(address) => write!(f, "Address {} is not allocated", address),
+            Self::UnknownSubnet(network) => write!(f, "No pool for subnet {}", network),
+            Self::InvalidRange(start, end) => write!(f, "Invalid range {} - {}", start, end),
+        }
+    }
+}
+
+impl std::error::Error for AllocationError {}
+
+/// Error returned when building the subnet configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The prefix length is greater than 32
+    InvalidPrefix(u8),
+    /// The network address has bits set outside of its prefix
+    InvalidNetwork(Ipv4Addr, u8),
+    /// The range doesn't belong to its subnet
+    RangeOutsideSubnet(Ipv4Addr, Ipv4Addr),
+    /// The subnet overlaps the subnet with the given network address
+    Overlap(Ipv4Addr, Ipv4Addr),
+    /// The reserved address is excluded from its subnet
+    ReservationExcluded(Ipv4Addr),
+    /// The reserved address doesn't belong to any subnet
+    ReservationOutsideSubnets(Ipv4Addr),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPrefix(prefix) => write!(f, "Invalid prefix length {}", prefix),
+            Self::InvalidNetwork(network, prefix) => {
+                write!(f, "Invalid network address {}/{}", network, prefix)
+            }
+            Self::RangeOutsideSubnet(start, end) => {
+                write!(f, "Range {} - {} is outside of its subnet", start, end)
+            }
+            Self::Overlap(subnet, other) => write!(f, "Subnet {} overlaps {}", subnet, other),
+            Self::ReservationExcluded(address) => {
+                write!(f, "Reserved address {} is excluded", address)
+            }
+            Self::ReservationOutsideSubnets(address) => {
+                write!(f, "Reserved address {} is outside of every subnet", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Error returned when encoding or decoding the value of an option
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    /// The domain name has an empty label, or a label longer than 63 bytes
+    InvalidLabel(String),
+    /// The domain name is longer than 255 bytes
+    NameTooLong(String),
+    /// The value ends in the middle of an item
+    Truncated,
+    /// The compression pointer at the given offset doesn't
+    /// point to a previous name
+    InvalidPointer(usize),
+}
+
+impl Display for OptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLabel(name) => write!(f, "Invalid label in domain name {}", name),
+            Self::NameTooLong(name) => write!(f, "Domain name {} is too long", name),
+            Self::Truncated => write!(f, "Option value is truncated"),
+            Self::InvalidPointer(offset) => {
+                write!(f, "Invalid compression pointer at offset {}", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OptionError {}
+
+/// Error returned by the [`DnsUpdater`]
+///
+/// [`DnsUpdater`]: crate::core::ddns::DnsUpdater
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DdnsError {
+    /// The update couldn't be sent, or its response received
+    Io(std::io::ErrorKind),
+    /// The domain name can't be encoded
+    Name(OptionError),
+    /// The DNS server answered with the given error code
+    Rcode(u8),
+    /// The response doesn't match the update
+    InvalidResponse,
+    /// The response isn't signed with the key of the update
+    InvalidSignature,
+    /// The update couldn't be queued, the queue being full or stopped
+    QueueFull,
+}
+
+impl Display for DdnsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(kind) => write!(f, "DNS update failed: {}", kind),
+            Self::Name(err) => write!(f, "{}", err),
+            Self::Rcode(rcode) => write!(f, "DNS update refused with error code {}", rcode),
+            Self::InvalidResponse => write!(f, "Invalid response to DNS update"),
+            Self::InvalidSignature => write!(f, "Invalid signature of DNS update response"),
+            Self::QueueFull => write!(f, "DNS update queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for DdnsError {}
+
+impl From<std::io::Error> for DdnsError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value.kind())
+    }
+}
+
+impl From<OptionError> for DdnsError {
+    fn from(value: OptionError) -> Self {
+        Self::Name(value)
+    }
+}
+
+/// Error returned when raw bytes can't be parsed into a [`PacketType`]
+///
+/// [`PacketType`]: crate::core::packet::PacketType
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The packet is shorter than the given minimum length
+    TooShort(usize),
+    /// The packet doesn't start its options with the DHCP magic cookie
+    InvalidMagicCookie([u8; 4]),
+    /// The packet is malformed
+    Malformed(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooShort(len) => write!(f, "Packet is shorter than {} bytes", len),
+            Self::InvalidMagicCookie(cookie) => write!(f, "Invalid magic cookie {:?}", cookie),
+            Self::Malformed(reason) => write!(f, "Malformed packet: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for std::io::Error {
+    fn from(value: ParseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, value)
+    }
+}
+
+impl From<OptionError> for ParseError {
+    fn from(value: OptionError) -> Self {
+        Self::Malformed(value.to_string())
+    }
+}
@@ -0,0 +1,42 @@
+//! Lifecycle events emitted by a [`StateSwitcher`].
+//!
+//! External components (REST API, metrics exporters,
+//! test assertions...) can observe the pipeline by
+//! subscribing to its [`PipelineEvent`] stream through
+//! a [`PipelineHandle`], without registering any [`Hook`].
+//!
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+//! [`PipelineHandle`]: super::handle::PipelineHandle
+//! [`Hook`]: crate::hooks::hook_registry::Hook
+
+use uuid::Uuid;
+
+use super::{processor::DropReason, state::PacketState};
+
+/// Number of events buffered for each subscriber
+/// before the slowest ones start lagging behind
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// An event in the lifecycle of a [`PacketContext`],
+/// identified by its [`Uuid`]
+///
+/// [`PacketContext`]: super::packet::PacketContext
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineEvent {
+    /// A packet was dequeued and wrapped
+    /// in a new [`PacketContext`]
+    ///
+    /// [`PacketContext`]: super::packet::PacketContext
+    Received { id: Uuid },
+    /// A packet entered a new state
+    StateEntered { id: Uuid, state: PacketState },
+    /// A packet was dropped
+    Dropped { id: Uuid, reason: DropReason },
+    /// A packet was dispatched to the [`Output`]
+    ///
+    /// [`Output`]: super::state_switcher::Output
+    Sent { id: Uuid, bytes: usize },
+    /// A packet was discarded before processing
+    /// because the queue was full
+    Overflow,
+}
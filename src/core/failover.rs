@@ -0,0 +1,467 @@
+//! High availability between two servers.
+//!
+//! Two servers serving the same subnets split their pools: each
+//! one only offers the addresses it owns, so that they never
+//! offer the same address. Every address committed or released
+//! by a server is replicated to its peer through a [`Failover`],
+//! over a TCP connection carrying length-prefixed messages.
+//!
+//! Servers exchange heartbeats. Once its peer was silent for
+//! longer than the timeout, a server knows that communications are
+//! interrupted, but not whether its peer is down: it keeps offering
+//! only the addresses it owns. Only once an operator declared the
+//! peer down does it enter the partner-down state and offer every
+//! address, so that both servers never offer the same address during
+//! a network partition.
+//!
+//! Only connections from the address of the peer are accepted. Once
+//! a [key](Failover::set_key) is set, peers also prove they know it
+//! by answering a challenge when connecting. Every time a server
+//! connects to its peer, it first sends all its bindings, along with
+//! the releases it failed to send, so that messages lost while the
+//! connection was down are recovered.
+
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+
+use super::{allocator::Allocator, client_id::ClientId};
+
+/// Time after which a silent peer is considered unreachable
+pub const DEFAULT_PEER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Length of the challenge sent to authenticate a peer
+pub const CHALLENGE_LEN: usize = 16;
+
+/// Role of a server in a failover pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Owns the even addresses
+    Primary,
+    /// Owns the odd addresses
+    Secondary,
+}
+
+/// State of the peer, as seen by a server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    /// The peer is up, each server offers the addresses it owns
+    Normal,
+    /// The peer is silent, each server still offers
+    /// only the addresses it owns
+    CommunicationsInterrupted,
+    /// The peer was declared down, every address is offered
+    PartnerDown,
+}
+
+/// Message exchanged by failover peers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailoverMessage {
+    /// The address was committed to the client
+    Bind(Ipv4Addr, ClientId),
+    /// The address was released
+    Release(Ipv4Addr),
+    Heartbeat,
+}
+
+impl FailoverMessage {
+    /// Returns the encoding of the message, without its length prefix
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Bind(address, client) => {
+                [&[0][..], &address.octets(), client.as_bytes()].concat()
+            }
+            Self::Release(address) => [&[1][..], &address.octets()].concat(),
+            Self::Heartbeat => vec![2],
+        }
+    }
+
+    /// Decodes a message, without its length prefix
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        match data {
+            [0, a, b, c, d, client @ ..] if !client.is_empty() => Some(Self::Bind(
+                Ipv4Addr::new(*a, *b, *c, *d),
+                ClientId::new(client.to_vec()),
+            )),
+            [1, a, b, c, d] => Some(Self::Release(Ipv4Addr::new(*a, *b, *c, *d))),
+            [2] => Some(Self::Heartbeat),
+            _ => None,
+        }
+    }
+}
+
+/// Link of a server to its failover peer
+pub struct Failover {
+    role: Role,
+    peer: SocketAddr,
+    timeout: Duration,
+    key: Option<Vec<u8>>,
+    last_seen: Mutex<Instant>,
+    partner_down: AtomicBool,
+    connection: Mutex<Option<TcpStream>>,
+    allocator: OnceLock<Arc<Allocator>>,
+    pending_releases: Mutex<HashSet<Ipv4Addr>>,
+}
+
+impl Failover {
+    /// Creates the link to `peer`, of a server with the given role
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut failover = Failover::new(Role::Primary, "10.0.0.3:647".parse()?);
+    /// failover.set_key(shared_secret);
+    /// let failover = Arc::new(failover);
+    /// let mut allocator = selector.allocator(hold);
+    /// let owner = failover.clone();
+    /// allocator.set_filter(move |address| owner.owns(address));
+    /// let allocator = Arc::new(allocator);
+    /// failover.listen("0.0.0.0:647", allocator.clone())?;
+    /// ```
+    pub fn new(role: Role, peer: SocketAddr) -> Self {
+        Self {
+            role,
+            peer,
+            timeout: DEFAULT_PEER_TIMEOUT,
+            key: None,
+            last_seen: Mutex::new(Instant::now()),
+            partner_down: AtomicBool::new(false),
+            connection: Mutex::new(None),
+            allocator: OnceLock::new(),
+            pending_releases: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Sets the time after which a silent peer is considered unreachable
+    ///
+    /// Connections silent for longer are closed as well.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the secret shared with the peer, which must prove
+    /// it knows it before its messages are applied
+    pub fn set_key(&mut self, key: impl Into<Vec<u8>>) {
+        self.key = Some(key.into());
+    }
+
+    /// Returns the state of the peer
+    pub fn state(&self) -> PeerState {
+        if self.partner_down.load(Ordering::SeqCst) {
+            return PeerState::PartnerDown;
+        }
+        match self.last_seen.lock().unwrap().elapsed() > self.timeout {
+            true => PeerState::CommunicationsInterrupted,
+            false => PeerState::Normal,
+        }
+    }
+
+    /// Declares the peer down, so that every address is offered
+    ///
+    /// Must only be called once an operator made sure the peer is
+    /// not serving clients anymore: a peer still serving clients
+    /// would offer the same addresses. The state goes back to
+    /// normal once the peer connects again.
+    pub fn set_partner_down(&self) {
+        log::warn!("Failover peer {} declared down", self.peer);
+        self.partner_down.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether the server can offer `address`
+    pub fn owns(&self, address: Ipv4Addr) -> bool {
+        if self.state() == PeerState::PartnerDown {
+            return true;
+        }
+        let even = u32::from(address) % 2 == 0;
+        even == (self.role == Role::Primary)
+    }
+
+    /// Replicates the commit of `address` to `client` to the peer
+    pub fn bind(&self, address: Ipv4Addr, client: ClientId) -> Result<(), std::io::Error> {
+        self.pending_releases.lock().unwrap().remove(&address);
+        self.send(FailoverMessage::Bind(address, client))
+    }
+
+    /// Replicates the release of `address` to the peer
+    ///
+    /// Releases which cannot be sent are sent again
+    /// once the peer is reachable.
+    pub fn release(&self, address: Ipv4Addr) -> Result<(), std::io::Error> {
+        let sent = self.send(FailoverMessage::Release(address));
+        if sent.is_err() {
+            self.pending_releases.lock().unwrap().insert(address);
+        }
+        sent
+    }
+
+    /// Sends a heartbeat to the peer, meant to be called
+    /// periodically, well within the timeout
+    pub fn heartbeat(&self) -> Result<(), std::io::Error> {
+        self.send(FailoverMessage::Heartbeat)
+    }
+
+    /// Sends `message` to the peer, connecting to it if needed
+    ///
+    /// A new connection first carries the bindings of the allocator
+    /// given to [`listen`](Failover::listen) and the pending releases.
+    pub fn send(&self, message: FailoverMessage) -> Result<(), std::io::Error> {
+        let mut connection = self.connection.lock().unwrap();
+        let stream = match connection.as_mut() {
+            Some(stream) => stream,
+            None => connection.insert(self.connect()?),
+        };
+        let sent = write_message(stream, &message);
+        //The connection is opened again on the next message
+        if sent.is_err() {
+            *connection = None;
+        }
+        sent
+    }
+
+    /// Connects to the peer, authenticates and resynchronizes
+    fn connect(&self) -> Result<TcpStream, std::io::Error> {
+        let mut stream = TcpStream::connect_timeout(&self.peer, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        if let Some(key) = &self.key {
+            let mut challenge = [0; CHALLENGE_LEN];
+            stream.read_exact(&mut challenge)?;
+            stream.write_all(&authenticator(key, &challenge)?)?;
+        }
+
+        if let Some(allocator) = self.allocator.get() {
+            for (address, client) in allocator.leased() {
+                write_message(&mut stream, &FailoverMessage::Bind(address, client))?;
+            }
+        }
+        let releases: Vec<Ipv4Addr> = self.pending_releases.lock().unwrap().drain().collect();
+        for (i, address) in releases.iter().enumerate() {
+            if let Err(err) = write_message(&mut stream, &FailoverMessage::Release(*address)) {
+                self.pending_releases.lock().unwrap().extend(&releases[i..]);
+                return Err(err);
+            }
+        }
+        Ok(stream)
+    }
+
+    /// Applies the messages received from the peer to `allocator`,
+    /// in a background thread accepting connections on `addr`
+    ///
+    /// Each connection is served by its own thread, so that a
+    /// restarted peer is accepted even while its previous connection
+    /// is still open, until it times out.
+    pub fn listen(
+        self: &Arc<Self>,
+        addr: &str,
+        allocator: Arc<Allocator>,
+    ) -> Result<JoinHandle<()>, std::io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let _ = self.allocator.set(allocator.clone());
+        let failover = self.clone();
+        Ok(std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        log::warn!("Failed to accept failover peer: {}", err);
+                        continue;
+                    }
+                };
+                match stream.peer_addr() {
+                    Ok(addr) if addr.ip() == failover.peer.ip() => {
+                        let failover = failover.clone();
+                        let allocator = allocator.clone();
+                        std::thread::spawn(move || failover.receive(stream, &allocator));
+                    }
+                    Ok(addr) => log::warn!("Refused failover connection from {}", addr),
+                    Err(err) => log::warn!("Failed to accept failover peer: {}", err),
+                }
+            }
+        }))
+    }
+
+    fn receive(&self, mut stream: TcpStream, allocator: &Allocator) {
+        if let Err(err) = self.authenticate(&mut stream) {
+            log::warn!("Failed to authenticate failover peer: {}", err);
+            return;
+        }
+        *self.last_seen.lock().unwrap() = Instant::now();
+        if self.partner_down.swap(false, Ordering::SeqCst) {
+            log::info!("Failover peer {} is back", self.peer);
+        }
+
+        let mut len = [0; 2];
+        while stream.read_exact(&mut len).is_ok() {
+            let mut data = vec![0; u16::from_be_bytes(len) as usize];
+            if stream.read_exact(&mut data).is_err() {
+                break;
+            }
+            *self.last_seen.lock().unwrap() = Instant::now();
+            match FailoverMessage::decode(&data) {
+                Some(message) => self.apply(message, allocator),
+                None => log::warn!("Invalid failover message {:?}", data),
+            }
+        }
+    }
+
+    /// Sets the timeouts of a connection from the peer, and
+    /// challenges the peer if a key is set
+    fn authenticate(&self, stream: &mut TcpStream) -> Result<(), std::io::Error> {
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let Some(key) = &self.key else {
+            return Ok(());
+        };
+        let challenge: [u8; CHALLENGE_LEN] = rand::random();
+        stream.write_all(&challenge)?;
+        let mut answer = [0; 32];
+        stream.read_exact(&mut answer)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+        mac.update(&challenge);
+        mac.verify_slice(&answer).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Invalid answer")
+        })
+    }
+
+    /// Applies a message received from the peer to `allocator`
+    pub fn apply(&self, message: FailoverMessage, allocator: &Allocator) {
+        let applied = match &message {
+            FailoverMessage::Bind(address, client) => allocator.commit(*address, client.clone()),
+            FailoverMessage::Release(address) => allocator.release(*address),
+            FailoverMessage::Heartbeat => Ok(()),
+        };
+        if let Err(err) = applied {
+            log::warn!("Conflicting failover message {:?}: {}", message, err);
+        }
+    }
+}
+
+/// Writes `message` to `stream`, prefixed by its length
+fn write_message(stream: &mut TcpStream, message: &FailoverMessage) -> Result<(), std::io::Error> {
+    let data = message.encode();
+    stream.write_all(&[&(data.len() as u16).to_be_bytes()[..], &data].concat())
+}
+
+/// Returns the answer to `challenge`, proving the knowledge of `key`
+fn authenticator(key: &[u8], challenge: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+    mac.update(challenge);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mac_address::MacAddress;
+
+    use super::*;
+    use crate::core::{
+        allocator::{AddressPool, AddressRange},
+        errors::AllocationError,
+    };
+
+    #[test]
+    fn test_failover() {
+        let network = Ipv4Addr::new(10, 0, 0, 0);
+        let range =
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 13)).unwrap();
+        let pool = || AddressPool::new(vec![range], Duration::from_secs(30));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut secondary = Failover::new(Role::Secondary, "127.0.0.1:9".parse().unwrap());
+        secondary.set_timeout(Duration::from_millis(300));
+        secondary.set_key("secret");
+        let secondary = Arc::new(secondary);
+        let mut allocator = Allocator::new();
+        allocator.add_pool(network, pool());
+        let owner = secondary.clone();
+        allocator.set_filter(move |address| owner.owns(address));
+        let allocator = Arc::new(allocator);
+        secondary
+            .listen(&addr.to_string(), allocator.clone())
+            .unwrap();
+
+        //The secondary only offers odd addresses
+        let client = |n| MacAddress::new([0xaa, 0, 0, 0, 0, n]);
+        assert_eq!(
+            allocator.allocate(network, client(1), Some(Ipv4Addr::new(10, 0, 0, 10))),
+            Ok(Ipv4Addr::new(10, 0, 0, 11))
+        );
+
+        //Peers without the key are refused
+        let mut intruder = Failover::new(Role::Primary, addr);
+        intruder.set_key("guess");
+        let _ = intruder.bind(Ipv4Addr::new(10, 0, 0, 12), client(5).into());
+        std::thread::sleep(Duration::from_millis(100));
+        let pool_of =
+            |allocator: &Allocator, address| allocator.pool(network).unwrap().client(address);
+        assert_eq!(pool_of(&allocator, Ipv4Addr::new(10, 0, 0, 12)), None);
+
+        //Bindings of the primary are sent when it connects,
+        //then its commits are replicated
+        let mut primary = Failover::new(Role::Primary, addr);
+        primary.set_key("secret");
+        let primary = Arc::new(primary);
+        let mut primary_allocator = Allocator::new();
+        primary_allocator.add_pool(network, pool());
+        primary_allocator
+            .commit(Ipv4Addr::new(10, 0, 0, 10), client(6))
+            .unwrap();
+        primary
+            .listen("127.0.0.1:0", Arc::new(primary_allocator))
+            .unwrap();
+        primary
+            .bind(Ipv4Addr::new(10, 0, 0, 13), client(2).into())
+            .unwrap();
+        primary.heartbeat().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            pool_of(&allocator, Ipv4Addr::new(10, 0, 0, 10)),
+            Some(client(6).into())
+        );
+        assert_eq!(
+            pool_of(&allocator, Ipv4Addr::new(10, 0, 0, 13)),
+            Some(client(2).into())
+        );
+        assert_eq!(secondary.state(), PeerState::Normal);
+
+        //While the primary is silent, the secondary
+        //still only offers odd addresses
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(secondary.state(), PeerState::CommunicationsInterrupted);
+        assert_eq!(
+            allocator.allocate(network, client(3), None),
+            Err(AllocationError::Exhausted)
+        );
+
+        //Once an operator declared the primary down, every address is offered
+        secondary.set_partner_down();
+        assert_eq!(secondary.state(), PeerState::PartnerDown);
+        assert_eq!(
+            allocator.allocate(network, client(3), None),
+            Ok(Ipv4Addr::new(10, 0, 0, 12))
+        );
+        assert_eq!(
+            FailoverMessage::decode(&FailoverMessage::Bind(network, client(4).into()).encode()),
+            Some(FailoverMessage::Bind(network, client(4).into()))
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Control handle over a running [`StateSwitcher`].
+//!
+//! A [`PipelineHandle`] can be cloned and moved freely
+//! across tasks, allowing the pipeline to be stopped,
+//! paused, resumed or inspected from anywhere in the program.
+//!
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{broadcast, Notify};
+
+use super::{
+    counters::{Counters, CountersSnapshot},
+    events::{PipelineEvent, EVENT_CAPACITY},
+};
+
+/// Interval at which a paused pipeline checks
+/// whether it was stopped in the meantime
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Point-in-time statistics of a pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineStats {
+    /// Packet counters of the pipeline
+    pub counters: CountersSnapshot,
+    /// Whether the pipeline is still reading packets
+    pub running: bool,
+    /// Whether the pipeline is currently paused
+    pub paused: bool,
+}
+
+/// Cloneable handle controlling the execution
+/// of a [`StateSwitcher`]
+///
+/// [`StateSwitcher`]: super::state_switcher::StateSwitcher
+#[derive(Clone)]
+pub struct PipelineHandle {
+    running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    resumed: Arc<Notify>,
+    counters: Arc<Counters>,
+    idle: Arc<Notify>,
+    terminated: Arc<AtomicBool>,
+    on_terminated: Arc<Notify>,
+    events: broadcast::Sender<PipelineEvent>,
+}
+
+/// Marks a packet as being processed
+/// for as long as it is alive
+pub(crate) struct InFlight {
+    counters: Arc<Counters>,
+    idle: Arc<Notify>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.counters.leave_flight() == 1 {
+            self.idle.notify_waiters();
+        }
+    }
+}
+
+impl PipelineHandle {
+    /// Creates a new `PipelineHandle` driven by
+    /// the given kill switch
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let handle = PipelineHandle::new(Arc::new(AtomicBool::new(true)));
+    /// ```
+    pub fn new(kill_switch: Arc<AtomicBool>) -> Self {
+        Self {
+            running: kill_switch,
+            paused: Arc::new(AtomicBool::new(false)),
+            resumed: Arc::new(Notify::new()),
+            counters: Arc::new(Counters::new()),
+            idle: Arc::new(Notify::new()),
+            terminated: Arc::new(AtomicBool::new(false)),
+            on_terminated: Arc::new(Notify::new()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    /// Stops reading new packets from the [`Input`].
+    ///
+    /// Packets already queued are still processed
+    /// before the pipeline terminates.
+    ///
+    /// [`Input`]: super::state_switcher::Input
+    pub fn stop(&self) {
+        self.running.store(false, SeqCst);
+        self.resumed.notify_waiters();
+    }
+
+    /// Returns whether the pipeline was not stopped yet
+    pub fn is_running(&self) -> bool {
+        self.running.load(SeqCst)
+    }
+
+    /// Stops dequeuing new packets until [`resume`] is called
+    ///
+    /// [`resume`]: PipelineHandle::resume
+    pub fn pause(&self) {
+        self.paused.store(true, SeqCst);
+    }
+
+    /// Resumes dequeuing packets after a call to [`pause`]
+    ///
+    /// [`pause`]: PipelineHandle::pause
+    pub fn resume(&self) {
+        self.paused.store(false, SeqCst);
+        self.resumed.notify_waiters();
+    }
+
+    /// Returns whether the pipeline is currently paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(SeqCst)
+    }
+
+    /// Returns the current [`PipelineStats`]
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let handle = state_switcher.spawn();
+    /// println!("{} packets dropped", handle.stats().counters.dropped);
+    /// ```
+    pub fn stats(&self) -> PipelineStats {
+        PipelineStats {
+            counters: self.counters.snapshot(),
+            running: self.is_running(),
+            paused: self.is_paused(),
+        }
+    }
+
+    /// Returns whether the pipeline terminated, meaning
+    /// it was stopped and every queued packet was processed
+    pub fn is_terminated(&self) -> bool {
+        self.terminated.load(SeqCst)
+    }
+
+    /// Waits until the pipeline terminates
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let handle = state_switcher.spawn();
+    /// handle.stop();
+    /// handle.await_terminated().await;
+    /// ```
+    pub async fn await_terminated(&self) {
+        let notified = self.on_terminated.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        if self.is_terminated() {
+            return;
+        }
+        notified.await;
+    }
+
+    /// Subscribes to the [`PipelineEvent`] stream
+    ///
+    /// Only events emitted after this call are received.
+    /// A subscriber falling more than [`EVENT_CAPACITY`] events
+    /// behind misses the oldest ones.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut events = handle.subscribe();
+    /// while let Ok(event) = events.recv().await {
+    ///     println!("{:?}", event);
+    /// }
+    /// ```
+    pub fn subscribe(&self) -> broadcast::Receiver<PipelineEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns the sending half of the [`PipelineEvent`] stream
+    pub(crate) fn events(&self) -> broadcast::Sender<PipelineEvent> {
+        self.events.clone()
+    }
+
+    /// Returns the [`Counters`] of the pipeline
+    pub fn counters(&self) -> Arc<Counters> {
+        self.counters.clone()
+    }
+
+    pub(crate) fn set_counters(&mut self, counters: Arc<Counters>) {
+        self.counters = counters;
+    }
+
+    /// Accounts for a new packet being processed,
+    /// until the returned guard is dropped
+    pub(crate) fn track(&self) -> InFlight {
+        self.counters.enter_flight();
+        InFlight {
+            counters: self.counters.clone(),
+            idle: self.idle.clone(),
+        }
+    }
+
+    /// Waits until the pipeline is resumed, or stopped
+    pub(crate) async fn wait_resumed(&self) {
+        while self.is_paused() && self.is_running() {
+            let _ = tokio::time::timeout(PAUSE_POLL_INTERVAL, self.resumed.notified()).await;
+        }
+    }
+
+    /// Waits until no packet is being processed, then
+    /// marks the pipeline as terminated
+    pub(crate) async fn terminate(&self) {
+        loop {
+            let notified = self.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.counters.in_flight() == 0 {
+                break;
+            }
+            notified.await;
+        }
+
+        self.terminated.store(true, SeqCst);
+        self.on_terminated.notify_waiters();
+    }
+}
@@ -0,0 +1,214 @@
+//! History of the leases.
+//!
+//! Every step of the lifecycle of a lease is recorded as a
+//! [`LeaseEvent`] in its own pool, indexed by hardware address
+//! and by address, so that operators can find which client held
+//! an address at a given time.
+//!
+//! Uids of a pool are limited, so old events should be
+//! [purged](LeaseHistory::purge) periodically.
+
+use std::{net::Ipv4Addr, time::SystemTime};
+
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{
+    backend::StorageBackend,
+    data::{DataPool, RuntimeStorage, Storable},
+    errors::StorageError,
+    serialized::{Serialized, SCHEMA},
+    uid::Uid,
+};
+
+/// Name of the pool, and table, holding the history
+pub const HISTORY_POOL: &str = "lease_history";
+
+/// Step of the lifecycle of a lease
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeaseEventKind {
+    Offered,
+    Acked,
+    Renewed,
+    Released,
+    Expired,
+    Declined,
+}
+
+impl LeaseEventKind {
+    /// Returns whether the client holds the address after the event
+    fn binds(&self) -> bool {
+        matches!(self, Self::Acked | Self::Renewed)
+    }
+}
+
+/// Step of the lifecycle of the lease of `address` by `hardware_address`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaseEvent {
+    pub kind: LeaseEventKind,
+    pub hardware_address: MacAddress,
+    pub address: Ipv4Addr,
+    pub timestamp: SystemTime,
+}
+
+impl LeaseEvent {
+    /// Creates an event which occurred now
+    pub fn new(kind: LeaseEventKind, hardware_address: MacAddress, address: Ipv4Addr) -> Self {
+        Self {
+            kind,
+            hardware_address,
+            address,
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
+/// Persistent history of [`LeaseEvent`]
+pub struct LeaseHistory {
+    storage: RuntimeStorage<Serialized<LeaseEvent>>,
+}
+
+impl LeaseHistory {
+    /// Creates the history persisted to `backend`, loading
+    /// the events already stored
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let history = LeaseHistory::new(db)?;
+    /// history.record(LeaseEvent::new(LeaseEventKind::Acked, packet.chaddr, address))?;
+    /// let holder = history.holder_at(Ipv4Addr::new(10, 0, 0, 42), last_tuesday);
+    /// ```
+    pub fn new(backend: impl StorageBackend + 'static) -> Result<Self, StorageError> {
+        let storage = RuntimeStorage::new(backend);
+        let pool = DataPool::new(HISTORY_POOL.to_string(), SCHEMA.to_string());
+        pool.add_index("hardware_address", |event: &Serialized<LeaseEvent>| {
+            Some(event.hardware_address.to_string())
+        });
+        pool.add_index("address", |event: &Serialized<LeaseEvent>| {
+            Some(event.address.to_string())
+        });
+        storage.add_pool(pool)?;
+        storage.load()?;
+        Ok(Self { storage })
+    }
+
+    /// Records `event`
+    pub fn record(&self, event: LeaseEvent) -> Result<Uid, StorageError> {
+        self.storage
+            .store(Serialized::new(event), HISTORY_POOL.to_string())
+    }
+
+    /// Returns the events of the given hardware address, oldest first
+    pub fn by_hardware_address(&self, hardware_address: MacAddress) -> Vec<LeaseEvent> {
+        self.find("hardware_address", &hardware_address.to_string())
+    }
+
+    /// Returns the events of the given address, oldest first
+    pub fn by_address(&self, address: Ipv4Addr) -> Vec<LeaseEvent> {
+        self.find("address", &address.to_string())
+    }
+
+    /// Returns the client which held `address` at `time`, if any
+    pub fn holder_at(&self, address: Ipv4Addr, time: SystemTime) -> Option<MacAddress> {
+        self.by_address(address)
+            .into_iter()
+            .rev()
+            .find(|event| event.timestamp <= time && event.kind != LeaseEventKind::Offered)
+            .filter(|event| event.kind.binds())
+            .map(|event| event.hardware_address)
+    }
+
+    /// Returns the last address held by the given hardware address, if any
+    pub fn last_address(&self, hardware_address: MacAddress) -> Option<Ipv4Addr> {
+        self.by_hardware_address(hardware_address)
+            .into_iter()
+            .rev()
+            .find(|event| event.kind.binds())
+            .map(|event| event.address)
+    }
+
+    /// Deletes the events which occurred before `time`,
+    /// returning the number of deleted events
+    pub fn purge(&self, time: SystemTime) -> Result<usize, StorageError> {
+        let expired: Vec<Uid> = self
+            .storage
+            .values(HISTORY_POOL)?
+            .into_iter()
+            .filter(|event| event.timestamp < time)
+            .map(|event| event.id())
+            .collect();
+        for uid in &expired {
+            self.storage.delete(*uid)?;
+        }
+        Ok(expired.len())
+    }
+
+    /// Writes the recorded events to disk
+    pub fn sync(&self) -> Result<(), StorageError> {
+        self.storage.sync()
+    }
+
+    fn find(&self, index: &str, key: &str) -> Vec<LeaseEvent> {
+        let mut events: Vec<LeaseEvent> = self
+            .storage
+            .find_by(HISTORY_POOL.to_string(), index, key)
+            .unwrap_or_default()
+            .into_iter()
+            .map(Serialized::into_inner)
+            .collect();
+        events.sort_by_key(|event| event.timestamp);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+    use crate::storage::memory_backend::MemoryBackend;
+
+    #[test]
+    fn test_history() {
+        let backend = MemoryBackend::new();
+        let history = LeaseHistory::new(backend.clone()).unwrap();
+        let first = MacAddress::new([0xaa, 0, 0, 0, 0, 1]);
+        let second = MacAddress::new([0xaa, 0, 0, 0, 0, 2]);
+        let address = Ipv4Addr::new(10, 0, 0, 42);
+        let start = SystemTime::now() - Duration::from_secs(3600);
+        let at = |minutes: u64| start + Duration::from_secs(minutes * 60);
+        let event = |kind, client, minutes: u64| LeaseEvent {
+            kind,
+            hardware_address: client,
+            address,
+            timestamp: at(minutes),
+        };
+
+        history
+            .record(event(LeaseEventKind::Offered, first, 0))
+            .unwrap();
+        history
+            .record(event(LeaseEventKind::Acked, first, 1))
+            .unwrap();
+        history
+            .record(event(LeaseEventKind::Released, first, 10))
+            .unwrap();
+        history
+            .record(event(LeaseEventKind::Acked, second, 20))
+            .unwrap();
+
+        assert_eq!(history.holder_at(address, at(0)), None);
+        assert_eq!(history.holder_at(address, at(5)), Some(first));
+        assert_eq!(history.holder_at(address, at(15)), None);
+        assert_eq!(history.holder_at(address, at(30)), Some(second));
+        assert_eq!(history.by_hardware_address(first).len(), 3);
+        assert_eq!(history.last_address(second), Some(address));
+
+        history.sync().unwrap();
+        let reloaded = LeaseHistory::new(backend).unwrap();
+        assert_eq!(reloaded.purge(at(15)), Ok(3));
+        assert_eq!(reloaded.by_address(address).len(), 1);
+    }
+}
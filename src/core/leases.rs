@@ -0,0 +1,328 @@
+//! Leases granted to clients.
+//!
+//! A [`Lease`] binds an address to a hardware address until its
+//! expiration. [`Leases`] are stored in their own pool, keyed by
+//! address and indexed by [`ClientId`], so they survive restarts.
+//!
+//! The leases can also be mirrored to an ISC dhcpd lease file,
+//! for the tools which parse `dhcpd.leases`.
+
+use std::{net::Ipv4Addr, time::SystemTime};
+
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+use super::client_id::ClientId;
+use crate::storage::{
+    backend::StorageBackend,
+    data::{DataPool, RuntimeStorage, Storable},
+    errors::StorageError,
+    isc_leases::{IscLease, IscLeaseWriter},
+    serialized::{Serialized, SCHEMA},
+    uid::Uid,
+};
+
+/// Name of the pool, and table, holding the leases
+pub const LEASE_POOL: &str = "lease";
+
+/// Address bound to a client until its expiration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    pub client_id: ClientId,
+    pub hardware_address: MacAddress,
+    pub address: Ipv4Addr,
+    /// Time the lease was granted, leases stored
+    /// without it starting when loaded
+    #[serde(default = "SystemTime::now")]
+    pub starts: SystemTime,
+    pub expiration: SystemTime,
+    pub hostname: Option<String>,
+}
+
+impl Lease {
+    /// Creates the lease of a client identified by its hardware address,
+    /// granted now
+    pub fn new(hardware_address: MacAddress, address: Ipv4Addr, expiration: SystemTime) -> Self {
+        Self {
+            client_id: ClientId::from(hardware_address),
+            hardware_address,
+            address,
+            starts: SystemTime::now(),
+            expiration,
+            hostname: None,
+        }
+    }
+
+    /// Identifies the client by the client identifier it sent
+    pub fn set_client_id(&mut self, client_id: ClientId) {
+        self.client_id = client_id;
+    }
+
+    pub fn set_hostname(&mut self, hostname: String) {
+        self.hostname = Some(hostname);
+    }
+
+    /// Returns whether the lease expired at `now`
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expiration <= now
+    }
+
+    /// Returns the lease as written in a lease file
+    pub fn to_isc(&self) -> IscLease {
+        let mut lease = IscLease::active(
+            self.address,
+            self.hardware_address,
+            utc(self.starts),
+            Some(utc(self.expiration)),
+        );
+        lease.client_hostname = self.hostname.clone();
+        lease
+    }
+}
+
+fn utc(time: SystemTime) -> PrimitiveDateTime {
+    let time = OffsetDateTime::from(time);
+    PrimitiveDateTime::new(time.date(), time.time())
+}
+
+/// Persistent set of [`Lease`], keyed by address
+pub struct Leases {
+    storage: RuntimeStorage<Serialized<Lease>>,
+    isc_writer: Option<IscLeaseWriter>,
+}
+
+impl Leases {
+    /// Creates the leases persisted to `backend`, loading
+    /// the leases already stored
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let leases = Arc::new(Leases::new(db)?);
+    /// leases.grant(Lease::new(packet.chaddr, address, SystemTime::now() + lease_time))?;
+    /// ```
+    pub fn new(backend: impl StorageBackend + 'static) -> Result<Self, StorageError> {
+        let storage = RuntimeStorage::new(backend);
+        let pool = DataPool::new(LEASE_POOL.to_string(), SCHEMA.to_string());
+        pool.set_key(|lease: &Serialized<Lease>| Some(lease.address))?;
+        pool.add_index("client_id", |lease: &Serialized<Lease>| {
+            Some(lease.client_id.to_string())
+        });
+        pool.add_index("hardware_address", |lease: &Serialized<Lease>| {
+            Some(lease.hardware_address.to_string())
+        });
+        storage.add_pool(pool)?;
+        storage.load()?;
+        Ok(Self {
+            storage,
+            isc_writer: None,
+        })
+    }
+
+    /// Mirrors the leases to the lease file of `writer`
+    ///
+    /// The file is rewritten with the current leases on every [`sync`](Leases::sync).
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut leases = Leases::new(db)?;
+    /// leases.set_isc_writer(IscLeaseWriter::open("/var/lib/dhcp/dhcpd.leases")?);
+    /// ```
+    pub fn set_isc_writer(&mut self, writer: IscLeaseWriter) {
+        self.isc_writer = Some(writer);
+    }
+
+    /// Stores `lease`, replacing the lease of its address if any
+    pub fn grant(&self, lease: Lease) -> Result<Uid, StorageError> {
+        let isc = lease.to_isc();
+        let uid = match self.find(lease.address) {
+            Some(existing) => {
+                let uid = existing.id();
+                self.storage.update(uid, Serialized::new(lease))?;
+                uid
+            }
+            None => self
+                .storage
+                .store(Serialized::new(lease), LEASE_POOL.to_string())?,
+        };
+        self.mirror(|| isc);
+        Ok(uid)
+    }
+
+    /// Returns the lease of the given address, if any
+    pub fn get(&self, address: Ipv4Addr) -> Option<Lease> {
+        self.find(address).map(Serialized::into_inner)
+    }
+
+    /// Returns the leases of the given client
+    pub fn by_client_id(&self, client_id: &ClientId) -> Vec<Lease> {
+        self.find_by("client_id", &client_id.to_string())
+    }
+
+    /// Returns the leases of the given hardware address
+    pub fn by_hardware_address(&self, hardware_address: MacAddress) -> Vec<Lease> {
+        self.find_by("hardware_address", &hardware_address.to_string())
+    }
+
+    /// Removes the lease of the given address, returning it if any
+    pub fn remove(&self, address: Ipv4Addr) -> Result<Option<Lease>, StorageError> {
+        let Some(lease) = self.find(address) else {
+            return Ok(None);
+        };
+        self.storage.delete(lease.id())?;
+        self.mirror(|| IscLease::free(address, utc(SystemTime::now())));
+        Ok(Some(lease.into_inner()))
+    }
+
+    /// Removes the lease of the given address if it is expired at `now`,
+    /// returning it
+    ///
+    /// The lease is read again right before its removal, so
+    /// that a lease renewed in the meantime is kept.
+    pub fn remove_expired(
+        &self,
+        address: Ipv4Addr,
+        now: SystemTime,
+    ) -> Result<Option<Lease>, StorageError> {
+        match self.find(address) {
+            Some(lease) if lease.is_expired(now) => {
+                self.storage.delete(lease.id())?;
+                self.mirror(|| IscLease::free(address, utc(SystemTime::now())));
+                Ok(Some(lease.into_inner()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns every lease
+    pub fn all(&self) -> Vec<Lease> {
+        self.storage
+            .values(LEASE_POOL)
+            .unwrap_or_default()
+            .into_iter()
+            .map(Serialized::into_inner)
+            .collect()
+    }
+
+    /// Returns the leases expired at `now`
+    pub fn expired(&self, now: SystemTime) -> Vec<Lease> {
+        self.all()
+            .into_iter()
+            .filter(|lease| lease.is_expired(now))
+            .collect()
+    }
+
+    /// Writes the changes made to the leases to disk
+    pub fn sync(&self) -> Result<(), StorageError> {
+        self.storage.sync()?;
+        if let Some(writer) = &self.isc_writer {
+            let leases: Vec<IscLease> = self.all().iter().map(Lease::to_isc).collect();
+            if let Err(e) = writer.rewrite(&leases) {
+                log::warn!("Could not rewrite the lease file : {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a change to the lease file, if any
+    ///
+    /// The database stays the reference, so failures are only logged.
+    fn mirror(&self, lease: impl FnOnce() -> IscLease) {
+        if let Some(writer) = &self.isc_writer {
+            if let Err(e) = writer.append(&lease()) {
+                log::warn!("Could not append to the lease file : {}", e);
+            }
+        }
+    }
+
+    fn find_by(&self, index: &str, key: &str) -> Vec<Lease> {
+        self.storage
+            .find_by(LEASE_POOL.to_string(), index, key)
+            .unwrap_or_default()
+            .into_iter()
+            .map(Serialized::into_inner)
+            .collect()
+    }
+
+    fn find(&self, address: Ipv4Addr) -> Option<Serialized<Lease>> {
+        self.storage.get_by_key(LEASE_POOL, &address).ok().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+    use crate::storage::{isc_leases::parse_leases, memory_backend::MemoryBackend};
+
+    #[test]
+    fn test_leases() {
+        let backend = MemoryBackend::new();
+        let leases = Leases::new(backend.clone()).unwrap();
+        let client = MacAddress::new([0xaa, 0, 0, 0, 0, 1]);
+        let now = SystemTime::now();
+        let address = Ipv4Addr::new(10, 0, 0, 10);
+        let mut lease = Lease::new(client, address, now + Duration::from_secs(60));
+
+        let uid = leases.grant(lease.clone()).unwrap();
+        lease.set_hostname(String::from("host"));
+        assert_eq!(leases.grant(lease.clone()), Ok(uid));
+        let mut other = Lease::new(client, Ipv4Addr::new(10, 0, 0, 11), now);
+        other.set_client_id(ClientId::new(b"\xffhost".to_vec()));
+        leases.grant(other.clone()).unwrap();
+        assert_eq!(leases.by_hardware_address(client).len(), 2);
+        assert_eq!(leases.by_client_id(&client.into()), vec![lease.clone()]);
+        assert_eq!(leases.by_client_id(&other.client_id), vec![other]);
+        assert_eq!(leases.expired(now).len(), 1);
+        assert_eq!(leases.remove_expired(address, now), Ok(None));
+
+        leases.sync().unwrap();
+        let reloaded = Leases::new(backend).unwrap();
+        assert_eq!(reloaded.get(address), Some(lease.clone()));
+        assert_eq!(reloaded.remove(address), Ok(Some(lease)));
+        assert_eq!(reloaded.get(address), None);
+    }
+
+    #[test]
+    fn test_isc_mirror() {
+        let dir = std::env::temp_dir().join(format!("fp_core_mirror_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dhcpd.leases");
+        let mut leases = Leases::new(MemoryBackend::new()).unwrap();
+        leases.set_isc_writer(IscLeaseWriter::open(&path).unwrap());
+        let read = || parse_leases(&std::fs::read_to_string(&path).unwrap()).unwrap();
+
+        let client = MacAddress::new([0xaa, 0, 0, 0, 0, 1]);
+        let expiration = SystemTime::now() + Duration::from_secs(60);
+        let mut lease = Lease::new(client, Ipv4Addr::new(10, 0, 0, 10), expiration);
+        lease.set_hostname(String::from("host"));
+        lease.starts -= Duration::from_secs(3600);
+        leases.grant(lease.clone()).unwrap();
+        leases
+            .grant(Lease::new(client, Ipv4Addr::new(10, 0, 0, 11), expiration))
+            .unwrap();
+        leases.remove(Ipv4Addr::new(10, 0, 0, 11)).unwrap();
+        let written = read();
+        assert_eq!(written.len(), 2);
+        assert_eq!(written[0].client_hostname.as_deref(), Some("host"));
+        assert_eq!(
+            written[0].ends,
+            Some(utc(expiration).replace_nanosecond(0).unwrap())
+        );
+        assert!(!written[1].is_active());
+
+        leases.sync().unwrap();
+        let written = read();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].hardware_address, Some(client));
+        assert_eq!(
+            written[0].starts,
+            Some(utc(lease.starts).replace_nanosecond(0).unwrap())
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,4 +1,30 @@
+pub mod allocator;
+pub mod batch;
+pub mod builder;
+pub mod classes;
+pub mod client_id;
+pub mod config;
+pub mod counters;
+pub mod ddns;
+pub mod dedup;
 pub mod errors;
+pub mod events;
+pub mod failover;
+pub mod handle;
+pub mod history;
+pub mod leases;
+pub mod options;
 pub mod packet;
+pub mod processor;
+pub mod pxe;
+pub mod queue;
+pub mod reaper;
+pub mod reload;
+pub mod reply;
+pub mod reservations;
+pub mod retry;
+pub mod scaling;
+pub mod scope;
 pub mod state;
 pub mod state_switcher;
+pub mod tap;
@@ -0,0 +1,898 @@
+//! Encoding of option values.
+//!
+//! Options are carried as raw values, by option code. This
+//! module converts the values whose encoding is error-prone
+//! to and from their typed representation, and encodes the
+//! options field of a packet, splitting and concatenating
+//! long options as described in RFC 3396.
+//!
+//! Option maps are shown to humans, in logs or with `dbg!`,
+//! through [`DisplayOptions`].
+//!
+//! Responses only carry the options their client asked for, in
+//! the order it asked for them, within the size it accepts: see
+//! [`response_options`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Display},
+    net::Ipv4Addr,
+};
+
+use super::{
+    classes::RELAY_AGENT_OPTION,
+    config::{LEASE_TIME_OPTION, RAPID_COMMIT_OPTION, REBINDING_TIME_OPTION, RENEWAL_TIME_OPTION},
+    errors::{OptionError, ParseError},
+};
+
+/// Code of the vendor-specific information option
+pub const VENDOR_SPECIFIC_OPTION: u8 = 43;
+/// Code of the message type option
+pub const MESSAGE_TYPE_OPTION: u8 = 53;
+/// Code of the server identifier option
+pub const SERVER_ID_OPTION: u8 = 54;
+/// Code of the parameter request list option
+pub const PARAMETER_REQUEST_OPTION: u8 = 55;
+/// Code of the maximum message size option
+pub const MAX_MESSAGE_SIZE_OPTION: u8 = 57;
+/// Code of the client FQDN option
+pub const CLIENT_FQDN_OPTION: u8 = 81;
+/// Code of the domain search option
+pub const DOMAIN_SEARCH_OPTION: u8 = 119;
+
+/// Length of the fixed BOOTP header, preceding the options field
+pub const HEADER_LEN: usize = 236;
+/// Magic cookie starting the options field of DHCP packets
+pub const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Size of the messages every client accepts, IP and UDP headers included
+pub const MIN_MESSAGE_SIZE: usize = 576;
+/// Length of the vendor extensions field of BOOTP messages,
+/// which starts with the magic cookie
+pub const BOOTP_VENDOR_LEN: usize = 64;
+/// Codes of the DHCP extensions of RFC 2132, which are not sent to BOOTP clients
+const DHCP_EXTENSIONS: std::ops::RangeInclusive<u8> = 50..=61;
+/// Length of the IP and UDP headers preceding the BOOTP header
+const IP_UDP_HEADER_LEN: usize = 28;
+
+/// Options sent in every response carrying them, whether requested or not
+const REQUIRED_OPTIONS: [u8; 5] = [
+    MESSAGE_TYPE_OPTION,
+    SERVER_ID_OPTION,
+    LEASE_TIME_OPTION,
+    RENEWAL_TIME_OPTION,
+    REBINDING_TIME_OPTION,
+];
+
+/// Code of the pad option
+pub const PAD_OPTION: u8 = 0;
+/// Code of the end option
+pub const END_OPTION: u8 = 255;
+
+/// Maximum length of an encoded domain name
+const MAX_NAME_LEN: usize = 255;
+/// Maximum length of a label
+const MAX_LABEL_LEN: usize = 63;
+/// Highest offset a compression pointer can reach
+const MAX_POINTER: usize = 0x3fff;
+
+/// Returns the options of a raw DHCP packet, following the magic cookie
+///
+/// Fails on packets shorter than the BOOTP header, and on packets which
+/// don't carry the magic cookie, so that non-DHCP traffic is rejected
+/// before its options are parsed.
+///
+/// # Examples:
+///
+/// ```
+/// fn try_from_raw_bytes(raw_data: &[u8]) -> Result<Self, ParseError> {
+///     let options = decode_options(options_field(raw_data)?)?;
+///     ...
+/// }
+/// ```
+pub fn options_field(raw: &[u8]) -> Result<&[u8], ParseError> {
+    if raw.len() < HEADER_LEN + MAGIC_COOKIE.len() {
+        return Err(ParseError::TooShort(HEADER_LEN + MAGIC_COOKIE.len()));
+    }
+    let (cookie, options) = raw[HEADER_LEN..].split_at(MAGIC_COOKIE.len());
+    match cookie == MAGIC_COOKIE {
+        true => Ok(options),
+        false => Err(ParseError::InvalidMagicCookie(cookie.try_into().unwrap())),
+    }
+}
+
+/// Encodes the options field of a packet, terminated by the end option
+///
+/// Values longer than 255 bytes are split across several
+/// consecutive instances of their option.
+///
+/// # Examples:
+///
+/// ```
+/// let mut options = BTreeMap::new();
+/// options.insert(DOMAIN_SEARCH_OPTION, encode_domain_search(&search_list)?);
+/// let field = encode_options(&options);
+/// ```
+pub fn encode_options(options: &BTreeMap<u8, Vec<u8>>) -> Vec<u8> {
+    let mut data = encode_tlvs(options);
+    data.push(END_OPTION);
+    data
+}
+
+/// Encodes the options field of a response, keeping the
+/// order of `options`, terminated by the end option
+pub fn encode_ordered_options(options: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut data = encode_tlvs(options.iter().map(|(code, value)| (code, value)));
+    data.push(END_OPTION);
+    data
+}
+
+/// Returns the options of `options` to send in response to
+/// a request with the given options, in the order to send them
+///
+/// Required options come first, then the options of the parameter
+/// request list in the client's order, then the relay agent
+/// information, which RFC 3046 requires to be last. Without
+/// parameter request list, every option is sent. Options which
+/// don't fit in the maximum message size of the client once the
+/// options before them were added are dropped.
+///
+/// # Examples:
+///
+/// ```
+/// let options = response_options(&subnet_options, &attributes.options);
+/// let field = encode_ordered_options(&options);
+/// ```
+pub fn response_options(
+    options: &BTreeMap<u8, Vec<u8>>,
+    request: &BTreeMap<u8, Vec<u8>>,
+) -> Vec<(u8, Vec<u8>)> {
+    let optional: Vec<u8> = match request.get(&PARAMETER_REQUEST_OPTION) {
+        Some(requested) => requested.clone(),
+        None => options.keys().copied().collect(),
+    };
+    let mut selected: Vec<u8> = REQUIRED_OPTIONS
+        .into_iter()
+        .filter(|code| options.contains_key(code))
+        .collect();
+    let mut size = selected
+        .iter()
+        .chain(&[RELAY_AGENT_OPTION])
+        .filter_map(|code| options.get(code))
+        .map(|value| encoded_len(value))
+        .sum::<usize>();
+
+    let max_size = request
+        .get(&MAX_MESSAGE_SIZE_OPTION)
+        .and_then(|value| Some(u16::from_be_bytes(value.as_slice().try_into().ok()?)))
+        .map_or(MIN_MESSAGE_SIZE, |size| {
+            (size as usize).max(MIN_MESSAGE_SIZE)
+        });
+    //The end option is always sent
+    let budget = max_size - IP_UDP_HEADER_LEN - HEADER_LEN - MAGIC_COOKIE.len() - 1;
+    for code in optional {
+        if selected.contains(&code) || code == RELAY_AGENT_OPTION {
+            continue;
+        }
+        let Some(value) = options.get(&code) else {
+            continue;
+        };
+        if size + encoded_len(value) > budget {
+            log::debug!("Option {} does not fit in the response, dropping it", code);
+            continue;
+        }
+        size += encoded_len(value);
+        selected.push(code);
+    }
+    if options.contains_key(&RELAY_AGENT_OPTION) {
+        selected.push(RELAY_AGENT_OPTION);
+    }
+    selected
+        .into_iter()
+        .map(|code| (code, options[&code].clone()))
+        .collect()
+}
+
+/// Returns whether a request with the given options comes
+/// from a legacy BOOTP client, which sends no message type
+pub fn is_bootp(request: &BTreeMap<u8, Vec<u8>>) -> bool {
+    !request.contains_key(&MESSAGE_TYPE_OPTION)
+}
+
+/// Returns the options of `options` to send to a BOOTP client,
+/// in the order to send them
+///
+/// The DHCP extensions (options 50 to 61, and rapid commit) are
+/// dropped, and options are kept in code order as long as they fit
+/// in the vendor extensions field.
+pub fn bootp_options(options: &BTreeMap<u8, Vec<u8>>) -> Vec<(u8, Vec<u8>)> {
+    //The end option is always sent
+    let budget = BOOTP_VENDOR_LEN - MAGIC_COOKIE.len() - 1;
+    let mut size = 0;
+    let mut selected = vec![];
+    for (&code, value) in options {
+        if DHCP_EXTENSIONS.contains(&code) || code == RAPID_COMMIT_OPTION {
+            continue;
+        }
+        if size + encoded_len(value) > budget {
+            log::debug!(
+                "Option {} does not fit in the BOOTP reply, dropping it",
+                code
+            );
+            continue;
+        }
+        size += encoded_len(value);
+        selected.push((code, value.clone()));
+    }
+    selected
+}
+
+/// Returns the length of the encoded option with the given value
+fn encoded_len(value: &[u8]) -> usize {
+    value.len() + 2 * value.len().div_ceil(u8::MAX as usize).max(1)
+}
+
+/// Encodes code/length/value items, skipping the pad and end codes
+fn encode_tlvs<'a>(options: impl IntoIterator<Item = (&'a u8, &'a Vec<u8>)>) -> Vec<u8> {
+    let mut data = vec![];
+    for (&code, value) in options {
+        if code == PAD_OPTION || code == END_OPTION {
+            continue;
+        }
+        if value.is_empty() {
+            data.extend_from_slice(&[code, 0]);
+        }
+        for chunk in value.chunks(u8::MAX as usize) {
+            data.extend_from_slice(&[code, chunk.len() as u8]);
+            data.extend_from_slice(chunk);
+        }
+    }
+    data
+}
+
+/// Decodes the options field of a packet, concatenating
+/// the values of the instances of a same option
+///
+/// Decoding stops at the end option, or at the end of the field.
+pub fn decode_options(data: &[u8]) -> Result<BTreeMap<u8, Vec<u8>>, OptionError> {
+    let mut options: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
+    let mut data = data;
+    loop {
+        match data {
+            [] | [END_OPTION, ..] => break,
+            [PAD_OPTION, rest @ ..] => data = rest,
+            [code, len, rest @ ..] => {
+                let value = rest.get(..*len as usize).ok_or(OptionError::Truncated)?;
+                options.entry(*code).or_default().extend_from_slice(value);
+                data = &rest[*len as usize..];
+            }
+            [_] => return Err(OptionError::Truncated),
+        }
+    }
+    Ok(options)
+}
+
+/// Sub-options of the vendor-specific information option
+///
+/// Their meaning depends on the vendor of the client, identified
+/// by its vendor class identifier, so they are usually set on the
+/// [`Class`](super::classes::Class) of the vendor.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VendorOptions {
+    suboptions: BTreeMap<u8, Vec<u8>>,
+}
+
+impl VendorOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the value of a vendor-specific information option
+    pub fn parse(value: &[u8]) -> Result<Self, OptionError> {
+        Ok(Self {
+            suboptions: decode_options(value)?,
+        })
+    }
+
+    /// Sets sub-option `code` to the given raw value
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut vendor = VendorOptions::new();
+    /// //Controller address of the access points
+    /// vendor.set_suboption(241, Ipv4Addr::new(10, 0, 0, 2).octets().to_vec());
+    /// access_points.set_vendor_options(&vendor);
+    /// ```
+    pub fn set_suboption(&mut self, code: u8, value: Vec<u8>) {
+        self.suboptions.insert(code, value);
+    }
+
+    /// Returns the raw value of sub-option `code`, if set
+    pub fn suboption(&self, code: u8) -> Option<&[u8]> {
+        self.suboptions.get(&code).map(Vec::as_slice)
+    }
+
+    /// Encodes the sub-options as the value of the vendor-specific information option
+    pub fn encode(&self) -> Vec<u8> {
+        encode_tlvs(&self.suboptions)
+    }
+}
+
+/// Encodes a domain search list (RFC 3397), compressing the
+/// suffixes shared with previous names of the list
+///
+/// # Examples:
+///
+/// ```
+/// let value = encode_domain_search(&[String::from("eng.example.com"), String::from("example.com")])?;
+/// options.insert(DOMAIN_SEARCH_OPTION, value);
+/// ```
+pub fn encode_domain_search(names: &[String]) -> Result<Vec<u8>, OptionError> {
+    let mut data = vec![];
+    let mut suffixes: HashMap<String, usize> = HashMap::new();
+    for name in names {
+        let labels = labels(name)?;
+        let mut pointer = None;
+        for (i, label) in labels.iter().enumerate() {
+            let suffix = labels[i..].join(".").to_ascii_lowercase();
+            if let Some(&offset) = suffixes.get(&suffix) {
+                pointer = Some(offset);
+                break;
+            }
+            if data.len() <= MAX_POINTER {
+                suffixes.insert(suffix, data.len());
+            }
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        match pointer {
+            Some(offset) => data.extend_from_slice(&(0xc000 | offset as u16).to_be_bytes()),
+            None => data.push(0),
+        }
+    }
+    Ok(data)
+}
+
+/// Encodes a domain name in the uncompressed wire format of DNS
+pub fn encode_name(name: &str) -> Result<Vec<u8>, OptionError> {
+    let mut data = vec![];
+    for label in labels(name)? {
+        data.push(label.len() as u8);
+        data.extend_from_slice(label.as_bytes());
+    }
+    data.push(0);
+    Ok(data)
+}
+
+/// Returns the labels of `name`, checking their length
+fn labels(name: &str) -> Result<Vec<&str>, OptionError> {
+    let labels: Vec<&str> = name.strip_suffix('.').unwrap_or(name).split('.').collect();
+    if labels
+        .iter()
+        .any(|label| label.is_empty() || label.len() > MAX_LABEL_LEN)
+    {
+        return Err(OptionError::InvalidLabel(name.to_string()));
+    }
+    if labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1 > MAX_NAME_LEN {
+        return Err(OptionError::NameTooLong(name.to_string()));
+    }
+    Ok(labels)
+}
+
+/// Decodes a domain search list (RFC 3397), following compression pointers
+pub fn decode_domain_search(data: &[u8]) -> Result<Vec<String>, OptionError> {
+    let mut names = vec![];
+    let mut position = 0;
+    while position < data.len() {
+        let (name, next) = decode_name(data, position)?;
+        names.push(name);
+        position = next;
+    }
+    Ok(names)
+}
+
+/// Decodes the name starting at `start`, returning it
+/// with the position following its encoding
+fn decode_name(data: &[u8], start: usize) -> Result<(String, usize), OptionError> {
+    let mut labels: Vec<String> = vec![];
+    let mut len = 1;
+    let mut position = start;
+    let mut next = None;
+    //Pointers must go backward, so following them always ends
+    let mut limit = start;
+    loop {
+        let &byte = data.get(position).ok_or(OptionError::Truncated)?;
+        match byte {
+            0 => break,
+            byte if byte & 0xc0 == 0xc0 => {
+                let &low = data.get(position + 1).ok_or(OptionError::Truncated)?;
+                let target = usize::from(u16::from_be_bytes([byte & 0x3f, low]));
+                if target >= limit {
+                    return Err(OptionError::InvalidPointer(position));
+                }
+                next.get_or_insert(position + 2);
+                limit = target;
+                position = target;
+            }
+            byte if byte as usize <= MAX_LABEL_LEN => {
+                let label = data
+                    .get(position + 1..position + 1 + byte as usize)
+                    .ok_or(OptionError::Truncated)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                len += label.len() + 1;
+                if len > MAX_NAME_LEN {
+                    return Err(OptionError::NameTooLong(labels.join(".")));
+                }
+                position += label.len() + 1;
+            }
+            _ => return Err(OptionError::InvalidLabel(labels.join("."))),
+        }
+    }
+    Ok((labels.join("."), next.unwrap_or(position + 1)))
+}
+
+/// Value of the message type option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl MessageType {
+    /// Returns the message type with the given code, if known
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::Discover),
+            2 => Some(Self::Offer),
+            3 => Some(Self::Request),
+            4 => Some(Self::Decline),
+            5 => Some(Self::Ack),
+            6 => Some(Self::Nak),
+            7 => Some(Self::Release),
+            8 => Some(Self::Inform),
+            _ => None,
+        }
+    }
+}
+
+impl Display for MessageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Discover => "DHCPDISCOVER",
+            Self::Offer => "DHCPOFFER",
+            Self::Request => "DHCPREQUEST",
+            Self::Decline => "DHCPDECLINE",
+            Self::Ack => "DHCPACK",
+            Self::Nak => "DHCPNAK",
+            Self::Release => "DHCPRELEASE",
+            Self::Inform => "DHCPINFORM",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Returns the message type set in `options`, if any and known
+///
+/// # Examples:
+///
+/// ```
+/// let options = decode_options(options_field(packet.to_raw_bytes())?)?;
+/// if message_type(&options) == Some(MessageType::Discover) {
+///     set_message_type(&mut reply_options, MessageType::Offer);
+/// }
+/// ```
+pub fn message_type(options: &BTreeMap<u8, Vec<u8>>) -> Option<MessageType> {
+    match options.get(&MESSAGE_TYPE_OPTION)?.as_slice() {
+        [code] => MessageType::from_code(*code),
+        _ => None,
+    }
+}
+
+/// Sets the message type option of `options`
+pub fn set_message_type(options: &mut BTreeMap<u8, Vec<u8>>, message_type: MessageType) {
+    options.insert(MESSAGE_TYPE_OPTION, vec![message_type as u8]);
+}
+
+/// How the value of an option is shown
+enum ValueFormat {
+    Addresses,
+    Text,
+    Seconds,
+    MessageType,
+    Codes,
+    Hex,
+}
+
+/// Returns the name of option `code`, and how its value is shown
+fn describe(code: u8) -> (Option<&'static str>, ValueFormat) {
+    use ValueFormat::*;
+    let (name, format) = match code {
+        1 => ("subnet mask", Addresses),
+        3 => ("router", Addresses),
+        6 => ("domain name server", Addresses),
+        12 => ("hostname", Text),
+        15 => ("domain name", Text),
+        28 => ("broadcast address", Addresses),
+        42 => ("ntp servers", Addresses),
+        43 => ("vendor-specific information", Hex),
+        50 => ("requested address", Addresses),
+        51 => ("lease time", Seconds),
+        53 => ("message type", MessageType),
+        54 => ("server identifier", Addresses),
+        55 => ("parameter request list", Codes),
+        56 => ("message", Text),
+        57 => ("maximum message size", Hex),
+        58 => ("renewal time", Seconds),
+        59 => ("rebinding time", Seconds),
+        60 => ("vendor class identifier", Text),
+        61 => ("client identifier", Hex),
+        66 => ("tftp server", Text),
+        67 => ("bootfile", Text),
+        80 => ("rapid commit", Hex),
+        81 => ("client fqdn", Hex),
+        82 => ("relay agent information", Hex),
+        119 => ("domain search", Hex),
+        _ => return (None, Hex),
+    };
+    (Some(name), format)
+}
+
+/// Formats the value of option `code` for humans
+///
+/// Values which can't be decoded as expected are shown in hexadecimal.
+pub fn format_option(code: u8, value: &[u8]) -> String {
+    let hex = || {
+        value
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(":")
+    };
+    match describe(code).1 {
+        ValueFormat::Addresses if !value.is_empty() && value.len().is_multiple_of(4) => value
+            .chunks(4)
+            .map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]).to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        ValueFormat::Text => match std::str::from_utf8(value) {
+            Ok(text) if !text.chars().any(char::is_control) => format!("{:?}", text),
+            _ => hex(),
+        },
+        ValueFormat::Seconds => match <[u8; 4]>::try_from(value) {
+            Ok(seconds) => format!("{}s", u32::from_be_bytes(seconds)),
+            Err(_) => hex(),
+        },
+        ValueFormat::MessageType => match value {
+            [code] => MessageType::from_code(*code).map_or_else(hex, |kind| kind.to_string()),
+            _ => hex(),
+        },
+        ValueFormat::Codes => value
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => hex(),
+    }
+}
+
+/// Human-readable view of an option map
+///
+/// Both `Display`, one option per line, and `Debug`, as a map,
+/// show the name of known options and their decoded value.
+///
+/// # Examples:
+///
+/// ```
+/// log::trace!("Received options:\n{}", DisplayOptions(&attributes.options));
+/// //option 53 (message type): DHCPDISCOVER
+/// //option 55 (parameter request list): 1, 3, 6
+/// ```
+pub struct DisplayOptions<'a>(pub &'a BTreeMap<u8, Vec<u8>>);
+
+impl Display for DisplayOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (&code, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match describe(code).0 {
+                Some(name) => write!(f, "option {} ({}): ", code, name)?,
+                None => write!(f, "option {}: ", code)?,
+            }
+            write!(f, "{}", format_option(code, value))?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for DisplayOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        /// Writes a string without quoting it
+        struct Raw(String);
+        impl Debug for Raw {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+        f.debug_map()
+            .entries(self.0.iter().map(|(&code, value)| {
+                let key = match describe(code).0 {
+                    Some(name) => format!("{} ({})", code, name),
+                    None => code.to_string(),
+                };
+                (Raw(key), Raw(format_option(code, value)))
+            }))
+            .finish()
+    }
+}
+
+/// Value of the client FQDN option (RFC 4702)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFqdn {
+    pub flags: u8,
+    /// Fully qualified domain name, or a single label
+    /// the server must complete with its domain
+    pub domain_name: String,
+}
+
+impl ClientFqdn {
+    /// The client asks the server to update its A record
+    pub const SERVER_UPDATE: u8 = 0x01;
+    /// The server overrode the preference of the client
+    pub const OVERRIDE: u8 = 0x02;
+    /// The domain name is in the wire format of DNS
+    pub const ENCODED: u8 = 0x04;
+    /// The server must not update any record
+    pub const NO_UPDATE: u8 = 0x08;
+
+    pub fn new(flags: u8, domain_name: String) -> Self {
+        Self { flags, domain_name }
+    }
+
+    /// Parses the raw value of the option
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// if let Some(value) = options.get(&CLIENT_FQDN_OPTION) {
+    ///     let fqdn = ClientFqdn::parse(value)?;
+    ///     ddns.register(&fqdn.domain_name, address, lease_time)?;
+    /// }
+    /// ```
+    pub fn parse(data: &[u8]) -> Result<Self, OptionError> {
+        let [flags, _rcode1, _rcode2, name @ ..] = data else {
+            return Err(OptionError::Truncated);
+        };
+        let domain_name = match flags & Self::ENCODED {
+            0 => String::from_utf8_lossy(name).into_owned(),
+            _ if name.is_empty() => String::new(),
+            //A partial name isn't terminated by the root label
+            _ => {
+                let mut terminated = name.to_vec();
+                if !terminated.ends_with(&[0]) {
+                    terminated.push(0);
+                }
+                decode_name(&terminated, 0)?.0
+            }
+        };
+        Ok(Self {
+            flags: *flags,
+            domain_name,
+        })
+    }
+
+    /// Returns the raw value of the option, with the
+    /// domain name encoded as requested by the flags
+    pub fn encode(&self) -> Result<Vec<u8>, OptionError> {
+        //RCODE1 and RCODE2 are deprecated, and set to 255 by servers
+        let mut data = vec![self.flags, 255, 255];
+        match self.flags & Self::ENCODED {
+            _ if self.domain_name.is_empty() => (),
+            0 => data.extend_from_slice(self.domain_name.as_bytes()),
+            _ => data.extend(encode_name(&self.domain_name)?),
+        }
+        Ok(data)
+    }
+
+    /// Returns whether the server is asked to update the A record
+    pub fn server_update(&self) -> bool {
+        self.flags & Self::SERVER_UPDATE != 0
+    }
+
+    /// Returns whether the server must not update any record
+    pub fn no_update(&self) -> bool {
+        self.flags & Self::NO_UPDATE != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_bootp_options() {
+        let mut options = BTreeMap::new();
+        options.insert(1, vec![255, 255, 255, 0]);
+        options.insert(LEASE_TIME_OPTION, vec![0, 0, 14, 16]);
+        options.insert(MESSAGE_TYPE_OPTION, vec![2]);
+        options.insert(15, vec![b'a'; 55]);
+        options.insert(42, vec![10, 0, 0, 1]);
+        assert!(is_bootp(&BTreeMap::new()));
+        assert!(!is_bootp(&options));
+        let codes: Vec<u8> = bootp_options(&options)
+            .into_iter()
+            .map(|(code, _)| code)
+            .collect();
+        //The domain name doesn't fit with the other options
+        assert_eq!(codes, vec![1, 42]);
+    }
+
+    #[test]
+    fn test_message_type() {
+        let mut options = BTreeMap::new();
+        assert_eq!(message_type(&options), None);
+        set_message_type(&mut options, MessageType::Ack);
+        assert_eq!(options[&MESSAGE_TYPE_OPTION], vec![5]);
+        assert_eq!(message_type(&options), Some(MessageType::Ack));
+        assert_eq!(MessageType::Ack.to_string(), "DHCPACK");
+        options.insert(MESSAGE_TYPE_OPTION, vec![9]);
+        assert_eq!(message_type(&options), None);
+    }
+
+    #[test]
+    fn test_display_options() {
+        let mut options = BTreeMap::new();
+        options.insert(MESSAGE_TYPE_OPTION, vec![1]);
+        options.insert(3, vec![10, 0, 0, 1, 10, 0, 0, 2]);
+        options.insert(12, b"laptop".to_vec());
+        options.insert(LEASE_TIME_OPTION, 3600u32.to_be_bytes().to_vec());
+        options.insert(PARAMETER_REQUEST_OPTION, vec![1, 3]);
+        options.insert(224, vec![0xde, 0xad]);
+        assert_eq!(
+            DisplayOptions(&options).to_string(),
+            "option 3 (router): 10.0.0.1, 10.0.0.2\n\
+             option 12 (hostname): \"laptop\"\n\
+             option 51 (lease time): 3600s\n\
+             option 53 (message type): DHCPDISCOVER\n\
+             option 55 (parameter request list): 1, 3\n\
+             option 224: de:ad"
+        );
+        assert!(
+            format!("{:?}", DisplayOptions(&options)).contains("53 (message type): DHCPDISCOVER")
+        );
+        assert_eq!(format_option(MESSAGE_TYPE_OPTION, &[42]), "2a");
+        assert_eq!(format_option(1, &[255, 255]), "ff:ff");
+    }
+
+    #[test]
+    fn test_response_options() {
+        let mut options = BTreeMap::new();
+        for code in [1, 3, 6, 15, MESSAGE_TYPE_OPTION, LEASE_TIME_OPTION] {
+            options.insert(code, vec![code; 4]);
+        }
+        options.insert(RELAY_AGENT_OPTION, vec![1, 1, 7]);
+        options.insert(DOMAIN_SEARCH_OPTION, vec![0; 400]);
+        let codes = |request: &BTreeMap<u8, Vec<u8>>| {
+            response_options(&options, request)
+                .into_iter()
+                .map(|(code, _)| code)
+                .collect::<Vec<_>>()
+        };
+
+        let mut request = BTreeMap::new();
+        assert_eq!(codes(&request), vec![53, 51, 1, 3, 6, 15, 82]);
+        request.insert(PARAMETER_REQUEST_OPTION, vec![6, 3, 51, 42, 82]);
+        assert_eq!(codes(&request), vec![53, 51, 6, 3, 82]);
+
+        //A large option doesn't fit the minimum message size
+        request.insert(PARAMETER_REQUEST_OPTION, vec![119, 1]);
+        assert_eq!(codes(&request), vec![53, 51, 1, 82]);
+        request.insert(MAX_MESSAGE_SIZE_OPTION, 1500u16.to_be_bytes().to_vec());
+        assert_eq!(codes(&request), vec![53, 51, 119, 1, 82]);
+
+        let field = encode_ordered_options(&response_options(&options, &BTreeMap::new()));
+        assert_eq!(field[..6], [53, 4, 53, 53, 53, 53]);
+        assert_eq!(field[field.len() - 6..], [82, 3, 1, 1, 7, END_OPTION]);
+    }
+
+    #[test]
+    fn test_vendor_options() {
+        let mut vendor = VendorOptions::new();
+        vendor.set_suboption(241, vec![10, 0, 0, 2]);
+        vendor.set_suboption(2, b"sip.lan".to_vec());
+        let value = vendor.encode();
+        assert_eq!(value[..9], [2, 7, b's', b'i', b'p', b'.', b'l', b'a', b'n']);
+        assert_eq!(value[9..], [241, 4, 10, 0, 0, 2]);
+        assert_eq!(VendorOptions::parse(&value), Ok(vendor.clone()));
+        assert_eq!(
+            VendorOptions::parse(&[1, 1, 7, 255]).unwrap().suboption(1),
+            Some(&[7][..])
+        );
+        assert_eq!(
+            VendorOptions::parse(&[1, 2, 7]),
+            Err(OptionError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_domain_search() {
+        let names = vec![
+            String::from("eng.apple.com"),
+            String::from("marketing.apple.com"),
+            String::from("apple.com"),
+        ];
+        let data = encode_domain_search(&names).unwrap();
+        //Example of RFC 3397, section 2
+        assert_eq!(
+            data,
+            b"\x03eng\x05apple\x03com\x00\x09marketing\xc0\x04\xc0\x04".to_vec()
+        );
+        assert_eq!(decode_domain_search(&data), Ok(names));
+
+        assert_eq!(
+            encode_domain_search(&[String::from("a..com")]),
+            Err(OptionError::InvalidLabel(String::from("a..com")))
+        );
+        assert_eq!(
+            decode_domain_search(b"\x03eng\xc0\x00"),
+            Err(OptionError::InvalidPointer(4))
+        );
+        assert_eq!(
+            decode_domain_search(b"\x03eng\x05app"),
+            Err(OptionError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_long_options() {
+        let mut options = BTreeMap::new();
+        options.insert(43, vec![7; 300]);
+        options.insert(80, vec![]);
+        let data = encode_options(&options);
+        assert_eq!(data.len(), 2 + 255 + 2 + 45 + 2 + 1);
+        assert_eq!(&data[..2], &[43, 255]);
+        assert_eq!(&data[257..259], &[43, 45]);
+        assert_eq!(decode_options(&data), Ok(options));
+
+        let decoded = decode_options(&[0, 1, 1, 2, 1, 1, 3, 255, 1]).unwrap();
+        assert_eq!(decoded[&1], vec![2, 3]);
+        assert_eq!(decode_options(&[1, 4, 2]), Err(OptionError::Truncated));
+    }
+
+    #[test]
+    fn test_options_field() {
+        let mut raw = vec![0; HEADER_LEN];
+        assert_eq!(options_field(&raw), Err(ParseError::TooShort(240)));
+        raw.extend_from_slice(b"GET ");
+        assert_eq!(
+            options_field(&raw),
+            Err(ParseError::InvalidMagicCookie(*b"GET "))
+        );
+        raw.truncate(HEADER_LEN);
+        raw.extend_from_slice(&MAGIC_COOKIE);
+        raw.extend_from_slice(&[53, 1, 1, 255]);
+        assert_eq!(options_field(&raw), Ok(&[53, 1, 1, 255][..]));
+    }
+
+    #[test]
+    fn test_client_fqdn() {
+        let fqdn = ClientFqdn::parse(b"\x05\x00\x00\x04host\x03lan\x00").unwrap();
+        assert_eq!(fqdn.domain_name, "host.lan");
+        assert!(fqdn.server_update());
+        assert_eq!(
+            fqdn.encode().unwrap(),
+            b"\x05\xff\xff\x04host\x03lan\x00".to_vec()
+        );
+        let fqdn = ClientFqdn::parse(b"\x08\x00\x00host").unwrap();
+        assert_eq!(fqdn.domain_name, "host");
+        assert!(fqdn.no_update());
+        assert_eq!(ClientFqdn::parse(b"\x00"), Err(OptionError::Truncated));
+    }
+}
@@ -5,30 +5,76 @@
 //! [`PacketContext`], which will be enriched by the
 //! [`Hook`] to create a valid output packet.
 
-use std::time::{Duration, SystemTime};
+use mac_address::MacAddress;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
 use uuid::Uuid;
 
-use super::state::PacketState;
+use super::{errors::ParseError, state::PacketState};
 
 pub trait PacketType: Clone {
     fn to_raw_bytes(&self) -> &[u8];
     fn empty() -> Self;
     fn from_raw_bytes(raw_data: &[u8]) -> Self;
+
+    /// Parses raw bytes, failing instead of panicking on malformed data
+    ///
+    /// Used by [`Input`] implementations, so that a malformed packet
+    /// is dropped instead of killing the input task. Types whose
+    /// parsing can fail must override it; the default implementation
+    /// calls `from_raw_bytes`.
+    ///
+    /// [`Input`]: super::state_switcher::Input
+    fn try_from_raw_bytes(raw_data: &[u8]) -> Result<Self, ParseError> {
+        Ok(Self::from_raw_bytes(raw_data))
+    }
+
+    /// Returns a key grouping the packets of the same kind, if any
+    ///
+    /// Used to count the packets of each kind in the [`Counters`],
+    /// e.g. by DHCP message type. The default implementation
+    /// returns `None`.
+    ///
+    /// [`Counters`]: super::counters::Counters
+    fn kind(&self) -> Option<u8> {
+        None
+    }
+}
+
+/// Where a packet was received from, and on which interface
+///
+/// Filled in by the [`Input`], each field being `None`
+/// when the input cannot tell.
+///
+/// [`Input`]: super::state_switcher::Input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketMetadata {
+    /// Address the packet was sent from
+    pub source: Option<SocketAddr>,
+    /// Address the packet was sent to, which may be a broadcast address
+    pub destination: Option<IpAddr>,
+    /// Index of the interface the packet was received on
+    pub interface: Option<u32>,
 }
 
 /// A `PacketContext` encapsulates two things:
 /// - An input packet, used to derive the [`PacketContext`]
 /// - An output packet, which is initially empty and is
-/// enriched with data through execution of [`Hook`]
+///   enriched with data through execution of [`Hook`]
 ///
 /// It is identified uniquely across the program using its [`Uuid`],
 /// and it holds a [`PacketState`]. Through [`Hook`] executions, it
 /// will undergo several successive state transitions.
-
 pub struct PacketContext<T: PacketType, U: PacketType> {
     time: SystemTime,
     id: Uuid,
     state: PacketState,
+    deadline: Option<Duration>,
+    metadata: PacketMetadata,
+    destination: Option<SocketAddr>,
+    link_address: Option<MacAddress>,
     input_packet: T,
     output_packet: U,
 }
@@ -148,6 +194,91 @@ impl<T: PacketType, U: PacketType> PacketContext<T, U> {
     pub fn lifetime(&self) -> Duration {
         SystemTime::now().duration_since(self.time).unwrap()
     }
+
+    /// Returns the time left before the [`PacketContext`]
+    /// exceeds its processing deadline, or `None` if
+    /// it has no deadline
+    ///
+    /// Long-running [`Hook`] should use it to bound their
+    /// own timeouts instead of blowing the overall budget.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let timeout = packet
+    ///     .remaining_time()
+    ///     .map_or(PROBE_TIMEOUT, |remaining| remaining.min(PROBE_TIMEOUT));
+    /// ```
+    pub fn remaining_time(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_sub(self.lifetime()))
+    }
+
+    /// Sets the processing deadline of the [`PacketContext`],
+    /// counted from its creation
+    pub(crate) fn set_deadline(&mut self, deadline: Duration) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Returns where the input packet was received from
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let relayed = packet.metadata().source.map(|source| source.port()) == Some(SERVER_PORT);
+    /// ```
+    pub fn metadata(&self) -> &PacketMetadata {
+        &self.metadata
+    }
+
+    /// Sets where the input packet was received from
+    pub fn set_metadata(&mut self, metadata: PacketMetadata) {
+        self.metadata = metadata;
+    }
+
+    /// Returns where the output packet will be sent
+    ///
+    /// Unless a [`Hook`] sets it, replies go back to the
+    /// source of the input packet, if the [`Input`] knows it.
+    ///
+    /// [`Input`]: super::state_switcher::Input
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.destination.or(self.metadata.source)
+    }
+
+    /// Sets where the output packet will be sent
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let addressing = ReplyAddressing::new(request.giaddr, request.ciaddr, request.flags);
+    /// packet.set_destination(addressing.destination(is_nak).into());
+    /// ```
+    pub fn set_destination(&mut self, destination: SocketAddr) {
+        self.destination = Some(destination);
+    }
+
+    /// Returns the hardware address the output packet
+    /// will be sent to at the link layer, if any
+    pub fn link_address(&self) -> Option<MacAddress> {
+        self.link_address
+    }
+
+    /// Sets the hardware address the output packet will be sent to,
+    /// by outputs able to send at the link layer
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let delivery = addressing.delivery(false, request.chaddr, offered);
+    /// packet.set_destination(delivery.destination().into());
+    /// if let Some(chaddr) = delivery.link_address() {
+    ///     packet.set_link_address(chaddr);
+    /// }
+    /// ```
+    pub fn set_link_address(&mut self, link_address: MacAddress) {
+        self.link_address = Some(link_address);
+    }
 }
 
 impl<T: PacketType, U: PacketType> From<T> for PacketContext<T, U> {
@@ -156,8 +287,49 @@ impl<T: PacketType, U: PacketType> From<T> for PacketContext<T, U> {
             time: SystemTime::now(),
             id: Uuid::new_v4(),
             state: PacketState::Received,
+            deadline: None,
+            metadata: PacketMetadata::default(),
+            destination: None,
+            link_address: None,
             input_packet: value,
             output_packet: U::empty(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct A {}
+
+    impl PacketType for A {
+        fn to_raw_bytes(&self) -> &[u8] {
+            &[]
+        }
+
+        fn empty() -> Self {
+            A {}
+        }
+
+        fn from_raw_bytes(_raw_data: &[u8]) -> Self {
+            A {}
+        }
+    }
+
+    #[test]
+    fn test_remaining_time() {
+        let mut context: PacketContext<A, A> = PacketContext::from(A {});
+        assert_eq!(context.remaining_time(), None);
+
+        context.set_deadline(Duration::from_secs(60));
+        let remaining = context.remaining_time().unwrap();
+        assert!(remaining <= Duration::from_secs(60));
+        assert!(remaining > Duration::from_secs(59));
+
+        context.set_deadline(Duration::ZERO);
+        assert_eq!(context.remaining_time(), Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,177 @@
+//! Processing of a single [`PacketContext`], shared by
+//! every entry point of the [`StateSwitcher`].
+//!
+//! A [`PacketProcessor`] walks a packet through each
+//! successive [`PacketState`], runs the associated [`Hook`]
+//! and dispatches the resulting output packet.
+//!
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+//! [`Hook`]: crate::hooks::hook_registry::Hook
+
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+use tokio::sync::broadcast;
+
+use crate::hooks::hook_registry::HookRegistry;
+
+use super::{
+    counters::Counters,
+    dedup::Deduplicator,
+    events::PipelineEvent,
+    packet::{PacketContext, PacketType},
+    retry::RetryPolicy,
+    state::PacketState,
+    state_switcher::{Output, StateCallback},
+    tap::PacketTap,
+};
+
+/// Reason why a packet did not make it
+/// through the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// A [`Hook`] holding the [`Fatal`] flag failed
+    ///
+    /// [`Hook`]: crate::hooks::hook_registry::Hook
+    /// [`Fatal`]: crate::hooks::flags::HookFlag::Fatal
+    HookFailure,
+    /// The packet exceeded its processing deadline
+    DeadlineExpired,
+    /// The [`Output`] failed to dispatch the packet
+    OutputFailure,
+    /// An identical packet was processed recently
+    Duplicate,
+}
+
+impl Display for DropReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HookFailure => write!(f, "fatal hook failure"),
+            Self::DeadlineExpired => write!(f, "deadline expired"),
+            Self::OutputFailure => write!(f, "output failure"),
+            Self::Duplicate => write!(f, "duplicate packet"),
+        }
+    }
+}
+
+/// Everything needed to process a single packet,
+/// cloned out of the [`StateSwitcher`] for each of them
+///
+/// [`StateSwitcher`]: super::state_switcher::StateSwitcher
+pub(crate) struct PacketProcessor<T: PacketType + Send + 'static, U: PacketType + Send + 'static> {
+    pub(crate) registry: Arc<HookRegistry<T, U>>,
+    pub(crate) output: Arc<Box<dyn Output<U>>>,
+    pub(crate) counters: Arc<Counters>,
+    pub(crate) tap: Option<Arc<PacketTap>>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) deadline: Option<Duration>,
+    pub(crate) on_enter: Option<Arc<StateCallback<T, U>>>,
+    pub(crate) on_exit: Option<Arc<StateCallback<T, U>>>,
+    pub(crate) events: broadcast::Sender<PipelineEvent>,
+    pub(crate) dedup: Option<Arc<Deduplicator<T>>>,
+}
+
+impl<T: PacketType + Send, U: PacketType + Send> PacketProcessor<T, U> {
+    /// Processes the given [`PacketContext`] and accounts
+    /// for its outcome in the [`Counters`]
+    ///
+    /// Returns the number of bytes sent, or the
+    /// reason why the packet was dropped.
+    pub(crate) async fn run(self, context: PacketContext<T, U>) -> Result<usize, DropReason> {
+        let id = context.id();
+        let counters = self.counters.clone();
+        let events = self.events.clone();
+        let _ = events.send(PipelineEvent::Received { id });
+
+        let duplicate = self
+            .dedup
+            .as_ref()
+            .is_some_and(|dedup| dedup.is_duplicate(context.get_input()));
+        let outcome = match duplicate {
+            true => Err(DropReason::Duplicate),
+            false => self.process(context).await,
+        };
+
+        match outcome {
+            Ok(bytes) => {
+                counters.record_sent();
+                let _ = events.send(PipelineEvent::Sent { id, bytes });
+            }
+            Err(reason) => {
+                counters.record_dropped();
+                let _ = events.send(PipelineEvent::Dropped { id, reason });
+            }
+        }
+        outcome
+    }
+
+    async fn process(self, mut context: PacketContext<T, U>) -> Result<usize, DropReason> {
+        if let Some(deadline) = self.deadline {
+            context.set_deadline(deadline);
+        }
+        let expired =
+            |context: &PacketContext<T, U>| context.remaining_time() == Some(Duration::ZERO);
+
+        for state in enum_iterator::all::<PacketState>().filter(|x| *x != PacketState::Failure) {
+            if expired(&context) {
+                return Err(DropReason::DeadlineExpired);
+            }
+            context.set_state(state);
+            let _ = self.events.send(PipelineEvent::StateEntered {
+                id: context.id(),
+                state,
+            });
+            if let Some(on_enter) = &self.on_enter {
+                on_enter(state, &context);
+            }
+            let ran = self.registry.run_hooks(&mut context);
+            if let Some(on_exit) = &self.on_exit {
+                on_exit(state, &context);
+            }
+            //Packets failing a fatal hook are not sent
+            if ran.is_err() {
+                return Err(DropReason::HookFailure);
+            }
+        }
+
+        if let Some(tap) = &self.tap {
+            tap.observe(&context);
+        }
+
+        let remaining = context.remaining_time();
+        let destination = context.destination();
+        let link_address = context.link_address();
+        let output_packet = context.drop();
+        let bytes_len = output_packet.to_raw_bytes().len();
+        let kind = output_packet.kind();
+        let output = self.output;
+        let dispatch = self.retry_policy.run(move || {
+            let output = output.clone();
+            let packet = output_packet.clone();
+            async move {
+                match (destination, link_address) {
+                    (Some(destination), Some(link_address)) => {
+                        output.send_to_link(packet, destination, link_address).await
+                    }
+                    (Some(destination), None) => output.send_to(packet, destination).await,
+                    (None, _) => output.send(packet).await,
+                }
+            }
+        });
+        let sent = match remaining {
+            Some(remaining) => tokio::time::timeout(remaining, dispatch)
+                .await
+                .map_err(|_| DropReason::DeadlineExpired)?,
+            None => dispatch.await,
+        };
+
+        if let (Ok(len), Some(kind)) = (&sent, kind) {
+            if *len == bytes_len {
+                self.counters.record_kind(kind);
+            }
+        }
+        match sent {
+            Ok(len) if len == bytes_len => Ok(len),
+            _ => Err(DropReason::OutputFailure),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Network boot of PXE clients.
+//!
+//! A [`BootImage`] tells a client where to fetch its boot file,
+//! through the `siaddr`, `sname` and `file` header fields and the
+//! TFTP server name (66) and bootfile name (67) options. The
+//! [`BootSelector`] picks the image matching the architecture a
+//! PXE client sends in its client system architecture option (93),
+//! so that BIOS and UEFI machines can boot from the same subnet.
+
+use std::{collections::BTreeMap, net::Ipv4Addr};
+
+use super::classes::{ClientAttributes, VENDOR_CLASS_OPTION};
+
+/// Code of the TFTP server name option
+pub const TFTP_SERVER_OPTION: u8 = 66;
+/// Code of the bootfile name option
+pub const BOOTFILE_OPTION: u8 = 67;
+/// Code of the client system architecture option
+pub const ARCHITECTURE_OPTION: u8 = 93;
+
+/// Length of the `sname` header field
+pub const SNAME_LEN: usize = 64;
+/// Length of the `file` header field
+pub const FILE_LEN: usize = 128;
+
+/// Client system architecture, as defined by RFC 4578 and the IANA registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Architecture {
+    Bios,
+    EfiIa32,
+    EfiByteCode,
+    EfiX64,
+    EfiArm32,
+    EfiArm64,
+    /// Any other architecture type
+    Other(u16),
+}
+
+impl From<u16> for Architecture {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => Self::Bios,
+            6 => Self::EfiIa32,
+            7 => Self::EfiByteCode,
+            9 => Self::EfiX64,
+            10 => Self::EfiArm32,
+            11 => Self::EfiArm64,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<Architecture> for u16 {
+    fn from(value: Architecture) -> Self {
+        match value {
+            Architecture::Bios => 0,
+            Architecture::EfiIa32 => 6,
+            Architecture::EfiByteCode => 7,
+            Architecture::EfiX64 => 9,
+            Architecture::EfiArm32 => 10,
+            Architecture::EfiArm64 => 11,
+            Architecture::Other(other) => other,
+        }
+    }
+}
+
+impl Architecture {
+    /// Returns the first architecture sent by the client, if any
+    pub fn of(attributes: &ClientAttributes) -> Option<Self> {
+        match attributes.option(ARCHITECTURE_OPTION)? {
+            [high, low, ..] => Some(Self::from(u16::from_be_bytes([*high, *low]))),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the architecture boots through UEFI
+    pub fn is_uefi(&self) -> bool {
+        !matches!(self, Self::Bios | Self::Other(_))
+    }
+}
+
+/// Returns whether the client identifies itself as a PXE client
+pub fn is_pxe_client(attributes: &ClientAttributes) -> bool {
+    attributes
+        .option(VENDOR_CLASS_OPTION)
+        .is_some_and(|vendor| vendor.starts_with(b"PXEClient"))
+}
+
+/// Boot file served to PXE clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootImage {
+    next_server: Ipv4Addr,
+    server_name: Option<String>,
+    file: String,
+}
+
+impl BootImage {
+    /// Creates the image `file`, fetched from the TFTP server `next_server`
+    pub fn new(next_server: Ipv4Addr, file: String) -> Self {
+        Self {
+            next_server,
+            server_name: None,
+            file,
+        }
+    }
+
+    /// Returns the address to set in the `siaddr` header field
+    pub fn next_server(&self) -> Ipv4Addr {
+        self.next_server
+    }
+
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Sends the host name of the TFTP server, in the `sname`
+    /// header field and the TFTP server name option
+    pub fn set_server_name(&mut self, server_name: String) {
+        self.server_name = Some(server_name);
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Returns the `sname` header field, null padded
+    /// and truncated to its fixed length
+    pub fn sname_field(&self) -> [u8; SNAME_LEN] {
+        padded(self.server_name.as_deref().unwrap_or_default())
+    }
+
+    /// Returns the `file` header field, null padded
+    /// and truncated to its fixed length
+    pub fn file_field(&self) -> [u8; FILE_LEN] {
+        padded(&self.file)
+    }
+
+    /// Returns the TFTP server name and bootfile name options, by option code
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// response.siaddr = image.next_server();
+    /// response.file = image.file_field();
+    /// response.options.extend(image.options());
+    /// ```
+    pub fn options(&self) -> BTreeMap<u8, Vec<u8>> {
+        let mut options = BTreeMap::from([(BOOTFILE_OPTION, self.file.as_bytes().to_vec())]);
+        if let Some(server_name) = &self.server_name {
+            options.insert(TFTP_SERVER_OPTION, server_name.as_bytes().to_vec());
+        }
+        options
+    }
+}
+
+fn padded<const N: usize>(value: &str) -> [u8; N] {
+    let mut field = [0; N];
+    //The last byte is kept as a terminating null
+    let len = value.len().min(N - 1);
+    field[..len].copy_from_slice(&value.as_bytes()[..len]);
+    field
+}
+
+/// Picks the [`BootImage`] of a PXE client based on its architecture
+#[derive(Debug, Clone, Default)]
+pub struct BootSelector {
+    images: BTreeMap<Architecture, BootImage>,
+    default: Option<BootImage>,
+}
+
+impl BootSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `image` to the clients of the given architecture
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut selector = BootSelector::new();
+    /// selector.set_image(Architecture::Bios, BootImage::new(tftp, String::from("pxelinux.0")));
+    /// selector.set_image(Architecture::EfiX64, BootImage::new(tftp, String::from("grubx64.efi")));
+    /// ```
+    pub fn set_image(&mut self, architecture: Architecture, image: BootImage) {
+        self.images.insert(architecture, image);
+    }
+
+    /// Serves `image` to the clients without an image for their architecture
+    pub fn set_default(&mut self, image: BootImage) {
+        self.default = Some(image);
+    }
+
+    /// Returns the image to serve to the client, if it is a PXE client
+    pub fn select(&self, attributes: &ClientAttributes) -> Option<&BootImage> {
+        if !is_pxe_client(attributes) {
+            return None;
+        }
+        Architecture::of(attributes)
+            .and_then(|architecture| self.images.get(&architecture))
+            .or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mac_address::MacAddress;
+
+    use super::*;
+
+    #[test]
+    fn test_boot_selection() {
+        let tftp = Ipv4Addr::new(10, 0, 0, 2);
+        let mut bios = BootImage::new(tftp, String::from("pxelinux.0"));
+        bios.set_server_name(String::from("tftp.lan"));
+        let uefi = BootImage::new(tftp, String::from("grubx64.efi"));
+        let mut selector = BootSelector::new();
+        selector.set_image(Architecture::Bios, bios.clone());
+        selector.set_image(Architecture::EfiX64, uefi.clone());
+
+        let mut client = ClientAttributes::new(MacAddress::new([0xaa, 0, 0, 0, 0, 1]));
+        client.set_option(ARCHITECTURE_OPTION, vec![0, 9]);
+        assert_eq!(selector.select(&client), None);
+        client.set_option(VENDOR_CLASS_OPTION, b"PXEClient:Arch:00009".to_vec());
+        assert_eq!(selector.select(&client), Some(&uefi));
+        client.set_option(ARCHITECTURE_OPTION, vec![0, 0]);
+        assert_eq!(selector.select(&client), Some(&bios));
+        client.set_option(ARCHITECTURE_OPTION, vec![0, 11]);
+        assert_eq!(selector.select(&client), None);
+
+        assert_eq!(&bios.sname_field()[..9], b"tftp.lan\0");
+        assert_eq!(bios.options()[&TFTP_SERVER_OPTION], b"tftp.lan");
+        assert_eq!(bios.options()[&BOOTFILE_OPTION], b"pxelinux.0");
+        assert!(Architecture::from(11).is_uefi());
+    }
+}
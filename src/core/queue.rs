@@ -0,0 +1,153 @@
+//! Bounded queue sitting between an [`Input`] and the
+//! packet processing tasks of a [`StateSwitcher`].
+//!
+//! When the queue is full, the configured [`OverflowPolicy`]
+//! decides which packet gets dropped, if any.
+//!
+//! [`Input`]: super::state_switcher::Input
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+
+use std::sync::Arc;
+
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    Mutex,
+};
+
+/// Behavior of the queue when a packet is pushed
+/// while it is already at full capacity
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Evict the oldest queued packet to make room
+    /// for the incoming one
+    DropOldest,
+    /// Discard the incoming packet
+    DropNewest,
+    /// Wait until room is available, which stops
+    /// reading from the [`Input`] in the meantime
+    ///
+    /// [`Input`]: super::state_switcher::Input
+    #[default]
+    Block,
+}
+
+pub type QueueReceiver<T> = Arc<Mutex<mpsc::Receiver<T>>>;
+
+/// Writing half of a packet queue, created
+/// through [`packet_queue`]
+///
+/// Once every `QueueSender` is dropped, the receiving
+/// half yields the remaining packets and then `None`.
+pub struct QueueSender<T> {
+    sender: mpsc::Sender<T>,
+    receiver: QueueReceiver<T>,
+    policy: OverflowPolicy,
+}
+
+/// Creates a bounded packet queue holding at most
+/// `capacity` packets, applying `policy` when full
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0
+///
+/// # Examples:
+///
+/// ```
+/// let (sender, receiver) = packet_queue(1024, OverflowPolicy::DropOldest);
+/// sender.push(packet).await;
+/// let packet = receiver.lock().await.recv().await;
+/// ```
+pub fn packet_queue<T>(
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> (QueueSender<T>, QueueReceiver<T>) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    let receiver = Arc::new(Mutex::new(receiver));
+    (
+        QueueSender {
+            sender,
+            receiver: receiver.clone(),
+            policy,
+        },
+        receiver,
+    )
+}
+
+impl<T> QueueSender<T> {
+    /// Pushes a packet at the end of the queue,
+    /// applying the [`OverflowPolicy`] if it is full
+    ///
+    /// Returns `false` if a packet had to be dropped
+    /// (either the incoming one or the oldest one),
+    /// or if the receiving half was dropped.
+    pub async fn push(&self, packet: T) -> bool {
+        let packet = match self.sender.try_send(packet) {
+            Ok(_) => return true,
+            Err(TrySendError::Closed(_)) => return false,
+            Err(TrySendError::Full(packet)) => packet,
+        };
+
+        match self.policy {
+            OverflowPolicy::Block => self.sender.send(packet).await.is_ok(),
+            OverflowPolicy::DropNewest => false,
+            OverflowPolicy::DropOldest => {
+                let evicted = self.receiver.lock().await.try_recv().is_ok();
+                self.sender.try_send(packet).is_ok() && !evicted
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drop_newest() {
+        let (sender, receiver) = packet_queue(1, OverflowPolicy::DropNewest);
+
+        assert!(sender.push(1).await);
+        assert!(!sender.push(2).await);
+        drop(sender);
+
+        let mut receiver = receiver.lock().await;
+        assert_eq!(receiver.recv().await, Some(1));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let (sender, receiver) = packet_queue(2, OverflowPolicy::DropOldest);
+
+        assert!(sender.push(1).await);
+        assert!(sender.push(2).await);
+        assert!(!sender.push(3).await);
+        drop(sender);
+
+        let mut receiver = receiver.lock().await;
+        assert_eq!(receiver.recv().await, Some(2));
+        assert_eq!(receiver.recv().await, Some(3));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let (sender, receiver) = packet_queue(1, OverflowPolicy::Block);
+
+        assert!(sender.push(1).await);
+        let consumer = tokio::spawn(async move {
+            let mut receiver = receiver.lock().await;
+            let mut received = vec![];
+            while let Some(packet) = receiver.recv().await {
+                received.push(packet);
+            }
+            received
+        });
+        assert!(sender.push(2).await);
+        drop(sender);
+
+        assert_eq!(consumer.await.unwrap(), vec![1, 2]);
+    }
+}
@@ -0,0 +1,199 @@
+//! Expiration of the leases.
+//!
+//! A [`LeaseReaper`] periodically removes the leases whose
+//! expiration passed: their address is returned to the
+//! [`Allocator`], the lease is deleted from the database, and
+//! subscribers are notified of the expired lease.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    sync::{broadcast, Notify},
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
+};
+
+use super::{
+    allocator::Allocator,
+    errors::AllocationError,
+    history::{LeaseEvent, LeaseEventKind, LeaseHistory},
+    leases::{Lease, Leases},
+};
+use crate::storage::errors::StorageError;
+
+/// Number of expired leases buffered for each subscriber
+pub const EXPIRATION_CAPACITY: usize = 1024;
+
+/// Removes expired leases
+pub struct LeaseReaper {
+    leases: Arc<Leases>,
+    allocator: Arc<Allocator>,
+    history: Option<Arc<LeaseHistory>>,
+    expired: broadcast::Sender<Lease>,
+}
+
+impl LeaseReaper {
+    /// Creates a reaper of `leases`, freeing their address in `allocator`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let reaper = Arc::new(LeaseReaper::new(leases.clone(), allocator.clone()));
+    /// let mut expired = reaper.subscribe();
+    /// let handle = reaper.start(Duration::from_secs(10));
+    /// ```
+    pub fn new(leases: Arc<Leases>, allocator: Arc<Allocator>) -> Self {
+        Self {
+            leases,
+            allocator,
+            history: None,
+            expired: broadcast::channel(EXPIRATION_CAPACITY).0,
+        }
+    }
+
+    /// Records the expiration of leases in `history`
+    pub fn set_history(&mut self, history: Arc<LeaseHistory>) {
+        self.history = Some(history);
+    }
+
+    /// Returns a receiver of the leases expired from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Lease> {
+        self.expired.subscribe()
+    }
+
+    /// Removes the leases expired at `now`, returning them
+    ///
+    /// This call is blocking, since it writes the removal to disk.
+    pub fn reap(&self, now: SystemTime) -> Result<Vec<Lease>, StorageError> {
+        let mut expired = Vec::new();
+        for candidate in self.leases.expired(now) {
+            //Leases renewed since they were listed are kept
+            let Some(lease) = self.leases.remove_expired(candidate.address, now)? else {
+                continue;
+            };
+            match self.allocator.release(lease.address) {
+                Ok(()) | Err(AllocationError::NotAllocated(_)) => (),
+                Err(err) => log::warn!("Failed to free expired lease {}: {}", lease.address, err),
+            }
+            if let Some(history) = &self.history {
+                history.record(LeaseEvent::new(
+                    LeaseEventKind::Expired,
+                    lease.hardware_address,
+                    lease.address,
+                ))?;
+            }
+            //Nobody may be listening
+            let _ = self.expired.send(lease.clone());
+            expired.push(lease);
+        }
+        if !expired.is_empty() {
+            log::info!("Expired {} leases", expired.len());
+            self.leases.sync()?;
+        }
+        Ok(expired)
+    }
+
+    /// Spawns a task removing expired leases every `interval`
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn start(self: &Arc<Self>, interval: Duration) -> ReaperHandle {
+        let stop = Arc::new(Notify::new());
+        let reaper = self.clone();
+        let stopped = stop.clone();
+        let task = tokio::spawn(async move {
+            let mut ticks = time::interval(interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {
+                        let reaper = reaper.clone();
+                        let reaped =
+                            tokio::task::spawn_blocking(move || reaper.reap(SystemTime::now())).await;
+                        match reaped {
+                            Ok(Ok(_)) => (),
+                            Ok(Err(e)) => log::error!("Failed to expire leases : {}", e),
+                            Err(e) => log::error!("Lease reaper panicked : {}", e),
+                        }
+                    }
+                    _ = stopped.notified() => break,
+                }
+            }
+        });
+        ReaperHandle { stop, task }
+    }
+}
+
+/// Handle over the task started by [`LeaseReaper::start`]
+///
+/// Dropping the handle does not stop the task.
+pub struct ReaperHandle {
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl ReaperHandle {
+    /// Stops the reaper, and waits for it to finish its current pass
+    pub async fn stop(self) {
+        self.stop.notify_one();
+        if let Err(e) = self.task.await {
+            log::error!("Lease reaper failed : {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::Ipv4Addr;
+
+    use mac_address::MacAddress;
+
+    use super::*;
+    use crate::{
+        core::allocator::{AddressPool, AddressRange},
+        storage::memory_backend::MemoryBackend,
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reaper() {
+        let network = Ipv4Addr::new(10, 0, 0, 0);
+        let range =
+            AddressRange::new(Ipv4Addr::new(10, 0, 0, 10), Ipv4Addr::new(10, 0, 0, 11)).unwrap();
+        let mut allocator = Allocator::new();
+        allocator.add_pool(
+            network,
+            AddressPool::new(vec![range], Duration::from_secs(30)),
+        );
+        let allocator = Arc::new(allocator);
+        let backend = MemoryBackend::new();
+        let leases = Arc::new(Leases::new(backend.clone()).unwrap());
+
+        let client = MacAddress::new([0xaa, 0, 0, 0, 0, 1]);
+        let address = allocator.allocate(network, client, None).unwrap();
+        allocator.commit(address, client).unwrap();
+        leases
+            .grant(Lease::new(
+                client,
+                address,
+                SystemTime::now() + Duration::from_millis(50),
+            ))
+            .unwrap();
+        leases.sync().unwrap();
+
+        let reaper = Arc::new(LeaseReaper::new(leases.clone(), allocator.clone()));
+        let mut expired = reaper.subscribe();
+        let handle = reaper.start(Duration::from_millis(20));
+        let lease = time::timeout(Duration::from_secs(1), expired.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        handle.stop().await;
+
+        assert_eq!(lease.address, address);
+        assert_eq!(allocator.pool(network).unwrap().available(), 2);
+        assert!(Leases::new(backend).unwrap().all().is_empty());
+    }
+}
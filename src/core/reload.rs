@@ -0,0 +1,151 @@
+//! Configuration reload while the pipeline keeps running.
+//!
+//! A [`ReloadSignal`] runs user-registered callbacks every time
+//! the process receives `SIGHUP`, or when triggered manually.
+//! Callbacks typically swap the [`HookRegistry`] of a running
+//! [`StateSwitcher`] through its [`Swappable`] slot, reload
+//! subnets, or reopen log files.
+//!
+//! [`HookRegistry`]: crate::hooks::hook_registry::HookRegistry
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+
+use std::sync::{Arc, RwLock};
+
+use log::info;
+
+/// Shared slot holding a value which can be
+/// replaced while readers keep using the previous one
+///
+/// Clones share the same slot.
+pub struct Swappable<V> {
+    value: Arc<RwLock<Arc<V>>>,
+}
+
+impl<V> Clone for Swappable<V> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl<V> Swappable<V> {
+    /// Creates a new `Swappable` holding `value`
+    pub fn new(value: Arc<V>) -> Self {
+        Self {
+            value: Arc::new(RwLock::new(value)),
+        }
+    }
+
+    /// Returns the value currently held
+    pub fn load(&self) -> Arc<V> {
+        self.value.read().unwrap().clone()
+    }
+
+    /// Replaces the value held, readers which already
+    /// loaded the previous one keep using it
+    pub fn store(&self, value: Arc<V>) {
+        *self.value.write().unwrap() = value;
+    }
+}
+
+pub type ReloadCallback = dyn Fn() + Send + Sync;
+
+/// Runs registered callbacks on `SIGHUP`
+#[derive(Default)]
+pub struct ReloadSignal {
+    callbacks: Vec<Box<ReloadCallback>>,
+}
+
+impl ReloadSignal {
+    /// Creates a new `ReloadSignal` with no callback
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a callback to run on every reload,
+    /// after the ones registered before it
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let registry = state_switcher.registry_slot();
+    /// reload.on_reload(move || registry.store(Arc::new(load_hooks())));
+    /// ```
+    pub fn on_reload(&mut self, callback: impl Fn() + Send + Sync + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Runs every registered callback
+    pub fn reload(&self) {
+        info!("Reloading configuration");
+        for callback in self.callbacks.iter() {
+            callback();
+        }
+    }
+
+    /// Spawns a task running every registered
+    /// callback each time `SIGHUP` is received
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`std::io::Error`] if the signal
+    /// handler cannot be installed
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let listener = reload.listen()?;
+    /// state_switcher.start().await;
+    /// listener.abort();
+    /// ```
+    #[cfg(unix)]
+    pub fn listen(self) -> Result<tokio::task::JoinHandle<()>, std::io::Error> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        Ok(tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                self.reload();
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn test_swappable() {
+        let slot = Swappable::new(Arc::new(1));
+        let previous = slot.load();
+
+        slot.clone().store(Arc::new(2));
+        assert_eq!(*previous, 1);
+        assert_eq!(*slot.load(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let reloads = Arc::new(Mutex::new(vec![]));
+        let mut reload = ReloadSignal::new();
+        for callback in 0..2 {
+            let reloads = reloads.clone();
+            reload.on_reload(move || reloads.lock().unwrap().push(callback));
+        }
+
+        reload.reload();
+        reload.reload();
+        assert_eq!(*reloads.lock().unwrap(), vec![0, 1, 0, 1]);
+
+        //Installing the handler does not run the callbacks
+        let listener = reload.listen().unwrap();
+        listener.abort();
+        assert!(listener.await.unwrap_err().is_cancelled());
+        assert_eq!(reloads.lock().unwrap().len(), 4);
+    }
+}
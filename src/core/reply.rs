@@ -0,0 +1,199 @@
+//! Addressing of replies.
+//!
+//! RFC 2131 decides where a reply is sent from the fields of
+//! the request it answers: relayed requests are answered to their
+//! relay agent, clients which already have an address are answered
+//! by unicast, and other clients by broadcast.
+//!
+//! The destination computed by [`ReplyAddressing::destination`]
+//! is set on the [`PacketContext`] of the reply, which the output
+//! sends it to. A [`Delivery`] link address is set on the context
+//! as well, so that outputs able to send frames, such as
+//! [`RawOutput`], reach clients which have no address yet.
+//!
+//! [`PacketContext`]: super::packet::PacketContext
+//! [`RawOutput`]: crate::netio::raw_output::RawOutput
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use mac_address::MacAddress;
+
+/// Port servers and relay agents listen on
+pub const SERVER_PORT: u16 = 67;
+/// Port clients listen on
+pub const CLIENT_PORT: u16 = 68;
+/// Bit of the flags field asking for broadcast replies
+pub const BROADCAST_FLAG: u16 = 0x8000;
+
+/// Fields of a request deciding where its reply is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplyAddressing {
+    pub giaddr: Ipv4Addr,
+    pub ciaddr: Ipv4Addr,
+    pub flags: u16,
+}
+
+impl ReplyAddressing {
+    pub fn new(giaddr: Ipv4Addr, ciaddr: Ipv4Addr, flags: u16) -> Self {
+        Self {
+            giaddr,
+            ciaddr,
+            flags,
+        }
+    }
+
+    /// Returns whether the client asked for broadcast replies
+    pub fn is_broadcast(&self) -> bool {
+        self.flags & BROADCAST_FLAG != 0
+    }
+
+    /// Returns where the reply must be sent
+    ///
+    /// Relayed requests are answered to the server port of the
+    /// relay agent. Otherwise, NAKs are always broadcast, and other
+    /// replies are unicast to ciaddr, unless it is unspecified or
+    /// the client set the broadcast flag.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let addressing = ReplyAddressing::new(request.giaddr, request.ciaddr, request.flags);
+    /// packet.set_destination(addressing.destination(is_nak).into());
+    /// ```
+    pub fn destination(&self, nak: bool) -> SocketAddrV4 {
+        if !self.giaddr.is_unspecified() {
+            return SocketAddrV4::new(self.giaddr, SERVER_PORT);
+        }
+        if nak || self.ciaddr.is_unspecified() || self.is_broadcast() {
+            return SocketAddrV4::new(Ipv4Addr::BROADCAST, CLIENT_PORT);
+        }
+        SocketAddrV4::new(self.ciaddr, CLIENT_PORT)
+    }
+
+    /// Returns how the reply must be delivered to a client
+    /// with hardware address `chaddr`, offered `yiaddr`
+    ///
+    /// As RFC 2131 requires, replies which would be broadcast
+    /// only because the client has no address yet are sent to
+    /// `yiaddr` at the link layer instead, when the client
+    /// did not ask for broadcast replies.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let delivery = addressing.delivery(false, request.chaddr, offered);
+    /// packet.set_destination(delivery.destination().into());
+    /// if let Some(chaddr) = delivery.link_address() {
+    ///     packet.set_link_address(chaddr);
+    /// }
+    /// ```
+    pub fn delivery(&self, nak: bool, chaddr: MacAddress, yiaddr: Ipv4Addr) -> Delivery {
+        let destination = self.destination(nak);
+        match destination.ip().is_broadcast() && !nak && !self.is_broadcast() {
+            true if !yiaddr.is_unspecified() => {
+                Delivery::Link(chaddr, SocketAddrV4::new(yiaddr, CLIENT_PORT))
+            }
+            _ => Delivery::Routed(destination),
+        }
+    }
+
+    /// Returns the flags of the reply
+    ///
+    /// Relay agents broadcast NAKs to their clients only
+    /// when the broadcast flag is set, so it is set on NAKs.
+    pub fn reply_flags(&self, nak: bool) -> u16 {
+        match nak && !self.giaddr.is_unspecified() {
+            true => self.flags | BROADCAST_FLAG,
+            false => self.flags,
+        }
+    }
+}
+
+/// How a reply reaches its destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Sent through the IP stack
+    Routed(SocketAddrV4),
+    /// Sent at the link layer to the given hardware address,
+    /// the destination not being reachable through the IP stack
+    Link(MacAddress, SocketAddrV4),
+}
+
+impl Delivery {
+    /// Returns the address the reply is sent to
+    pub fn destination(&self) -> SocketAddrV4 {
+        match self {
+            Delivery::Routed(destination) | Delivery::Link(_, destination) => *destination,
+        }
+    }
+
+    /// Returns the hardware address the reply is sent
+    /// to at the link layer, if any
+    pub fn link_address(&self) -> Option<MacAddress> {
+        match self {
+            Delivery::Routed(_) => None,
+            Delivery::Link(chaddr, _) => Some(*chaddr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_reply_destination() {
+        let unspecified = Ipv4Addr::UNSPECIFIED;
+        let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, CLIENT_PORT);
+        let ciaddr = Ipv4Addr::new(10, 0, 0, 42);
+        let giaddr = Ipv4Addr::new(10, 0, 1, 1);
+
+        let discover = ReplyAddressing::new(unspecified, unspecified, 0);
+        assert_eq!(discover.destination(false), broadcast);
+        let renewal = ReplyAddressing::new(unspecified, ciaddr, 0);
+        assert_eq!(
+            renewal.destination(false),
+            SocketAddrV4::new(ciaddr, CLIENT_PORT)
+        );
+        assert_eq!(renewal.destination(true), broadcast);
+        let broadcast_renewal = ReplyAddressing::new(unspecified, ciaddr, BROADCAST_FLAG);
+        assert_eq!(broadcast_renewal.destination(false), broadcast);
+
+        let relayed = ReplyAddressing::new(giaddr, ciaddr, 0);
+        assert_eq!(
+            relayed.destination(true),
+            SocketAddrV4::new(giaddr, SERVER_PORT)
+        );
+        assert_eq!(relayed.reply_flags(true), BROADCAST_FLAG);
+        assert_eq!(relayed.reply_flags(false), 0);
+    }
+
+    #[test]
+    fn test_delivery() {
+        let unspecified = Ipv4Addr::UNSPECIFIED;
+        let chaddr = MacAddress::new([0xaa, 0, 0, 0, 0, 1]);
+        let yiaddr = Ipv4Addr::new(10, 0, 0, 42);
+        let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, CLIENT_PORT);
+
+        let discover = ReplyAddressing::new(unspecified, unspecified, 0);
+        let offer = discover.delivery(false, chaddr, yiaddr);
+        assert_eq!(
+            offer,
+            Delivery::Link(chaddr, SocketAddrV4::new(yiaddr, CLIENT_PORT))
+        );
+        assert_eq!(
+            discover.delivery(true, chaddr, unspecified),
+            Delivery::Routed(broadcast)
+        );
+        let broadcast_discover = ReplyAddressing::new(unspecified, unspecified, BROADCAST_FLAG);
+        assert_eq!(
+            broadcast_discover.delivery(false, chaddr, yiaddr),
+            Delivery::Routed(broadcast)
+        );
+
+        assert_eq!(offer.destination(), SocketAddrV4::new(yiaddr, CLIENT_PORT));
+        assert_eq!(offer.link_address(), Some(chaddr));
+        assert_eq!(Delivery::Routed(broadcast).link_address(), None);
+    }
+}
@@ -0,0 +1,206 @@
+//! Static reservations of IPv4 addresses.
+//!
+//! A [`Reservation`] binds a fixed address, and optionally
+//! per-host options, to a hardware address. [`Reservations`]
+//! are stored in their own pool, so they survive restarts and
+//! can be edited while the server is running, and are consulted
+//! by the [`Allocator`] before any dynamic assignment.
+//!
+//! [`Allocator`]: super::allocator::Allocator
+
+use std::{collections::BTreeMap, net::Ipv4Addr};
+
+use mac_address::MacAddress;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{
+    backend::StorageBackend,
+    data::{DataPool, RuntimeStorage, Storable},
+    errors::StorageError,
+    serialized::{Serialized, SCHEMA},
+    uid::Uid,
+};
+
+/// Name of the pool, and table, holding the reservations
+pub const RESERVATION_POOL: &str = "reservation";
+
+/// Fixed address of a host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub hardware_address: MacAddress,
+    pub address: Ipv4Addr,
+    pub hostname: Option<String>,
+    /// Raw value of the options sent to the host, by option code,
+    /// overriding the options of its subnet
+    pub options: BTreeMap<u8, Vec<u8>>,
+}
+
+impl Reservation {
+    pub fn new(hardware_address: MacAddress, address: Ipv4Addr) -> Self {
+        Self {
+            hardware_address,
+            address,
+            hostname: None,
+            options: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_hostname(&mut self, hostname: String) {
+        self.hostname = Some(hostname);
+    }
+
+    /// Sends option `code` with the given raw value to the host
+    pub fn set_option(&mut self, code: u8, value: Vec<u8>) {
+        self.options.insert(code, value);
+    }
+}
+
+/// Persistent set of [`Reservation`], keyed by hardware address
+pub struct Reservations {
+    storage: RuntimeStorage<Serialized<Reservation>>,
+}
+
+impl Reservations {
+    /// Creates the reservations persisted to `backend`, loading
+    /// the reservations already stored
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let reservations = Arc::new(Reservations::new(db)?);
+    /// reservations.set(Reservation::new(printer_mac, Ipv4Addr::new(10, 0, 0, 5)))?;
+    /// allocator.set_reservations(reservations.clone());
+    /// ```
+    pub fn new(backend: impl StorageBackend + 'static) -> Result<Self, StorageError> {
+        let storage = RuntimeStorage::new(backend);
+        let pool = DataPool::new(RESERVATION_POOL.to_string(), SCHEMA.to_string());
+        pool.set_key(|reservation: &Serialized<Reservation>| Some(reservation.hardware_address))?;
+        pool.add_index("address", |reservation: &Serialized<Reservation>| {
+            Some(reservation.address.to_string())
+        });
+        storage.add_pool(pool)?;
+        storage.load()?;
+        Ok(Self { storage })
+    }
+
+    /// Returns the reservation of the given hardware address, if any
+    pub fn get(&self, hardware_address: MacAddress) -> Option<Reservation> {
+        self.find(hardware_address).map(Serialized::into_inner)
+    }
+
+    /// Returns the reservation of the given address, if any
+    pub fn by_address(&self, address: Ipv4Addr) -> Option<Reservation> {
+        self.storage
+            .find_by(
+                RESERVATION_POOL.to_string(),
+                "address",
+                &address.to_string(),
+            )
+            .ok()?
+            .into_iter()
+            .next()
+            .map(Serialized::into_inner)
+    }
+
+    /// Adds `reservation`, replacing the reservation of
+    /// its hardware address if any
+    ///
+    /// Fails with [`StorageError::KeyCollision`] if its address
+    /// is reserved for another host.
+    pub fn set(&self, reservation: Reservation) -> Result<Uid, StorageError> {
+        let existing = self.find(reservation.hardware_address);
+        if let Some(other) = self
+            .storage
+            .find_by(
+                RESERVATION_POOL.to_string(),
+                "address",
+                &reservation.address.to_string(),
+            )?
+            .into_iter()
+            .find(|other| other.hardware_address != reservation.hardware_address)
+        {
+            return Err(StorageError::KeyCollision(other.id()));
+        }
+        match existing {
+            Some(existing) => {
+                let uid = existing.id();
+                self.storage.update(uid, Serialized::new(reservation))?;
+                Ok(uid)
+            }
+            None => self
+                .storage
+                .store(Serialized::new(reservation), RESERVATION_POOL.to_string()),
+        }
+    }
+
+    /// Removes the reservation of the given hardware address, returning it if any
+    pub fn remove(
+        &self,
+        hardware_address: MacAddress,
+    ) -> Result<Option<Reservation>, StorageError> {
+        let Some(reservation) = self.find(hardware_address) else {
+            return Ok(None);
+        };
+        self.storage.delete(reservation.id())?;
+        Ok(Some(reservation.into_inner()))
+    }
+
+    /// Returns every reservation
+    pub fn all(&self) -> Vec<Reservation> {
+        self.storage
+            .values(RESERVATION_POOL)
+            .unwrap_or_default()
+            .into_iter()
+            .map(Serialized::into_inner)
+            .collect()
+    }
+
+    /// Writes the changes made to the reservations to disk
+    pub fn sync(&self) -> Result<(), StorageError> {
+        self.storage.sync()
+    }
+
+    fn find(&self, hardware_address: MacAddress) -> Option<Serialized<Reservation>> {
+        self.storage
+            .get_by_key(RESERVATION_POOL, &hardware_address)
+            .ok()
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::memory_backend::MemoryBackend;
+
+    #[test]
+    fn test_reservations() {
+        let backend = MemoryBackend::new();
+        let reservations = Reservations::new(backend.clone()).unwrap();
+        let printer = MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]);
+        let camera = MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x02]);
+        let address = Ipv4Addr::new(10, 0, 0, 5);
+
+        let mut reservation = Reservation::new(printer, address);
+        reservation.set_option(15, b"printers.lan".to_vec());
+        let uid = reservations.set(reservation.clone()).unwrap();
+        assert!(matches!(
+            reservations.set(Reservation::new(camera, address)),
+            Err(StorageError::KeyCollision(other)) if other == uid
+        ));
+        assert_eq!(reservations.by_address(address), Some(reservation.clone()));
+
+        //Reservations are edited in place
+        reservation.set_hostname(String::from("printer"));
+        assert_eq!(reservations.set(reservation.clone()), Ok(uid));
+        assert_eq!(reservations.get(printer), Some(reservation.clone()));
+
+        //Reservations survive a restart
+        reservations.sync().unwrap();
+        let reloaded = Reservations::new(backend).unwrap();
+        assert_eq!(reloaded.all(), vec![reservation.clone()]);
+        assert_eq!(reloaded.remove(printer), Ok(Some(reservation)));
+        assert_eq!(reloaded.get(printer), None);
+    }
+}
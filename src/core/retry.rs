@@ -0,0 +1,156 @@
+//! Retry logic used by the [`StateSwitcher`] around
+//! fallible asynchronous operations, such as dispatching
+//! a packet through an [`Output`].
+//!
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+//! [`Output`]: super::state_switcher::Output
+
+use std::{future::Future, time::Duration};
+
+use log::debug;
+
+/// Describes how many times an operation is attempted,
+/// and how long to wait between two attempts.
+///
+/// The delay between attempts starts at `initial_backoff`
+/// and doubles after each failed attempt, up to `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a new `RetryPolicy` attempting an operation
+    /// at most `max_attempts` times, waiting `initial_backoff`
+    /// after the first failure
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_attempts` is 0
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let policy = RetryPolicy::new(3, Duration::from_millis(10));
+    /// ```
+    pub fn new(max_attempts: usize, initial_backoff: Duration) -> Self {
+        assert!(
+            max_attempts > 0,
+            "A retry policy needs at least one attempt"
+        );
+        Self {
+            max_attempts,
+            initial_backoff,
+            max_backoff: initial_backoff.saturating_mul(1 << (max_attempts - 1).min(16)),
+        }
+    }
+
+    /// Creates a `RetryPolicy` which never retries
+    pub fn none() -> Self {
+        Self::new(1, Duration::ZERO)
+    }
+
+    /// Caps the delay between two attempts
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let policy = RetryPolicy::new(10, Duration::from_millis(10))
+    ///     .with_max_backoff(Duration::from_millis(100));
+    /// ```
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Returns the maximum number of attempts
+    pub fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Returns the delay to wait after the given
+    /// failed attempt, starting from 1
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Runs `operation` until it succeeds or until
+    /// the maximum number of attempts is reached
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the last attempt if
+    /// every attempt failed
+    pub async fn run<F, Fut, R, E>(&self, mut operation: F) -> Result<R, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+        E: std::fmt::Display,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Ok(res) => return Ok(res),
+                Err(e) if attempt >= self.max_attempts => return Err(e),
+                Err(e) => {
+                    debug!(
+                        "Attempt {}/{} failed ({}), retrying",
+                        attempt, self.max_attempts, e
+                    );
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(10))
+            .with_max_backoff(Duration::from_millis(30));
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(10));
+        assert_eq!(policy.backoff(2), Duration::from_millis(20));
+        assert_eq!(policy.backoff(3), Duration::from_millis(30));
+        assert_eq!(policy.backoff(4), Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let attempts = AtomicUsize::new(0);
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        let res: Result<usize, &str> = policy
+            .run(|| async {
+                match attempts.fetch_add(1, SeqCst) {
+                    0 | 1 => Err("transient"),
+                    _ => Ok(1),
+                }
+            })
+            .await;
+        assert_eq!(res, Ok(1));
+        assert_eq!(attempts.load(SeqCst), 3);
+
+        let res: Result<usize, &str> = policy.run(|| async { Err("fatal") }).await;
+        assert_eq!(res, Err("fatal"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Horizontal scaling across several [`StateSwitcher`].
+//!
+//! Each `StateSwitcher` of a [`PipelineGroup`] runs in its own
+//! task, typically reading from its own [`UdpInput`] bound with
+//! `SO_REUSEPORT` to a shared port, so that the kernel distributes
+//! the load across cores. The [`HookRegistry`], its services and
+//! the [`Counters`] can be shared through an [`Arc`] between them.
+//!
+//! # Examples:
+//!
+//! ```
+//! let registry = Arc::new(registry);
+//! let counters = Arc::new(Counters::new());
+//! let mut switchers = Vec::new();
+//! for _ in 0..workers {
+//!     let (state_switcher, _) = StateSwitcher::builder()
+//!         .input(UdpInput::start_reuse_port("0.0.0.0:67")?)
+//!         .output(UdpOutput::start_reuse_port("0.0.0.0:68")?)
+//!         .shared_registry(registry.clone())
+//!         .metrics(counters.clone())
+//!         .build()?;
+//!     switchers.push(state_switcher);
+//! }
+//! let group = PipelineGroup::spawn(switchers);
+//! ```
+//!
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+//! [`UdpInput`]: crate::netio::udp_input::UdpInput
+//! [`HookRegistry`]: crate::hooks::hook_registry::HookRegistry
+//! [`Counters`]: super::counters::Counters
+//! [`Arc`]: std::sync::Arc
+
+use super::{handle::PipelineHandle, packet::PacketType, state_switcher::StateSwitcher};
+
+/// A set of [`StateSwitcher`] running concurrently,
+/// controlled as a whole
+pub struct PipelineGroup {
+    handles: Vec<PipelineHandle>,
+}
+
+impl PipelineGroup {
+    /// Spawns every given [`StateSwitcher`] in its own task
+    pub fn spawn<T: PacketType + Send + 'static, U: PacketType + Send + 'static>(
+        switchers: impl IntoIterator<Item = StateSwitcher<T, U>>,
+    ) -> Self {
+        Self {
+            handles: switchers.into_iter().map(StateSwitcher::spawn).collect(),
+        }
+    }
+
+    /// Returns the [`PipelineHandle`] of each [`StateSwitcher`]
+    pub fn handles(&self) -> &[PipelineHandle] {
+        &self.handles
+    }
+
+    /// Stops every [`StateSwitcher`] of the group
+    pub fn stop(&self) {
+        self.handles.iter().for_each(PipelineHandle::stop);
+    }
+
+    /// Pauses every [`StateSwitcher`] of the group
+    pub fn pause(&self) {
+        self.handles.iter().for_each(PipelineHandle::pause);
+    }
+
+    /// Resumes every [`StateSwitcher`] of the group
+    pub fn resume(&self) {
+        self.handles.iter().for_each(PipelineHandle::resume);
+    }
+
+    /// Waits until every [`StateSwitcher`] of the group terminates
+    pub async fn await_terminated(&self) {
+        for handle in self.handles.iter() {
+            handle.await_terminated().await;
+        }
+    }
+}
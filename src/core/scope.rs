@@ -0,0 +1,157 @@
+//! Selection of the subnet serving a request.
+//!
+//! Relayed requests are served from the subnet of their relay
+//! agent address (giaddr). Requests of directly connected clients
+//! are served from the subnet configured on the interface they were
+//! received on, or else from the subnet of the address of that
+//! interface.
+//!
+//! A [`ScopeSelector`] is meant to be registered as a service,
+//! so that every hook selects subnets the same way.
+
+use std::{net::Ipv4Addr, sync::Arc};
+
+use super::{
+    config::{Subnet, SubnetSelector},
+    reload::Swappable,
+};
+
+/// Where a request was received from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ingress {
+    /// Relay agent address of the request, unspecified if it was not relayed
+    pub giaddr: Ipv4Addr,
+    /// Name of the interface the request was received on, if known
+    pub interface: Option<String>,
+    /// Address of the interface the request was received on, if known
+    pub local_address: Option<Ipv4Addr>,
+}
+
+impl Ingress {
+    /// Creates the ingress of a request relayed by `giaddr`, which
+    /// is unspecified for directly connected clients
+    pub fn new(giaddr: Ipv4Addr) -> Self {
+        Self {
+            giaddr,
+            interface: None,
+            local_address: None,
+        }
+    }
+
+    pub fn set_interface(&mut self, interface: String) {
+        self.interface = Some(interface);
+    }
+
+    pub fn set_local_address(&mut self, local_address: Ipv4Addr) {
+        self.local_address = Some(local_address);
+    }
+
+    /// Returns whether the request was relayed
+    pub fn is_relayed(&self) -> bool {
+        !self.giaddr.is_unspecified()
+    }
+}
+
+/// Service picking the [`Subnet`] serving a request
+///
+/// Subnets can be [reloaded](ScopeSelector::reload) while
+/// hooks keep selecting from them.
+pub struct ScopeSelector {
+    subnets: Swappable<SubnetSelector>,
+}
+
+impl ScopeSelector {
+    /// Creates a selector picking from the subnets of `subnets`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// registry.register_service(ScopeSelector::new(subnets));
+    /// //In a hook
+    /// let services = services.lock().unwrap();
+    /// let subnet = services
+    ///     .get::<ScopeSelector>()
+    ///     .and_then(|scopes| scopes.select(&ingress))
+    ///     .ok_or(HookError::new("No subnet"))?;
+    /// ```
+    pub fn new(subnets: SubnetSelector) -> Self {
+        Self {
+            subnets: Swappable::new(Arc::new(subnets)),
+        }
+    }
+
+    /// Replaces the subnets, requests already being
+    /// served keep their subnet
+    pub fn reload(&self, subnets: SubnetSelector) {
+        self.subnets.store(Arc::new(subnets));
+    }
+
+    /// Returns the subnets currently selected from
+    pub fn subnets(&self) -> Arc<SubnetSelector> {
+        self.subnets.load()
+    }
+
+    /// Returns the subnet serving a request received from `ingress`, if any
+    ///
+    /// Relayed requests are only served from the subnet of their giaddr.
+    pub fn select(&self, ingress: &Ingress) -> Option<Subnet> {
+        let subnets = self.subnets.load();
+        if let Some(subnet) = subnets.select(ingress.giaddr, ingress.interface.as_deref()) {
+            return Some(subnet.clone());
+        }
+        if ingress.is_relayed() {
+            return None;
+        }
+        let local_address = ingress.local_address?;
+        subnets
+            .subnets()
+            .iter()
+            .find(|subnet| subnet.contains(local_address))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn subnet(network: [u8; 4], interface: Option<&str>) -> Subnet {
+        let mut subnet = Subnet::new(Ipv4Addr::from(network), 24).unwrap();
+        if let Some(interface) = interface {
+            subnet.add_interface(interface.to_string());
+        }
+        subnet
+    }
+
+    #[test]
+    fn test_scope_selection() {
+        let mut subnets = SubnetSelector::new();
+        subnets
+            .add_subnet(subnet([10, 0, 0, 0], Some("eth0")))
+            .unwrap();
+        subnets.add_subnet(subnet([10, 0, 1, 0], None)).unwrap();
+        let scopes = ScopeSelector::new(subnets);
+        let network = |ingress: &Ingress| scopes.select(ingress).map(|subnet| subnet.network());
+
+        let relayed = Ingress::new(Ipv4Addr::new(10, 0, 1, 1));
+        assert_eq!(network(&relayed), Some(Ipv4Addr::new(10, 0, 1, 0)));
+        let mut unknown_relay = Ingress::new(Ipv4Addr::new(10, 0, 9, 1));
+        unknown_relay.set_local_address(Ipv4Addr::new(10, 0, 1, 2));
+        assert_eq!(network(&unknown_relay), None);
+
+        let mut direct = Ingress::new(Ipv4Addr::UNSPECIFIED);
+        direct.set_interface(String::from("eth0"));
+        assert_eq!(network(&direct), Some(Ipv4Addr::new(10, 0, 0, 0)));
+        direct.set_interface(String::from("eth1"));
+        assert_eq!(network(&direct), None);
+        direct.set_local_address(Ipv4Addr::new(10, 0, 1, 2));
+        assert_eq!(network(&direct), Some(Ipv4Addr::new(10, 0, 1, 0)));
+
+        let mut reloaded = SubnetSelector::new();
+        reloaded.add_subnet(subnet([10, 0, 2, 0], None)).unwrap();
+        scopes.reload(reloaded);
+        assert_eq!(network(&direct), None);
+        assert_eq!(scopes.subnets().subnets().len(), 1);
+    }
+}
@@ -6,45 +6,118 @@
 //! used to gather incoming data and dispatch
 //! outgoing one.
 
-use std::sync::{
-    atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
-    Arc,
+use std::{
+    net::SocketAddr,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
 };
 
 use crate::hooks::hook_registry::HookRegistry;
 use async_trait::async_trait;
+use mac_address::MacAddress;
+use tokio::sync::Semaphore;
 
 use super::{
-    packet::{PacketContext, PacketType},
+    builder::StateSwitcherBuilder,
+    counters::Counters,
+    dedup::Deduplicator,
+    events::PipelineEvent,
+    handle::PipelineHandle,
+    packet::{PacketContext, PacketMetadata, PacketType},
+    processor::{DropReason, PacketProcessor},
+    queue::{packet_queue, OverflowPolicy, QueueSender},
+    reload::Swappable,
+    retry::RetryPolicy,
     state::PacketState,
+    tap::{PacketSample, PacketTap},
 };
 
+/// Callback invoked by the [`StateSwitcher`] when
+/// a [`PacketContext`] enters or exits a state
+pub type StateCallback<T, U> = dyn Fn(PacketState, &PacketContext<T, U>) + Send + Sync;
+
+/// Default capacity of the queue between
+/// the [`Input`] and the processing tasks
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
 #[async_trait]
 pub trait Output<T: PacketType>: Send + Sync {
     async fn send(&self, packet: T) -> Result<usize, std::io::Error>;
+
+    /// Sends a packet to `destination`
+    ///
+    /// This is what the [`StateSwitcher`] calls when the destination
+    /// of the output packet is known, from [`PacketContext::destination`].
+    /// Outputs which send to an address must override it; the default
+    /// implementation ignores `destination` and calls `send`.
+    async fn send_to(&self, packet: T, destination: SocketAddr) -> Result<usize, std::io::Error>
+    where
+        T: Send + 'async_trait,
+    {
+        let _ = destination;
+        self.send(packet).await
+    }
+
+    /// Sends a packet to `destination`, at the link
+    /// layer to the hardware address `link_address`
+    ///
+    /// This is what the [`StateSwitcher`] calls when a [`Hook`] set
+    /// [`PacketContext::link_address`]. Outputs able to send frames
+    /// must override it; the default implementation ignores
+    /// `link_address` and calls `send_to`.
+    ///
+    /// [`Hook`]: crate::hooks::hook_registry::Hook
+    async fn send_to_link(
+        &self,
+        packet: T,
+        destination: SocketAddr,
+        link_address: MacAddress,
+    ) -> Result<usize, std::io::Error>
+    where
+        T: Send + 'async_trait,
+    {
+        let _ = link_address;
+        self.send_to(packet, destination).await
+    }
 }
 
 #[async_trait]
 pub trait Input<T: PacketType>: Send + Sync {
     async fn get(&self) -> Result<T, std::io::Error>;
+
+    /// Returns the next packet along with where it was received from
+    ///
+    /// This is what the [`StateSwitcher`] reads, the metadata being
+    /// stored in the [`PacketContext`]. Inputs which know where their
+    /// packets come from must override it; the default implementation
+    /// calls `get` and returns empty metadata.
+    async fn get_with_metadata(&self) -> Result<(T, PacketMetadata), std::io::Error> {
+        Ok((self.get().await?, PacketMetadata::default()))
+    }
 }
 
 /// A StateSwitcher serves the following purposes:
 /// - Gather incoming packets from an [`Input`]
 /// - Make the packet go through each successive state
-/// while executing every defined [`Hook`] each time
+///   while executing every defined [`Hook`] each time
 /// - Dispatch the packet using an [`Output`]
-
 pub struct StateSwitcher<T: PacketType + Send + 'static, U: PacketType + Send + 'static> {
-    registry: Arc<HookRegistry<T, U>>,
+    registry: Swappable<HookRegistry<T, U>>,
     output: Arc<Box<dyn Output<U>>>,
     input: Arc<Box<dyn Input<T>>>,
-    dropped: Arc<AtomicUsize>,
-    running: Arc<AtomicBool>,
+    control: PipelineHandle,
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    tap: Option<Arc<PacketTap>>,
+    retry_policy: RetryPolicy,
+    concurrency: Option<Arc<Semaphore>>,
+    deadline: Option<Duration>,
+    on_enter: Option<Arc<StateCallback<T, U>>>,
+    on_exit: Option<Arc<StateCallback<T, U>>>,
+    dedup: Option<Arc<Deduplicator<T>>>,
+    dry_run: Option<Arc<Box<dyn Output<U>>>>,
 }
 
-unsafe impl<T: PacketType + Send, U: PacketType + Send> Sync for StateSwitcher<T, U> {}
-
 impl<T: PacketType + Send, U: PacketType + Send> StateSwitcher<T, U> {
     /// Crates a new `StateSwitcher` from
     /// a [`HookRegistry`], an [`Input`] from which
@@ -63,14 +136,102 @@ impl<T: PacketType + Send, U: PacketType + Send> StateSwitcher<T, U> {
         kill_switch: Arc<AtomicBool>,
     ) -> Self {
         Self {
-            registry: Arc::new(registry),
+            registry: Swappable::new(Arc::new(registry)),
             output: Arc::new(output),
             input: Arc::new(input),
-            dropped: Arc::new(AtomicUsize::new(0)),
-            running: kill_switch,
+            control: PipelineHandle::new(kill_switch),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::default(),
+            tap: None,
+            retry_policy: RetryPolicy::default(),
+            concurrency: None,
+            deadline: None,
+            on_enter: None,
+            on_exit: None,
+            dedup: None,
+            dry_run: None,
         }
     }
 
+    /// Returns a [`StateSwitcherBuilder`] to configure
+    /// a new `StateSwitcher`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let (state_switcher, handle) = StateSwitcher::builder()
+    ///     .input(input)
+    ///     .output(output)
+    ///     .registry(registry)
+    ///     .build()?;
+    /// ```
+    pub fn builder() -> StateSwitcherBuilder<T, U> {
+        StateSwitcherBuilder::new()
+    }
+
+    /// Returns the [`HookRegistry`] of this `StateSwitcher`
+    pub fn registry(&self) -> Arc<HookRegistry<T, U>> {
+        self.registry.load()
+    }
+
+    /// Replaces the [`HookRegistry`] of this `StateSwitcher`.
+    ///
+    /// The same registry, along with its services, can be
+    /// shared across several `StateSwitcher`. Packets already
+    /// being processed keep using the previous registry.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let registry = Arc::new(registry);
+    /// first.set_registry(registry.clone());
+    /// second.set_registry(registry);
+    /// ```
+    pub fn set_registry(&self, registry: Arc<HookRegistry<T, U>>) {
+        self.registry.store(registry);
+    }
+
+    /// Returns the slot holding the [`HookRegistry`], through
+    /// which it can be replaced while the `StateSwitcher` runs,
+    /// e.g. from a [`ReloadSignal`] callback
+    ///
+    /// [`ReloadSignal`]: super::reload::ReloadSignal
+    pub fn registry_slot(&self) -> Swappable<HookRegistry<T, U>> {
+        self.registry.clone()
+    }
+
+    /// Returns a [`PipelineHandle`] controlling
+    /// this `StateSwitcher`
+    pub fn handle(&self) -> PipelineHandle {
+        self.control.clone()
+    }
+
+    /// Configures the bounded queue between the [`Input`]
+    /// and the processing tasks.
+    ///
+    /// At most `capacity` packets can wait for processing,
+    /// and `policy` decides what happens to incoming packets
+    /// once this limit is reached. Packets discarded this way
+    /// are accounted for in [`drop_count`].
+    ///
+    /// [`drop_count`]: StateSwitcher::drop_count
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut state_switcher = StateSwitcher::new(input, output, registry, kill_switch);
+    /// state_switcher.set_backpressure(256, OverflowPolicy::DropOldest);
+    /// ```
+    pub fn set_backpressure(&mut self, capacity: usize, policy: OverflowPolicy) {
+        assert!(capacity > 0, "Queue capacity must be greater than 0");
+        self.queue_capacity = capacity;
+        self.overflow_policy = policy;
+    }
+
     /// Initiate the state switching process.
     /// Usually, it should be the main loop
     /// of the program.
@@ -80,6 +241,10 @@ impl<T: PacketType + Send, U: PacketType + Send> StateSwitcher<T, U> {
     /// the [`Hook`] and then send them to foreign
     /// devices using the [`Output`]
     ///
+    /// It returns once the `StateSwitcher` is stopped
+    /// through its kill switch or its [`PipelineHandle`],
+    /// and every packet in the queue was processed.
+    ///
     /// # Examples:
     /// ```
     /// let state_switcher = StateSwitcher::new(input, output, registry);
@@ -87,72 +252,372 @@ impl<T: PacketType + Send, U: PacketType + Send> StateSwitcher<T, U> {
     /// state_switcher.start().await;
     /// ```
     pub async fn start(&self) {
+        let (sender, receiver) = packet_queue(self.queue_capacity, self.overflow_policy);
+
+        tokio::spawn(Self::read_input(
+            self.input.clone(),
+            sender,
+            self.control.clone(),
+            self.control.counters(),
+        ));
+
         loop {
-            if !self.running.load(SeqCst) {
-                break;
-            }
+            self.control.wait_resumed().await;
 
-            let packet = match self.input.get().await {
-                Ok(pak) => pak,
-                Err(_) => {
-                    continue;
+            let packet = receiver.lock().await.recv().await;
+            let (packet, metadata) = match packet {
+                Some(pak) => pak,
+                None => {
+                    break;
                 }
             };
+            let permit = match &self.concurrency {
+                Some(semaphore) => Some(
+                    semaphore
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("Concurrency semaphore was closed"),
+                ),
+                None => None,
+            };
             let mut context = PacketContext::from(packet);
-            let registry = self.registry.clone();
-            let output = self.output.clone();
-            let drops = self.dropped.clone();
+            context.set_metadata(metadata);
+            let processor = self.processor();
+            let in_flight = self.control.track();
 
             tokio::spawn(async move {
-                for state in
-                    enum_iterator::all::<PacketState>().filter(|x| *x != PacketState::Failure)
-                {
-                    if state == PacketState::Failure {
-                        continue;
+                let _permit = permit;
+                let _in_flight = in_flight;
+                let _ = processor.run(context).await;
+            });
+        }
+
+        self.control.terminate().await;
+    }
+
+    /// Processes a single packet right away, bypassing the [`Input`]
+    /// and the queue: the packet goes through each successive state
+    /// and the resulting output packet is dispatched to the [`Output`].
+    ///
+    /// This allows alternate sources of packets, as well as tests,
+    /// to drive the pipeline directly. The packet is accounted for
+    /// in the [`Counters`] like any other.
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`DropReason`] if the packet was dropped
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let sent = state_switcher.process(packet).await?;
+    /// ```
+    pub async fn process(&self, packet: T) -> Result<usize, DropReason> {
+        let counters = self.control.counters();
+        counters.record_received();
+        if let Some(kind) = packet.kind() {
+            counters.record_kind(kind);
+        }
+        let _in_flight = self.control.track();
+        self.processor().run(PacketContext::from(packet)).await
+    }
+
+    fn processor(&self) -> PacketProcessor<T, U> {
+        PacketProcessor {
+            registry: self.registry.load(),
+            output: self.dry_run.as_ref().unwrap_or(&self.output).clone(),
+            counters: self.control.counters(),
+            tap: self.tap.clone(),
+            retry_policy: self.retry_policy,
+            deadline: self.deadline,
+            on_enter: self.on_enter.clone(),
+            on_exit: self.on_exit.clone(),
+            events: self.control.events(),
+            dedup: self.dedup.clone(),
+        }
+    }
+
+    /// Moves the `StateSwitcher` into a new task running
+    /// [`start`], and returns a [`PipelineHandle`] to
+    /// control it
+    ///
+    /// [`start`]: StateSwitcher::start
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let handle = state_switcher.spawn();
+    ///
+    /// handle.stop();
+    /// handle.await_terminated().await;
+    /// println!("{} packets dropped", handle.stats().counters.dropped);
+    /// ```
+    pub fn spawn(self) -> PipelineHandle {
+        let handle = self.handle();
+        tokio::spawn(async move { self.start().await });
+        handle
+    }
+
+    /// Sets the [`RetryPolicy`] applied when dispatching
+    /// packets through the [`Output`].
+    ///
+    /// By default, a packet whose dispatch fails is dropped
+    /// right away.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// state_switcher.set_retry_policy(RetryPolicy::new(3, Duration::from_millis(5)));
+    /// ```
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Limits the number of packets processed
+    /// concurrently to `limit`.
+    ///
+    /// Once the limit is reached, packets wait in the
+    /// queue until a processing task completes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0
+    pub fn set_concurrency_limit(&mut self, limit: usize) {
+        assert!(limit > 0, "Concurrency limit must be greater than 0");
+        self.concurrency = Some(Arc::new(Semaphore::new(limit)));
+    }
+
+    /// Sets the maximum time a packet can spend in the
+    /// pipeline, from its reception to its dispatch.
+    ///
+    /// A packet exceeding its deadline is dropped instead
+    /// of going through its remaining states, or instead
+    /// of being dispatched. [`Hook`] can read the time left
+    /// through [`PacketContext::remaining_time`].
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// state_switcher.set_deadline(Duration::from_millis(500));
+    /// ```
+    pub fn set_deadline(&mut self, deadline: Duration) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Replaces the [`Counters`] of this `StateSwitcher`,
+    /// which allows sharing them with external components
+    /// or across several `StateSwitcher`
+    ///
+    /// Handles obtained before this call keep
+    /// reporting the previous counters.
+    pub fn set_metrics(&mut self, counters: Arc<Counters>) {
+        self.control.set_counters(counters);
+    }
+
+    /// Registers a callback invoked every time a [`PacketContext`]
+    /// enters a state, right before its [`Hook`] are executed.
+    ///
+    /// Any previously registered callback is replaced.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// state_switcher.on_enter(|state, context| trace!("{} entered {:?}", context.id(), state));
+    /// ```
+    pub fn on_enter(
+        &mut self,
+        callback: impl Fn(PacketState, &PacketContext<T, U>) + Send + Sync + 'static,
+    ) {
+        self.on_enter = Some(Arc::new(callback));
+    }
+
+    /// Registers a callback invoked every time a [`PacketContext`]
+    /// exits a state, right after its [`Hook`] were executed.
+    ///
+    /// Any previously registered callback is replaced.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// state_switcher.on_exit(|state, context| {
+    ///     trace!("{} exited {:?} after {:?}", context.id(), state, context.lifetime())
+    /// });
+    /// ```
+    pub fn on_exit(
+        &mut self,
+        callback: impl Fn(PacketState, &PacketContext<T, U>) + Send + Sync + 'static,
+    ) {
+        self.on_exit = Some(Arc::new(callback));
+    }
+
+    /// Drops packets whose fingerprint matches the one of
+    /// a packet received less than `ttl` earlier, so that client
+    /// retransmissions during slow processing are not processed twice.
+    ///
+    /// Duplicates are accounted for in [`drop_count`].
+    ///
+    /// [`drop_count`]: StateSwitcher::drop_count
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// state_switcher.set_dedup(Duration::from_secs(2), |packet| {
+    ///     [&packet.xid.to_be_bytes()[..], &packet.chaddr[..], &[packet.message_type]].concat()
+    /// });
+    /// ```
+    pub fn set_dedup(
+        &mut self,
+        ttl: Duration,
+        fingerprint: impl Fn(&T) -> Vec<u8> + Send + Sync + 'static,
+    ) {
+        self.set_deduplicator(Deduplicator::new(ttl, fingerprint));
+    }
+
+    pub(crate) fn set_deduplicator(&mut self, dedup: Deduplicator<T>) {
+        self.dedup = Some(Arc::new(dedup));
+    }
+
+    /// Enables the dry-run mode when `sink` is set, disables it otherwise.
+    ///
+    /// In dry-run mode, packets go through every state as
+    /// usual but the resulting output packets are handed over
+    /// to `sink` instead of the [`Output`]: a [`RecordingOutput`]
+    /// keeps them for inspection, a [`NullOutput`] only logs them.
+    ///
+    /// [`RecordingOutput`]: crate::netio::dry_run::RecordingOutput
+    /// [`NullOutput`]: crate::netio::dry_run::NullOutput
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let recording = RecordingOutput::new();
+    /// state_switcher.set_dry_run(Some(Box::new(recording.clone())));
+    /// for packet in captured {
+    ///     state_switcher.process(packet).await?;
+    /// }
+    /// let replies = recording.take();
+    /// ```
+    pub fn set_dry_run(&mut self, sink: Option<Box<dyn Output<U>>>) {
+        self.dry_run = sink.map(Arc::new);
+    }
+
+    /// Returns whether the dry-run mode is enabled
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.is_some()
+    }
+
+    /// Installs a sampling tap forwarding the raw input
+    /// and output bytes of one [`PacketContext`] out of `rate`
+    /// to `sink`, right before its output packet is dispatched.
+    ///
+    /// Any previously installed tap is replaced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is 0
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// state_switcher.set_tap(100, |sample| debug!("{:?}", sample.output));
+    /// ```
+    pub fn set_tap(&mut self, rate: usize, sink: impl Fn(PacketSample) + Send + Sync + 'static) {
+        self.tap = Some(Arc::new(PacketTap::new(rate, sink)));
+    }
+
+    /// Removes the sampling tap, if any
+    pub fn clear_tap(&mut self) {
+        self.tap = None;
+    }
+
+    /// Stops dequeuing new packets until [`resume`] is called.
+    ///
+    /// Packets already being processed go through their
+    /// remaining states and are sent as usual. The [`Input`]
+    /// keeps being read in the meantime, so incoming packets
+    /// pile up in the queue, subject to its [`OverflowPolicy`].
+    ///
+    /// [`resume`]: StateSwitcher::resume
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// state_switcher.pause();
+    /// storage.sync();
+    /// state_switcher.resume();
+    /// ```
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    /// Resumes dequeuing packets after a call to [`pause`]
+    ///
+    /// [`pause`]: StateSwitcher::pause
+    pub fn resume(&self) {
+        self.control.resume();
+    }
+
+    /// Returns whether the `StateSwitcher` is currently paused
+    pub fn is_paused(&self) -> bool {
+        self.control.is_paused()
+    }
+
+    /// Reads packets from the [`Input`] and pushes them
+    /// into the processing queue until the kill switch
+    /// is turned off
+    async fn read_input(
+        input: Arc<Box<dyn Input<T>>>,
+        queue: QueueSender<(T, PacketMetadata)>,
+        control: PipelineHandle,
+        counters: Arc<Counters>,
+    ) {
+        while control.is_running() {
+            let (packet, metadata) = match input.get_with_metadata().await {
+                Ok(pak) => pak,
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::InvalidData {
+                        counters.record_malformed();
                     }
-                    context.set_state(state);
-                    match registry.run_hooks(&mut context) {
-                        Ok(_) => (),
-                        Err(_) => {
-                            drops.store(drops.load(SeqCst) + 1, SeqCst);
-                        }
-                    };
+                    continue;
                 }
+            };
 
-                let output_packet = context.drop();
-                let bytes_len = output_packet.to_raw_bytes().len();
-                let success = output
-                    .send(output_packet)
-                    .await
-                    .ok()
-                    .map(|len| len == bytes_len)
-                    .unwrap_or(false);
-
-                if !success {
-                    drops.store(drops.load(SeqCst) + 1, SeqCst);
-                }
-            });
+            counters.record_received();
+            if let Some(kind) = packet.kind() {
+                counters.record_kind(kind);
+            }
+            if !queue.push((packet, metadata)).await {
+                counters.record_dropped();
+                let _ = control.events().send(PipelineEvent::Overflow);
+            }
         }
     }
 
     /// Returns the number of packet dropped
     /// either through unsuccessful fatal [`Hook`]
-    /// execution, or at the output.
-
+    /// execution, at the output, or because the
+    /// processing queue was full.
+    ///
+    /// Each packet is accounted for at most once.
     pub fn drop_count(&self) -> usize {
-        self.dropped.load(SeqCst)
+        self.control.counters().dropped()
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::time::Duration;
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
     use tokio::time::sleep;
 
-    use crate::hooks::{
-        flags::HookFlag,
-        hook_registry::{Hook, HookClosure},
+    use crate::{
+        core::errors::{HookError, ParseError},
+        hooks::{
+            flags::HookFlag,
+            hook_registry::{Hook, HookClosure},
+        },
+        netio::dry_run::RecordingOutput,
     };
 
     use super::*;
@@ -272,4 +737,475 @@ mod tests {
 
         assert_eq!(state_switcher.drop_count(), 0);
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pause_resume() {
+        let processed = Arc::new(AtomicUsize::new(0));
+        let counter = processed.clone();
+
+        let mut registry: HookRegistry<A, A> = HookRegistry::new();
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("test_hook"),
+                HookClosure(Box::new(move |_, packet: &mut PacketContext<A, A>| {
+                    counter.fetch_add(1, SeqCst);
+                    packet.get_mut_output().name = 2;
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+        let input = SimpleInput {};
+        let output = SimpleOutput {};
+
+        let switch = Arc::new(AtomicBool::new(true));
+        let state_switcher = Arc::new(StateSwitcher::new(
+            Box::new(input),
+            Box::new(output),
+            registry,
+            switch.clone(),
+        ));
+        state_switcher.pause();
+        assert!(state_switcher.is_paused());
+
+        let runner = state_switcher.clone();
+        let handle = tokio::spawn(async move { runner.start().await });
+
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(processed.load(SeqCst), 0);
+
+        state_switcher.resume();
+        sleep(Duration::from_millis(300)).await;
+        assert!(processed.load(SeqCst) > 0);
+
+        switch.store(false, SeqCst);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_builder() {
+        let missing_input = StateSwitcher::<A, A>::builder()
+            .output(SimpleOutput {})
+            .build();
+        assert!(missing_input.is_err());
+
+        let counters = Arc::new(Counters::new());
+        let mut registry: HookRegistry<A, A> = HookRegistry::new();
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("test_hook"),
+                HookClosure(Box::new(|_, packet: &mut PacketContext<A, A>| {
+                    packet.get_mut_output().name = 2;
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+        let (state_switcher, handle) = StateSwitcher::builder()
+            .input(SimpleInput {})
+            .output(SimpleOutput {})
+            .registry(registry)
+            .concurrency_limit(4)
+            .metrics(counters.clone())
+            .build()
+            .unwrap();
+
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(300)).await;
+            handle.stop();
+        });
+        state_switcher.start().await;
+
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot.dropped, 0);
+        assert!(snapshot.sent > 0);
+        assert_eq!(snapshot.received, snapshot.sent);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deadline() {
+        let prepared = Arc::new(AtomicUsize::new(0));
+        let counter = prepared.clone();
+
+        let mut registry: HookRegistry<A, A> = HookRegistry::new();
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("slow_hook"),
+                HookClosure(Box::new(|_, packet: &mut PacketContext<A, A>| {
+                    std::thread::sleep(Duration::from_millis(20));
+                    packet.get_mut_output().name = 2;
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+        registry.register_hook(
+            PacketState::Prepared,
+            Hook::new(
+                String::from("unreachable_hook"),
+                HookClosure(Box::new(move |_, _: &mut PacketContext<A, A>| {
+                    counter.fetch_add(1, SeqCst);
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+        let (state_switcher, handle) = StateSwitcher::builder()
+            .input(SimpleInput {})
+            .output(SimpleOutput {})
+            .registry(registry)
+            .queue(1, OverflowPolicy::Block)
+            .concurrency_limit(1)
+            .deadline(Duration::from_millis(5))
+            .build()
+            .unwrap();
+
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(200)).await;
+            handle.stop();
+        });
+        state_switcher.start().await;
+        sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(prepared.load(SeqCst), 0);
+        assert!(state_switcher.drop_count() > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pipeline_handle() {
+        let mut registry: HookRegistry<A, A> = HookRegistry::new();
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("test_hook"),
+                HookClosure(Box::new(|_, packet: &mut PacketContext<A, A>| {
+                    packet.get_mut_output().name = 2;
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+        let (state_switcher, _) = StateSwitcher::builder()
+            .input(SimpleInput {})
+            .output(SimpleOutput {})
+            .registry(registry)
+            .build()
+            .unwrap();
+
+        let handle = state_switcher.spawn();
+        sleep(Duration::from_millis(200)).await;
+        assert!(handle.stats().running);
+        assert!(!handle.is_terminated());
+
+        handle.stop();
+        handle.await_terminated().await;
+
+        let stats = handle.stats();
+        assert!(!stats.running);
+        assert_eq!(stats.counters.in_flight, 0);
+        assert_eq!(stats.counters.dropped, 0);
+    }
+
+    struct MalformedInput {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Input<A> for MalformedInput {
+        async fn get(&self) -> Result<A, std::io::Error> {
+            sleep(Duration::from_millis(1)).await;
+            match self.calls.fetch_add(1, SeqCst) % 2 {
+                0 => Err(ParseError::TooShort(4).into()),
+                _ => Ok(A::empty()),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_malformed_packets() {
+        let (state_switcher, _) = StateSwitcher::builder()
+            .input(MalformedInput {
+                calls: AtomicUsize::new(0),
+            })
+            .output(SimpleOutput {})
+            .registry(HookRegistry::new())
+            .build()
+            .unwrap();
+
+        let handle = state_switcher.spawn();
+        sleep(Duration::from_millis(100)).await;
+        handle.stop();
+        handle.await_terminated().await;
+
+        //Malformed packets are counted, and don't stop the input
+        let counters = handle.stats().counters;
+        assert!(counters.malformed > 0);
+        assert!(counters.received > 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_state_callbacks() {
+        let transitions = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let entered = transitions.clone();
+        let exited = transitions.clone();
+
+        let mut registry: HookRegistry<A, A> = HookRegistry::new();
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("test_hook"),
+                HookClosure(Box::new(|_, packet: &mut PacketContext<A, A>| {
+                    packet.get_mut_output().name = 2;
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+        let (mut state_switcher, handle) = StateSwitcher::builder()
+            .input(SimpleInput {})
+            .output(SimpleOutput {})
+            .registry(registry)
+            .queue(1, OverflowPolicy::Block)
+            .concurrency_limit(1)
+            .build()
+            .unwrap();
+        state_switcher.on_enter(move |state, context: &PacketContext<A, A>| {
+            entered
+                .lock()
+                .unwrap()
+                .push((true, state, context.get_output().name));
+        });
+        state_switcher.on_exit(move |state, context: &PacketContext<A, A>| {
+            exited
+                .lock()
+                .unwrap()
+                .push((false, state, context.get_output().name));
+        });
+
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(50)).await;
+            handle.stop();
+        });
+        state_switcher.start().await;
+
+        let transitions = transitions.lock().unwrap();
+        assert!(!transitions.is_empty());
+        assert_eq!(
+            transitions[..4],
+            [
+                (true, PacketState::Received, 1),
+                (false, PacketState::Received, 2),
+                (true, PacketState::Prepared, 2),
+                (false, PacketState::Prepared, 2),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process() {
+        let mut registry: HookRegistry<A, A> = HookRegistry::new();
+        registry.register_hook(
+            PacketState::Prepared,
+            Hook::new(
+                String::from("test_hook"),
+                HookClosure(Box::new(|_, packet: &mut PacketContext<A, A>| {
+                    packet.get_mut_output().name = packet.get_input().name;
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("failing_hook"),
+                HookClosure(Box::new(|_, _| Err(HookError::new("Failing hook")))),
+                Vec::default(),
+            ),
+        );
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("fatal_hook"),
+                HookClosure(Box::new(|_, packet: &mut PacketContext<A, A>| match packet
+                    .get_input()
+                    .name
+                {
+                    4 => Err(HookError::new("Fatal hook")),
+                    _ => Ok(1),
+                })),
+                vec![HookFlag::Fatal],
+            ),
+        );
+        let (state_switcher, handle) = StateSwitcher::builder()
+            .input(SimpleInput {})
+            .output(SimpleOutput {})
+            .registry(registry)
+            .build()
+            .unwrap();
+
+        assert_eq!(state_switcher.process(A { name: 2 }).await, Ok(1));
+        assert_eq!(
+            state_switcher.process(A { name: 3 }).await,
+            Err(DropReason::OutputFailure)
+        );
+        assert_eq!(
+            state_switcher.process(A { name: 4 }).await,
+            Err(DropReason::HookFailure)
+        );
+
+        let counters = handle.stats().counters;
+        assert_eq!(counters.received, 3);
+        assert_eq!(counters.sent, 1);
+        assert_eq!(counters.dropped, 2);
+        assert_eq!(counters.in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let (state_switcher, handle) = StateSwitcher::<A, A>::builder()
+            .input(SimpleInput {})
+            .output(SimpleOutput {})
+            .build()
+            .unwrap();
+        let mut events = handle.subscribe();
+
+        assert_eq!(
+            state_switcher.process(A { name: 3 }).await,
+            Err(DropReason::OutputFailure)
+        );
+
+        let PipelineEvent::Received { id } = events.recv().await.unwrap() else {
+            panic!("expected a Received event");
+        };
+        for state in enum_iterator::all::<PacketState>().filter(|x| *x != PacketState::Failure) {
+            assert_eq!(
+                events.recv().await.unwrap(),
+                PipelineEvent::StateEntered { id, state }
+            );
+        }
+        assert_eq!(
+            events.recv().await.unwrap(),
+            PipelineEvent::Dropped {
+                id,
+                reason: DropReason::OutputFailure
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let (state_switcher, handle) = StateSwitcher::<A, A>::builder()
+            .input(SimpleInput {})
+            .output(SimpleOutput {})
+            .dedup(Duration::from_secs(60), |packet| {
+                packet.name.to_be_bytes().to_vec()
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            state_switcher.process(A { name: 3 }).await,
+            Err(DropReason::OutputFailure)
+        );
+        assert_eq!(
+            state_switcher.process(A { name: 3 }).await,
+            Err(DropReason::Duplicate)
+        );
+        assert_eq!(handle.stats().counters.dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let output = RecordingOutput::new();
+        let recording = RecordingOutput::new();
+        let (mut state_switcher, handle) = StateSwitcher::<A, A>::builder()
+            .input(SimpleInput {})
+            .output(output.clone())
+            .dry_run(recording.clone())
+            .build()
+            .unwrap();
+
+        assert!(state_switcher.is_dry_run());
+        assert_eq!(state_switcher.process(A { name: 3 }).await, Ok(1));
+        assert!(output.is_empty());
+        assert_eq!(recording.len(), 1);
+
+        state_switcher.set_dry_run(None);
+        assert_eq!(state_switcher.process(A { name: 3 }).await, Ok(1));
+        assert_eq!(output.len(), 1);
+        assert_eq!(recording.len(), 1);
+        assert_eq!(handle.stats().counters.sent, 2);
+    }
+
+    struct AddressedOutput {
+        destinations: Arc<std::sync::Mutex<Vec<Option<SocketAddr>>>>,
+    }
+
+    #[async_trait]
+    impl Output<A> for AddressedOutput {
+        async fn send(&self, _packet: A) -> Result<usize, std::io::Error> {
+            self.destinations.lock().unwrap().push(None);
+            Ok(1)
+        }
+
+        async fn send_to(
+            &self,
+            _packet: A,
+            destination: SocketAddr,
+        ) -> Result<usize, std::io::Error> {
+            self.destinations.lock().unwrap().push(Some(destination));
+            Ok(1)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_output_destination() {
+        let relay: SocketAddr = "10.0.1.1:67".parse().unwrap();
+        let mut registry: HookRegistry<A, A> = HookRegistry::new();
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("address_hook"),
+                HookClosure(Box::new(move |_, packet: &mut PacketContext<A, A>| {
+                    if packet.get_input().name >= 2 {
+                        packet.set_destination(relay);
+                    }
+                    if packet.get_input().name == 4 {
+                        packet.set_link_address(MacAddress::new([0xaa, 0, 0, 0, 0, 1]));
+                    }
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+        let destinations = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let state_switcher = StateSwitcher::new(
+            Box::new(SimpleInput {}),
+            Box::new(AddressedOutput {
+                destinations: destinations.clone(),
+            }),
+            registry,
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        state_switcher.process(A { name: 2 }).await.unwrap();
+        state_switcher.process(A { name: 1 }).await.unwrap();
+        //Outputs unable to send frames fall back to the destination
+        state_switcher.process(A { name: 4 }).await.unwrap();
+        assert_eq!(
+            *destinations.lock().unwrap(),
+            vec![Some(relay), None, Some(relay)]
+        );
+    }
+
+    #[test]
+    fn test_thread_safety() {
+        fn assert_send_sync<V: Send + Sync>() {}
+        assert_send_sync::<StateSwitcher<A, A>>();
+        assert_send_sync::<HookRegistry<A, A>>();
+    }
 }
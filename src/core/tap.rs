@@ -0,0 +1,120 @@
+//! Lightweight live traffic inspection for the
+//! [`StateSwitcher`].
+//!
+//! A [`PacketTap`] copies the raw input and output bytes
+//! of every Nth [`PacketContext`] going through the pipeline
+//! and hands them over to a user-provided sink.
+//!
+//! [`StateSwitcher`]: super::state_switcher::StateSwitcher
+
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use uuid::Uuid;
+
+use super::packet::{PacketContext, PacketType};
+
+/// Raw bytes of a sampled [`PacketContext`], copied
+/// right before its output packet is dispatched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketSample {
+    pub id: Uuid,
+    pub input: Vec<u8>,
+    pub output: Vec<u8>,
+}
+
+pub type TapSink = dyn Fn(PacketSample) + Send + Sync;
+
+/// Samples one [`PacketContext`] out of `rate`
+/// and forwards its raw bytes to a sink
+pub struct PacketTap {
+    rate: usize,
+    seen: AtomicUsize,
+    sink: Box<TapSink>,
+}
+
+impl PacketTap {
+    /// Creates a new `PacketTap` forwarding one packet
+    /// out of `rate` to `sink`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is 0
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let tap = PacketTap::new(100, |sample| println!("{:?}", sample.output));
+    /// ```
+    pub fn new(rate: usize, sink: impl Fn(PacketSample) + Send + Sync + 'static) -> Self {
+        assert!(rate > 0, "Sampling rate must be greater than 0");
+        Self {
+            rate,
+            seen: AtomicUsize::new(0),
+            sink: Box::new(sink),
+        }
+    }
+
+    /// Returns the sampling rate of this `PacketTap`
+    pub fn rate(&self) -> usize {
+        self.rate
+    }
+
+    /// Accounts for a new [`PacketContext`], forwarding
+    /// its raw bytes to the sink if it is sampled
+    pub fn observe<T: PacketType, U: PacketType>(&self, context: &PacketContext<T, U>) {
+        if !self.seen.fetch_add(1, SeqCst).is_multiple_of(self.rate) {
+            return;
+        }
+
+        (self.sink)(PacketSample {
+            id: context.id(),
+            input: context.input_to_raw().to_vec(),
+            output: context.output_to_raw().to_vec(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct A {
+        raw: [u8; 2],
+    }
+    impl PacketType for A {
+        fn empty() -> Self {
+            Self { raw: [4, 2] }
+        }
+        fn from_raw_bytes(_: &[u8]) -> Self {
+            todo!()
+        }
+
+        fn to_raw_bytes(&self) -> &[u8] {
+            &self.raw
+        }
+    }
+
+    #[test]
+    fn test_sampling_rate() {
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let sink = samples.clone();
+        let tap = PacketTap::new(3, move |sample| sink.lock().unwrap().push(sample));
+
+        let contexts: Vec<PacketContext<A, A>> =
+            (0..7).map(|_| PacketContext::from(A::empty())).collect();
+        for context in contexts.iter() {
+            tap.observe(context);
+        }
+
+        let samples = samples.lock().unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].id, contexts[0].id());
+        assert_eq!(samples[1].id, contexts[3].id());
+        assert_eq!(samples[2].id, contexts[6].id());
+        assert_eq!(samples[0].output, vec![4, 2]);
+    }
+}
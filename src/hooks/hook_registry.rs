@@ -24,11 +24,14 @@ use crate::core::{
 
 use super::{flags::HookFlag, typemap::TypeMap};
 
-pub struct HookClosure<T: PacketType, U: PacketType>(
-    pub Box<dyn Fn(Arc<Mutex<TypeMap>>, &mut PacketContext<T, U>) -> Result<isize, HookError>>,
-);
-unsafe impl<T: PacketType, U: PacketType> Send for HookClosure<T, U> {}
-unsafe impl<T: PacketType, U: PacketType> Sync for HookClosure<T, U> {}
+type HookFn<T, U> =
+    dyn Fn(Arc<Mutex<TypeMap>>, &mut PacketContext<T, U>) -> Result<isize, HookError> + Send + Sync;
+
+/// The closure executed by a [`Hook`]
+///
+/// As [`Hook`] are executed concurrently on several
+/// packets, the closure must be [`Send`] and [`Sync`].
+pub struct HookClosure<T: PacketType, U: PacketType>(pub Box<HookFn<T, U>>);
 
 /// An encapsulated closure, to be executed on a [`PacketContext`]
 /// to perform all types of actions. They make most of the
@@ -90,7 +93,6 @@ impl<T: PacketType + Send, U: PacketType + Send> Hook<T, U> {
     /// let test_hook = Hook::new("My hook", Box::new(|_, _| {} ));
     /// println!(test_hook.id());
     /// ```
-
     pub fn id(&self) -> Uuid {
         self.id
     }
@@ -103,7 +105,6 @@ impl<T: PacketType + Send, U: PacketType + Send> Hook<T, U> {
     /// let test_hook = Hook::new("My hook", Box::new(|_, _| {} ));
     /// test_hook.add_flag(HookFlags::Fatal);
     /// ```
-
     pub fn add_flag(&mut self, new_flag: HookFlag) {
         self.flags.push(new_flag);
     }
@@ -117,7 +118,6 @@ impl<T: PacketType + Send, U: PacketType + Send> Hook<T, U> {
     /// test_hook.add_flag(HookFlag::Fatal);
     /// test_hook.flags().contains(&HookFlag::Fatal);
     /// ```
-
     pub fn flags(&self) -> &Vec<HookFlag> {
         &self.flags
     }
@@ -132,7 +132,6 @@ impl<T: PacketType + Send, U: PacketType + Send> Hook<T, U> {
     ///
     /// dependent_hook.must(my_hook.id);
     /// ```
-
     pub fn must(&mut self, hook: Uuid) {
         self.dependencies.insert(hook, true);
     }
@@ -185,7 +184,7 @@ impl<T: PacketType + Send, U: PacketType + Send> HookRegistry<T, U> {
             registry: HashMap::new(),
             services: Arc::new(Mutex::new(TypeMap::new())),
             exec_order: HashMap::new(),
-            need_update: true,
+            need_update: false,
         }
     }
 
@@ -195,7 +194,8 @@ impl<T: PacketType + Send, U: PacketType + Send> HookRegistry<T, U> {
     /// # Errors
     ///
     /// Returns [`HookError`] if any [`Hook`] holding the [`Fatal`]
-    /// flag panics.
+    /// flag fails, once the failure hooks ran. The remaining
+    /// hooks are not executed.
     ///
     /// [`Fatal`]: crate::hooks::flags::HookFlag::Fatal
     ///
@@ -246,21 +246,20 @@ impl<T: PacketType + Send, U: PacketType + Send> HookRegistry<T, U> {
             }
 
             if self.can_execute(&exec_code, &hook.dependencies) {
-                (hook.exec.0)(self.services.clone(), packet)
-                    .map(|x| {
+                match (hook.exec.0)(self.services.clone(), packet) {
+                    Ok(x) => {
                         exec_code.insert(hook.id, x);
                         trace!("Hook {} exited successfully (exit code {})", hook.name, x);
-                    })
-                    .or_else(|_| {
-                        if hook.flags.contains(&HookFlag::Fatal) {
-                            self.run_failure_chain(packet)
-                        } else {
-                            exec_code.insert(hook.id, -1);
-                            debug!("Hook {} exited with failure (exit code -1)", hook.name);
-                            Ok::<(), HookError>(())
-                        }
-                    })
-                    .unwrap();
+                    }
+                    Err(_) if hook.flags.contains(&HookFlag::Fatal) => {
+                        debug!("Fatal hook {} exited with failure", hook.name);
+                        return self.run_failure_chain(packet);
+                    }
+                    Err(_) => {
+                        exec_code.insert(hook.id, -1);
+                        debug!("Hook {} exited with failure (exit code -1)", hook.name);
+                    }
+                }
             } else {
                 trace!(
                     "Skipped execution of hook {} because of unmet requirements",
@@ -281,7 +280,6 @@ impl<T: PacketType + Send, U: PacketType + Send> HookRegistry<T, U> {
     /// let my_hook = Hook::new("My hook", Box::new(|_, _| { }));
     /// registry.register_hook(PacketState::Received, my_hook);
     /// ```
-
     pub fn register_hook(&mut self, state: PacketState, hook: Hook<T, U>) {
         self.need_update = true;
         if let Entry::Vacant(e) = self.registry.entry(state) {
@@ -300,7 +298,6 @@ impl<T: PacketType + Send, U: PacketType + Send> HookRegistry<T, U> {
     ///
     /// The service's type must implement the following traits:
     /// [`Send`] and [`Sync`]
-
     pub fn register_service<V: Send + Sync + 'static>(&mut self, service: V) {
         self.services
             .lock()
@@ -437,6 +434,34 @@ mod tests {
         assert_eq!(packet.get_output().name, 2);
     }
 
+    #[test]
+    fn test_fatal_hook() {
+        let mut registry: HookRegistry<A, A> = HookRegistry::new();
+        registry.register_hook(
+            PacketState::Received,
+            Hook::new(
+                String::from("fatal_hook"),
+                HookClosure(Box::new(|_, _| Err(HookError::new("Fatal hook")))),
+                vec![HookFlag::Fatal],
+            ),
+        );
+        registry.register_hook(
+            PacketState::Failure,
+            Hook::new(
+                String::from("failure_hook"),
+                HookClosure(Box::new(|_, packet: &mut PacketContext<A, A>| {
+                    packet.get_mut_output().name = 3;
+                    Ok(1)
+                })),
+                Vec::default(),
+            ),
+        );
+
+        let mut packet: PacketContext<A, A> = PacketContext::from(A::empty());
+        assert!(registry.run_hooks(&mut packet).is_err());
+        assert_eq!(packet.get_output().name, 3);
+    }
+
     #[test]
     fn test_dependency_hook() {
         let mut registry: HookRegistry<A, A> = HookRegistry::new();
@@ -0,0 +1,81 @@
+//! UDP sockets bound to a network interface, so that a
+//! [`StateSwitcher`] only serves the clients of that interface.
+//!
+//! Linux uses `SO_BINDTODEVICE`, Apple systems `IP_BOUND_IF`.
+//! Binding to a device requires `CAP_NET_RAW` on Linux kernels
+//! older than 5.7.
+//!
+//! [`StateSwitcher`]: crate::core::state_switcher::StateSwitcher
+
+use std::{io, net::SocketAddr};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+/// Binds a non-blocking [`UdpSocket`] to `addr`, only
+/// receiving and sending through `interface`
+///
+/// `SO_REUSEADDR` is enabled, so that one socket per
+/// interface can be bound to the same address.
+///
+/// # Errors
+///
+/// Returns an [`io::Error`] if `addr` cannot be parsed, if
+/// `interface` does not exist or if the socket cannot be bound
+///
+/// # Examples:
+///
+/// ```
+/// let lan = bind_device("0.0.0.0:67", "eth0")?;
+/// let guests = bind_device("0.0.0.0:67", "eth1")?;
+/// ```
+pub fn bind_device(addr: &str, interface: &str) -> Result<UdpSocket, io::Error> {
+    let addr = super::ipv6::parse_addr(addr)?;
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    set_device(&socket, addr, interface)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+#[cfg(target_os = "linux")]
+fn set_device(socket: &Socket, _addr: SocketAddr, interface: &str) -> Result<(), io::Error> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(target_vendor = "apple")]
+fn set_device(socket: &Socket, addr: SocketAddr, interface: &str) -> Result<(), io::Error> {
+    let name = std::ffi::CString::new(interface)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+    // SAFETY: name is a valid NUL-terminated string
+    let index = std::num::NonZeroU32::new(unsafe { libc::if_nametoindex(name.as_ptr()) })
+        .ok_or_else(io::Error::last_os_error)?;
+    match addr {
+        SocketAddr::V4(_) => socket.bind_device_by_index_v4(Some(index)),
+        SocketAddr::V6(_) => socket.bind_device_by_index_v6(Some(index)),
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_device() {
+        let socket = match bind_device("127.0.0.1:0", "lo") {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            socket => socket.unwrap(),
+        };
+        let addr = socket.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.send_to(&[1, 2, 3], addr).await.unwrap();
+        let mut buf = [0; 3];
+        assert_eq!(socket.recv(&mut buf).await.unwrap(), 3);
+
+        assert!(bind_device("127.0.0.1:0", "fp_core_none").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Pool of receive buffers.
+//!
+//! Receiving a datagram requires a buffer large enough for any
+//! datagram. Instead of allocating one per packet, inputs take a
+//! [`PooledBuffer`] from a [`BufferPool`], parse the packet right
+//! from it, and the buffer goes back to the pool once dropped, so
+//! that steady-state reception does not allocate.
+
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+/// Size of a buffer able to hold any UDP datagram
+pub const DATAGRAM_BUFFER_LEN: usize = 65535;
+/// Default number of idle buffers kept by a pool
+pub const DEFAULT_POOL_CAPACITY: usize = 64;
+
+/// Shared pool of fixed-size buffers
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+    buffer_len: usize,
+    capacity: usize,
+}
+
+impl BufferPool {
+    /// Creates a pool of buffers of `buffer_len` bytes,
+    /// keeping at most `capacity` idle buffers
+    ///
+    /// Buffers are allocated on demand, so a pool never runs
+    /// out of buffers; buffers dropped while the pool is full
+    /// are freed.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let pool = BufferPool::new(DATAGRAM_BUFFER_LEN, 256);
+    /// let mut udp_input = UdpInput::start("0.0.0.0:67").await?;
+    /// udp_input.set_buffer_pool(pool.clone());
+    /// ```
+    pub fn new(buffer_len: usize, capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            free: Mutex::new(Vec::with_capacity(capacity)),
+            buffer_len,
+            capacity,
+        })
+    }
+
+    /// Returns an idle buffer, allocating one if none is
+    pub fn get(self: &Arc<Self>) -> PooledBuffer {
+        let buffer = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| vec![0u8; self.buffer_len]);
+        PooledBuffer {
+            len: buffer.len(),
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    /// Returns the number of idle buffers
+    pub fn available(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    fn put(&self, buffer: Vec<u8>) {
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.capacity {
+            free.push(buffer);
+        }
+    }
+}
+
+/// Buffer taken from a [`BufferPool`], going back to
+/// the pool when dropped
+///
+/// It dereferences to its first [`len`](PooledBuffer::set_len)
+/// bytes, which are all of its bytes until the length is set.
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    len: usize,
+    pool: Arc<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// Returns the whole buffer, to receive data into
+    pub fn as_mut_buffer(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    /// Sets the number of bytes of the buffer holding data
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is larger than the buffer
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.buffer.len(), "Length exceeds the buffer");
+        self.len = len;
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_buffer_reuse() {
+        let pool = BufferPool::new(16, 1);
+        let mut first = pool.get();
+        assert_eq!(first.len(), 16);
+        first.as_mut_buffer()[..3].copy_from_slice(&[1, 2, 3]);
+        first.set_len(3);
+        assert_eq!(&*first, &[1, 2, 3]);
+        let address = first.as_ptr();
+
+        let second = pool.get();
+        drop(first);
+        assert_eq!(pool.available(), 1);
+        let reused = pool.get();
+        assert_eq!(reused.as_ptr(), address);
+        assert_eq!(reused.len(), 16);
+        assert_eq!(pool.available(), 0);
+
+        //Only one idle buffer is kept
+        drop(second);
+        drop(reused);
+        assert_eq!(pool.available(), 1);
+    }
+}
@@ -0,0 +1,179 @@
+//! Construction of UDP datagrams, IP header included.
+//!
+//! Used wherever datagrams do not go through the IP stack:
+//! when sending at the link layer, or writing captures.
+
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+
+/// Time to live, or hop limit, of the datagrams built
+pub const DEFAULT_TTL: u8 = 64;
+/// Length of an IPv4 header without options
+pub const IPV4_HEADER_LEN: usize = 20;
+/// Length of an IPv6 header without extension headers
+pub const IPV6_HEADER_LEN: usize = 40;
+/// Length of a UDP header
+pub const UDP_HEADER_LEN: usize = 8;
+/// Protocol number of UDP
+pub const IPPROTO_UDP: u8 = 17;
+
+/// Builds the IPv4 datagram carrying `payload` from `source` to
+/// `destination` over UDP
+pub fn build_datagram(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (UDP_HEADER_LEN + payload.len()) as u16;
+    let total_len = IPV4_HEADER_LEN as u16 + udp_len;
+    let mut datagram = Vec::with_capacity(total_len as usize);
+
+    //Version 4, 5 words of header, no DSCP
+    datagram.extend_from_slice(&[0x45, 0]);
+    datagram.extend_from_slice(&total_len.to_be_bytes());
+    //No identification, fragment or flags
+    datagram.extend_from_slice(&[0, 0, 0, 0]);
+    datagram.extend_from_slice(&[DEFAULT_TTL, IPPROTO_UDP, 0, 0]);
+    datagram.extend_from_slice(&source.ip().octets());
+    datagram.extend_from_slice(&destination.ip().octets());
+    let checksum = internet_checksum(&datagram, 0);
+    datagram[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    datagram.extend_from_slice(&source.port().to_be_bytes());
+    datagram.extend_from_slice(&destination.port().to_be_bytes());
+    datagram.extend_from_slice(&udp_len.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+
+    let pseudo_header = [
+        &source.ip().octets()[..],
+        &destination.ip().octets(),
+        &[0, IPPROTO_UDP],
+        &udp_len.to_be_bytes(),
+    ]
+    .concat();
+    let checksum = match internet_checksum(&datagram[IPV4_HEADER_LEN..], sum_words(&pseudo_header))
+    {
+        //A zero checksum means no checksum for UDP
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    datagram[IPV4_HEADER_LEN + 6..IPV4_HEADER_LEN + 8].copy_from_slice(&checksum.to_be_bytes());
+    datagram
+}
+
+/// Builds the IPv6 datagram carrying `payload` from `source` to
+/// `destination` over UDP
+pub fn build_datagram_v6(
+    source: SocketAddrV6,
+    destination: SocketAddrV6,
+    payload: &[u8],
+) -> Vec<u8> {
+    let udp_len = (UDP_HEADER_LEN + payload.len()) as u16;
+    let mut datagram = Vec::with_capacity(IPV6_HEADER_LEN + udp_len as usize);
+
+    //Version 6, no traffic class or flow label
+    datagram.extend_from_slice(&[0x60, 0, 0, 0]);
+    datagram.extend_from_slice(&udp_len.to_be_bytes());
+    datagram.extend_from_slice(&[IPPROTO_UDP, DEFAULT_TTL]);
+    datagram.extend_from_slice(&source.ip().octets());
+    datagram.extend_from_slice(&destination.ip().octets());
+
+    datagram.extend_from_slice(&source.port().to_be_bytes());
+    datagram.extend_from_slice(&destination.port().to_be_bytes());
+    datagram.extend_from_slice(&udp_len.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+
+    let pseudo_header = [
+        &source.ip().octets()[..],
+        &destination.ip().octets(),
+        &(udp_len as u32).to_be_bytes(),
+        &[0, 0, 0, IPPROTO_UDP],
+    ]
+    .concat();
+    let checksum = match internet_checksum(&datagram[IPV6_HEADER_LEN..], sum_words(&pseudo_header))
+    {
+        //UDP checksums are mandatory over IPv6, zero is sent as all ones
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    datagram[IPV6_HEADER_LEN + 6..IPV6_HEADER_LEN + 8].copy_from_slice(&checksum.to_be_bytes());
+    datagram
+}
+
+/// Builds the datagram carrying `payload` from `source` to
+/// `destination`, or `None` if their IP versions differ
+pub fn build_any_datagram(
+    source: SocketAddr,
+    destination: SocketAddr,
+    payload: &[u8],
+) -> Option<Vec<u8>> {
+    match (source, destination) {
+        (SocketAddr::V4(source), SocketAddr::V4(destination)) => {
+            Some(build_datagram(source, destination, payload))
+        }
+        (SocketAddr::V6(source), SocketAddr::V6(destination)) => {
+            Some(build_datagram_v6(source, destination, payload))
+        }
+        _ => None,
+    }
+}
+
+fn sum_words(data: &[u8]) -> u32 {
+    data.chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .sum()
+}
+
+/// Computes the checksum of RFC 1071 over `data`, starting from `initial`
+fn internet_checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial + sum_words(data);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn test_build_datagram() {
+        let source = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 67);
+        let destination = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 42), 68);
+        let datagram = build_datagram(source, destination, &[1, 2, 3]);
+
+        assert_eq!(datagram.len(), 31);
+        assert_eq!(&datagram[2..4], &[0, 31]);
+        assert_eq!(&datagram[12..16], &[10, 0, 0, 1]);
+        assert_eq!(&datagram[16..20], &[10, 0, 0, 42]);
+        assert_eq!(&datagram[20..26], &[0, 67, 0, 68, 0, 11]);
+        //Valid checksums sum to zero
+        assert_eq!(internet_checksum(&datagram[..20], 0), 0);
+        let pseudo_header = [&datagram[12..20], &[0, 17, 0, 11]].concat();
+        assert_eq!(
+            internet_checksum(&datagram[20..], sum_words(&pseudo_header)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_build_datagram_v6() {
+        let source = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 547, 0, 0);
+        let destination = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 546, 0, 0);
+        let datagram = build_datagram_v6(source, destination, &[1, 2, 3]);
+
+        assert_eq!(datagram.len(), 51);
+        assert_eq!(&datagram[4..7], &[0, 11, IPPROTO_UDP]);
+        let pseudo_header = [&datagram[8..40], &[0, 0, 0, 11, 0, 0, 0, 17]].concat();
+        assert_eq!(
+            internet_checksum(&datagram[40..], sum_words(&pseudo_header)),
+            0
+        );
+        let v4 = SocketAddr::from((Ipv4Addr::LOCALHOST, 67));
+        assert_eq!(
+            build_any_datagram(v4, SocketAddr::V6(destination), &[]),
+            None
+        );
+    }
+}
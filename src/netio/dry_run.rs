@@ -0,0 +1,106 @@
+//! [`Output`] implementations which never send anything,
+//! allowing the whole pipeline to be exercised against
+//! captured traffic, e.g. to stage new [`Hook`] configurations.
+//!
+//! [`Hook`]: crate::hooks::hook_registry::Hook
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use log::debug;
+
+use crate::core::{packet::PacketType, state_switcher::Output};
+
+/// `NullOutput` logs and discards every packet,
+/// reporting them as successfully sent
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullOutput;
+
+#[async_trait]
+impl<T: PacketType + Send + 'static> Output<T> for NullOutput {
+    /// Discards the packet, returning its length
+    async fn send(&self, packet: T) -> Result<usize, std::io::Error> {
+        let len = packet.to_raw_bytes().len();
+        debug!("Dry run: discarded {} bytes", len);
+        Ok(len)
+    }
+}
+
+/// `RecordingOutput` keeps a copy of every packet
+/// instead of sending it, reporting them as
+/// successfully sent
+///
+/// Clones share the same records, so a clone can be
+/// kept to inspect the packets handed over to the
+/// `StateSwitcher`.
+///
+/// # Examples:
+///
+/// ```
+/// let output = RecordingOutput::new();
+/// let (state_switcher, _) = StateSwitcher::builder()
+///     .input(input)
+///     .output(output.clone())
+///     .build()?;
+/// state_switcher.process(packet).await?;
+/// assert_eq!(output.records().len(), 1);
+/// ```
+pub struct RecordingOutput<T> {
+    records: Arc<Mutex<Vec<T>>>,
+}
+
+impl<T> Clone for RecordingOutput<T> {
+    fn clone(&self) -> Self {
+        Self {
+            records: self.records.clone(),
+        }
+    }
+}
+
+impl<T> Default for RecordingOutput<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> RecordingOutput<T> {
+    /// Creates a new `RecordingOutput` with no records
+    pub fn new() -> Self {
+        Self {
+            records: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Removes and returns every packet recorded so far
+    pub fn take(&self) -> Vec<T> {
+        std::mem::take(&mut *self.records.lock().unwrap())
+    }
+
+    /// Returns the number of packets recorded so far
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    /// Returns whether no packet was recorded so far
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Clone> RecordingOutput<T> {
+    /// Returns a copy of every packet recorded so far
+    pub fn records(&self) -> Vec<T> {
+        self.records.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl<T: PacketType + Send + 'static> Output<T> for RecordingOutput<T> {
+    /// Records the packet, returning its length
+    async fn send(&self, packet: T) -> Result<usize, std::io::Error> {
+        let len = packet.to_raw_bytes().len();
+        debug!("Dry run: recorded {} bytes", len);
+        self.records.lock().unwrap().push(packet);
+        Ok(len)
+    }
+}
@@ -0,0 +1,357 @@
+//! [`Input`] listening on several network interfaces.
+//!
+//! An [`InterfaceManager`] opens a [`UdpInput`] bound to each
+//! selected interface of the system, and merges the packets they
+//! receive, so that a single [`StateSwitcher`] serves them all.
+//! Every packet is tagged with the index of the interface it was
+//! received on, in its [`PacketMetadata`].
+//!
+//! Interfaces appearing, or disappearing, after the manager was
+//! created are picked up by [`InterfaceManager::start`], which
+//! periodically rescans the interfaces of the system.
+//!
+//! [`StateSwitcher`]: crate::core::state_switcher::StateSwitcher
+
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{
+    sync::{mpsc, Notify},
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
+};
+
+use super::{buffer_pool::PooledBuffer, udp_input::UdpInput};
+use crate::core::{
+    packet::{PacketMetadata, PacketType},
+    state_switcher::Input,
+};
+
+/// Number of packets buffered between the listeners and the [`Input`]
+pub const INTERFACE_QUEUE_CAPACITY: usize = 1024;
+
+type InterfaceFilter = dyn Fn(&NetworkInterface) -> bool + Send + Sync;
+
+/// Network interface of the system
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NetworkInterface {
+    pub index: u32,
+    pub name: String,
+}
+
+/// Returns the network interfaces of the system
+pub fn interfaces() -> Result<Vec<NetworkInterface>, io::Error> {
+    // SAFETY: the array returned by if_nameindex is terminated by
+    // an entry of index 0, and freed once it was copied
+    unsafe {
+        let first = libc::if_nameindex();
+        if first.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let mut interfaces = Vec::new();
+        let mut entry = first;
+        while (*entry).if_index != 0 {
+            interfaces.push(NetworkInterface {
+                index: (*entry).if_index,
+                name: CStr::from_ptr((*entry).if_name)
+                    .to_string_lossy()
+                    .into_owned(),
+            });
+            entry = entry.add(1);
+        }
+        libc::if_freenameindex(first);
+        Ok(interfaces)
+    }
+}
+
+struct Listener {
+    interface: NetworkInterface,
+    input: Arc<UdpInput>,
+    task: JoinHandle<()>,
+}
+
+struct Listeners {
+    addr: String,
+    filter: Box<InterfaceFilter>,
+    listeners: Mutex<HashMap<u32, Listener>>,
+    sender: mpsc::Sender<(PooledBuffer, PacketMetadata)>,
+}
+
+impl Listeners {
+    fn scan(&self) -> Result<usize, io::Error> {
+        let selected: Vec<NetworkInterface> = interfaces()?
+            .into_iter()
+            .filter(|interface| (self.filter)(interface))
+            .collect();
+        let mut listeners = self.listeners.lock().unwrap();
+
+        listeners.retain(|_, listener| {
+            let present = selected.contains(&listener.interface);
+            let alive = !listener.task.is_finished();
+            if !present || !alive {
+                log::info!("Stopped listening on {}", listener.interface.name);
+                listener.task.abort();
+            }
+            present && alive
+        });
+
+        let mut opened = 0;
+        for interface in selected {
+            if listeners.contains_key(&interface.index) {
+                continue;
+            }
+            let input = match UdpInput::start_on_device(&self.addr, &interface.name) {
+                Ok(input) => Arc::new(input),
+                Err(e) => {
+                    log::warn!("Could not listen on {} : {}", interface.name, e);
+                    continue;
+                }
+            };
+            log::info!("Listening on {}", interface.name);
+            let task = tokio::spawn(Self::listen(
+                input.clone(),
+                interface.clone(),
+                self.sender.clone(),
+            ));
+            listeners.insert(
+                interface.index,
+                Listener {
+                    interface,
+                    input,
+                    task,
+                },
+            );
+            opened += 1;
+        }
+        Ok(opened)
+    }
+
+    async fn listen(
+        input: Arc<UdpInput>,
+        interface: NetworkInterface,
+        sender: mpsc::Sender<(PooledBuffer, PacketMetadata)>,
+    ) {
+        loop {
+            let (bytes, mut metadata) = match input.get_next().await {
+                Ok(received) => received,
+                Err(e) => {
+                    log::warn!("Failed to receive on {} : {}", interface.name, e);
+                    break;
+                }
+            };
+            metadata.interface = Some(interface.index);
+            if sender.send((bytes, metadata)).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// `InterfaceManager` provides an [`Input`] receiving
+/// from several network interfaces
+pub struct InterfaceManager {
+    listeners: Arc<Listeners>,
+    receiver: tokio::sync::Mutex<mpsc::Receiver<(PooledBuffer, PacketMetadata)>>,
+}
+
+impl InterfaceManager {
+    /// Creates a manager listening on `addr` on every
+    /// interface selected by `filter`
+    ///
+    /// No interface is listened on until the first [`scan`](InterfaceManager::scan).
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let manager = InterfaceManager::new("0.0.0.0:67", |interface| interface.name != "lo");
+    /// manager.scan()?;
+    /// let hotplug = manager.start(Duration::from_secs(5));
+    /// let state_switcher = StateSwitcher::new(Box::new(manager), output, registry);
+    /// ```
+    pub fn new(
+        addr: &str,
+        filter: impl Fn(&NetworkInterface) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(INTERFACE_QUEUE_CAPACITY);
+        Self {
+            listeners: Arc::new(Listeners {
+                addr: addr.to_string(),
+                filter: Box::new(filter),
+                listeners: Mutex::new(HashMap::new()),
+                sender,
+            }),
+            receiver: tokio::sync::Mutex::new(receiver),
+        }
+    }
+
+    /// Listens on the selected interfaces which appeared, and stops
+    /// listening on the ones which disappeared, returning the number
+    /// of interfaces newly listened on
+    ///
+    /// Interfaces which cannot be listened on are skipped, and
+    /// tried again on the next scan. Must be called from within
+    /// a tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the interfaces cannot be enumerated
+    pub fn scan(&self) -> Result<usize, io::Error> {
+        self.listeners.scan()
+    }
+
+    /// Spawns a task scanning the interfaces every `interval`
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn start(&self, interval: Duration) -> HotplugHandle {
+        let stop = Arc::new(Notify::new());
+        let listeners = self.listeners.clone();
+        let stopped = stop.clone();
+        let task = tokio::spawn(async move {
+            let mut ticks = time::interval(interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {
+                        if let Err(e) = listeners.scan() {
+                            log::error!("Failed to scan interfaces : {}", e);
+                        }
+                    }
+                    _ = stopped.notified() => break,
+                }
+            }
+        });
+        HotplugHandle { stop, task }
+    }
+
+    /// Returns the interfaces currently listened on
+    pub fn interfaces(&self) -> Vec<NetworkInterface> {
+        let listeners = self.listeners.listeners.lock().unwrap();
+        let mut interfaces: Vec<NetworkInterface> = listeners
+            .values()
+            .map(|listener| listener.interface.clone())
+            .collect();
+        interfaces.sort_by_key(|interface| interface.index);
+        interfaces
+    }
+
+    /// Returns the name of the interface of the given index,
+    /// if it is listened on
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// if let Some(name) = packet.metadata().interface.and_then(|index| manager.interface_name(index)) {
+    ///     ingress.set_interface(name);
+    /// }
+    /// ```
+    pub fn interface_name(&self, index: u32) -> Option<String> {
+        let listeners = self.listeners.listeners.lock().unwrap();
+        listeners
+            .get(&index)
+            .map(|listener| listener.interface.name.clone())
+    }
+
+    /// Returns the address the listener of the given interface is bound to
+    pub fn local_addr(&self, index: u32) -> Option<SocketAddr> {
+        let listeners = self.listeners.listeners.lock().unwrap();
+        listeners.get(&index)?.input.local_addr().ok()
+    }
+}
+
+impl Drop for InterfaceManager {
+    fn drop(&mut self) {
+        for listener in self.listeners.listeners.lock().unwrap().values() {
+            listener.task.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl<T: PacketType> Input<T> for InterfaceManager {
+    async fn get(&self) -> Result<T, io::Error> {
+        Ok(self.get_with_metadata().await?.0)
+    }
+
+    async fn get_with_metadata(&self) -> Result<(T, PacketMetadata), io::Error> {
+        let received = self.receiver.lock().await.recv().await;
+        let (bytes, metadata) = received.ok_or(io::ErrorKind::BrokenPipe)?;
+        Ok((T::try_from_raw_bytes(&bytes)?, metadata))
+    }
+}
+
+/// Handle over the task started by [`InterfaceManager::start`]
+///
+/// Dropping the handle does not stop the task.
+pub struct HotplugHandle {
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl HotplugHandle {
+    /// Stops scanning the interfaces
+    pub async fn stop(self) {
+        self.stop.notify_one();
+        if let Err(e) = self.task.await {
+            log::error!("Interface scanner failed : {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use tokio::net::UdpSocket;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Raw(Vec<u8>);
+
+    impl PacketType for Raw {
+        fn to_raw_bytes(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn empty() -> Self {
+            Self(Vec::new())
+        }
+
+        fn from_raw_bytes(raw_data: &[u8]) -> Self {
+            Self(raw_data.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interface_manager() {
+        let loopback = interfaces()
+            .unwrap()
+            .into_iter()
+            .find(|interface| interface.name == "lo")
+            .unwrap();
+        let manager = InterfaceManager::new("127.0.0.1:0", |interface| interface.name == "lo");
+        //Binding to a device may not be permitted
+        if manager.scan().unwrap() == 0 {
+            return;
+        }
+        assert_eq!(manager.interfaces(), vec![loopback.clone()]);
+        assert_eq!(manager.scan().unwrap(), 0);
+        assert_eq!(
+            manager.interface_name(loopback.index).as_deref(),
+            Some("lo")
+        );
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = manager.local_addr(loopback.index).unwrap();
+        sender.send_to(&[1, 2, 3], addr).await.unwrap();
+        let (packet, metadata): (Raw, _) = manager.get_with_metadata().await.unwrap();
+        assert_eq!(packet.0, vec![1, 2, 3]);
+        assert_eq!(metadata.interface, Some(loopback.index));
+    }
+}
@@ -0,0 +1,101 @@
+//! Addressing of DHCPv6 sockets.
+//!
+//! DHCPv6 servers listen on link-local addresses, which are only
+//! meaningful along with the interface they belong to: their scope
+//! id. [`parse_addr`] accepts scope ids given either as an interface
+//! index or as an interface name, as in `[fe80::1%eth0]:547`.
+
+use std::{
+    io,
+    net::{Ipv6Addr, SocketAddr, SocketAddrV6},
+};
+
+/// Multicast group of all the DHCPv6 relay agents and servers of a link
+pub const ALL_DHCP_RELAY_AGENTS_AND_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+/// Port DHCPv6 clients listen on
+pub const DHCPV6_CLIENT_PORT: u16 = 546;
+/// Port DHCPv6 servers and relay agents listen on
+pub const DHCPV6_SERVER_PORT: u16 = 547;
+
+/// Parses a socket address, the scope id of IPv6
+/// addresses being an interface index or name
+///
+/// # Errors
+///
+/// Returns an [`io::Error`] if `addr` cannot be parsed
+/// or if its interface does not exist
+///
+/// # Examples:
+///
+/// ```
+/// let addr = parse_addr("[fe80::1%eth0]:547")?;
+/// ```
+pub fn parse_addr(addr: &str) -> Result<SocketAddr, io::Error> {
+    if let Ok(addr) = addr.parse() {
+        return Ok(addr);
+    }
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid socket address");
+    let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+    let (ip, interface) = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .and_then(|host| host.split_once('%'))
+        .ok_or_else(invalid)?;
+    let ip: Ipv6Addr = ip.parse().map_err(|_| invalid())?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    Ok(SocketAddr::V6(SocketAddrV6::new(
+        ip,
+        port,
+        0,
+        interface_index(interface)?,
+    )))
+}
+
+/// Returns the index of the interface named `name`
+#[cfg(unix)]
+pub fn interface_index(name: &str) -> Result<u32, io::Error> {
+    let name = std::ffi::CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+    // SAFETY: name is a valid NUL-terminated string
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+/// Returns the index of the interface named `name`
+#[cfg(not(unix))]
+pub fn interface_index(_name: &str) -> Result<u32, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Interface names are not supported",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_addr() {
+        assert_eq!(
+            parse_addr("0.0.0.0:67").unwrap(),
+            "0.0.0.0:67".parse::<SocketAddr>().unwrap()
+        );
+        let numeric = parse_addr("[fe80::1%2]:547").unwrap();
+        assert_eq!(numeric, "[fe80::1%2]:547".parse::<SocketAddr>().unwrap());
+
+        #[cfg(target_os = "linux")]
+        {
+            let named = parse_addr("[fe80::1%lo]:547").unwrap();
+            let SocketAddr::V6(named) = named else {
+                panic!("Parsed an IPv4 address");
+            };
+            assert_eq!(named.scope_id(), interface_index("lo").unwrap());
+            assert_eq!(named.port(), DHCPV6_SERVER_PORT);
+        }
+        assert!(parse_addr("[fe80::1%fp_core_none]:547").is_err());
+        assert!(parse_addr("fe80::1%lo").is_err());
+    }
+}
@@ -0,0 +1,444 @@
+//! High-throughput UDP [`Input`] and [`BatchOutput`], receiving
+//! and sending several datagrams per system call through the
+//! `recvmmsg` and `sendmmsg` calls of Linux.
+//!
+//! During packet storms, a [`UdpInput`] makes one system call per
+//! datagram. An [`MmsgInput`] reads every datagram already queued
+//! on its socket, up to its batch size, in a single call, and hands
+//! them out one by one. An [`MmsgOutput`] is meant to be wrapped in
+//! a [`BatchingOutput`], which gathers the packets it sends.
+//!
+//! Kernels lacking these calls fall back to one
+//! system call per datagram.
+//!
+//! [`UdpInput`]: super::udp_input::UdpInput
+//! [`BatchingOutput`]: crate::core::batch::BatchingOutput
+
+use std::{
+    collections::VecDeque,
+    io, mem,
+    net::SocketAddr,
+    os::fd::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use socket2::SockAddr;
+use tokio::{net::UdpSocket, sync::Mutex};
+
+use super::{
+    buffer_pool::{BufferPool, PooledBuffer, DATAGRAM_BUFFER_LEN},
+    pktinfo::{metadata_of, ControlBuffer},
+};
+use crate::core::{
+    batch::BatchOutput,
+    packet::{PacketMetadata, PacketType},
+    state_switcher::Input,
+};
+
+/// Default number of datagrams received or sent per system call
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// `MmsgInput` provides an [`Input`] receiving
+/// datagrams in batches
+pub struct MmsgInput {
+    socket: UdpSocket,
+    batch_size: usize,
+    fallback: AtomicBool,
+    pool: Arc<BufferPool>,
+    pending: Mutex<VecDeque<(PooledBuffer, PacketMetadata)>>,
+}
+
+impl MmsgInput {
+    /// Binds the `MmsgInput` listener to the provided address,
+    /// receiving up to `batch_size` datagrams per system call
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is 0
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mmsg_input = MmsgInput::start("0.0.0.0:67", DEFAULT_BATCH_SIZE).await?;
+    /// ```
+    pub async fn start(addr: &str, batch_size: usize) -> Result<Self, io::Error> {
+        assert!(batch_size > 0, "Batch size must be greater than 0");
+        let socket = UdpSocket::bind(super::ipv6::parse_addr(addr)?).await?;
+        super::pktinfo::enable_pktinfo(&socket)?;
+        Ok(Self {
+            socket,
+            batch_size,
+            fallback: AtomicBool::new(false),
+            //Keeps enough buffers for a batch being read while the next one is received
+            pool: BufferPool::new(DATAGRAM_BUFFER_LEN, 2 * batch_size),
+            pending: Mutex::new(VecDeque::with_capacity(batch_size)),
+        })
+    }
+
+    /// Returns the address the listener is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.socket.local_addr()
+    }
+
+    /// Receives the datagrams queued on the socket, waiting for one if none is
+    async fn receive(
+        &self,
+        pending: &mut VecDeque<(PooledBuffer, PacketMetadata)>,
+    ) -> Result<(), io::Error> {
+        if !self.fallback.load(Ordering::Relaxed) {
+            let mut buffers: Vec<PooledBuffer> =
+                (0..self.batch_size).map(|_| self.pool.get()).collect();
+            let batch = self
+                .socket
+                .async_io(tokio::io::Interest::READABLE, || {
+                    recv_mmsg(&self.socket, &mut buffers)
+                })
+                .await;
+            match batch {
+                Ok(batch) => {
+                    for (mut buffer, (len, metadata)) in buffers.into_iter().zip(batch) {
+                        buffer.set_len(len);
+                        pending.push_back((buffer, metadata));
+                    }
+                    return Ok(());
+                }
+                Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+                    log::warn!("recvmmsg is not supported, receiving one datagram at a time");
+                    self.fallback.store(true, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut buffer = self.pool.get();
+        let (len, metadata) = self
+            .socket
+            .async_io(tokio::io::Interest::READABLE, || {
+                super::pktinfo::recv_with_pktinfo(self.socket.as_raw_fd(), buffer.as_mut_buffer())
+            })
+            .await?;
+        buffer.set_len(len);
+        pending.push_back((buffer, metadata));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: PacketType> Input<T> for MmsgInput {
+    async fn get(&self) -> Result<T, io::Error> {
+        Ok(self.get_with_metadata().await?.0)
+    }
+
+    async fn get_with_metadata(&self) -> Result<(T, PacketMetadata), io::Error> {
+        let mut pending = self.pending.lock().await;
+        if pending.is_empty() {
+            self.receive(&mut pending).await?;
+        }
+        let (bytes, metadata) = pending.pop_front().ok_or(io::ErrorKind::WouldBlock)?;
+        Ok((T::try_from_raw_bytes(&bytes)?, metadata))
+    }
+}
+
+/// Receives up to one datagram per buffer of `buffers` in one
+/// `recvmmsg` call, returning their length and metadata
+fn recv_mmsg(
+    socket: &UdpSocket,
+    buffers: &mut [PooledBuffer],
+) -> Result<Vec<(usize, PacketMetadata)>, io::Error> {
+    let batch_size = buffers.len();
+    // SAFETY: all-zero is a valid value of these C structs
+    let mut sources: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; batch_size];
+    let mut controls: Vec<ControlBuffer> = vec![[0; 8]; batch_size];
+    let mut iovs: Vec<libc::iovec> = buffers
+        .iter_mut()
+        .map(|buffer| {
+            let buffer = buffer.as_mut_buffer();
+            libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            }
+        })
+        .collect();
+    let mut messages: Vec<libc::mmsghdr> = (0..batch_size)
+        .map(|i| {
+            let mut message: libc::mmsghdr = unsafe { mem::zeroed() };
+            message.msg_hdr.msg_name = &mut sources[i] as *mut _ as *mut libc::c_void;
+            message.msg_hdr.msg_namelen =
+                mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            message.msg_hdr.msg_iov = &mut iovs[i];
+            message.msg_hdr.msg_iovlen = 1;
+            message.msg_hdr.msg_control = controls[i].as_mut_ptr() as *mut libc::c_void;
+            message.msg_hdr.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+            message
+        })
+        .collect();
+
+    // SAFETY: every buffer referenced by the messages outlives the call
+    let received = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            messages.as_mut_ptr(),
+            batch_size as libc::c_uint,
+            libc::MSG_DONTWAIT,
+            std::ptr::null_mut(),
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(messages[..received as usize]
+        .iter()
+        // SAFETY: the messages were filled by recvmmsg
+        .map(|message| {
+            (message.msg_len as usize, unsafe {
+                metadata_of(&message.msg_hdr)
+            })
+        })
+        .collect())
+}
+
+/// `MmsgOutput` provides a [`BatchOutput`] sending
+/// datagrams in batches
+///
+/// As with a [`UdpOutput`](super::udp_output::UdpOutput), packets
+/// without a destination cannot be sent.
+pub struct MmsgOutput {
+    socket: UdpSocket,
+    batch_size: usize,
+    fallback: AtomicBool,
+}
+
+impl MmsgOutput {
+    /// Binds the `MmsgOutput` to the provided address,
+    /// sending up to `batch_size` datagrams per system call
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is 0
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mmsg_output = MmsgOutput::start("0.0.0.0:67", DEFAULT_BATCH_SIZE).await?;
+    /// let output = BatchingOutput::new(mmsg_output, DEFAULT_BATCH_SIZE, Duration::from_millis(1));
+    /// ```
+    pub async fn start(addr: &str, batch_size: usize) -> Result<Self, io::Error> {
+        assert!(batch_size > 0, "Batch size must be greater than 0");
+        let socket = UdpSocket::bind(super::ipv6::parse_addr(addr)?).await?;
+        Ok(Self {
+            socket,
+            batch_size,
+            fallback: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns the address the output is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.socket.local_addr()
+    }
+
+    /// Sends the datagrams one at a time
+    async fn send_each(
+        &self,
+        datagrams: &[(SocketAddr, &[u8])],
+        results: &mut Vec<Result<usize, io::Error>>,
+    ) {
+        for (addr, payload) in datagrams {
+            results.push(self.socket.send_to(payload, addr).await);
+        }
+    }
+}
+
+#[async_trait]
+impl<T: PacketType + Sync + Send + 'static> BatchOutput<T> for MmsgOutput {
+    async fn send_batch(
+        &self,
+        packets: Vec<(T, Option<SocketAddr>)>,
+    ) -> Vec<Result<usize, io::Error>> {
+        let mut results = Vec::with_capacity(packets.len());
+        let mut datagrams = Vec::with_capacity(packets.len());
+        let mut undeliverable = Vec::new();
+        for (i, (packet, destination)) in packets.iter().enumerate() {
+            match destination {
+                Some(addr) => datagrams.push((*addr, packet.to_raw_bytes())),
+                None => undeliverable.push(i),
+            }
+        }
+
+        let mut sent = 0;
+        while sent < datagrams.len() && !self.fallback.load(Ordering::Relaxed) {
+            let end = datagrams.len().min(sent + self.batch_size);
+            let batch = &datagrams[sent..end];
+            let count = self
+                .socket
+                .async_io(tokio::io::Interest::WRITABLE, || {
+                    send_mmsg(&self.socket, batch)
+                })
+                .await;
+            match count {
+                Ok(count) => {
+                    results.extend(batch[..count].iter().map(|(_, payload)| Ok(payload.len())));
+                    sent += count;
+                }
+                Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+                    log::warn!("sendmmsg is not supported, sending one datagram at a time");
+                    self.fallback.store(true, Ordering::Relaxed);
+                }
+                //The first datagram of the batch failed, skip it
+                Err(e) => {
+                    results.push(Err(e));
+                    sent += 1;
+                }
+            }
+        }
+        self.send_each(&datagrams[sent..], &mut results).await;
+
+        for i in undeliverable {
+            results.insert(
+                i,
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "No destination to send the packet to",
+                )),
+            );
+        }
+        results
+    }
+}
+
+/// Sends `datagrams` in one `sendmmsg` call,
+/// returning the number of datagrams sent
+fn send_mmsg(socket: &UdpSocket, datagrams: &[(SocketAddr, &[u8])]) -> Result<usize, io::Error> {
+    let addrs: Vec<SockAddr> = datagrams
+        .iter()
+        .map(|(addr, _)| SockAddr::from(*addr))
+        .collect();
+    let mut iovs: Vec<libc::iovec> = datagrams
+        .iter()
+        .map(|(_, payload)| libc::iovec {
+            iov_base: payload.as_ptr() as *mut libc::c_void,
+            iov_len: payload.len(),
+        })
+        .collect();
+    let mut messages: Vec<libc::mmsghdr> = (0..datagrams.len())
+        .map(|i| {
+            // SAFETY: all-zero is a valid mmsghdr
+            let mut message: libc::mmsghdr = unsafe { mem::zeroed() };
+            message.msg_hdr.msg_name = addrs[i].as_ptr() as *mut libc::c_void;
+            message.msg_hdr.msg_namelen = addrs[i].len();
+            message.msg_hdr.msg_iov = &mut iovs[i];
+            message.msg_hdr.msg_iovlen = 1;
+            message
+        })
+        .collect();
+
+    // SAFETY: every buffer referenced by the messages outlives the
+    // call, and the payloads are only read
+    let sent = unsafe {
+        libc::sendmmsg(
+            socket.as_raw_fd(),
+            messages.as_mut_ptr(),
+            messages.len() as libc::c_uint,
+            libc::MSG_DONTWAIT,
+        )
+    };
+    match sent < 0 {
+        true => Err(io::Error::last_os_error()),
+        false => Ok(sent as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Instant;
+
+    use super::*;
+    use crate::netio::udp_input::UdpInput;
+
+    #[derive(Clone)]
+    struct Raw(Vec<u8>);
+
+    impl PacketType for Raw {
+        fn to_raw_bytes(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn empty() -> Self {
+            Self(Vec::new())
+        }
+
+        fn from_raw_bytes(raw_data: &[u8]) -> Self {
+            Self(raw_data.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mmsg() {
+        let input = MmsgInput::start("127.0.0.1:0", 4).await.unwrap();
+        let output = MmsgOutput::start("127.0.0.1:0", 4).await.unwrap();
+        let addr = input.local_addr().unwrap();
+
+        let mut packets: Vec<(Raw, _)> = (1..=6)
+            .map(|len| (Raw(vec![len; len as usize]), Some(addr)))
+            .collect();
+        packets.insert(2, (Raw(vec![1]), None));
+        let results = output.send_batch(packets).await;
+        let sent: Vec<Option<usize>> = results.into_iter().map(Result::ok).collect();
+        assert_eq!(
+            sent,
+            vec![Some(1), Some(2), None, Some(3), Some(4), Some(5), Some(6)]
+        );
+
+        for len in 1..=6 {
+            let (packet, metadata): (Raw, _) = input.get_with_metadata().await.unwrap();
+            assert_eq!(packet.0, vec![len; len as usize]);
+            assert_eq!(metadata.source, Some(output.local_addr().unwrap()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn receive_benchmark() {
+        const DATAGRAMS: usize = 100_000;
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let payload = [0u8; 300];
+
+        let input = UdpInput::start("127.0.0.1:0").await.unwrap();
+        let addr = input.local_addr().unwrap();
+        let start = Instant::now();
+        let mut received = 0;
+        while received < DATAGRAMS {
+            for _ in 0..64 {
+                sender.send_to(&payload, addr).unwrap();
+            }
+            for _ in 0..64 {
+                let _: Raw = input.get().await.unwrap();
+            }
+            received += 64;
+        }
+        println!("recvmsg: {:?}", start.elapsed());
+
+        let input = MmsgInput::start("127.0.0.1:0", DEFAULT_BATCH_SIZE)
+            .await
+            .unwrap();
+        let addr = input.local_addr().unwrap();
+        let start = Instant::now();
+        let mut received = 0;
+        while received < DATAGRAMS {
+            for _ in 0..64 {
+                sender.send_to(&payload, addr).unwrap();
+            }
+            for _ in 0..64 {
+                let _: Raw = input.get().await.unwrap();
+            }
+            received += 64;
+        }
+        println!("recvmmsg: {:?}", start.elapsed());
+    }
+}
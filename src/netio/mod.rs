@@ -1,2 +1,22 @@
+#[cfg(any(target_os = "linux", target_vendor = "apple"))]
+pub mod bind_device;
+pub mod buffer_pool;
+pub mod datagram;
+pub mod dry_run;
+#[cfg(any(target_os = "linux", target_vendor = "apple"))]
+pub mod interfaces;
+pub mod ipv6;
+#[cfg(target_os = "linux")]
+pub mod mmsg;
+pub mod pcap;
+pub mod pcap_tap;
+#[cfg(target_os = "linux")]
+pub mod pktinfo;
+#[cfg(target_os = "linux")]
+pub mod raw_output;
+#[cfg(unix)]
+pub mod reuse_port;
+pub mod socket_options;
+pub mod tcp;
 pub mod udp_input;
 pub mod udp_output;
@@ -0,0 +1,678 @@
+//! Replay of captured traffic.
+//!
+//! A [`PcapInput`] reads a pcap or pcapng capture, and yields the
+//! UDP payloads it contains, so that production traffic can go
+//! through the hooks again, in tests or dry runs. Packets are
+//! yielded either at their original pace, or as fast as possible.
+//!
+//! Frames captured on Ethernet, Linux cooked (v1 and v2) and raw
+//! IP links are understood. Frames which are not UDP over IPv4 or
+//! IPv6 are skipped.
+//!
+//! A [`PcapWriter`] does the opposite, writing datagrams to a
+//! pcap capture, as done by the taps of [`pcap_tap`](super::pcap_tap).
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    sync::{Mutex, Notify},
+    time::Instant,
+};
+
+use super::datagram::build_any_datagram;
+use crate::core::{
+    packet::{PacketMetadata, PacketType},
+    state_switcher::Input,
+};
+
+/// Link type of Ethernet captures
+pub const LINKTYPE_ETHERNET: u16 = 1;
+/// Link type of raw IP captures
+pub const LINKTYPE_RAW: u16 = 101;
+/// Link type of Linux cooked captures
+pub const LINKTYPE_LINUX_SLL: u16 = 113;
+/// Link type of Linux cooked captures, version 2
+pub const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_IF_TSRESOL: u16 = 9;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_UDP: u8 = 17;
+
+/// UDP datagram read from a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedDatagram {
+    /// Time of capture, since the Unix epoch
+    pub timestamp: Duration,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+/// Pace at which a [`PcapInput`] yields its packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayTiming {
+    /// Packets are spaced as they were captured
+    Original,
+    /// Packets are yielded as soon as they are read
+    AsFastAsPossible,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the UDP datagrams of a pcap or pcapng capture
+///
+/// # Errors
+///
+/// Returns an [`InvalidData`](io::ErrorKind::InvalidData) error
+/// if `capture` is neither a pcap nor a pcapng capture, or is
+/// truncated
+pub fn read_capture(capture: &[u8]) -> Result<Vec<CapturedDatagram>, io::Error> {
+    let magic = capture
+        .get(..4)
+        .ok_or_else(|| invalid("Capture is too short"))?;
+    let magic = u32::from_le_bytes(magic.try_into().unwrap());
+    match magic {
+        PCAPNG_SECTION_HEADER => read_pcapng(capture),
+        _ => read_pcap(capture),
+    }
+}
+
+/// Reads integers of a capture in its byte order
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+const LITTLE_ENDIAN: Endian = Endian { big: false };
+
+impl Endian {
+    fn u16(&self, bytes: &[u8], at: usize) -> Result<u16, io::Error> {
+        let bytes: [u8; 2] = bytes
+            .get(at..at + 2)
+            .ok_or_else(|| invalid("Capture is truncated"))?
+            .try_into()
+            .unwrap();
+        Ok(match self.big {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, bytes: &[u8], at: usize) -> Result<u32, io::Error> {
+        let bytes: [u8; 4] = bytes
+            .get(at..at + 4)
+            .ok_or_else(|| invalid("Capture is truncated"))?
+            .try_into()
+            .unwrap();
+        Ok(match self.big {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+}
+
+fn read_pcap(capture: &[u8]) -> Result<Vec<CapturedDatagram>, io::Error> {
+    let magic = LITTLE_ENDIAN.u32(capture, 0)?;
+    let (endian, nanos) = match magic {
+        PCAP_MAGIC_MICROS => (Endian { big: false }, false),
+        PCAP_MAGIC_NANOS => (Endian { big: false }, true),
+        _ if magic.swap_bytes() == PCAP_MAGIC_MICROS => (Endian { big: true }, false),
+        _ if magic.swap_bytes() == PCAP_MAGIC_NANOS => (Endian { big: true }, true),
+        _ => return Err(invalid("Not a pcap or pcapng capture")),
+    };
+    let linktype = endian.u32(capture, 20)? as u16;
+
+    let mut datagrams = Vec::new();
+    let mut at = 24;
+    while at < capture.len() {
+        let seconds = endian.u32(capture, at)? as u64;
+        let fraction = endian.u32(capture, at + 4)? as u64;
+        let len = endian.u32(capture, at + 8)? as usize;
+        let frame = capture
+            .get(at + 16..at + 16 + len)
+            .ok_or_else(|| invalid("Capture is truncated"))?;
+        let timestamp = match nanos {
+            true => Duration::from_secs(seconds) + Duration::from_nanos(fraction),
+            false => Duration::from_secs(seconds) + Duration::from_micros(fraction),
+        };
+        datagrams.extend(parse_frame(linktype, frame, timestamp));
+        at += 16 + len;
+    }
+    Ok(datagrams)
+}
+
+/// Link type and timestamp resolution, in units per second,
+/// of a pcapng interface
+struct PcapngInterface {
+    linktype: u16,
+    resolution: u64,
+}
+
+impl PcapngInterface {
+    fn timestamp(&self, units: u64) -> Duration {
+        let seconds = units / self.resolution;
+        let rest = (units % self.resolution) as u128 * 1_000_000_000 / self.resolution as u128;
+        Duration::from_secs(seconds) + Duration::from_nanos(rest as u64)
+    }
+}
+
+fn read_pcapng(capture: &[u8]) -> Result<Vec<CapturedDatagram>, io::Error> {
+    let mut endian = Endian { big: false };
+    let mut interfaces: Vec<PcapngInterface> = Vec::new();
+    let mut datagrams = Vec::new();
+    let mut at = 0;
+    while at < capture.len() {
+        if LITTLE_ENDIAN.u32(capture, at)? == PCAPNG_SECTION_HEADER {
+            let magic = LITTLE_ENDIAN.u32(capture, at + 8)?;
+            endian = Endian {
+                big: magic != PCAPNG_BYTE_ORDER_MAGIC,
+            };
+            interfaces.clear();
+        }
+        let kind = endian.u32(capture, at)?;
+        let len = endian.u32(capture, at + 4)? as usize;
+        if len < 12 {
+            return Err(invalid("Invalid pcapng block length"));
+        }
+        let body = capture
+            .get(at + 8..at + len - 4)
+            .ok_or_else(|| invalid("Capture is truncated"))?;
+
+        match kind {
+            PCAPNG_INTERFACE_DESCRIPTION => interfaces.push(PcapngInterface {
+                linktype: endian.u16(body, 0)?,
+                resolution: pcapng_resolution(endian, body.get(8..).unwrap_or_default())?,
+            }),
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = interfaces
+                    .get(endian.u32(body, 0)? as usize)
+                    .ok_or_else(|| invalid("Packet of an undescribed interface"))?;
+                let units = (endian.u32(body, 4)? as u64) << 32 | endian.u32(body, 8)? as u64;
+                let captured = endian.u32(body, 12)? as usize;
+                let frame = body
+                    .get(20..20 + captured)
+                    .ok_or_else(|| invalid("Capture is truncated"))?;
+                datagrams.extend(parse_frame(
+                    interface.linktype,
+                    frame,
+                    interface.timestamp(units),
+                ));
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| invalid("Packet of an undescribed interface"))?;
+                let frame = body.get(4..).unwrap_or_default();
+                datagrams.extend(parse_frame(interface.linktype, frame, Duration::ZERO));
+            }
+            _ => (),
+        }
+        at += len;
+    }
+    Ok(datagrams)
+}
+
+/// Returns the timestamp resolution set by the options of an
+/// interface description block, microseconds by default
+fn pcapng_resolution(endian: Endian, mut options: &[u8]) -> Result<u64, io::Error> {
+    while options.len() >= 4 {
+        let code = endian.u16(options, 0)?;
+        let len = endian.u16(options, 2)? as usize;
+        if code == PCAPNG_IF_TSRESOL && len >= 1 {
+            let resolution = *options
+                .get(4)
+                .ok_or_else(|| invalid("Capture is truncated"))?;
+            let exponent = (resolution & 0x7f) as u32;
+            return match resolution & 0x80 {
+                0 => 10u64.checked_pow(exponent),
+                _ => 2u64.checked_pow(exponent),
+            }
+            .ok_or_else(|| invalid("Invalid timestamp resolution"));
+        }
+        if code == 0 {
+            break;
+        }
+        options = options.get(4 + len.div_ceil(4) * 4..).unwrap_or_default();
+    }
+    Ok(1_000_000)
+}
+
+/// Returns the UDP datagram carried by a frame, if any
+fn parse_frame(linktype: u16, frame: &[u8], timestamp: Duration) -> Option<CapturedDatagram> {
+    let (ethertype, packet) = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+            let mut at = 14;
+            while ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes(frame.get(at + 2..at + 4)?.try_into().ok()?);
+                at += 4;
+            }
+            (ethertype, frame.get(at..)?)
+        }
+        LINKTYPE_LINUX_SLL => (
+            u16::from_be_bytes(frame.get(14..16)?.try_into().ok()?),
+            frame.get(16..)?,
+        ),
+        LINKTYPE_LINUX_SLL2 => (
+            u16::from_be_bytes(frame.get(0..2)?.try_into().ok()?),
+            frame.get(20..)?,
+        ),
+        LINKTYPE_RAW => match frame.first()? >> 4 {
+            4 => (ETHERTYPE_IPV4, frame),
+            6 => (ETHERTYPE_IPV6, frame),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (source, destination, segment) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = ((packet.first()? & 0x0f) as usize) * 4;
+            let total_len = u16::from_be_bytes(packet.get(2..4)?.try_into().ok()?) as usize;
+            if *packet.get(9)? != IPPROTO_UDP {
+                return None;
+            }
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::V4(Ipv4Addr::from(source)),
+                IpAddr::V4(Ipv4Addr::from(destination)),
+                packet.get(header_len..total_len.min(packet.len()))?,
+            )
+        }
+        ETHERTYPE_IPV6 => {
+            if *packet.get(6)? != IPPROTO_UDP {
+                return None;
+            }
+            let payload_len = u16::from_be_bytes(packet.get(4..6)?.try_into().ok()?) as usize;
+            let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::V6(Ipv6Addr::from(source)),
+                IpAddr::V6(Ipv6Addr::from(destination)),
+                packet.get(40..(40 + payload_len).min(packet.len()))?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes(segment.get(0..2)?.try_into().ok()?);
+    let destination_port = u16::from_be_bytes(segment.get(2..4)?.try_into().ok()?);
+    let udp_len = u16::from_be_bytes(segment.get(4..6)?.try_into().ok()?) as usize;
+    Some(CapturedDatagram {
+        timestamp,
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        payload: segment.get(8..udp_len.clamp(8, segment.len()))?.to_vec(),
+    })
+}
+
+/// `PcapInput` provides an [`Input`] replaying
+/// the UDP datagrams of a capture
+pub struct PcapInput {
+    datagrams: Mutex<VecDeque<CapturedDatagram>>,
+    timing: ReplayTiming,
+    port: Option<u16>,
+    start: Mutex<Option<(Instant, Duration)>>,
+    exhausted: AtomicBool,
+    done: Arc<Notify>,
+}
+
+impl PcapInput {
+    /// Reads the capture at `path`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut pcap_input = PcapInput::open("storm.pcapng")?;
+    /// pcap_input.set_port(67);
+    /// let done = pcap_input.done();
+    /// let state_switcher = StateSwitcher::new(Box::new(pcap_input), output, registry);
+    /// ```
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        Ok(Self::new(read_capture(&std::fs::read(path)?)?))
+    }
+
+    /// Creates an input replaying `datagrams`, as fast as possible
+    pub fn new(datagrams: Vec<CapturedDatagram>) -> Self {
+        Self {
+            datagrams: Mutex::new(datagrams.into()),
+            timing: ReplayTiming::AsFastAsPossible,
+            port: None,
+            start: Mutex::new(None),
+            exhausted: AtomicBool::new(false),
+            done: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn set_timing(&mut self, timing: ReplayTiming) {
+        self.timing = timing;
+    }
+
+    /// Only replays the datagrams sent to `port`
+    pub fn set_port(&mut self, port: u16) {
+        self.port = Some(port);
+    }
+
+    /// Returns a [`Notify`] notified once every datagram was yielded
+    ///
+    /// The input then waits forever, as no more packet will come.
+    pub fn done(&self) -> Arc<Notify> {
+        self.done.clone()
+    }
+
+    /// Returns the number of datagrams left to replay
+    pub async fn remaining(&self) -> usize {
+        self.datagrams.lock().await.len()
+    }
+
+    /// Returns the next datagram to replay, once it is due
+    async fn next(&self) -> Option<CapturedDatagram> {
+        let datagram = loop {
+            let datagram = self.datagrams.lock().await.pop_front()?;
+            if self
+                .port
+                .is_none_or(|port| datagram.destination.port() == port)
+            {
+                break datagram;
+            }
+        };
+        if self.timing == ReplayTiming::Original {
+            let (started, first) = *self
+                .start
+                .lock()
+                .await
+                .get_or_insert((Instant::now(), datagram.timestamp));
+            let offset = datagram.timestamp.saturating_sub(first);
+            tokio::time::sleep_until(started + offset).await;
+        }
+        Some(datagram)
+    }
+}
+
+#[async_trait]
+impl<T: PacketType> Input<T> for PcapInput {
+    async fn get(&self) -> Result<T, io::Error> {
+        Ok(self.get_with_metadata().await?.0)
+    }
+
+    async fn get_with_metadata(&self) -> Result<(T, PacketMetadata), io::Error> {
+        let Some(datagram) = self.next().await else {
+            if !self.exhausted.swap(true, Ordering::SeqCst) {
+                self.done.notify_one();
+            }
+            std::future::pending::<()>().await;
+            unreachable!();
+        };
+        let metadata = PacketMetadata {
+            source: Some(datagram.source),
+            destination: Some(datagram.destination.ip()),
+            interface: None,
+        };
+        Ok((T::try_from_raw_bytes(&datagram.payload)?, metadata))
+    }
+}
+
+/// `PcapWriter` writes UDP datagrams to a pcap capture,
+/// as raw IP frames
+///
+/// Records are flushed as soon as they are written, so that
+/// the capture can be read while the server is running.
+pub struct PcapWriter {
+    file: std::sync::Mutex<BufWriter<File>>,
+}
+
+impl PcapWriter {
+    /// Creates the capture at `path`, truncating it if it exists
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let writer = PcapWriter::create("replies.pcap")?;
+    /// writer.write(source, destination, &payload)?;
+    /// ```
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&PCAP_MAGIC_MICROS.to_le_bytes())?;
+        //Version 2.4, no time zone nor accuracy
+        file.write_all(&[2, 0, 4, 0])?;
+        file.write_all(&[0; 8])?;
+        file.write_all(&65535u32.to_le_bytes())?;
+        file.write_all(&(LINKTYPE_RAW as u32).to_le_bytes())?;
+        file.flush()?;
+        Ok(Self {
+            file: std::sync::Mutex::new(file),
+        })
+    }
+
+    /// Writes the datagram carrying `payload` from `source` to
+    /// `destination`, timestamped with the current time
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if the capture cannot be written,
+    /// or if `source` and `destination` are not of the same family
+    pub fn write(
+        &self,
+        source: SocketAddr,
+        destination: SocketAddr,
+        payload: &[u8],
+    ) -> Result<(), io::Error> {
+        let datagram = build_any_datagram(source, destination, payload).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Source and destination are not of the same family",
+            )
+        })?;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let len = (datagram.len() as u32).to_le_bytes();
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&(timestamp.as_secs() as u32).to_le_bytes())?;
+        file.write_all(&timestamp.subsec_micros().to_le_bytes())?;
+        file.write_all(&len)?;
+        file.write_all(&len)?;
+        file.write_all(&datagram)?;
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Raw(Vec<u8>);
+
+    impl PacketType for Raw {
+        fn to_raw_bytes(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn empty() -> Self {
+            Self(Vec::new())
+        }
+
+        fn from_raw_bytes(raw_data: &[u8]) -> Self {
+            Self(raw_data.to_vec())
+        }
+    }
+
+    /// IPv4 packet carrying `payload` over UDP, without checksums
+    fn ipv4_udp(
+        source: [u8; 4],
+        destination: [u8; 4],
+        ports: (u16, u16),
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let udp_len = 8 + payload.len() as u16;
+        [
+            &[0x45, 0][..],
+            &(20 + udp_len).to_be_bytes(),
+            &[0, 0, 0, 0, 64, IPPROTO_UDP, 0, 0],
+            &source,
+            &destination,
+            &ports.0.to_be_bytes(),
+            &ports.1.to_be_bytes(),
+            &udp_len.to_be_bytes(),
+            &[0, 0],
+            payload,
+        ]
+        .concat()
+    }
+
+    fn ethernet(packet: &[u8]) -> Vec<u8> {
+        [&[0xff; 6][..], &[0xaa, 0, 0, 0, 0, 1], &[0x08, 0], packet].concat()
+    }
+
+    fn pcap_record(seconds: u32, micros: u32, frame: &[u8]) -> Vec<u8> {
+        let len = (frame.len() as u32).to_le_bytes();
+        [
+            &seconds.to_le_bytes()[..],
+            &micros.to_le_bytes(),
+            &len,
+            &len,
+            frame,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_read_pcap() {
+        let discover = ipv4_udp([0; 4], [255; 4], (68, 67), &[1, 2, 3]);
+        let other = ipv4_udp([10, 0, 0, 1], [10, 0, 0, 2], (5353, 53), &[4]);
+        let capture = [
+            &PCAP_MAGIC_MICROS.to_le_bytes()[..],
+            &[2, 0, 4, 0],
+            &[0; 8],
+            &65535u32.to_le_bytes(),
+            &(LINKTYPE_ETHERNET as u32).to_le_bytes(),
+            &pcap_record(10, 500, &ethernet(&discover)),
+            &pcap_record(11, 0, &ethernet(&other)),
+            //ARP frames are skipped
+            &pcap_record(12, 0, &[&[0xff; 12][..], &[0x08, 0x06], &[0; 28]].concat()),
+        ]
+        .concat();
+
+        let datagrams = read_capture(&capture).unwrap();
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0].payload, vec![1, 2, 3]);
+        assert_eq!(datagrams[0].source, "0.0.0.0:68".parse().unwrap());
+        assert_eq!(
+            datagrams[0].destination,
+            "255.255.255.255:67".parse().unwrap()
+        );
+        assert_eq!(datagrams[0].timestamp, Duration::from_micros(10_000_500));
+        assert!(read_capture(&capture[..capture.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_read_pcapng() {
+        let block = |kind: u32, body: &[u8]| {
+            let len = (12 + body.len() as u32).to_le_bytes();
+            [&kind.to_le_bytes()[..], &len, body, &len].concat()
+        };
+        let packet = ipv4_udp([10, 0, 0, 1], [10, 0, 0, 2], (67, 68), &[9, 9, 9, 9]);
+        let units: u64 = 1_500; //1.5 second, in milliseconds
+        let capture = [
+            block(
+                PCAPNG_SECTION_HEADER,
+                &[
+                    &PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes()[..],
+                    &[1, 0, 0, 0],
+                    &[0xff; 8],
+                ]
+                .concat(),
+            ),
+            block(
+                PCAPNG_INTERFACE_DESCRIPTION,
+                &[
+                    &LINKTYPE_RAW.to_le_bytes()[..],
+                    &[0, 0],
+                    &0u32.to_le_bytes(),
+                    &PCAPNG_IF_TSRESOL.to_le_bytes(),
+                    &1u16.to_le_bytes(),
+                    &[3, 0, 0, 0],
+                    &[0, 0, 0, 0],
+                ]
+                .concat(),
+            ),
+            block(
+                PCAPNG_ENHANCED_PACKET,
+                &[
+                    &0u32.to_le_bytes()[..],
+                    &((units >> 32) as u32).to_le_bytes(),
+                    &(units as u32).to_le_bytes(),
+                    &(packet.len() as u32).to_le_bytes(),
+                    &(packet.len() as u32).to_le_bytes(),
+                    &packet,
+                ]
+                .concat(),
+            ),
+        ]
+        .concat();
+
+        let datagrams = read_capture(&capture).unwrap();
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].payload, vec![9, 9, 9, 9]);
+        assert_eq!(datagrams[0].timestamp, Duration::from_millis(1500));
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let datagram = |millis: u64, port: u16, payload: u8| CapturedDatagram {
+            timestamp: Duration::from_millis(millis),
+            source: "0.0.0.0:68".parse().unwrap(),
+            destination: SocketAddr::new(Ipv4Addr::BROADCAST.into(), port),
+            payload: vec![payload],
+        };
+        let mut input = PcapInput::new(vec![
+            datagram(100_000, 67, 1),
+            datagram(100_010, 53, 2),
+            datagram(100_050, 67, 3),
+        ]);
+        input.set_port(67);
+        input.set_timing(ReplayTiming::Original);
+        let done = input.done();
+
+        let start = Instant::now();
+        let (first, metadata): (Raw, _) = input.get_with_metadata().await.unwrap();
+        assert_eq!(first.0, vec![1]);
+        assert_eq!(metadata.destination, Some(Ipv4Addr::BROADCAST.into()));
+        let second: Raw = input.get().await.unwrap();
+        assert_eq!(second.0, vec![3]);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let exhausted = tokio::time::timeout(Duration::from_millis(20), async {
+            let _: Result<Raw, _> = input.get().await;
+        });
+        assert!(exhausted.await.is_err());
+        done.notified().await;
+    }
+}
@@ -0,0 +1,218 @@
+//! Taps writing the traffic of a server to a pcap capture.
+//!
+//! A [`PcapTapOutput`] wraps the [`Output`] of a `StateSwitcher`
+//! and writes every packet it sends to a [`PcapWriter`] before
+//! handing it over. Wrapping the [`Input`] as well in a
+//! [`PcapTapInput`] sharing the same writer records each request
+//! along with its replies, in a capture any pcap tool can open.
+//!
+//! Failing to write the capture never fails the wrapped input or
+//! output: the error is logged and the packet goes through.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use mac_address::MacAddress;
+
+use super::pcap::PcapWriter;
+use crate::core::{
+    packet::{PacketMetadata, PacketType},
+    state_switcher::{Input, Output},
+};
+
+/// `PcapTapOutput` writes every packet to a capture,
+/// then sends it through the wrapped [`Output`]
+///
+/// Only packets sent with [`send_to`](Output::send_to) can be
+/// captured, as the others have no destination.
+pub struct PcapTapOutput<O> {
+    output: O,
+    writer: Arc<PcapWriter>,
+    source: SocketAddr,
+}
+
+impl<O> PcapTapOutput<O> {
+    /// Wraps `output`, capturing its packets as sent from `source`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let writer = Arc::new(PcapWriter::create("dhcp.pcap")?);
+    /// let udp_output = UdpOutput::start("0.0.0.0:67").await?;
+    /// let source = udp_output.local_addr()?;
+    /// let output = PcapTapOutput::new(udp_output, writer.clone(), source);
+    /// let input = PcapTapInput::new(udp_input, writer, source);
+    /// ```
+    pub fn new(output: O, writer: Arc<PcapWriter>, source: SocketAddr) -> Self {
+        Self {
+            output,
+            writer,
+            source,
+        }
+    }
+}
+
+#[async_trait]
+impl<T, O> Output<T> for PcapTapOutput<O>
+where
+    T: PacketType + Send + 'static,
+    O: Output<T>,
+{
+    async fn send(&self, packet: T) -> Result<usize, std::io::Error> {
+        log::warn!("Failed to capture a packet without destination");
+        self.output.send(packet).await
+    }
+
+    async fn send_to(&self, packet: T, destination: SocketAddr) -> Result<usize, std::io::Error> {
+        let payload = packet.to_raw_bytes();
+        if let Err(e) = self.writer.write(self.source, destination, payload) {
+            log::warn!("Failed to capture a packet to {} : {}", destination, e);
+        }
+        self.output.send_to(packet, destination).await
+    }
+
+    async fn send_to_link(
+        &self,
+        packet: T,
+        destination: SocketAddr,
+        link_address: MacAddress,
+    ) -> Result<usize, std::io::Error> {
+        let payload = packet.to_raw_bytes();
+        if let Err(e) = self.writer.write(self.source, destination, payload) {
+            log::warn!("Failed to capture a packet to {} : {}", destination, e);
+        }
+        self.output
+            .send_to_link(packet, destination, link_address)
+            .await
+    }
+}
+
+/// `PcapTapInput` writes every packet read from
+/// the wrapped [`Input`] to a capture
+///
+/// Packets are captured as sent from the source of their
+/// [`PacketMetadata`] to the address the input listens on.
+pub struct PcapTapInput<I> {
+    input: I,
+    writer: Arc<PcapWriter>,
+    local: SocketAddr,
+}
+
+impl<I> PcapTapInput<I> {
+    /// Wraps `input`, capturing its packets as sent to `local`
+    ///
+    /// The destination IP address of the metadata is used
+    /// instead of the one of `local` when known.
+    pub fn new(input: I, writer: Arc<PcapWriter>, local: SocketAddr) -> Self {
+        Self {
+            input,
+            writer,
+            local,
+        }
+    }
+
+    fn capture(&self, payload: &[u8], metadata: &PacketMetadata) {
+        let destination = SocketAddr::new(
+            metadata.destination.unwrap_or(self.local.ip()),
+            self.local.port(),
+        );
+        let source = metadata.source.unwrap_or_else(|| {
+            let unspecified = match destination {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+            SocketAddr::new(unspecified, 0)
+        });
+        if let Err(e) = self.writer.write(source, destination, payload) {
+            log::warn!("Failed to capture a packet from {} : {}", source, e);
+        }
+    }
+}
+
+#[async_trait]
+impl<T, I> Input<T> for PcapTapInput<I>
+where
+    T: PacketType + Send + 'static,
+    I: Input<T>,
+{
+    async fn get(&self) -> Result<T, io::Error> {
+        Ok(self.get_with_metadata().await?.0)
+    }
+
+    async fn get_with_metadata(&self) -> Result<(T, PacketMetadata), io::Error> {
+        let (packet, metadata) = self.input.get_with_metadata().await?;
+        self.capture(packet.to_raw_bytes(), &metadata);
+        Ok((packet, metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use super::*;
+    use crate::netio::{
+        dry_run::RecordingOutput,
+        pcap::{read_capture, CapturedDatagram, PcapInput},
+    };
+
+    #[derive(Clone)]
+    struct Raw(Vec<u8>);
+
+    impl PacketType for Raw {
+        fn to_raw_bytes(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn empty() -> Self {
+            Self(Vec::new())
+        }
+
+        fn from_raw_bytes(raw_data: &[u8]) -> Self {
+            Self(raw_data.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pcap_tap() {
+        let path = std::env::temp_dir().join(format!("fp_core_tap_{}.pcap", std::process::id()));
+        let writer = Arc::new(PcapWriter::create(&path).unwrap());
+        let server: SocketAddr = "10.0.0.1:67".parse().unwrap();
+        let client: SocketAddr = "10.0.0.42:68".parse().unwrap();
+
+        let request = CapturedDatagram {
+            timestamp: Duration::ZERO,
+            source: client,
+            destination: "255.255.255.255:67".parse().unwrap(),
+            payload: vec![1, 2, 3],
+        };
+        let input = PcapTapInput::new(PcapInput::new(vec![request]), writer.clone(), server);
+        let recording = RecordingOutput::new();
+        let output = PcapTapOutput::new(recording.clone(), writer, server);
+
+        let (packet, metadata): (Raw, _) = input.get_with_metadata().await.unwrap();
+        assert_eq!(packet.0, vec![1, 2, 3]);
+        assert_eq!(metadata.source, Some(client));
+        let sent = output.send_to(Raw(vec![4, 5]), client).await;
+        assert_eq!(sent.unwrap(), 2);
+        assert_eq!(recording.records()[0].0, vec![4, 5]);
+
+        let datagrams = read_capture(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0].source, client);
+        assert_eq!(
+            datagrams[0].destination,
+            "255.255.255.255:67".parse().unwrap()
+        );
+        assert_eq!(datagrams[0].payload, vec![1, 2, 3]);
+        assert_eq!(datagrams[1].source, server);
+        assert_eq!(datagrams[1].destination, client);
+        assert_eq!(datagrams[1].payload, vec![4, 5]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Reception of datagrams along with the address they were
+//! sent to and the interface they were received on, using the
+//! `IP_PKTINFO` and `IPV6_PKTINFO` control messages of Linux.
+//!
+//! A DHCP server needs both: replies are sent from the interface
+//! the request arrived on, and a request sent to the broadcast
+//! address is not answered the same way as a unicast one.
+
+use std::{
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::fd::{AsRawFd, RawFd},
+    ptr,
+};
+
+use socket2::SockAddr;
+use tokio::net::UdpSocket;
+
+use crate::core::packet::PacketMetadata;
+
+/// Room for a packet information control message, aligned as `cmsghdr`
+pub(crate) type ControlBuffer = [u64; 8];
+
+/// Asks the kernel to attach packet information to the
+/// datagrams received by `socket`
+///
+/// # Errors
+///
+/// Returns an [`io::Error`] if the option cannot be set
+pub fn enable_pktinfo(socket: &UdpSocket) -> Result<(), io::Error> {
+    let (level, option) = match socket.local_addr()?.is_ipv4() {
+        true => (libc::IPPROTO_IP, libc::IP_PKTINFO),
+        false => (libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO),
+    };
+    let enable: libc::c_int = 1;
+    // SAFETY: the option value points to a live c_int of the given size
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &enable as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receives a datagram from `fd` into `buf`, returning its length
+/// and where it was received from
+///
+/// The socket must be non-blocking, a [`WouldBlock`] error being
+/// returned when no datagram is available. The destination and
+/// interface are only known if [`enable_pktinfo`] was called.
+///
+/// [`WouldBlock`]: io::ErrorKind::WouldBlock
+pub fn recv_with_pktinfo(fd: RawFd, buf: &mut [u8]) -> Result<(usize, PacketMetadata), io::Error> {
+    // SAFETY: all-zero is a valid value of these C structs
+    let mut source: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    let mut control: ControlBuffer = [0; 8];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    msg.msg_name = &mut source as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+
+    // SAFETY: every buffer referenced by msg outlives the call
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: msg was filled by recvmsg, its name being source
+    Ok((len as usize, unsafe { metadata_of(&msg) }))
+}
+
+/// Returns where the datagram received through `msg` was received from
+///
+/// # Safety
+///
+/// `msg` must have been filled by `recvmsg` or `recvmmsg`, its name
+/// pointing to a `sockaddr_storage`, and its control buffer being alive.
+pub(crate) unsafe fn metadata_of(msg: &libc::msghdr) -> PacketMetadata {
+    let source = ptr::read(msg.msg_name as *const libc::sockaddr_storage);
+    let mut metadata = PacketMetadata {
+        source: SockAddr::new(source, msg.msg_namelen).as_socket(),
+        ..Default::default()
+    };
+
+    if !msg.msg_control.is_null() {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_PKTINFO {
+                let info = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo);
+                metadata.destination = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    info.ipi_addr.s_addr,
+                ))));
+                metadata.interface = Some(info.ipi_ifindex as u32);
+            }
+            if (*cmsg).cmsg_level == libc::IPPROTO_IPV6 && (*cmsg).cmsg_type == libc::IPV6_PKTINFO {
+                let info = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in6_pktinfo);
+                metadata.destination = Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)));
+                metadata.interface = Some(info.ipi6_ifindex);
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    metadata
+}
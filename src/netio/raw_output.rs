@@ -0,0 +1,166 @@
+//! [`Output`] implementation able to reach clients which
+//! have no address yet.
+//!
+//! A `RawOutput` sends packets without a link address through a UDP
+//! socket, like a [`UdpOutput`] would. Packets whose [`PacketContext`]
+//! has a link address, as set from a link [`Delivery`], are sent
+//! through a Linux `AF_PACKET` socket bound to an interface, the IP
+//! and UDP headers being built by [`build_datagram`], and the frame
+//! addressed to the hardware address of the client.
+//!
+//! Opening an `AF_PACKET` socket requires `CAP_NET_RAW`.
+//!
+//! [`UdpOutput`]: super::udp_output::UdpOutput
+//! [`PacketContext`]: crate::core::packet::PacketContext
+//! [`Delivery`]: crate::core::reply::Delivery
+
+use std::{
+    ffi::CString,
+    io, mem,
+    net::{SocketAddr, SocketAddrV4},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use async_trait::async_trait;
+use mac_address::MacAddress;
+use tokio::{io::unix::AsyncFd, net::UdpSocket};
+
+use super::datagram::{build_datagram, IPV4_HEADER_LEN, UDP_HEADER_LEN};
+use crate::core::{packet::PacketType, state_switcher::Output};
+
+const ETH_P_IP: u16 = 0x0800;
+
+/// `RawOutput` sends packets either through the IP stack,
+/// or directly at the link layer
+pub struct RawOutput {
+    socket: UdpSocket,
+    link: AsyncFd<OwnedFd>,
+    interface: i32,
+    source: SocketAddrV4,
+}
+
+impl RawOutput {
+    /// Opens a `RawOutput` sending from `source` on `interface`
+    ///
+    /// `source` is both the address the UDP socket is bound to,
+    /// and the source of the datagrams sent at the link layer.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let raw_output = RawOutput::start("eth0", "10.0.0.1:67".parse()?)?;
+    /// ```
+    pub fn start(interface: &str, source: SocketAddrV4) -> Result<Self, io::Error> {
+        let name = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+        // SAFETY: name is a valid NUL-terminated string
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let socket = std::net::UdpSocket::bind(source)?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+
+        // SAFETY: plain socket creation, the descriptor is owned right away
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                ETH_P_IP.to_be() as libc::c_int,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a freshly created descriptor nobody else owns
+        let link = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        Ok(Self {
+            socket: UdpSocket::from_std(socket)?,
+            link: AsyncFd::new(link)?,
+            interface: index as i32,
+            source,
+        })
+    }
+
+    /// Sends `payload` to `destination` at the link layer,
+    /// in a frame addressed to `chaddr`
+    async fn send_link(
+        &self,
+        chaddr: MacAddress,
+        destination: SocketAddrV4,
+        payload: &[u8],
+    ) -> Result<usize, io::Error> {
+        let datagram = build_datagram(self.source, destination, payload);
+        // SAFETY: all-zero is a valid sockaddr_ll
+        let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_protocol = ETH_P_IP.to_be();
+        address.sll_ifindex = self.interface;
+        address.sll_halen = 6;
+        address.sll_addr[..6].copy_from_slice(&chaddr.bytes());
+
+        loop {
+            let mut guard = self.link.writable().await?;
+            let sent = guard.try_io(|link| {
+                // SAFETY: the datagram and the address outlive the call
+                let sent = unsafe {
+                    libc::sendto(
+                        link.as_raw_fd(),
+                        datagram.as_ptr() as *const libc::c_void,
+                        datagram.len(),
+                        0,
+                        &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+                    )
+                };
+                match sent < 0 {
+                    true => Err(io::Error::last_os_error()),
+                    false => Ok(sent as usize),
+                }
+            });
+            match sent {
+                Ok(sent) => return Ok(sent?.saturating_sub(IPV4_HEADER_LEN + UDP_HEADER_LEN)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<T: PacketType + Sync + Send + 'static> Output<T> for RawOutput {
+    /// Fails, as a datagram cannot be sent without a destination
+    async fn send(&self, _packet: T) -> Result<usize, std::io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No destination to send the packet to",
+        ))
+    }
+
+    /// Send a packet to `destination` through the IP stack
+    async fn send_to(&self, packet: T, destination: SocketAddr) -> Result<usize, std::io::Error> {
+        self.socket
+            .send_to(packet.to_raw_bytes(), destination)
+            .await
+    }
+
+    /// Send a packet to `destination` at the link layer,
+    /// in a frame addressed to `link_address`
+    async fn send_to_link(
+        &self,
+        packet: T,
+        destination: SocketAddr,
+        link_address: MacAddress,
+    ) -> Result<usize, std::io::Error> {
+        let SocketAddr::V4(destination) = destination else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only IPv4 datagrams are sent at the link layer",
+            ));
+        };
+        self.send_link(link_address, destination, packet.to_raw_bytes())
+            .await
+    }
+}
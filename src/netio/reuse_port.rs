@@ -0,0 +1,44 @@
+//! UDP sockets bound with `SO_REUSEPORT`, allowing several
+//! [`StateSwitcher`] to listen on the same port while the
+//! kernel distributes incoming datagrams between them.
+//!
+//! [`StateSwitcher`]: crate::core::state_switcher::StateSwitcher
+
+use std::io;
+
+use tokio::net::UdpSocket;
+
+use super::socket_options::SocketOptions;
+
+/// Binds a non-blocking [`UdpSocket`] to `addr`
+/// with `SO_REUSEPORT` enabled
+///
+/// # Errors
+///
+/// Returns an [`io::Error`] if `addr` cannot be parsed
+/// or if the socket cannot be bound
+///
+/// # Examples:
+///
+/// ```
+/// let first = bind_reuse_port("0.0.0.0:67")?;
+/// let second = bind_reuse_port("0.0.0.0:67")?;
+/// ```
+pub fn bind_reuse_port(addr: &str) -> Result<UdpSocket, io::Error> {
+    SocketOptions::new().reuse_port(true).bind(addr)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shared_port() {
+        let first = bind_reuse_port("127.0.0.1:0").unwrap();
+        let addr = first.local_addr().unwrap().to_string();
+
+        let second = bind_reuse_port(&addr).unwrap();
+        assert_eq!(second.local_addr().unwrap(), first.local_addr().unwrap());
+    }
+}
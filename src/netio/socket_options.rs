@@ -0,0 +1,165 @@
+//! Options of the UDP sockets bound by the inputs and outputs.
+//!
+//! DHCP servers usually need more than the defaults of the system:
+//! `SO_BROADCAST` to answer clients which have no address yet, and
+//! a large receive buffer to absorb packet storms. A [`SocketOptions`]
+//! gathers these options, and binds sockets configured with them.
+//!
+//! Options left unset keep the default of the system.
+
+use std::{io, net::SocketAddr};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+/// Step by step configuration of a UDP socket
+///
+/// # Examples:
+///
+/// ```
+/// let options = SocketOptions::new()
+///     .broadcast(true)
+///     .recv_buffer_size(4 * 1024 * 1024);
+/// let udp_input = UdpInput::start_with_options("0.0.0.0:67", &options)?;
+/// let udp_output = UdpOutput::start_with_options("0.0.0.0:67", &options.reuse_address(true))?;
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    reuse_address: bool,
+    #[cfg(unix)]
+    reuse_port: bool,
+    broadcast: bool,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+    ttl: Option<u32>,
+}
+
+impl SocketOptions {
+    /// Creates a new `SocketOptions` keeping
+    /// the defaults of the system
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `SO_REUSEADDR`
+    pub fn reuse_address(mut self, enabled: bool) -> Self {
+        self.reuse_address = enabled;
+        self
+    }
+
+    /// Sets `SO_REUSEPORT`, so that several sockets
+    /// can share the same port
+    #[cfg(unix)]
+    pub fn reuse_port(mut self, enabled: bool) -> Self {
+        self.reuse_port = enabled;
+        self
+    }
+
+    /// Sets `SO_BROADCAST`, allowing datagrams
+    /// to be sent to broadcast addresses
+    pub fn broadcast(mut self, enabled: bool) -> Self {
+        self.broadcast = enabled;
+        self
+    }
+
+    /// Sets the size of the receive buffer, in bytes
+    ///
+    /// The system may round it, or cap it to its own limit
+    /// (`net.core.rmem_max` on Linux).
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the size of the send buffer, in bytes
+    ///
+    /// The system may round it, or cap it to its own limit
+    /// (`net.core.wmem_max` on Linux).
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the time to live of the datagrams sent,
+    /// or their hop limit on IPv6 sockets
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Binds a non-blocking [`UdpSocket`] to `addr`,
+    /// configured with these options
+    ///
+    /// Must be called from within a tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if `addr` cannot be parsed, if
+    /// an option cannot be set or if the socket cannot be bound
+    pub fn bind(&self, addr: &str) -> Result<UdpSocket, io::Error> {
+        let addr = super::ipv6::parse_addr(addr)?;
+
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        self.apply(&socket, addr)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+
+        UdpSocket::from_std(socket.into())
+    }
+
+    fn apply(&self, socket: &Socket, addr: SocketAddr) -> Result<(), io::Error> {
+        if self.reuse_address {
+            socket.set_reuse_address(true)?;
+        }
+        #[cfg(unix)]
+        if self.reuse_port {
+            socket.set_reuse_port(true)?;
+        }
+        if self.broadcast {
+            socket.set_broadcast(true)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        match (self.ttl, addr) {
+            (Some(ttl), SocketAddr::V4(_)) => socket.set_ttl(ttl)?,
+            (Some(hops), SocketAddr::V6(_)) => socket.set_unicast_hops_v6(hops)?,
+            (None, _) => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use socket2::SockRef;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socket_options() {
+        let socket = SocketOptions::new()
+            .reuse_address(true)
+            .broadcast(true)
+            .recv_buffer_size(256 * 1024)
+            .send_buffer_size(128 * 1024)
+            .ttl(16)
+            .bind("127.0.0.1:0")
+            .unwrap();
+
+        let options = SockRef::from(&socket);
+        assert!(options.reuse_address().unwrap());
+        assert!(options.broadcast().unwrap());
+        assert!(options.recv_buffer_size().unwrap() >= 128 * 1024);
+        assert!(options.send_buffer_size().unwrap() >= 64 * 1024);
+        assert_eq!(options.ttl().unwrap(), 16);
+
+        let defaults = SocketOptions::new().bind("127.0.0.1:0").unwrap();
+        assert!(!SockRef::from(&defaults).broadcast().unwrap());
+        assert!(SocketOptions::new().bind("127.0.0.1").is_err());
+    }
+}
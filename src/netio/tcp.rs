@@ -0,0 +1,331 @@
+//! [`Input`] and [`Output`] implementations over TCP.
+//!
+//! TCP carries streams rather than datagrams, so every packet is
+//! framed: its length, as a big-endian `u32`, followed by its bytes.
+//! This lets the same pipeline serve protocols, or links between
+//! servers such as failover, which are not datagram-based.
+//!
+//! A [`TcpInput`] accepts connections and reads frames from all of
+//! them. The [`TcpOutput`] it returns through [`TcpInput::output`]
+//! replies on the connection of the peer a packet is sent to, and
+//! connects to peers which are not connected yet.
+//!
+//! As with a [`UdpOutput`], packets are sent to the destination
+//! given to [`send_to`](Output::send_to).
+//!
+//! [`UdpOutput`]: super::udp_output::UdpOutput
+
+use std::{collections::HashMap, io, net::SocketAddr, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+};
+
+use crate::core::{
+    packet::{PacketMetadata, PacketType},
+    state_switcher::{Input, Output},
+};
+
+/// Largest frame accepted, connections sending
+/// larger ones are closed
+pub const MAX_FRAME_LEN: usize = 65535;
+/// Number of frames buffered between the connections and the [`Input`]
+pub const TCP_QUEUE_CAPACITY: usize = 1024;
+
+/// Reads a frame from `reader`, returning `None` once
+/// the stream was closed between two frames
+pub async fn read_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes is too large", len),
+        ));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+/// Writes `payload` to `writer` as a single frame
+pub async fn write_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    payload: &[u8],
+) -> Result<(), io::Error> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Frame of {} bytes is too large", payload.len()),
+        ));
+    }
+    let frame = [&(payload.len() as u32).to_be_bytes()[..], payload].concat();
+    writer.write_all(&frame).await
+}
+
+type Connections = Mutex<HashMap<SocketAddr, Arc<Mutex<OwnedWriteHalf>>>>;
+type Frames = mpsc::Sender<(Vec<u8>, PacketMetadata)>;
+
+/// `TcpInput` provides an implementation of an
+/// [`Input`] reading framed packets from TCP connections
+pub struct TcpInput {
+    local_addr: SocketAddr,
+    connections: Arc<Connections>,
+    frames: Frames,
+    receiver: Mutex<mpsc::Receiver<(Vec<u8>, PacketMetadata)>>,
+    task: JoinHandle<()>,
+}
+
+impl TcpInput {
+    /// Listens for connections on the provided address
+    ///
+    /// Must be called from within a tokio runtime.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let tcp_input = TcpInput::start("0.0.0.0:647").await?;
+    /// let tcp_output = tcp_input.output();
+    /// let state_switcher = StateSwitcher::new(Box::new(tcp_input), Box::new(tcp_output), registry);
+    /// ```
+    pub async fn start(addr: &str) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(super::ipv6::parse_addr(addr)?).await?;
+        let local_addr = listener.local_addr()?;
+        let connections = Arc::new(Connections::default());
+        let (frames, receiver) = mpsc::channel(TCP_QUEUE_CAPACITY);
+        let task = tokio::spawn(Self::accept(listener, connections.clone(), frames.clone()));
+        Ok(Self {
+            local_addr,
+            connections,
+            frames,
+            receiver: Mutex::new(receiver),
+            task,
+        })
+    }
+
+    /// Returns the address the listener is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns an output replying on the connections of this input,
+    /// and reading the replies sent on the connections it opens
+    pub fn output(&self) -> TcpOutput {
+        TcpOutput {
+            connections: self.connections.clone(),
+            frames: Some(self.frames.clone()),
+        }
+    }
+
+    async fn accept(listener: TcpListener, connections: Arc<Connections>, frames: Frames) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    register(stream, peer, &connections, &frames).await;
+                }
+                Err(e) => log::warn!("Failed to accept a connection : {}", e),
+            }
+        }
+    }
+}
+
+impl Drop for TcpInput {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[async_trait]
+impl<T: PacketType> Input<T> for TcpInput {
+    async fn get(&self) -> Result<T, io::Error> {
+        Ok(self.get_with_metadata().await?.0)
+    }
+
+    async fn get_with_metadata(&self) -> Result<(T, PacketMetadata), io::Error> {
+        let received = self.receiver.lock().await.recv().await;
+        let (bytes, metadata) = received.ok_or(io::ErrorKind::BrokenPipe)?;
+        Ok((T::try_from_raw_bytes(&bytes)?, metadata))
+    }
+}
+
+/// Keeps the write half of `stream` for replies, and spawns
+/// a task forwarding the frames of its read half
+///
+/// The connection is closed once its read half is.
+async fn register(
+    stream: TcpStream,
+    peer: SocketAddr,
+    connections: &Arc<Connections>,
+    frames: &Frames,
+) -> Arc<Mutex<OwnedWriteHalf>> {
+    let (mut reader, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+    connections.lock().await.insert(peer, writer.clone());
+
+    let frames = frames.clone();
+    let connections = connections.clone();
+    tokio::spawn(async move {
+        let metadata = PacketMetadata {
+            source: Some(peer),
+            ..Default::default()
+        };
+        loop {
+            match read_frame(&mut reader).await {
+                Ok(Some(frame)) => {
+                    if frames.send((frame, metadata)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Closing the connection of {} : {}", peer, e);
+                    break;
+                }
+            }
+        }
+        connections.lock().await.remove(&peer);
+    });
+    writer
+}
+
+/// `TcpOutput` provides an implementation of an
+/// [`Output`] writing framed packets to TCP connections
+pub struct TcpOutput {
+    connections: Arc<Connections>,
+    frames: Option<Frames>,
+}
+
+impl TcpOutput {
+    /// Creates an output connecting to the destination of its packets
+    ///
+    /// Replies sent by the peers are ignored. Use [`TcpInput::output`]
+    /// to process them.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let tcp_output = TcpOutput::new();
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            connections: Arc::new(Connections::default()),
+            frames: None,
+        }
+    }
+
+    /// Returns the connection to `peer`, connecting if needed
+    async fn connection(&self, peer: SocketAddr) -> Result<Arc<Mutex<OwnedWriteHalf>>, io::Error> {
+        if let Some(writer) = self.connections.lock().await.get(&peer) {
+            return Ok(writer.clone());
+        }
+        let stream = TcpStream::connect(peer).await?;
+        match &self.frames {
+            Some(frames) => Ok(register(stream, peer, &self.connections, frames).await),
+            None => {
+                let (_, writer) = stream.into_split();
+                let writer = Arc::new(Mutex::new(writer));
+                self.connections.lock().await.insert(peer, writer.clone());
+                Ok(writer)
+            }
+        }
+    }
+}
+
+impl Default for TcpOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl<T: PacketType + Sync + Send + 'static> Output<T> for TcpOutput {
+    /// Fails, as a frame cannot be sent without a destination
+    async fn send(&self, _packet: T) -> Result<usize, std::io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No destination to send the packet to",
+        ))
+    }
+
+    /// Send a packet on the connection of `peer`
+    ///
+    /// A connection which fails is closed, and opened again once.
+    async fn send_to(&self, packet: T, peer: SocketAddr) -> Result<usize, std::io::Error> {
+        let payload = packet.to_raw_bytes();
+
+        let writer = self.connection(peer).await?;
+        let written = write_frame(&mut *writer.lock().await, payload).await;
+        if let Err(e) = written {
+            log::warn!("Reconnecting to {} : {}", peer, e);
+            self.connections.lock().await.remove(&peer);
+            let writer = self.connection(peer).await?;
+            write_frame(&mut *writer.lock().await, payload).await?;
+        }
+        Ok(payload.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Raw(Vec<u8>);
+
+    impl PacketType for Raw {
+        fn to_raw_bytes(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn empty() -> Self {
+            Self(Vec::new())
+        }
+
+        fn from_raw_bytes(raw_data: &[u8]) -> Self {
+            Self(raw_data.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_transport() {
+        let input = TcpInput::start("127.0.0.1:0").await.unwrap();
+        let output = input.output();
+
+        let mut client = TcpStream::connect(input.local_addr()).await.unwrap();
+        write_frame(&mut client, &[1, 2, 3]).await.unwrap();
+        let (request, metadata): (Raw, _) = input.get_with_metadata().await.unwrap();
+        assert_eq!(request.0, vec![1, 2, 3]);
+        let client_addr = client.local_addr().unwrap();
+        assert_eq!(metadata.source, Some(client_addr));
+
+        let sent = Output::send_to(&output, Raw(vec![4, 5]), client_addr).await;
+        assert_eq!(sent.unwrap(), 2);
+        assert_eq!(read_frame(&mut client).await.unwrap(), Some(vec![4, 5]));
+
+        let peer = TcpOutput::new();
+        Output::send_to(&peer, Raw(vec![6]), input.local_addr())
+            .await
+            .unwrap();
+        let packet: Raw = input.get().await.unwrap();
+        assert_eq!(packet.0, vec![6]);
+
+        let mut oversized = TcpStream::connect(input.local_addr()).await.unwrap();
+        oversized
+            .write_all(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes())
+            .await
+            .unwrap();
+        assert_eq!(read_frame(&mut oversized).await.unwrap(), None);
+    }
+}
@@ -1,19 +1,37 @@
 //! Simple [`Input`] implementation using the
 //! UDP protocol. It reads bytes from a [`UdpSocket`]
 //! and turns them into a [`PacketType`] implementation
-//! by calling `from_raw_bytes`
+//! by calling `try_from_raw_bytes`. Malformed packets are
+//! reported as [`InvalidData`] errors.
+//!
+//! Datagrams are received into buffers of a [`BufferPool`],
+//! and parsed from there without being copied.
+//!
+//! On Linux, the address each packet was sent to and the
+//! interface it was received on are read from `IP_PKTINFO`
+//! and returned as its [`PacketMetadata`].
+//!
+//! [`InvalidData`]: std::io::ErrorKind::InvalidData
 
-use std::io;
+use std::{io, sync::Arc};
 
 use async_trait::async_trait;
 use tokio::net::UdpSocket;
 
-use crate::core::{packet::PacketType, state_switcher::Input};
+use super::{
+    buffer_pool::{BufferPool, PooledBuffer, DATAGRAM_BUFFER_LEN, DEFAULT_POOL_CAPACITY},
+    socket_options::SocketOptions,
+};
+use crate::core::{
+    packet::{PacketMetadata, PacketType},
+    state_switcher::Input,
+};
 
 /// `UdpInput` provides a simple implementation of
 /// an [`Input`] using the UDP protocol.
 pub struct UdpInput {
     socket: UdpSocket,
+    pool: Arc<BufferPool>,
 }
 
 impl UdpInput {
@@ -25,24 +43,194 @@ impl UdpInput {
     /// let udp_input = UdpInput::start("0.0.0.0:53");
     /// ```
     pub async fn start(addr: &str) -> Result<Self, std::io::Error> {
+        Self::new(UdpSocket::bind(super::ipv6::parse_addr(addr)?).await?)
+    }
+
+    /// Binds the `UdpInput` listener to the provided address
+    /// with `SO_REUSEPORT` enabled, so that several `UdpInput`
+    /// can share the same port, each one in its own `StateSwitcher`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let udp_inputs = (0..4)
+    ///     .map(|_| UdpInput::start_reuse_port("0.0.0.0:67"))
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// ```
+    #[cfg(unix)]
+    pub fn start_reuse_port(addr: &str) -> Result<Self, std::io::Error> {
+        Self::new(super::reuse_port::bind_reuse_port(addr)?)
+    }
+
+    /// Binds the `UdpInput` listener to the provided address,
+    /// with the given socket options
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let options = SocketOptions::new().recv_buffer_size(4 * 1024 * 1024);
+    /// let udp_input = UdpInput::start_with_options("0.0.0.0:67", &options)?;
+    /// ```
+    pub fn start_with_options(addr: &str, options: &SocketOptions) -> Result<Self, io::Error> {
+        Self::new(options.bind(addr)?)
+    }
+
+    /// Binds the `UdpInput` listener to the provided address,
+    /// only receiving packets from `interface`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let lan = UdpInput::start_on_device("0.0.0.0:67", "eth0")?;
+    /// let guests = UdpInput::start_on_device("0.0.0.0:67", "eth1")?;
+    /// ```
+    #[cfg(any(target_os = "linux", target_vendor = "apple"))]
+    pub fn start_on_device(addr: &str, interface: &str) -> Result<Self, std::io::Error> {
+        Self::new(super::bind_device::bind_device(addr, interface)?)
+    }
+
+    fn new(socket: UdpSocket) -> Result<Self, io::Error> {
+        #[cfg(target_os = "linux")]
+        super::pktinfo::enable_pktinfo(&socket)?;
         Ok(Self {
-            socket: UdpSocket::bind(addr).await?,
+            socket,
+            pool: BufferPool::new(DATAGRAM_BUFFER_LEN, DEFAULT_POOL_CAPACITY),
         })
     }
 
-    /// Returns the next message received
-    async fn get_next(&self) -> Result<Vec<u8>, io::Error> {
-        let mut buf = [0u8; 65535];
-        let (bytes_len, src_addr) = self.socket.recv_from(&mut buf).await?;
+    /// Receives datagrams into the buffers of `pool`,
+    /// which may be shared with other inputs
+    ///
+    /// Buffers must be large enough for any datagram received,
+    /// larger datagrams being truncated.
+    pub fn set_buffer_pool(&mut self, pool: Arc<BufferPool>) {
+        self.pool = pool;
+    }
 
-        Ok(buf[..bytes_len].to_vec())
+    /// Joins the All_DHCP_Relay_Agents_and_Servers multicast group
+    /// on the interface of the given index, so that the listener
+    /// receives the messages of the DHCPv6 clients of its link
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let udp_input = UdpInput::start("[::]:547").await?;
+    /// udp_input.join_dhcpv6_servers(interface_index("eth0")?)?;
+    /// ```
+    pub fn join_dhcpv6_servers(&self, interface: u32) -> Result<(), io::Error> {
+        self.socket
+            .join_multicast_v6(&super::ipv6::ALL_DHCP_RELAY_AGENTS_AND_SERVERS, interface)
+    }
+
+    /// Returns the address the listener is bound to
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, io::Error> {
+        self.socket.local_addr()
+    }
+
+    /// Returns the next message received, and where it was received from
+    #[cfg(target_os = "linux")]
+    pub(crate) async fn get_next(&self) -> Result<(PooledBuffer, PacketMetadata), io::Error> {
+        use std::os::fd::AsRawFd;
+
+        let mut buf = self.pool.get();
+        let (bytes_len, metadata) = self
+            .socket
+            .async_io(tokio::io::Interest::READABLE, || {
+                super::pktinfo::recv_with_pktinfo(self.socket.as_raw_fd(), buf.as_mut_buffer())
+            })
+            .await?;
+        buf.set_len(bytes_len);
+
+        Ok((buf, metadata))
+    }
+
+    /// Returns the next message received, and where it was received from
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) async fn get_next(&self) -> Result<(PooledBuffer, PacketMetadata), io::Error> {
+        let mut buf = self.pool.get();
+        let (bytes_len, source) = self.socket.recv_from(buf.as_mut_buffer()).await?;
+        buf.set_len(bytes_len);
+        let metadata = PacketMetadata {
+            source: Some(source),
+            ..Default::default()
+        };
+
+        Ok((buf, metadata))
     }
 }
 
 #[async_trait]
 impl<T: PacketType> Input<T> for UdpInput {
     async fn get(&self) -> Result<T, io::Error> {
-        let buf = self.get_next().await?;
-        Ok(T::from_raw_bytes(&buf))
+        Ok(self.get_with_metadata().await?.0)
+    }
+
+    async fn get_with_metadata(&self) -> Result<(T, PacketMetadata), io::Error> {
+        let (buf, metadata) = self.get_next().await?;
+        Ok((T::try_from_raw_bytes(&buf)?, metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+    use crate::{core::state_switcher::Output, netio::udp_output::UdpOutput};
+
+    #[derive(Clone)]
+    struct Raw(Vec<u8>);
+
+    impl PacketType for Raw {
+        fn to_raw_bytes(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn empty() -> Self {
+            Self(Vec::new())
+        }
+
+        fn from_raw_bytes(raw_data: &[u8]) -> Self {
+            Self(raw_data.to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_packet_metadata() {
+        let input = UdpInput::start("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .send_to(&[1, 2, 3], input.socket.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let (packet, metadata): (Raw, _) = input.get_with_metadata().await.unwrap();
+        assert_eq!(packet.0, vec![1, 2, 3]);
+        assert_eq!(metadata.source, Some(sender.local_addr().unwrap()));
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(metadata.destination, Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+            assert!(metadata.interface.is_some_and(|index| index > 0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ipv6() {
+        //IPv6 may be disabled
+        let Ok(input) = UdpInput::start("[::1]:0").await else {
+            return;
+        };
+        let output = UdpOutput::start("[::1]:0").await.unwrap();
+        let addr = input.local_addr().unwrap();
+        let sent = Output::send_to(&output, Raw(vec![1, 2, 3]), addr).await;
+        assert_eq!(sent.unwrap(), 3);
+        assert!(Output::send(&output, Raw(vec![1])).await.is_err());
+
+        let (packet, metadata): (Raw, _) = input.get_with_metadata().await.unwrap();
+        assert_eq!(packet.0, vec![1, 2, 3]);
+        assert_eq!(metadata.source, Some(output.local_addr().unwrap()));
+        #[cfg(target_os = "linux")]
+        assert_eq!(metadata.destination, Some(IpAddr::V6(Ipv6Addr::LOCALHOST)));
     }
 }
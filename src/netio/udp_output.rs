@@ -2,16 +2,21 @@
 //! UDP protocol. It reads bytes from a [`PacketType`]
 //! by calling `to_raw_bytes`, and turns these into
 //! a UDP packet.
-use std::net::{Ipv4Addr, SocketAddrV4};
+//!
+//! Packets are sent, unchanged, to the destination given to
+//! [`send_to`](Output::send_to), which the `StateSwitcher` reads
+//! from the [`PacketContext`](crate::core::packet::PacketContext).
+use std::{io, net::SocketAddr};
 
 use async_trait::async_trait;
 use tokio::net::UdpSocket;
 
+use super::socket_options::SocketOptions;
 use crate::core::{packet::PacketType, state_switcher::Output};
 
 /// `UdpOutput` provides a simple implementation of
 /// an [`Output`] using the UDP protocol.
-struct UdpOutput {
+pub struct UdpOutput {
     socket: UdpSocket,
 }
 
@@ -23,27 +28,79 @@ impl UdpOutput {
     /// ```
     /// let udp_output = UdpInput::start("0.0.0.0:53");
     /// ```
-
     pub async fn start(addr: &str) -> Result<Self, std::io::Error> {
-        Ok(Self {
-            socket: UdpSocket::bind(addr).await?,
-        })
+        Self::new(UdpSocket::bind(super::ipv6::parse_addr(addr)?).await?)
+    }
+
+    /// Binds the `UdpOutput` listener to the provided address
+    /// with `SO_REUSEPORT` enabled, so that several `UdpOutput`
+    /// can share the same port, each one in its own `StateSwitcher`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let udp_outputs = (0..4)
+    ///     .map(|_| UdpOutput::start_reuse_port("0.0.0.0:67"))
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// ```
+    #[cfg(unix)]
+    pub fn start_reuse_port(addr: &str) -> Result<Self, std::io::Error> {
+        Self::new(super::reuse_port::bind_reuse_port(addr)?)
+    }
+
+    /// Binds the `UdpOutput` listener to the provided address,
+    /// with the given socket options
+    ///
+    /// Outputs sending replies to broadcast addresses
+    /// must enable [`broadcast`](SocketOptions::broadcast).
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let options = SocketOptions::new().broadcast(true).reuse_address(true);
+    /// let udp_output = UdpOutput::start_with_options("0.0.0.0:67", &options)?;
+    /// ```
+    pub fn start_with_options(addr: &str, options: &SocketOptions) -> Result<Self, io::Error> {
+        Self::new(options.bind(addr)?)
+    }
+
+    /// Binds the `UdpOutput` listener to the provided address,
+    /// only sending packets through `interface`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let udp_output = UdpOutput::start_on_device("0.0.0.0:67", "eth0")?;
+    /// ```
+    #[cfg(any(target_os = "linux", target_vendor = "apple"))]
+    pub fn start_on_device(addr: &str, interface: &str) -> Result<Self, std::io::Error> {
+        Self::new(super::bind_device::bind_device(addr, interface)?)
+    }
+
+    /// Returns the address the output is bound to
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, std::io::Error> {
+        self.socket.local_addr()
+    }
+
+    fn new(socket: UdpSocket) -> Result<Self, std::io::Error> {
+        Ok(Self { socket })
     }
 }
 
 #[async_trait]
 impl<T: PacketType + Sync + Send + 'static> Output<T> for UdpOutput {
-    /// Send a packet through the opened socket
-    async fn send(&self, packet: T) -> Result<usize, std::io::Error> {
-        let raw_bytes = packet.to_raw_bytes();
-        if let Some(addr) = &raw_bytes.get(..6) {
-            let addr = SocketAddrV4::new(
-                Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]),
-                ((addr[4] as u16) << 8) | addr[5] as u16,
-            );
-            self.socket.send_to(&raw_bytes[6..], addr).await
-        } else {
-            return Ok(0);
-        }
+    /// Fails, as a datagram cannot be sent without a destination
+    async fn send(&self, _packet: T) -> Result<usize, std::io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No destination to send the packet to",
+        ))
+    }
+
+    /// Send a packet to `destination` through the opened socket
+    async fn send_to(&self, packet: T, destination: SocketAddr) -> Result<usize, std::io::Error> {
+        self.socket
+            .send_to(packet.to_raw_bytes(), destination)
+            .await
     }
 }
@@ -0,0 +1,293 @@
+//! Storage of data of different types, without a common enum.
+//!
+//! A [`RuntimeStorage`] holds a single type of data, usually an
+//! enum wrapping every storable type. A `RuntimeStorage<AnyData>`
+//! instead holds type-erased [`AnyData`], each pool storing a
+//! single type declared with [`DataPool::typed`]. Data is then
+//! accessed through the typed view returned by
+//! [`RuntimeStorage::pool`], so that adding a type of record
+//! doesn't require editing a central enum.
+//!
+//! Typed pools must be added before [`RuntimeStorage::load`] is
+//! called, since records are converted by the pool they belong to.
+
+use std::{any::Any, marker::PhantomData};
+
+use super::{
+    data::{DataPool, FromRecord, RuntimeStorage, Storable},
+    errors::{StorageError, ValidationError},
+    sql::Record,
+    uid::Uid,
+};
+
+/// Data which can be stored in a pool of a `RuntimeStorage<AnyData>`
+///
+/// Implemented by every [`Storable`] type which can be cloned and
+/// shared between threads.
+pub trait StorableAny: Storable + Send + Sync + 'static {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+    fn clone_boxed(&self) -> Box<dyn StorableAny>;
+    /// Name of the concrete type, for error messages
+    fn type_name(&self) -> &'static str;
+}
+
+impl<T: Storable + Clone + Send + Sync + 'static> StorableAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn clone_boxed(&self) -> Box<dyn StorableAny> {
+        Box::new(self.clone())
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+}
+
+/// Type-erased data, stored in a `RuntimeStorage<AnyData>`
+pub struct AnyData(Box<dyn StorableAny>);
+
+impl AnyData {
+    pub fn new<T: StorableAny>(data: T) -> Self {
+        Self(Box::new(data))
+    }
+
+    /// Returns whether the data is a `T`
+    pub fn is<T: StorableAny>(&self) -> bool {
+        self.0.as_any().is::<T>()
+    }
+
+    /// Returns a reference to the data, if it is a `T`
+    pub fn downcast_ref<T: StorableAny>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref()
+    }
+
+    /// Returns a mutable reference to the data, if it is a `T`
+    pub fn downcast_mut<T: StorableAny>(&mut self) -> Option<&mut T> {
+        self.0.as_any_mut().downcast_mut()
+    }
+
+    /// Returns the data, or itself if it is not a `T`
+    pub fn downcast<T: StorableAny>(self) -> Result<T, Self> {
+        match self.is::<T>() {
+            true => Ok(*self.0.into_any().downcast().unwrap()),
+            false => Err(self),
+        }
+    }
+}
+
+impl Clone for AnyData {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_boxed())
+    }
+}
+
+impl Storable for AnyData {
+    fn to_record(&self) -> Record {
+        self.0.to_record()
+    }
+
+    fn id(&self) -> Uid {
+        self.0.id()
+    }
+
+    fn set_uid(&mut self, uid: Uid) {
+        self.0.set_uid(uid)
+    }
+}
+
+/// Records are converted by the pool they belong to, see [`DataPool::typed`]
+impl FromRecord for AnyData {
+    fn from_record(_record: &Record) -> Option<Self> {
+        None
+    }
+}
+
+impl DataPool<AnyData> {
+    /// Creates a pool of a `RuntimeStorage<AnyData>` holding data of type `T`
+    ///
+    /// Records are converted into `T` when loaded, and data of any
+    /// other type is rejected.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let storage: RuntimeStorage<AnyData> = RuntimeStorage::new(db);
+    /// storage.add_pool(DataPool::typed::<LeaseV4>(String::from("lease"), schema))?;
+    /// storage.load()?;
+    /// ```
+    pub fn typed<T: StorableAny + FromRecord>(name: String, schema: String) -> Self {
+        let mut pool = Self::new(name, schema);
+        pool.set_decoder(|record| T::from_record(record).map(AnyData::new));
+        pool.add_validator(|data| match data.is::<T>() {
+            true => Ok(()),
+            false => Err(ValidationError::new(format!(
+                "expected {}, found {}",
+                std::any::type_name::<T>(),
+                data.0.type_name()
+            ))),
+        });
+        pool
+    }
+}
+
+/// Typed view over a pool of a `RuntimeStorage<AnyData>`,
+/// returned by [`RuntimeStorage::pool`]
+pub struct TypedPool<'a, T> {
+    storage: &'a RuntimeStorage<AnyData>,
+    name: String,
+    _type: PhantomData<T>,
+}
+
+impl<T: StorableAny> TypedPool<'_, T> {
+    /// Stores data in the pool, returning its new uid
+    pub fn store(&self, data: T) -> Result<Uid, StorageError> {
+        self.storage.store(AnyData::new(data), self.name.clone())
+    }
+
+    /// Stores data in the pool, keeping its uid
+    pub fn store_or_replace(&self, data: T) -> Result<Uid, StorageError> {
+        self.storage
+            .store_or_replace(AnyData::new(data), self.name.clone())
+    }
+
+    /// Returns whether data with the given uid is stored in the pool
+    pub fn contains(&self, uid: Uid) -> bool {
+        self.storage
+            .get_pool(&self.name)
+            .is_ok_and(|pool| pool.contains(uid))
+    }
+
+    /// Returns the data of the pool with the given uid
+    pub fn get(&self, uid: Uid) -> Result<T, StorageError> {
+        if !self.contains(uid) {
+            return Err(StorageError::NotFound(uid));
+        }
+        self.storage
+            .get(uid)?
+            .downcast()
+            .map_err(|_| StorageError::NotFound(uid))
+    }
+
+    /// Replaces the data of the pool with the given uid
+    pub fn update(&self, uid: Uid, data: T) -> Result<(), StorageError> {
+        if !self.contains(uid) {
+            return Err(StorageError::NotFound(uid));
+        }
+        self.storage.update(uid, AnyData::new(data))
+    }
+
+    /// Applies `f` to the data of the pool with the given uid
+    pub fn modify(&self, uid: Uid, f: impl FnOnce(&mut T)) -> Result<(), StorageError> {
+        let mut data = self.get(uid)?;
+        f(&mut data);
+        self.update(uid, data)
+    }
+
+    /// Deletes the data of the pool with the given uid
+    pub fn delete(&self, uid: Uid) -> Result<(), StorageError> {
+        if !self.contains(uid) {
+            return Err(StorageError::NotFound(uid));
+        }
+        self.storage.delete(uid)
+    }
+
+    /// Returns the number of data stored in the pool
+    pub fn len(&self) -> Result<usize, StorageError> {
+        self.storage.count(&self.name)
+    }
+
+    /// Returns whether the pool holds no data
+    pub fn is_empty(&self) -> Result<bool, StorageError> {
+        Ok(self.len()? == 0)
+    }
+}
+
+impl RuntimeStorage<AnyData> {
+    /// Returns a typed view over the pool with the given name,
+    /// holding data of type `T`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let leases = storage.pool::<LeaseV4>("lease");
+    /// let uid = leases.store(lease)?;
+    /// leases.modify(uid, |lease| lease.expiration += lease_time)?;
+    /// ```
+    pub fn pool<T: StorableAny>(&self, name: &str) -> TypedPool<'_, T> {
+        TypedPool {
+            storage: self,
+            name: name.to_string(),
+            _type: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::{
+        memory_backend::MemoryBackend,
+        serialized::{Serialized, SCHEMA},
+    };
+
+    #[test]
+    fn test_typed_pools() {
+        let backend = MemoryBackend::new();
+        let storage: RuntimeStorage<AnyData> = RuntimeStorage::new(backend.clone());
+        storage
+            .add_pool(DataPool::typed::<Serialized<u32>>(
+                String::from("counter"),
+                String::from(SCHEMA),
+            ))
+            .unwrap();
+        storage
+            .add_pool(DataPool::typed::<Serialized<String>>(
+                String::from("name"),
+                String::from(SCHEMA),
+            ))
+            .unwrap();
+
+        let counters = storage.pool::<Serialized<u32>>("counter");
+        let names = storage.pool::<Serialized<String>>("name");
+        let counter = counters.store(Serialized::new(1)).unwrap();
+        let name = names.store(Serialized::new(String::from("a"))).unwrap();
+        counters
+            .modify(counter, |counter| *counter = Serialized::new(**counter + 1))
+            .unwrap();
+        assert_eq!(*counters.get(counter).unwrap(), 2);
+        assert_eq!(*names.get(name).unwrap(), "a");
+
+        //Pools only hold their own type
+        assert_eq!(counters.get(name), Err(StorageError::NotFound(name)));
+        assert!(matches!(
+            storage.store(AnyData::new(Serialized::new(3u32)), String::from("name")),
+            Err(StorageError::Invalid(_))
+        ));
+
+        storage.sync().unwrap();
+        let reloaded: RuntimeStorage<AnyData> = RuntimeStorage::new(backend);
+        reloaded
+            .add_pool(DataPool::typed::<Serialized<u32>>(
+                String::from("counter"),
+                String::from(SCHEMA),
+            ))
+            .unwrap();
+        reloaded.load().unwrap();
+        let counters = reloaded.pool::<Serialized<u32>>("counter");
+        assert_eq!(*counters.get(counter).unwrap(), 2);
+        assert_eq!(counters.len(), Ok(1));
+    }
+}
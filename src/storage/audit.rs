@@ -0,0 +1,229 @@
+//! Audit trail of the mutations of a [`RuntimeStorage`].
+//!
+//! Once an [`AuditTrail`] is attached with
+//! [`RuntimeStorage::set_audit_trail`], every store, update and
+//! delete appends an [`AuditEntry`] holding the old and new
+//! values, the time and the actor responsible for the mutation.
+//! Hooks name themselves with [`as_actor`].
+//!
+//! Entries are kept in their own storage, usually in a separate
+//! table or database, and are synchronized along with the audited
+//! storage. They expire after the retention period, and the oldest
+//! entries are evicted first once the trail is full.
+
+use std::{
+    cell::RefCell,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    backend::StorageBackend,
+    data::{DataPool, EvictionPolicy, RuntimeStorage},
+    errors::StorageError,
+    serialized::{Serialized, SCHEMA},
+    sql::Record,
+    uid::Uid,
+};
+
+/// Name of the pool, and table, holding the entries
+pub const AUDIT_POOL: &str = "audit";
+
+/// Actor of mutations made outside of [`as_actor`]
+pub const DEFAULT_ACTOR: &str = "runtime";
+
+thread_local! {
+    static ACTOR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, recording `actor` as responsible for
+/// every mutation it makes on the current thread
+///
+/// # Examples:
+///
+/// ```
+/// audit::as_actor("static_leases", || storage.store(lease, String::from("lease")))?;
+/// ```
+pub fn as_actor<R>(actor: &str, f: impl FnOnce() -> R) -> R {
+    let previous = ACTOR.with(|current| current.replace(Some(actor.to_string())));
+    let result = f();
+    ACTOR.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+fn current_actor() -> String {
+    ACTOR.with(|current| {
+        current
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| DEFAULT_ACTOR.to_string())
+    })
+}
+
+/// Kind of mutation recorded by an [`AuditEntry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    Store,
+    Update,
+    Delete,
+}
+
+/// A single mutation of audited data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: SystemTime,
+    pub actor: String,
+    pub operation: Operation,
+    pub pool: String,
+    pub uid: Uid,
+    /// Value before the mutation, `None` when stored
+    pub old: Option<Record>,
+    /// Value after the mutation, `None` when deleted
+    pub new: Option<Record>,
+}
+
+/// Append-only log of the mutations of a [`RuntimeStorage`]
+pub struct AuditTrail {
+    storage: RuntimeStorage<Serialized<AuditEntry>>,
+    retention: Duration,
+    //Entries are appended one at a time, so that they keep their order
+    append: Mutex<()>,
+}
+
+impl AuditTrail {
+    /// Creates an `AuditTrail` persisted to `backend`, keeping entries
+    /// for `retention` and at most `max_entries` entries at once
+    ///
+    /// Entries already stored in `backend` are loaded, and kept
+    /// until evicted by newer entries.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let trail = AuditTrail::new(audit_db, Duration::from_secs(7 * 86400), 50_000)?;
+    /// storage.set_audit_trail(Arc::new(trail));
+    /// ```
+    pub fn new(
+        backend: impl StorageBackend + 'static,
+        retention: Duration,
+        max_entries: usize,
+    ) -> Result<Self, StorageError> {
+        let storage = RuntimeStorage::new(backend);
+        let mut pool = DataPool::new(AUDIT_POOL.to_string(), SCHEMA.to_string());
+        pool.set_max_size(max_entries, EvictionPolicy::OldestExpirationFirst);
+        pool.add_index("uid", |entry: &Serialized<AuditEntry>| {
+            Some(entry.uid.to_string())
+        });
+        storage.add_pool(pool)?;
+        storage.load()?;
+        Ok(Self {
+            storage,
+            retention,
+            append: Mutex::new(()),
+        })
+    }
+
+    /// Appends an entry for a mutation made by the current actor
+    pub(crate) fn record(
+        &self,
+        operation: Operation,
+        pool: &str,
+        uid: Uid,
+        old: Option<Record>,
+        new: Option<Record>,
+    ) {
+        let _append = self.append.lock().unwrap();
+        let entry = AuditEntry {
+            at: SystemTime::now(),
+            actor: current_actor(),
+            operation,
+            pool: pool.to_string(),
+            uid,
+            old,
+            new,
+        };
+        let stored = self.storage.store_with_ttl(
+            Serialized::new(entry),
+            AUDIT_POOL.to_string(),
+            self.retention,
+        );
+        if let Err(e) = stored {
+            log::error!("Could not audit mutation of data {} : {}", uid, e);
+        }
+    }
+
+    /// Returns every entry of the data with the given uid, oldest first
+    pub fn history(&self, uid: Uid) -> Result<Vec<AuditEntry>, StorageError> {
+        let mut entries: Vec<AuditEntry> = self
+            .storage
+            .find_by(AUDIT_POOL.to_string(), "uid", &uid.to_string())?
+            .into_iter()
+            .map(Serialized::into_inner)
+            .collect();
+        entries.sort_by_key(|entry| entry.at);
+        Ok(entries)
+    }
+
+    /// Returns the number of entries currently kept
+    pub fn len(&self) -> usize {
+        self.storage.count(AUDIT_POOL).unwrap_or_default()
+    }
+
+    /// Returns whether no entry is currently kept
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes new entries to disk, and drops expired entries
+    pub fn sync(&self) -> Result<(), StorageError> {
+        self.storage.sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::memory_backend::MemoryBackend;
+
+    #[test]
+    fn test_audit_trail() {
+        let audit_backend = MemoryBackend::new();
+        let trail =
+            Arc::new(AuditTrail::new(audit_backend.clone(), Duration::from_secs(60), 100).unwrap());
+        let mut storage: RuntimeStorage<Serialized<u32>> =
+            RuntimeStorage::new(MemoryBackend::new());
+        storage.set_audit_trail(trail.clone());
+        storage
+            .add_pool(DataPool::new(String::from("counter"), String::from(SCHEMA)))
+            .unwrap();
+
+        let uid = as_actor("hook", || {
+            storage
+                .store(Serialized::new(1), String::from("counter"))
+                .unwrap()
+        });
+        storage.modify(uid, |_| {}).unwrap();
+        storage.delete(uid).unwrap();
+
+        let history = trail.history(uid).unwrap();
+        let operations: Vec<Operation> = history.iter().map(|entry| entry.operation).collect();
+        assert_eq!(
+            operations,
+            vec![Operation::Store, Operation::Update, Operation::Delete]
+        );
+        assert_eq!(history[0].actor, "hook");
+        assert_eq!(history[1].actor, DEFAULT_ACTOR);
+        assert!(history[0].old.is_none() && history[2].new.is_none());
+        assert_eq!(history[1].old, history[1].new);
+
+        //Entries survive a restart
+        storage.sync().unwrap();
+        let reloaded = AuditTrail::new(audit_backend, Duration::from_secs(60), 100).unwrap();
+        assert_eq!(reloaded.len(), 3);
+    }
+}
@@ -0,0 +1,151 @@
+//! Database layer behind a [`RuntimeStorage`].
+//!
+//! A [`StorageBackend`] persists [`Record`] into tables,
+//! independently of the database actually used.
+//!
+//! [`RuntimeStorage`]: super::data::RuntimeStorage
+
+use std::fmt::Display;
+
+use super::{sql::Record, uid::Uid};
+
+/// Error reported by a [`StorageBackend`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendError(pub String);
+
+impl BackendError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self(message.into())
+    }
+}
+
+impl Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Storage backend error: {}", self.0)
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<mysql::Error> for BackendError {
+    fn from(value: mysql::Error) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<postgres::Error> for BackendError {
+    fn from(value: postgres::Error) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<redis::RedisError> for BackendError {
+    fn from(value: redis::RedisError) -> Self {
+        Self(value.to_string())
+    }
+}
+
+/// Error reported by [`StorageBackend::sync_table`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncError {
+    /// The transaction could not be started
+    Begin(BackendError),
+    /// A statement failed, the transaction was rolled back
+    RolledBack(BackendError),
+    /// A statement failed, and so did the rollback
+    RollbackFailed {
+        cause: BackendError,
+        rollback: BackendError,
+    },
+    /// Every statement succeeded, but the
+    /// transaction could not be committed
+    CommitFailed(BackendError),
+}
+
+impl Display for SyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Begin(e) => write!(f, "Could not start transaction: {}", e),
+            Self::RolledBack(e) => write!(f, "Transaction rolled back: {}", e),
+            Self::RollbackFailed { cause, rollback } => {
+                write!(
+                    f,
+                    "Transaction failed ({}) and could not be rolled back: {}",
+                    cause, rollback
+                )
+            }
+            Self::CommitFailed(e) => write!(f, "Could not commit transaction: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+/// Persistent storage of [`Record`], one table per pool
+///
+/// Every record holds an `id` column, unique in its table.
+pub trait StorageBackend: Send {
+    /// Returns the name of every table
+    fn tables(&mut self) -> Result<Vec<String>, BackendError>;
+
+    /// Creates `table` with the given schema,
+    /// unless it already exists
+    fn create_table(&mut self, table: &str, schema: &str) -> Result<(), BackendError>;
+
+    /// Returns every record of `table`
+    fn select_all(&mut self, table: &str) -> Result<Vec<Record>, BackendError>;
+
+    /// Returns at most `limit` records of `table`, ordered by id,
+    /// starting right after the record with id `after`, or at
+    /// the first record if `after` is `None`
+    fn select_page(
+        &mut self,
+        table: &str,
+        after: Option<Uid>,
+        limit: usize,
+    ) -> Result<Vec<Record>, BackendError>;
+
+    /// Returns the id of every record of `table`
+    fn select_ids(&mut self, table: &str) -> Result<Vec<Uid>, BackendError>;
+
+    /// Returns the record of `table` with the given id, if any
+    fn select_by_id(&mut self, table: &str, id: Uid) -> Result<Option<Record>, BackendError>;
+
+    /// Returns the records of `table` with the given ids, in a single
+    /// query, in no particular order. Missing ids are ignored.
+    fn select_by_ids(&mut self, table: &str, ids: &[Uid]) -> Result<Vec<Record>, BackendError>;
+
+    /// Returns the records of `table` whose columns equal every column
+    /// of `filters`, ordered by id, up to `limit` records if any
+    fn select_where(
+        &mut self,
+        table: &str,
+        filters: &Record,
+        limit: Option<usize>,
+    ) -> Result<Vec<Record>, BackendError>;
+
+    /// Inserts `record` into `table`
+    fn insert(&mut self, table: &str, record: &Record) -> Result<(), BackendError>;
+
+    /// Inserts `record` into `table`, replacing
+    /// the record with the same id if any
+    fn upsert(&mut self, table: &str, record: &Record) -> Result<(), BackendError>;
+
+    /// Replaces the record of `table` with the same id as `record`
+    fn update(&mut self, table: &str, record: &Record) -> Result<(), BackendError>;
+
+    /// Deletes the records of `table` with the given ids
+    fn delete(&mut self, table: &str, ids: &[Uid]) -> Result<(), BackendError>;
+
+    /// Inserts `inserts` into `table`, updates the records matching
+    /// `updates` and deletes the records with the given ids, as a
+    /// single transaction: if any statement fails, `table` is left
+    /// untouched.
+    fn sync_table(
+        &mut self,
+        table: &str,
+        inserts: &[Record],
+        updates: &[Record],
+        deletes: &[Uid],
+    ) -> Result<(), SyncError>;
+}
@@ -1,546 +1,2632 @@
-//! This module provides tools to store your data with a mysql synchronization
+//! This module provides tools to store your data with a database synchronization
+use dashmap::{mapref::entry::Entry as IndexEntry, DashMap};
 use itertools::Itertools;
 use log;
-use mysql::{
-    self, params,
-    prelude::{FromRow, FromValue, Queryable},
-    Opts, Params, Pool,
-};
-use rand;
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
-    sync::{Arc, Mutex},
+    hash::Hash,
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    audit::{AuditTrail, Operation},
+    backend::{StorageBackend, SyncError},
+    errors::{StorageError, ValidationError},
+    export::{export_records, ExportFormat},
+    index::{KeyIndex, SecondaryIndex, UniqueIndex},
+    isc_leases::{parse_leases, ImportError, IscLease},
+    snapshot::{PoolSnapshot, Snapshot},
+    sql::Record,
+    uid::Uid,
 };
 
 ///Trait implementing methods for data that will be stored in RuntimeStorage.
 pub trait Storable {
-    fn value(&self) -> params::Params;
-    fn insert_statement(&self, place: String) -> String;
-    fn id(&self) -> u16;
-    fn set_uid(&mut self, uid: u16);
+    ///Columns of the data, as stored in its pool table. It must include an `id` column.
+    fn to_record(&self) -> Record;
+    fn id(&self) -> Uid;
+    fn set_uid(&mut self, uid: Uid);
+}
+
+///Trait implementing the conversion of a [`Record`] loaded from the backend into data.
+pub trait FromRecord: Sized {
+    fn from_record(record: &Record) -> Option<Self>;
+}
+
+type PoolMap<V> = HashMap<String, Arc<DataPool<V>>>;
+
+///Condition dropping data from a pool when it returns true, see [`DataPool::add_filter`].
+pub type Filter<V> = dyn Fn(&Uid, &V) -> bool + Send + Sync;
+
+///Check run on data before it is stored, see [`DataPool::add_validator`].
+pub type Validator<V> = dyn Fn(&V) -> Result<(), ValidationError> + Send + Sync;
+
+///Conversion of the records of a pool into data, see [`DataPool::set_decoder`].
+pub type Decoder<V> = dyn Fn(&Record) -> Option<V> + Send + Sync;
+
+///Default number of records fetched at once by [`RuntimeStorage::load`].
+pub const DEFAULT_LOAD_BATCH_SIZE: usize = 1000;
+
+///Policy applied by [`RuntimeStorage::load`] to a record whose id is already in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    ///Keep the data already in runtime, and log a warning
+    #[default]
+    SkipWithWarning,
+    ///Replace the data already in runtime with the loaded record
+    PreferDisk,
+    ///Keep the data already in runtime
+    PreferRuntime,
+    ///Stop loading and return [`StorageError::LoadConflict`]
+    Fail,
+}
+
+///Record loaded by [`RuntimeStorage::load`] whose id was already in use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadConflict {
+    pub uid: Uid,
+    ///Table the record was loaded from
+    pub table: String,
+    ///Pool holding the data with the same uid
+    pub pool: String,
+}
+
+///Summary of a [`RuntimeStorage::load`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    ///Number of records loaded into runtime
+    pub loaded: usize,
+    ///Every conflict met, whatever the [`ConflictPolicy`]
+    pub conflicts: Vec<LoadConflict>,
+    ///Uids of the records which couldn't be decoded, kept untouched on disk
+    pub undecodable: Vec<Uid>,
 }
 
-///DbManager aims to manage MySql connections and interactions.
-pub struct DbManager {
-    pub db_name: String,
-    pub user: String,
-    pub password: String,
-    pub pool: Arc<Pool>,
+///Side taken as the reference when [`RuntimeStorage::verify`] repairs inconsistencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repair {
+    ///Rewrite disk to match runtime
+    RuntimeToDisk,
+    ///Reload runtime from disk
+    DiskToRuntime,
+}
+
+///Data stored both in runtime and on disk, with different values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub pool: String,
+    pub uid: Uid,
+    ///Columns whose value differs
+    pub columns: Vec<String>,
+}
+
+///Differences between runtime pools and their tables, found by [`RuntimeStorage::verify`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    ///Data in runtime but not on disk, as `(pool, uid)`
+    pub missing_on_disk: Vec<(String, Uid)>,
+    ///Data on disk but not in runtime, as `(pool, uid)`
+    pub missing_in_memory: Vec<(String, Uid)>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ConsistencyReport {
+    ///Returns whether runtime and disk hold the same data
+    pub fn is_consistent(&self) -> bool {
+        self.missing_on_disk.is_empty()
+            && self.missing_in_memory.is_empty()
+            && self.mismatches.is_empty()
+    }
 }
 
 ///RuntimeStorage manage storage. It is the interface between user and runtime/backend storage.
+///Every data operation takes `&self`, so a RuntimeStorage can be shared behind an `Arc` without any
+///outer lock: reads only take shared locks and never contend with each other.
 pub struct RuntimeStorage<V: Storable + Clone> {
-    pools: Arc<Mutex<HashMap<String, Arc<Mutex<DataPool<V>>>>>>,
-    dbmanager: Arc<Mutex<DbManager>>,
-    index: Arc<Mutex<HashMap<u16, String>>>,
+    pools: Arc<RwLock<PoolMap<V>>>,
+    backend: Arc<Mutex<Box<dyn StorageBackend>>>,
+    index: Arc<DashMap<Uid, String>>,
+    load_batch_size: usize,
+    conflict_policy: ConflictPolicy,
+    lazy_load: bool,
+    cold: Arc<RwLock<HashSet<String>>>,
+    audit: Option<Arc<AuditTrail>>,
+    write_through: bool,
 }
 
-///`DataPool` is a high-level storage manager tha allows you to quickly access and store data, while ensuring your data are protected from code interruption with live MySql Database synchronization.
+///`DataPool` is a high-level storage manager tha allows you to quickly access and store data, while ensuring your data are protected from code interruption with live database synchronization.
 pub struct DataPool<V: Storable> {
     name: String,
-    filters: Vec<fn(&u16, &V) -> bool>,
-    runtime: Arc<Mutex<HashMap<u16, V>>>,
+    filters: Vec<Box<Filter<V>>>,
+    validators: Vec<Box<Validator<V>>>,
+    decoder: Option<Box<Decoder<V>>>,
+    runtime: Arc<RwLock<HashMap<Uid, V>>>,
+    expirations: Arc<Mutex<HashMap<Uid, Instant>>>,
+    ///Column of the table holding expirations, see [`DataPool::set_expiration_column`]
+    expiration_column: Option<String>,
+    indexes: Arc<Mutex<HashMap<String, SecondaryIndex<V>>>>,
+    key: Arc<Mutex<Option<Box<dyn KeyIndex<V>>>>>,
+    modified: Arc<Mutex<HashSet<Uid>>>,
+    ///Uids of the records of the table which couldn't be decoded, never deleted by a sync
+    undecodable: Arc<Mutex<HashSet<Uid>>>,
+    max_size: Option<(usize, EvictionPolicy)>,
+    sync_interval: Option<Duration>,
+    sync_priority: u8,
+    last_sync: Mutex<Option<Instant>>,
+    usage: Arc<Mutex<HashMap<Uid, u64>>>,
+    clock: AtomicU64,
     schema: String,
 }
 
-impl DbManager {
-    ///Exec statement with given params and return the result
-    pub fn exec_and_return<T: FromRow>(
-        &self,
-        stmt: String,
-        params: Params,
-    ) -> Result<Vec<T>, mysql::Error> {
-        //Exec statement with given params and return result
-        let pool = self.pool.clone();
-        match pool.get_conn() {
-            Err(e) => return Err(e),
-            Ok(mut conn) => conn.exec(stmt, params),
+///Data evicted first from a [`DataPool`] once it reaches its [maximum size](DataPool::set_max_size).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    ///The data which was stored or read the longest time ago.
+    LeastRecentlyUsed,
+    ///The data expiring first, then the least recently used data if none expires.
+    OldestExpirationFirst,
+}
+
+impl<V: Storable + Clone + FromRecord> RuntimeStorage<V> {
+    ///Load data from the database backend.
+    /// Each table is read in batches of [`load_batch_size`](RuntimeStorage::set_load_batch_size) records,
+    /// so that the whole table is never held in memory twice.
+    /// Records whose uid is already in use are handled following the
+    /// [conflict policy](RuntimeStorage::set_conflict_policy), and reported in the returned [`LoadReport`].
+    /// In [lazy mode](RuntimeStorage::set_lazy_load), only uids are read, and the data of a pool is loaded
+    /// on first access to the pool.
+    pub fn load(&self) -> Result<LoadReport, StorageError> {
+        let mut report = LoadReport::default();
+        //Load data from database
+        let tables = self.backend.lock().unwrap().tables()?;
+        for table in tables {
+            if !self.pools.read().unwrap().contains_key(&table) {
+                self.add_pool(DataPool::empty(table.clone()))?;
+            }
+            match self.lazy_load {
+                true => self.register_table(&table)?,
+                false => self.load_table(&table, &mut report)?,
+            }
+        }
+        if !report.conflicts.is_empty() {
+            log::warn!(
+                "{} loaded records conflicted with existing data ({:?})",
+                report.conflicts.len(),
+                self.conflict_policy
+            );
+        }
+        if !report.undecodable.is_empty() {
+            log::warn!(
+                "{} records couldn't be decoded, and were kept on disk",
+                report.undecodable.len()
+            );
         }
+        Ok(report)
     }
 
-    ///Exec guven query.
-    pub fn query<T: FromValue>(&self, query: String) -> Result<Vec<T>, mysql::Error> {
-        //Query database
-        let pool = self.pool.clone();
-        pool.get_conn()?.query(query)
+    ///Load every record of `table` into the pool with the same name.
+    fn load_table(&self, table: &str, report: &mut LoadReport) -> Result<(), StorageError> {
+        let decode = self.decoder(table);
+        let pool = self.loaded_pool(table)?;
+        let mut after = None;
+        let mut loaded = 0;
+        loop {
+            let records =
+                self.backend
+                    .lock()
+                    .unwrap()
+                    .select_page(table, after, self.load_batch_size)?;
+            after = match records.last().and_then(Record::id) {
+                Some(id) => Some(id),
+                None => break,
+            };
+            for record in &records {
+                let Some(data) = decode(record) else {
+                    self.keep_undecodable(record, table, report)?;
+                    continue;
+                };
+                let id = data.id();
+                if let Some(conflict) = self.conflict(id, table) {
+                    match self.conflict_policy {
+                        ConflictPolicy::Fail => return Err(StorageError::LoadConflict(conflict)),
+                        ConflictPolicy::SkipWithWarning => {
+                            log::warn!(
+                                "Skipped data {} from {}, uid already used in pool {}",
+                                id,
+                                table,
+                                conflict.pool
+                            );
+                            report.conflicts.push(conflict);
+                            continue;
+                        }
+                        ConflictPolicy::PreferRuntime => {
+                            report.conflicts.push(conflict);
+                            continue;
+                        }
+                        ConflictPolicy::PreferDisk => {
+                            self.loaded_pool(&conflict.pool)?.delete(&id);
+                            self.index.remove(&id);
+                            report.conflicts.push(conflict);
+                        }
+                    }
+                }
+                match self.insert(data, table) {
+                    Err(StorageError::KeyCollision(other)) => {
+                        log::warn!(
+                            "Skipped data {} from {}, its key is already used by data {}",
+                            id,
+                            table,
+                            other
+                        );
+                        self.index.remove(&id);
+                        continue;
+                    }
+                    result => result?,
+                };
+                if let Some(expiration) = pool.stored_expiration(record) {
+                    pool.set_expiration(id, expiration);
+                }
+                report.loaded += 1;
+                log::debug!("Loaded data {}", id);
+            }
+            loaded += records.len();
+            log::info!("Loaded {} records from {}", loaded, table);
+            if records.len() < self.load_batch_size {
+                break;
+            }
+        }
+        Ok(())
     }
 
-    ///Exec statement with given params and drop the result (usefull for drop statement for example)
-    fn exec_and_drop(&self, stmt: String, params: Params) -> Result<(), mysql::Error> {
-        //Exec statement with given params and drop result (useful for dropping data for instance)
-        let pool = self.pool.clone();
-        pool.get_conn()?.exec_drop(stmt, params)
+    ///Keep a record of `table` which couldn't be decoded out of runtime, reserving its uid so that
+    /// it is neither reused nor deleted from disk by the next sync.
+    fn keep_undecodable(
+        &self,
+        record: &Record,
+        table: &str,
+        report: &mut LoadReport,
+    ) -> Result<(), StorageError> {
+        let Some(id) = record.id() else {
+            log::warn!("Could not decode a record without uid from {}", table);
+            return Ok(());
+        };
+        log::warn!("Could not decode data {} from {}, kept on disk", id, table);
+        self.loaded_pool(table)?
+            .undecodable
+            .lock()
+            .unwrap()
+            .insert(id);
+        self.index.entry(id).or_insert_with(|| table.to_string());
+        report.undecodable.push(id);
+        Ok(())
     }
 
-    ///Insert data in a given table
-    pub fn insert<V: Storable>(&self, data: &V, place: String) -> Result<(), mysql::Error> {
-        //Insert data in db
-        self.exec_and_drop(data.insert_statement(place), data.value())
+    ///Index the uids of `table` without loading its records, which are loaded on first access.
+    /// Conflicting uids are only reported once the pool is loaded, unless the conflict policy is
+    /// [`ConflictPolicy::Fail`].
+    fn register_table(&self, table: &str) -> Result<(), StorageError> {
+        let ids = self.backend.lock().unwrap().select_ids(table)?;
+        let mut registered = 0;
+        for id in ids {
+            match self.conflict(id, table) {
+                Some(conflict) if self.conflict_policy == ConflictPolicy::Fail => {
+                    return Err(StorageError::LoadConflict(conflict))
+                }
+                Some(_) => {}
+                None => {
+                    self.index.insert(id, table.to_string());
+                    registered += 1;
+                }
+            }
+        }
+        self.cold.write().unwrap().insert(table.to_string());
+        log::info!("Registered {} records from {}", registered, table);
+        Ok(())
     }
 
-    ///Drop data having given id. A table must be given.
-    pub fn drop(&self, table: String, ids: Vec<u16>) -> Result<(), mysql::Error> {
-        //Drop data from db
-        self.exec_and_drop(
-            String::from("DELETE FROM :table WHERE id = :id"),
-            params! {"table" => table, "id" => ids.iter().join(",")},
-        )
+    ///Returns the conflict raised by loading the record of `table` with the given uid, if any.
+    fn conflict(&self, uid: Uid, table: &str) -> Option<LoadConflict> {
+        let pool = self.pool_of(uid).ok()?;
+        //Uids of a cold pool are indexed before its records are loaded
+        if pool == table
+            && self
+                .loaded_pool(table)
+                .is_ok_and(|pool| !pool.contains(uid))
+        {
+            return None;
+        }
+        Some(LoadConflict {
+            uid,
+            table: table.to_string(),
+            pool,
+        })
     }
 
-    pub fn new(db_name: String, user: String, password: String, host: String) -> Self {
-        let url = format!("mysql://{}:{}@{}/{}", user, password, host, db_name);
-        let opts = Opts::from_url(&url).unwrap();
-        let pool = Pool::new(opts).unwrap();
-        Self {
-            db_name,
-            user,
-            password,
-            pool: Arc::new(pool),
-        }
+    ///Enable or disable lazy loading. Disabled by default.
+    /// When enabled, [`load`](RuntimeStorage::load) only reads the uids of each table, and the data of a pool
+    /// is loaded on first access to the pool, or by [`warm`](RuntimeStorage::warm). This cuts startup time when
+    /// many tables are rarely used.
+    /// # Example
+    /// ```rust
+    /// runtime.set_lazy_load(true);
+    /// runtime.load()?;
+    /// runtime.warm("lease")?;
+    /// ```
+    pub fn set_lazy_load(&mut self, lazy_load: bool) {
+        self.lazy_load = lazy_load;
     }
-}
 
-impl<V: Storable + Clone + FromRow> RuntimeStorage<V> {
-    ///Load data from static mysql database.
-    pub fn load(&mut self, database: Mutex<DbManager>) {
-        //Load data from database
-        let db = database.lock().unwrap();
-        let tables: Vec<String> = db
-            .exec_and_return(String::from("SHOW TABLES"), Params::Empty)
-            .unwrap();
-        for table in tables {
-            let pool = DataPool::empty(table.clone());
-            self.add_pool(pool);
-            let rows: Vec<V> = db
-                .exec_and_return(
-                    String::from("SELECT * FROM :table "),
-                    params! {"table" => table.clone()},
-                )
-                .unwrap();
-            for data in rows {
-                let id = data.id();
-                if !self.index.clone().lock().unwrap().contains_key(&data.id()) {
-                    self.store(data, table.clone()).unwrap();
-                    log::info!("Loaded data {}", id);
-                } else {
-                    log::info!("Tried to load already existing data : {}", id);
-                }
+    ///Load the data of a pool registered by a lazy [`load`](RuntimeStorage::load), if not loaded yet.
+    /// Other accesses to the storage wait until the pool is loaded.
+    pub fn warm(&self, pool_name: &str) -> Result<LoadReport, StorageError> {
+        let mut report = LoadReport::default();
+        let mut cold = self.cold.write().unwrap();
+        if cold.remove(pool_name) {
+            if let Err(e) = self.load_table(pool_name, &mut report) {
+                cold.insert(pool_name.to_string());
+                return Err(e);
             }
         }
+        Ok(report)
     }
-    ///Get data from disk storage given its UID
-    pub fn get_from_disk(&self, uid: u16) -> Result<V, String> {
-        let index = self.index.clone();
-        let index = index.lock().unwrap();
-        let pool = index
-            .get(&uid)
-            .ok_or_else(|| String::from("UID doesn't exist in any pool"))?;
-        let db = self.dbmanager.clone();
-        let db = db.lock().unwrap();
-        let data: Vec<V> = db
-            .exec_and_return(
-                format!("SELECT * FROM {} WHERE id = {}", pool, uid),
-                Params::Empty,
-            )
-            .unwrap();
 
-        match data.len() {
-            0 => Err(String::from("No data with given uid")),
-            _ => Ok(data[0].clone()),
+    ///Load the data of every pool not loaded yet, see [`warm`](RuntimeStorage::warm).
+    pub fn warm_all(&self) -> Result<LoadReport, StorageError> {
+        let mut report = LoadReport::default();
+        let cold: Vec<String> = self.cold.read().unwrap().iter().cloned().collect();
+        for pool_name in cold {
+            let mut warmed = self.warm(&pool_name)?;
+            report.loaded += warmed.loaded;
+            report.conflicts.append(&mut warmed.conflicts);
         }
+        Ok(report)
     }
 
-    /// Delete data given its id
-    pub fn delete(&mut self, id: u16, pool_name: String) {
-        let pools = self.pools.clone();
-        let pools = pools.lock().unwrap();
-        let pool = pools.get(&pool_name).unwrap().clone();
-        let pool = pool.lock().unwrap();
-        pool.delete(&id)
+    ///Set the policy applied by [`load`](RuntimeStorage::load) to records whose uid is already in use,
+    ///either because of data stored before loading or because the same uid appears in several tables.
+    ///With [`ConflictPolicy::PreferDisk`], data replaced in another pool is deleted from that pool's table
+    ///on the next synchronization. Defaults to [`ConflictPolicy::SkipWithWarning`].
+    /// # Example
+    /// ```rust
+    /// runtime.set_conflict_policy(ConflictPolicy::Fail);
+    /// ```
+    pub fn set_conflict_policy(&mut self, policy: ConflictPolicy) {
+        self.conflict_policy = policy;
     }
 
-    pub fn get(&self, uid: u16) -> Result<V, String> {
-        let index = self.index.clone();
-        let index = index.lock().unwrap();
-        let pool = index.get(&uid).unwrap();
-        let pools = self.pools.clone();
-        let pools = pools.lock().unwrap();
-        let pool = pools.get(pool).unwrap().clone();
-        let pool = pool.lock().unwrap();
-        let data = pool
-            .get(uid)
-            .ok_or_else(|| String::from("No current data for given id..."));
-        data
+    ///Set the number of records fetched at once by [`load`](RuntimeStorage::load).
+    /// Defaults to [`DEFAULT_LOAD_BATCH_SIZE`].
+    /// # Example
+    /// ```rust
+    /// runtime.set_load_batch_size(10_000);
+    /// ```
+    pub fn set_load_batch_size(&mut self, batch_size: usize) {
+        self.load_batch_size = batch_size.max(1);
+    }
+    ///Run `f` on the backend, for queries built outside of this module
+    pub(crate) fn with_backend<R>(&self, f: impl FnOnce(&mut dyn StorageBackend) -> R) -> R {
+        f(self.backend.lock().unwrap().as_mut())
     }
 
-    ///Synchronizes given pool with database : inserts missing data in database and remove old data
-    fn pool_sync(&self, pool: &Arc<Mutex<DataPool<V>>>) -> Result<(), mysql::Error> {
-        //Sync database with runtime
-        let db = self.dbmanager.lock().unwrap();
-        let pool = pool.clone();
-        let pool = pool.lock().unwrap();
-        //Compute ids stored on disk
-        let disk_ids: Vec<u16> =
-            db.exec_and_return(format!("SELECT id FROM {} ", pool.name), Params::Empty)?;
-        let disk_ids: HashSet<u16> = disk_ids.iter().cloned().collect();
-        //Compute ids in runtime
-        let runtime = pool.runtime.lock().unwrap();
-        let runtime_ids: HashSet<u16> = runtime.keys().cloned().collect();
-        //Set differences
-        let deprecated_ids = &disk_ids - &runtime_ids;
-        let new_ids = &runtime_ids - &disk_ids;
-
-        //Add new ids to disk
-        for id in new_ids {
-            let value = runtime.get(&id).unwrap();
-            db.insert(
-                value,
-                String::from(self.index.clone().lock().unwrap().get(&id).unwrap()),
-            )
-            .unwrap();
+    ///Returns the pool with the given name, loading its data first if it was lazily loaded
+    pub(crate) fn get_pool(&self, pool_name: &str) -> Result<Arc<DataPool<V>>, StorageError> {
+        if self.cold.read().unwrap().contains(pool_name) {
+            self.warm(pool_name)?;
         }
+        self.loaded_pool(pool_name)
+    }
 
-        let ids = deprecated_ids.iter().join(",");
-        //Remove old ids from disk
-        if !ids.is_empty() {
-            db.exec_and_drop(
-                format!("DELETE FROM {} WHERE id IN ( {} )", pool.name, ids),
-                Params::Empty,
-            )
-        } else {
-            Ok(())
-        }
+    ///Returns the pool with the given name, as is
+    fn loaded_pool(&self, pool_name: &str) -> Result<Arc<DataPool<V>>, StorageError> {
+        self.pools
+            .read()
+            .unwrap()
+            .get(pool_name)
+            .cloned()
+            .ok_or_else(|| StorageError::PoolMissing(pool_name.to_string()))
     }
 
-    ///Generate uid
-    fn get_unused_id(&self) -> u16 {
-        let index = self.index.clone();
-        let index = index.lock().unwrap();
-        let uid = {
-            let mut rd: u16 = rand::random();
-            while (&index).contains_key(&rd) {
-                rd = rand::random();
-            }
-            rd
-        };
-        uid
+    ///Returns the conversion of the records of the given pool into data.
+    fn decoder(&self, pool_name: &str) -> impl Fn(&Record) -> Option<V> {
+        let pool = self.pools.read().unwrap().get(pool_name).cloned();
+        move |record| match &pool {
+            Some(pool) => pool.decode(record),
+            None => V::from_record(record),
+        }
     }
 
-    /// Store data in the pool given the pool name and return an uid representing the data. The uid is unique among all pools.
-    /// Example
-    /// ```rust
-    /// runtime.store(data, String::from("pool_name"));
-    /// ```
-    pub fn store(&mut self, mut data: V, pool_name: String) -> Result<u16, String> {
-        //Store data
-        let uid = self.get_unused_id();
-        let pool = self
-            .pools
-            .clone()
-            .lock()
-            .unwrap()
-            .get(&pool_name)
-            .unwrap()
-            .clone();
-        let pool = pool.lock().unwrap();
-        data.set_uid(uid);
-        self.index.clone().lock().unwrap().insert(uid, pool.name());
-        pool.insert(data)
+    ///Returns the name of the pool holding the given uid
+    fn pool_of(&self, uid: Uid) -> Result<String, StorageError> {
+        self.index
+            .get(&uid)
+            .map(|pool| pool.clone())
+            .ok_or(StorageError::NotFound(uid))
     }
 
-    pub fn new(db: Arc<Mutex<DbManager>>) -> Self {
-        Self {
-            dbmanager: db,
-            pools: Arc::new(Mutex::new(HashMap::new())),
-            index: Arc::new(Mutex::new(HashMap::new())),
-        }
+    ///Get data from disk storage given its UID
+    pub fn get_from_disk(&self, uid: Uid) -> Result<V, StorageError> {
+        let pool = self.pool_of(uid)?;
+        let decode = self.decoder(&pool);
+        let record = self.backend.lock().unwrap().select_by_id(&pool, uid)?;
+
+        record
+            .as_ref()
+            .and_then(decode)
+            .ok_or(StorageError::NotFound(uid))
     }
 
-    ///Run every task for synchronization.
-    /// To synchronize your RuntimeStorage, you will need to use something like :
-    /// ```rust
-    /// let runtime = RuntimeStorage::new();
-    /// let runtime = Arc::new(Mutex::new(runtime));
-    /// let synchronizer = runtime.clone();
-    /// tokio::spawn(async move {
-    ///     loop {
-    ///         time::sleep(duration).await;
-    ///         synchronizer.lock().unwrap().sync();
-    ///     }
-    /// }).await;
-    /// ```
-    pub fn sync(&mut self) {
-        let mut removed_overall: Vec<u16> = vec![];
-        for pool in self.pools.clone().lock().unwrap().values() {
-            //Run every sync task
-            self.pool_sync(pool).unwrap();
-            //Filter data
-            let mut removed = pool.clone().lock().unwrap().purge();
-            removed_overall.append(&mut removed);
+    ///Get data from disk storage given their UIDs, in the same order, with a single query per pool
+    /// and per [`load_batch_size`](RuntimeStorage::set_load_batch_size) uids.
+    /// # Example
+    /// ```rust
+    /// let leases = runtime.get_many_from_disk(&uids)?;
+    /// ```
+    pub fn get_many_from_disk(&self, uids: &[Uid]) -> Result<Vec<V>, StorageError> {
+        let mut by_pool: HashMap<String, Vec<Uid>> = HashMap::new();
+        for &uid in uids {
+            by_pool.entry(self.pool_of(uid)?).or_default().push(uid);
         }
-        for k in removed_overall {
-            self.index.clone().lock().unwrap().remove(&k);
+        let decoders: HashMap<&String, _> = by_pool
+            .keys()
+            .map(|pool| (pool, self.decoder(pool)))
+            .collect();
+        let mut found: HashMap<Uid, V> = HashMap::new();
+        let mut backend = self.backend.lock().unwrap();
+        for (pool, ids) in &by_pool {
+            for ids in ids.chunks(self.load_batch_size) {
+                let records = backend.select_by_ids(pool, ids)?;
+                for data in records.iter().filter_map(&decoders[pool]) {
+                    found.insert(data.id(), data);
+                }
+            }
         }
+        uids.iter()
+            .map(|uid| found.get(uid).cloned().ok_or(StorageError::NotFound(*uid)))
+            .collect()
     }
 
-    ///Add a pool `DataPool` to storage.
+    /// Delete data given its id, from the pool holding it.
+    /// Its uid is released, and it is deleted from disk on next sync, or right away in write-through mode.
     /// # Example
     /// ```rust
-    /// let pool = DataPool::new();
-    /// runtime.add_pool(pool);
+    /// runtime.delete(uid)?;
+    /// assert!(!runtime.contains(uid));
     /// ```
-    pub fn add_pool(&self, pool: DataPool<V>) {
-        let mut pools = self.pools.lock().unwrap();
-        let name = pool.name();
-        let schema = pool.schema();
-        pools.insert(name.clone(), Arc::new(Mutex::new(pool)));
-        self.dbmanager
-            .lock()
-            .unwrap()
-            .exec_and_drop(
-                format!("CREATE TABLE IF NOT EXISTS {} {}", name, schema),
-                Params::Empty,
-            )
-            .unwrap();
-    }
-}
-
-impl<V: Storable + FromRow + Clone> DataPool<V> {
-    ///Iter over filters and drop data that return false when passed as argument to condition functions.
-    pub fn purge(&self) -> Vec<u16> {
-        let mut overall_removed: Vec<u16> = vec![];
-        log::info!("Purging pool {}", self.name);
-        for filter in &self.filters {
-            let mut removed: Vec<u16> = vec![];
-            let mut data = self.runtime.lock().unwrap();
-            for (k, v) in data.iter() {
-                if filter(&k, &v) {
-                    removed.push(*k);
-                }
-            }
-            for k in removed.iter() {
-                data.remove(k);
+    pub fn delete(&self, id: Uid) -> Result<(), StorageError> {
+        let pool_name = self.pool_of(id)?;
+        let pool = self.get_pool(&pool_name)?;
+        let old = self.audited(|| pool.get(id));
+        pool.delete(&id);
+        self.index.remove(&id);
+        if old.is_some() {
+            self.audit(Operation::Delete, &pool_name, id, old, None);
+        }
+        if self.write_through {
+            if let Err(e) = self.backend.lock().unwrap().delete(&pool_name, &[id]) {
+                log::warn!(
+                    "Could not delete data {} from disk, deferring to next sync : {}",
+                    id,
+                    e
+                );
             }
-            overall_removed.append(&mut removed);
         }
-        overall_removed
+        Ok(())
     }
 
-    ///Add filter to filter list.
-    pub fn add_filter(&mut self, filter: fn(&u16, &V) -> bool) {
-        //Add filter to filters
-        self.filters.push(filter);
+    pub fn get(&self, uid: Uid) -> Result<V, StorageError> {
+        self.get_pool(&self.pool_of(uid)?)?
+            .get(uid)
+            .ok_or(StorageError::NotFound(uid))
     }
 
-    ///Inserts data in a pool, this function is private, meaning that to store data in a pool, you would use :
-    /// ```ignore
-    /// let data = Data::new();
-    /// dataPool.store(data, pool_name);
-    /// ```
-    fn insert(&self, data: V) -> Result<u16, String> {
-        let mut runtime = self.runtime.lock().unwrap();
-        if let Entry::Vacant(e) = runtime.entry(data.id()) {
-            let id = data.id();
-            e.insert(data);
-            Ok(id)
-        } else {
-            Err(String::from("Id already in use"))
-        }
+    ///Returns whether data with the given uid is stored, in any pool.
+    pub fn contains(&self, uid: Uid) -> bool {
+        self.pool_of(uid)
+            .and_then(|pool| self.get_pool(&pool))
+            .is_ok_and(|pool| pool.contains(uid))
     }
 
-    fn get(&self, uid: u16) -> Option<V> {
-        let runtime = self.runtime.lock().unwrap();
-        runtime.get(&uid).cloned()
+    ///Returns the number of data held by a pool.
+    /// # Example
+    /// ```rust
+    /// let utilization = runtime.count("lease")? as f64 / range_size as f64;
+    /// ```
+    pub fn count(&self, pool_name: &str) -> Result<usize, StorageError> {
+        Ok(self.get_pool(pool_name)?.len())
     }
 
-    ///Drops data given its id.
-    fn delete(&self, id: &u16) {
-        self.runtime.lock().unwrap().remove(id);
+    ///Returns every data of a pool, ordered by uid.
+    pub fn values(&self, pool_name: &str) -> Result<Vec<V>, StorageError> {
+        Ok(self.get_pool(pool_name)?.values())
     }
 
-    ///Create an empty pool with a given name.
-    pub fn empty(name: String) -> Self {
-        Self {
-            name,
-            filters: vec![],
-            runtime: Arc::new(Mutex::new(HashMap::new())),
-            schema: String::from("(id INT)"),
-        }
+    ///Returns the number of data of a pool for which `predicate` returns true, see [`DataPool::count_where`].
+    /// # Example
+    /// ```rust
+    /// let bound = runtime.count_where("lease", |data| matches!(data, Data::Lease(lease) if lease.is_bound()))?;
+    /// ```
+    pub fn count_where(
+        &self,
+        pool_name: &str,
+        predicate: impl Fn(&V) -> bool,
+    ) -> Result<usize, StorageError> {
+        Ok(self.get_pool(pool_name)?.count_where(predicate))
     }
 
-    pub fn new(name: String, schema: String) -> Self {
-        Self {
-            name,
-            filters: vec![],
-            runtime: Arc::new(Mutex::new(HashMap::new())),
-            schema,
-        }
+    ///Get data given their uids, in the same order.
+    /// # Example
+    /// ```rust
+    /// let leases = runtime.get_many(&uids)?;
+    /// ```
+    pub fn get_many(&self, uids: &[Uid]) -> Result<Vec<V>, StorageError> {
+        uids.iter().map(|&uid| self.get(uid)).collect()
     }
 
-    ///Getter
-    pub fn name(&self) -> String {
-        self.name.clone()
+    ///Get data of the given pool from its key, see [`DataPool::set_key`].
+    /// # Example
+    /// ```rust
+    /// let lease = runtime.get_by_key("lease", &(subnet, hardware_address))?;
+    /// ```
+    pub fn get_by_key<K: Hash + Eq + 'static>(
+        &self,
+        pool_name: &str,
+        key: &K,
+    ) -> Result<Option<V>, StorageError>
+    where
+        V: 'static,
+    {
+        Ok(self.get_pool(pool_name)?.get_by_key(key))
     }
 
-    ///Getter
-    pub fn schema(&self) -> String {
-        self.schema.clone()
+    ///Synchronizes given pool with database in a single transaction : inserts missing data in database and remove old data
+    fn pool_sync(&self, pool: &DataPool<V>) -> Result<(), SyncError> {
+        //Sync database with runtime
+        let mut backend = self.backend.lock().unwrap();
+        //Compute ids stored on disk
+        let disk_ids: HashSet<Uid> = backend
+            .select_ids(&pool.name)
+            .map_err(SyncError::Begin)?
+            .into_iter()
+            .collect();
+        //Compute ids in runtime
+        let runtime = pool.runtime.read().unwrap();
+        let runtime_ids: HashSet<Uid> = runtime.keys().cloned().collect();
+        //Set differences, records which couldn't be decoded are not deleted
+        let deprecated_ids = &(&disk_ids - &runtime_ids) - &*pool.undecodable.lock().unwrap();
+        let new_ids = &runtime_ids - &disk_ids;
+
+        //Add new ids to disk and remove old ids from disk, all at once
+        let inserts = new_ids
+            .iter()
+            .sorted()
+            .map(|id| pool.record(runtime.get(id).unwrap()))
+            .collect_vec();
+        //Data inserted during this sync is already up to date
+        let mut modified = pool.modified.lock().unwrap();
+        let updates = modified
+            .intersection(&disk_ids)
+            .filter(|id| runtime_ids.contains(id))
+            .sorted()
+            .map(|id| pool.record(runtime.get(id).unwrap()))
+            .collect_vec();
+        let deletes = deprecated_ids.into_iter().sorted().collect_vec();
+        backend.sync_table(&pool.name, &inserts, &updates, &deletes)?;
+        modified.clear();
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use derive_data::Storable;
-    use std::time::{Duration, Instant};
+    ///Generate an uid, reserved for the given pool
+    fn get_unused_id(&self, pool_name: &str) -> Uid {
+        loop {
+            let rd = Uid::random();
+            if let IndexEntry::Vacant(e) = self.index.entry(rd) {
+                e.insert(pool_name.to_string());
+                return rd;
+            }
+        }
+    }
 
-    #[derive(Clone, Debug, PartialEq, Eq)]
-    pub struct Lease {
-        name: String,
-        address: String,
-        uid: u16,
+    /// Store data in the pool given the pool name and return an uid representing the data. The uid is unique among all pools.
+    /// Example
+    /// ```rust
+    /// runtime.store(data, String::from("pool_name"));
+    /// ```
+    /// In write-through mode, data is also written to disk before returning, and is not stored at all if that fails.
+    pub fn store(&self, data: V, pool_name: String) -> Result<Uid, StorageError> {
+        self.store_expiring(data, pool_name, None)
     }
 
-    impl Storable for Lease {
-        fn id(&self) -> u16 {
-            self.uid.clone()
-        }
-        fn insert_statement(&self, place: String) -> String {
-            format!("INSERT INTO {} VALUE ( :type, :id, :name, :address)", place)
+    ///Store data like [`store`](RuntimeStorage::store), setting its expiration before it is written to disk
+    fn store_expiring(
+        &self,
+        mut data: V,
+        pool_name: String,
+        expiration: Option<Instant>,
+    ) -> Result<Uid, StorageError> {
+        let pool = self.get_pool(&pool_name)?;
+        pool.validate(&data)?;
+        //Store data
+        let uid = self.get_unused_id(&pool_name);
+        data.set_uid(uid);
+        let record = data.to_record();
+        if let Err(e) = self.insert(data, &pool_name) {
+            self.index.remove(&uid);
+            return Err(e);
         }
-        fn set_uid(&mut self, uid: u16) {
-            self.uid = uid;
+        if let Some(expiration) = expiration {
+            pool.set_expiration(uid, expiration);
         }
-        fn value(&self) -> params::Params {
-            let name = self.name.clone();
-            let uid = self.uid;
-            let address = self.address.clone();
-            params! {"type" => "lease", "id" => uid, "name" => name, "address" => address}
+        if self.write_through {
+            let written = self
+                .backend
+                .lock()
+                .unwrap()
+                .insert(&pool_name, &pool.with_expiration(record.clone()));
+            if let Err(e) = written {
+                pool.delete(&uid);
+                self.index.remove(&uid);
+                return Err(e.into());
+            }
         }
+        self.audit(Operation::Store, &pool_name, uid, None, Some(record));
+        Ok(uid)
     }
 
-    impl FromRow for Lease {
-        fn from_row(row: mysql::Row) -> Self
-        where
-            Self: Sized,
-        {
-            let id: u16 = row.get(1).unwrap();
-            let name: String = row.get(2).unwrap();
-            let address = row.get(3).unwrap();
-            Self {
-                name,
-                address,
-                uid: id,
+    /// Store data in the pool keeping its uid, replacing the data with the same uid if any.
+    /// In write-through mode, data is written to disk with a single upsert statement, and the storage is left
+    /// unchanged if that fails.
+    /// Example
+    /// ```rust
+    /// runtime.store_or_replace(renewed_lease, String::from("lease"))?;
+    /// ```
+    pub fn store_or_replace(&self, data: V, pool_name: String) -> Result<Uid, StorageError> {
+        let uid = data.id();
+        let current_pool = self.pool_of(uid).ok();
+        let record = data.to_record();
+        let pool = self.get_pool(&pool_name)?;
+        pool.validate(&data)?;
+        let previous = match current_pool {
+            Some(current_pool) if current_pool != pool_name => {
+                return Err(StorageError::IdCollision(uid))
+            }
+            Some(_) => {
+                let previous = pool.get(uid).ok_or(StorageError::NotFound(uid))?;
+                pool.replace(data)?;
+                Some(previous)
+            }
+            None => {
+                self.insert(data, &pool_name)?;
+                None
+            }
+        };
+        let old = self.audited(|| previous.clone());
+        let operation = match previous {
+            Some(_) => Operation::Update,
+            None => Operation::Store,
+        };
+        if self.write_through {
+            let written = self
+                .backend
+                .lock()
+                .unwrap()
+                .upsert(&pool_name, &pool.with_expiration(record.clone()));
+            if let Err(e) = written {
+                match previous {
+                    Some(previous) => pool.replace(previous)?,
+                    None => {
+                        pool.delete(&uid);
+                        self.index.remove(&uid);
+                    }
+                }
+                return Err(e.into());
             }
         }
+        self.audit(operation, &pool_name, uid, old, Some(record));
+        Ok(uid)
+    }
 
-        fn from_row_opt(row: mysql::Row) -> Result<Self, mysql::FromRowError>
-        where
-            Self: Sized,
-        {
-            let id: u16 = row.get(1).unwrap();
-            let name: String = row.get(2).unwrap();
-            let address: String = row.get(3).unwrap();
-            Ok(Self {
-                name,
-                address,
-                uid: id,
-            })
+    ///Replace data given its uid, keeping the uid. The data is updated on disk on the next [`sync`](RuntimeStorage::sync),
+    /// or right away in write-through mode, in which case the previous data is kept if writing to disk fails.
+    /// # Example
+    /// ```rust
+    /// runtime.update(uid, renewed_lease)?;
+    /// ```
+    pub fn update(&self, uid: Uid, mut data: V) -> Result<(), StorageError> {
+        let pool_name = self.pool_of(uid)?;
+        data.set_uid(uid);
+        let record = data.to_record();
+        let pool = self.get_pool(&pool_name)?;
+        pool.validate(&data)?;
+        let previous = pool.get(uid).ok_or(StorageError::NotFound(uid))?;
+        let old = self.audited(|| Some(previous.clone()));
+        pool.replace(data)?;
+        if self.write_through {
+            let written = self
+                .backend
+                .lock()
+                .unwrap()
+                .update(&pool_name, &pool.with_expiration(record.clone()));
+            if let Err(e) = written {
+                pool.replace(previous)?;
+                return Err(e.into());
+            }
         }
+        self.audit(Operation::Update, &pool_name, uid, old, Some(record));
+        Ok(())
     }
 
-    #[derive(Clone, Storable, PartialEq, Eq)]
-    pub enum Data {
-        Lease(Lease),
-        Null,
+    ///Modify data in place given its uid, see [`update`](RuntimeStorage::update).
+    /// # Example
+    /// ```rust
+    /// runtime.modify(uid, |data| {
+    ///     if let Data::Lease(lease) = data {
+    ///         lease.expiration += lease_time;
+    ///     }
+    /// })?;
+    /// ```
+    pub fn modify(&self, uid: Uid, f: impl FnOnce(&mut V)) -> Result<(), StorageError> {
+        let mut data = self.get(uid)?;
+        f(&mut data);
+        self.update(uid, data)
     }
 
-    impl FromRow for Data {
-        fn from_row(row: mysql::Row) -> Self
-        where
-            Self: Sized,
-        {
-            let data: String = row.get(0).unwrap();
-            match data.as_str() {
-                "lease" => return Data::Lease(Lease::from_row(row)),
-                _ => Data::Null,
-            }
-        }
+    ///Record every store, update and delete in `audit`, which is synchronized along with the storage.
+    /// # Example
+    /// ```rust
+    /// let trail = Arc::new(AuditTrail::new(audit_db, Duration::from_secs(7 * 86400), 50_000)?);
+    /// runtime.set_audit_trail(trail.clone());
+    /// let history = trail.history(uid)?;
+    /// ```
+    pub fn set_audit_trail(&mut self, audit: Arc<AuditTrail>) {
+        self.audit = Some(audit);
+    }
 
-        fn from_row_opt(row: mysql::Row) -> Result<Self, mysql::FromRowError>
-        where
-            Self: Sized,
-        {
-            let data: String = row.get(0).unwrap();
-            match data.as_str() {
-                "lease" => {
-                    let opt = Lease::from_row_opt(row);
-                    match opt {
-                        Ok(lease) => return Ok(Data::Lease(lease)),
-                        Err(e) => return Err(e),
-                    }
-                }
-                _ => Ok(Data::Null),
-            }
+    ///Returns the record of the data returned by `get`, only if mutations are audited.
+    fn audited(&self, get: impl FnOnce() -> Option<V>) -> Option<Record> {
+        self.audit.as_ref()?;
+        get().map(|data| data.to_record())
+    }
+
+    fn audit(
+        &self,
+        operation: Operation,
+        pool_name: &str,
+        uid: Uid,
+        old: Option<Record>,
+        new: Option<Record>,
+    ) {
+        if let Some(audit) = &self.audit {
+            audit.record(operation, pool_name, uid, old, new);
         }
     }
 
-    async fn insert_retrieve_benchmark(bench: Arc<Mutex<RuntimeStorage<Data>>>) {
-        let lease = Lease {
-            name: String::from("test"),
-            address: String::from("127.0.0.1"),
-            uid: 0,
+    ///Enable or disable write-through mode. Disabled by default.
+    /// In write-through mode, [`store`](RuntimeStorage::store) and [`delete`](RuntimeStorage::delete)
+    /// immediately write to disk, so that a crash between two [`sync`](RuntimeStorage::sync) cannot lose data.
+    /// Periodic synchronization is still needed to remove purged data and retry failed deletions.
+    /// # Example
+    /// ```rust
+    /// runtime.set_write_through(true);
+    /// ```
+    pub fn set_write_through(&mut self, write_through: bool) {
+        self.write_through = write_through;
+    }
+
+    /// Store data in the pool like [`store`](RuntimeStorage::store), and drop it once `ttl` elapsed.
+    /// Expired data is removed from both runtime and disk on the next [`sync`](RuntimeStorage::sync).
+    /// The expiration is written to disk along with the data if the pool has an
+    /// [expiration column](DataPool::set_expiration_column), and restored on [`load`](RuntimeStorage::load).
+    /// Otherwise it is only kept in runtime, and data loaded from disk never expires.
+    /// Example
+    /// ```rust
+    /// runtime.store_with_ttl(lease, String::from("lease"), Duration::from_secs(3600));
+    /// ```
+    pub fn store_with_ttl(
+        &self,
+        data: V,
+        pool_name: String,
+        ttl: Duration,
+    ) -> Result<Uid, StorageError> {
+        self.store_expiring(data, pool_name, Some(Instant::now() + ttl))
+    }
+
+    ///Insert data in the pool, keeping its current uid
+    fn insert(&self, data: V, pool_name: &str) -> Result<Uid, StorageError> {
+        let pool = self.loaded_pool(pool_name)?;
+        self.index.insert(data.id(), pool.name());
+        let uid = pool.insert(data)?;
+        for id in pool.evict() {
+            self.index.remove(&id);
+        }
+        Ok(uid)
+    }
+
+    ///Create a RuntimeStorage synchronized with the given backend.
+    /// # Example
+    /// ```rust
+    /// let db = DbManager::new(db_name, user, password, host)?;
+    /// let runtime: RuntimeStorage<Data> = RuntimeStorage::new(db);
+    /// ```
+    pub fn new(backend: impl StorageBackend + 'static) -> Self {
+        Self {
+            backend: Arc::new(Mutex::new(Box::new(backend))),
+            pools: Arc::new(RwLock::new(HashMap::new())),
+            index: Arc::new(DashMap::new()),
+            load_batch_size: DEFAULT_LOAD_BATCH_SIZE,
+            conflict_policy: ConflictPolicy::default(),
+            lazy_load: false,
+            cold: Arc::new(RwLock::new(HashSet::new())),
+            audit: None,
+            write_through: false,
+        }
+    }
+
+    ///Run every task for synchronization.
+    /// To synchronize your RuntimeStorage periodically, use [`start_sync`](RuntimeStorage::start_sync).
+    /// This call is blocking, call it from [`tokio::task::spawn_blocking`] in an async context.
+    /// Every pool is synchronized even if another one fails, the first failure is returned.
+    /// Pools are synchronized by decreasing [priority](DataPool::set_sync_priority).
+    pub fn sync(&self) -> Result<(), StorageError> {
+        self.sync_pools(Instant::now(), |_| true)
+    }
+
+    ///Synchronize the pools whose [sync interval](DataPool::set_sync_interval) elapsed at `now`,
+    /// and every pool without a sync interval.
+    pub(crate) fn sync_due(&self, now: Instant) -> Result<(), StorageError> {
+        self.sync_pools(now, |pool| pool.is_sync_due(now))
+    }
+
+    fn sync_pools(
+        &self,
+        now: Instant,
+        due: impl Fn(&DataPool<V>) -> bool,
+    ) -> Result<(), StorageError> {
+        let mut removed_overall: Vec<Uid> = vec![];
+        let mut result = Ok(());
+        //Pools not loaded yet have nothing to synchronize
+        let cold = self.cold.read().unwrap().clone();
+        let pools: Vec<Arc<DataPool<V>>> = self
+            .pools
+            .read()
+            .unwrap()
+            .values()
+            .filter(|pool| !cold.contains(&pool.name) && due(pool))
+            .cloned()
+            .sorted_by_key(|pool| std::cmp::Reverse(pool.sync_priority))
+            .collect();
+        for pool in pools {
+            //Drop expired and filtered data, so that it is also removed from disk
+            let mut removed = pool.purge();
+            removed_overall.append(&mut removed);
+            //Run every sync task, a failed pool is retried on the next tick
+            match self.pool_sync(&pool) {
+                Ok(()) => *pool.last_sync.lock().unwrap() = Some(now),
+                Err(e) => {
+                    log::error!("Could not synchronize pool {} : {}", pool.name, e);
+                    result = result.and(Err(e.into()));
+                }
+            }
+        }
+        for k in removed_overall {
+            self.index.remove(&k);
+        }
+        if let Some(audit) = &self.audit {
+            if let Err(e) = audit.sync() {
+                log::error!("Could not synchronize audit trail : {}", e);
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    ///Returns every data of the pool whose key in the index `index` is `key`.
+    /// # Example
+    /// ```rust
+    /// let leases = runtime.find_by(String::from("lease"), "hardware_address", "aa:bb:cc:dd:ee:ff");
+    /// ```
+    pub fn find_by(
+        &self,
+        pool_name: String,
+        index: &str,
+        key: &str,
+    ) -> Result<Vec<V>, StorageError> {
+        Ok(self.get_pool(&pool_name)?.find_by(index, key))
+    }
+
+    ///Write every pool to `path` in a single file, atomically replacing any previous snapshot.
+    /// Data can be snapshotted while the storage is in use, expirations are not saved.
+    /// # Example
+    /// ```rust
+    /// runtime.snapshot("/var/lib/dhcp/storage.json")?;
+    /// ```
+    pub fn snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.warm_all().map_err(io::Error::other)?;
+        let pools = self.pools.read().unwrap();
+        let pools = pools
+            .values()
+            .map(|pool| {
+                let records = pool
+                    .runtime
+                    .read()
+                    .unwrap()
+                    .values()
+                    .sorted_by_key(|data| data.id())
+                    .map(Storable::to_record)
+                    .collect();
+                PoolSnapshot {
+                    name: pool.name(),
+                    schema: pool.schema(),
+                    records,
+                }
+            })
+            .collect();
+        Snapshot { pools }.write(path)
+    }
+
+    ///Compare every loaded pool with its table, and return their differences, ordered by uid.
+    /// Changes made since the last [`sync`](RuntimeStorage::sync) are reported as well, so it is best run right after a sync,
+    /// for instance after a crash. If `repair` is set, differences are then repaired using the given side as reference.
+    /// # Example
+    /// ```rust
+    /// let report = runtime.verify(Some(Repair::DiskToRuntime))?;
+    /// if !report.is_consistent() {
+    ///     log::warn!("Repaired storage : {:?}", report);
+    /// }
+    /// ```
+    pub fn verify(&self, repair: Option<Repair>) -> Result<ConsistencyReport, StorageError> {
+        let mut report = ConsistencyReport::default();
+        let cold = self.cold.read().unwrap().clone();
+        let pools: Vec<Arc<DataPool<V>>> = self
+            .pools
+            .read()
+            .unwrap()
+            .values()
+            .filter(|pool| !cold.contains(&pool.name))
+            .cloned()
+            .sorted_by_key(|pool| pool.name())
+            .collect();
+        for pool in pools {
+            let disk: HashMap<Uid, Record> = self
+                .backend
+                .lock()
+                .unwrap()
+                .select_all(&pool.name)?
+                .into_iter()
+                .filter_map(|record| Some((record.id()?, record)))
+                .collect();
+            let runtime: HashMap<Uid, Record> = pool
+                .runtime
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(uid, data)| (*uid, data.to_record()))
+                .collect();
+
+            let missing_on_disk: Vec<Uid> = runtime
+                .keys()
+                .filter(|uid| !disk.contains_key(uid))
+                .cloned()
+                .sorted()
+                .collect();
+            let missing_in_memory: Vec<Uid> = disk
+                .keys()
+                .filter(|uid| !runtime.contains_key(uid))
+                .cloned()
+                .sorted()
+                .collect();
+            let mut mismatches = vec![];
+            for (uid, record) in runtime.iter().sorted_by_key(|(uid, _)| **uid) {
+                let Some(stored) = disk.get(uid) else {
+                    continue;
+                };
+                //Compare what disk data decodes to, so that columns typed differently by the database still match
+                let stored = pool.decode(stored).map(|data| data.to_record());
+                let columns: Vec<String> = record
+                    .columns()
+                    .into_iter()
+                    .filter(|column| {
+                        stored.as_ref().and_then(|stored| stored.get(column)) != record.get(column)
+                    })
+                    .map(String::from)
+                    .collect();
+                if !columns.is_empty() {
+                    mismatches.push(*uid);
+                    report.mismatches.push(Mismatch {
+                        pool: pool.name(),
+                        uid: *uid,
+                        columns,
+                    });
+                }
+            }
+
+            match repair {
+                Some(Repair::RuntimeToDisk) => {
+                    let inserts: Vec<Record> = missing_on_disk
+                        .iter()
+                        .map(|uid| pool.with_expiration(runtime[uid].clone()))
+                        .collect();
+                    let updates: Vec<Record> = mismatches
+                        .iter()
+                        .map(|uid| pool.with_expiration(runtime[uid].clone()))
+                        .collect();
+                    //Records which couldn't be decoded are kept
+                    let undecodable = pool.undecodable.lock().unwrap().clone();
+                    let deletes: Vec<Uid> = missing_in_memory
+                        .iter()
+                        .filter(|uid| !undecodable.contains(uid))
+                        .cloned()
+                        .collect();
+                    self.backend
+                        .lock()
+                        .unwrap()
+                        .sync_table(&pool.name, &inserts, &updates, &deletes)?;
+                }
+                Some(Repair::DiskToRuntime) => {
+                    pool.remove_entries(&mut pool.runtime.write().unwrap(), &missing_on_disk);
+                    for uid in &missing_on_disk {
+                        self.index.remove(uid);
+                    }
+                    for uid in &missing_in_memory {
+                        let Some(data) = pool.decode(&disk[uid]) else {
+                            continue;
+                        };
+                        match self.pool_of(*uid) {
+                            Ok(other) => {
+                                log::warn!(
+                                    "Could not repair data {}, uid used in pool {}",
+                                    uid,
+                                    other
+                                )
+                            }
+                            Err(_) => {
+                                self.insert(data, &pool.name)?;
+                                if let Some(expiration) = pool.stored_expiration(&disk[uid]) {
+                                    pool.set_expiration(*uid, expiration);
+                                }
+                            }
+                        }
+                    }
+                    for uid in &mismatches {
+                        if let Some(data) = pool.decode(&disk[uid]) {
+                            pool.replace(data)?;
+                        }
+                    }
+                }
+                None => {}
+            }
+            let name = pool.name();
+            report
+                .missing_on_disk
+                .extend(missing_on_disk.into_iter().map(|uid| (name.clone(), uid)));
+            report
+                .missing_in_memory
+                .extend(missing_in_memory.into_iter().map(|uid| (name.clone(), uid)));
+        }
+        Ok(report)
+    }
+
+    ///Export the current data of a pool to `writer`, ordered by uid.
+    /// # Example
+    /// ```rust
+    /// let file = File::create("leases.csv")?;
+    /// runtime.export(String::from("lease"), ExportFormat::Csv, file)?;
+    /// ```
+    pub fn export(
+        &self,
+        pool_name: String,
+        format: ExportFormat,
+        writer: impl Write,
+    ) -> io::Result<()> {
+        let pool = self
+            .get_pool(&pool_name)
+            .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+        let records: Vec<Record> = pool
+            .runtime
+            .read()
+            .unwrap()
+            .values()
+            .sorted_by_key(|data| data.id())
+            .map(Storable::to_record)
+            .collect();
+        export_records(&records, format, writer)
+    }
+
+    ///Import the leases of an ISC dhcpd lease file into a pool, and return the number of stored data.
+    /// Each lease is converted with `convert`, leases for which it returns `None` are skipped.
+    /// Nothing is stored if the file is malformed.
+    /// # Example
+    /// ```rust
+    /// let leases = std::fs::read_to_string("/var/lib/dhcp/dhcpd.leases")?;
+    /// runtime.import_isc_leases(&leases, String::from("lease"), |lease| {
+    ///     lease.is_active().then(|| Data::Lease(LeaseV4::from(lease)))
+    /// })?;
+    /// ```
+    pub fn import_isc_leases(
+        &self,
+        input: &str,
+        pool_name: String,
+        convert: impl Fn(&IscLease) -> Option<V>,
+    ) -> Result<usize, ImportError> {
+        let mut stored = 0;
+        for lease in parse_leases(input)? {
+            let Some(data) = convert(&lease) else {
+                continue;
+            };
+            match self.store(data, pool_name.clone()) {
+                Ok(_) => stored += 1,
+                Err(e) => log::warn!("Could not import lease of {} : {}", lease.address, e),
+            }
+        }
+        log::info!("Imported {} leases into {}", stored, pool_name);
+        Ok(stored)
+    }
+
+    ///Restore data from a snapshot written by [`snapshot`](RuntimeStorage::snapshot), keeping uids.
+    /// Missing pools are created, data whose uid is already in use is skipped.
+    /// # Example
+    /// ```rust
+    /// let mut runtime: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+    /// runtime.restore("/var/lib/dhcp/storage.json")?;
+    /// ```
+    pub fn restore(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let snapshot = Snapshot::read(path)?;
+        for pool in snapshot.pools {
+            if self.get_pool(&pool.name).is_err() {
+                self.add_pool(DataPool::new(pool.name.clone(), pool.schema))
+                    .map_err(io::Error::other)?;
+            }
+            let decode = self.decoder(&pool.name);
+            for record in &pool.records {
+                let Some(data) = decode(record) else {
+                    log::warn!(
+                        "Could not restore a record of {}, it couldn't be decoded",
+                        pool.name
+                    );
+                    continue;
+                };
+                let id = data.id();
+                if self.index.contains_key(&id) {
+                    log::info!("Tried to restore already existing data : {}", id);
+                } else if let Err(e) = self.insert(data, &pool.name) {
+                    log::warn!("Could not restore data {} : {}", id, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///Add a pool `DataPool` to storage.
+    /// # Example
+    /// ```rust
+    /// let pool = DataPool::new();
+    /// runtime.add_pool(pool);
+    /// ```
+    pub fn add_pool(&self, pool: DataPool<V>) -> Result<(), StorageError> {
+        let mut pools = self.pools.write().unwrap();
+        let name = pool.name();
+        let schema = pool.schema();
+        self.backend.lock().unwrap().create_table(&name, &schema)?;
+        self.cold.write().unwrap().remove(&name);
+        pools.insert(name, Arc::new(pool));
+        Ok(())
+    }
+}
+
+impl<V: Storable + FromRecord + Clone> DataPool<V> {
+    ///Drop expired data, then iter over filters and drop data for which a filter returns true.
+    pub fn purge(&self) -> Vec<Uid> {
+        log::info!("Purging pool {}", self.name);
+        let mut overall_removed = self.expire();
+        for filter in &self.filters {
+            let mut removed: Vec<Uid> = vec![];
+            let mut data = self.runtime.write().unwrap();
+            for (k, v) in data.iter() {
+                if filter(k, v) {
+                    removed.push(*k);
+                }
+            }
+            self.remove_entries(&mut data, &removed);
+            overall_removed.append(&mut removed);
+        }
+        overall_removed
+    }
+
+    ///Drop data whose expiration is past, returning their ids.
+    pub fn expire(&self) -> Vec<Uid> {
+        let now = Instant::now();
+        let expired: Vec<Uid> = self
+            .expirations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, expiration)| **expiration <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        self.remove_entries(&mut self.runtime.write().unwrap(), &expired);
+        expired
+    }
+
+    ///Removes data from runtime, along with its expiration and index entries.
+    fn remove_entries(&self, runtime: &mut HashMap<Uid, V>, ids: &[Uid]) {
+        let mut expirations = self.expirations.lock().unwrap();
+        let mut indexes = self.indexes.lock().unwrap();
+        let mut key = self.key.lock().unwrap();
+        let mut modified = self.modified.lock().unwrap();
+        let mut usage = self.usage.lock().unwrap();
+        for id in ids {
+            expirations.remove(id);
+            modified.remove(id);
+            usage.remove(id);
+            if let Some(value) = runtime.remove(id) {
+                for index in indexes.values_mut() {
+                    index.remove(*id, &value);
+                }
+                if let Some(key) = key.as_mut() {
+                    key.remove(*id, &value);
+                }
+            }
+        }
+    }
+
+    ///Add a secondary index named `name`, mapping the key computed by `key` to data.
+    ///Data for which `key` returns `None` is not indexed. Existing data is indexed right away.
+    /// # Example
+    /// ```rust
+    /// pool.add_index("hardware_address", |data| match data {
+    ///     Data::Lease(lease) => Some(lease.hardware_address.to_string()),
+    ///     _ => None,
+    /// });
+    /// ```
+    pub fn add_index(
+        &self,
+        name: &str,
+        key: impl Fn(&V) -> Option<String> + Send + Sync + 'static,
+    ) {
+        let mut index = SecondaryIndex::new(key);
+        for (id, value) in self.runtime.read().unwrap().iter() {
+            index.insert(*id, value);
+        }
+        self.indexes.lock().unwrap().insert(name.to_string(), index);
+    }
+
+    ///Returns every data whose key in the index `name` is `key`.
+    pub fn find_by(&self, name: &str, key: &str) -> Vec<V> {
+        let runtime = self.runtime.read().unwrap();
+        let indexes = self.indexes.lock().unwrap();
+        indexes
+            .get(name)
+            .map(|index| {
+                index
+                    .get(key)
+                    .iter()
+                    .filter_map(|id| runtime.get(id).cloned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    ///Key the pool by the value computed by `key`, such as a compound `(subnet, hardware address)` key.
+    ///Storing data whose key is already used by other data of the pool then fails with
+    ///[`StorageError::KeyCollision`]. Data for which `key` returns `None` is not keyed.
+    ///Data keeps its uid, which still identifies it across pools.
+    ///Fails if existing data already shares a key, in which case the pool is left unkeyed.
+    /// # Example
+    /// ```rust
+    /// pool.set_key(|data| match data {
+    ///     Data::Lease(lease) => Some((lease.subnet, lease.hardware_address)),
+    ///     _ => None,
+    /// })?;
+    /// ```
+    pub fn set_key<K: Hash + Eq + Send + 'static>(
+        &self,
+        key: impl Fn(&V) -> Option<K> + Send + Sync + 'static,
+    ) -> Result<(), StorageError>
+    where
+        V: 'static,
+    {
+        let mut index = UniqueIndex::new(key);
+        for (id, value) in self.runtime.read().unwrap().iter() {
+            KeyIndex::insert(&mut index, *id, value).map_err(StorageError::KeyCollision)?;
+        }
+        *self.key.lock().unwrap() = Some(Box::new(index));
+        Ok(())
+    }
+
+    ///Returns the data whose key is `key`, see [`set_key`](DataPool::set_key).
+    ///Returns `None` if the pool is not keyed by values of type `K`.
+    pub fn get_by_key<K: Hash + Eq + 'static>(&self, key: &K) -> Option<V>
+    where
+        V: 'static,
+    {
+        let uid = self
+            .key
+            .lock()
+            .unwrap()
+            .as_ref()?
+            .as_any()
+            .downcast_ref::<UniqueIndex<V, K>>()?
+            .get(key)?;
+        self.get(uid)
+    }
+
+    ///Returns the time left before data expires, if it has an expiration.
+    pub fn time_to_live(&self, uid: Uid) -> Option<Duration> {
+        let expiration = *self.expirations.lock().unwrap().get(&uid)?;
+        Some(expiration.saturating_duration_since(Instant::now()))
+    }
+
+    fn set_expiration(&self, uid: Uid, expiration: Instant) {
+        self.expirations.lock().unwrap().insert(uid, expiration);
+    }
+
+    ///Persist expirations in `column`, which the schema of the pool must declare as a 64 bits integer.
+    /// The column holds the expiration in milliseconds since the UNIX epoch, or `NULL` for data which
+    /// never expires, and is read back on [`load`](RuntimeStorage::load) so that data stored with
+    /// [`store_with_ttl`](RuntimeStorage::store_with_ttl) still expires after a restart.
+    /// # Example
+    /// ```rust
+    /// let mut pool = DataPool::new(String::from("lease"), String::from("(id INT, ..., expires_at BIGINT)"));
+    /// pool.set_expiration_column("expires_at");
+    /// ```
+    pub fn set_expiration_column(&mut self, column: &str) {
+        self.expiration_column = Some(column.to_string());
+    }
+
+    ///Returns the record of data, as written to the table of the pool.
+    fn record(&self, data: &V) -> Record {
+        self.with_expiration(data.to_record())
+    }
+
+    ///Appends the expiration of the data of `record` to it, if the pool has an expiration column.
+    fn with_expiration(&self, mut record: Record) -> Record {
+        let (Some(column), Some(id)) = (&self.expiration_column, record.id()) else {
+            return record;
         };
-        let lease = Data::Lease(lease);
+        let expiration = self.expirations.lock().unwrap().get(&id).copied();
+        record.push(column, expiration.map(expiration_to_millis));
+        record
+    }
 
-        //Insert nb lease
-        let nb = 1000u16;
-        let manager = bench.clone();
-        let ids = tokio::spawn(async move {
-            println!("Starting {} insertions...", nb);
-            let start = Instant::now();
-            let mut ids = vec![];
-            let mut manager = manager.lock().unwrap();
-            for _i in 0..nb {
-                let id = manager.store(lease.clone(), String::from("lease")).unwrap();
-                ids.push(id);
-            }
-            println!("Inserted {} data in {:.2?}", ids.len(), start.elapsed());
-            ids
+    ///Returns the expiration stored in `record`, if the pool has an expiration column.
+    fn stored_expiration(&self, record: &Record) -> Option<Instant> {
+        let millis = record.get(self.expiration_column.as_ref()?)?.as_int()?;
+        Some(expiration_from_millis(millis))
+    }
+
+    ///Add filter to filter list. Data for which `filter` returns true is dropped on the next purge.
+    ///Filters may capture their configuration.
+    /// # Example
+    /// ```rust
+    /// let max_age = config.lease_max_age;
+    /// pool.add_filter(move |_, data| match data {
+    ///     Data::Lease(lease) => lease.cltt.elapsed() > max_age,
+    ///     _ => false,
+    /// });
+    /// ```
+    pub fn add_filter(&mut self, filter: impl Fn(&Uid, &V) -> bool + Send + Sync + 'static) {
+        //Add filter to filters
+        self.filters.push(Box::new(filter));
+    }
+
+    ///Add a validator, run on data before it is stored or updated in the pool.
+    ///Data rejected by any validator is not stored, and the error is returned as [`StorageError::Invalid`].
+    ///Data loaded from disk is not validated.
+    /// # Example
+    /// ```rust
+    /// pool.add_validator(|data| match data {
+    ///     Data::Lease(lease) if lease.address.is_unspecified() => {
+    ///         Err(ValidationError::new("lease address is unspecified"))
+    ///     }
+    ///     _ => Ok(()),
+    /// });
+    /// ```
+    pub fn add_validator(
+        &mut self,
+        validator: impl Fn(&V) -> Result<(), ValidationError> + Send + Sync + 'static,
+    ) {
+        self.validators.push(Box::new(validator));
+    }
+
+    ///Runs every validator on data, returning the first error.
+    fn validate(&self, data: &V) -> Result<(), ValidationError> {
+        self.validators
+            .iter()
+            .try_for_each(|validator| validator(data))
+    }
+
+    ///Sets how the records of the pool are converted into data, instead of [`FromRecord::from_record`].
+    /// Pools of a [`RuntimeStorage<AnyData>`](super::any::AnyData) decode records into their own type this way.
+    /// # Example
+    /// ```rust
+    /// pool.set_decoder(|record| LeaseV4::from_record(record).map(Data::Lease));
+    /// ```
+    pub fn set_decoder(&mut self, decoder: impl Fn(&Record) -> Option<V> + Send + Sync + 'static) {
+        self.decoder = Some(Box::new(decoder));
+    }
+
+    ///Converts a record of the pool into data.
+    fn decode(&self, record: &Record) -> Option<V> {
+        match &self.decoder {
+            Some(decoder) => decoder(record),
+            None => V::from_record(record),
+        }
+    }
+
+    ///Inserts data in a pool, this function is private, meaning that to store data in a pool, you would use :
+    /// ```ignore
+    /// let data = Data::new();
+    /// dataPool.store(data, pool_name);
+    /// ```
+    fn insert(&self, data: V) -> Result<Uid, StorageError> {
+        let mut runtime = self.runtime.write().unwrap();
+        if let Entry::Vacant(e) = runtime.entry(data.id()) {
+            let id = data.id();
+            if let Some(key) = self.key.lock().unwrap().as_mut() {
+                key.insert(id, &data).map_err(StorageError::KeyCollision)?;
+            }
+            for index in self.indexes.lock().unwrap().values_mut() {
+                index.insert(id, &data);
+            }
+            e.insert(data);
+            self.touch(id);
+            Ok(id)
+        } else {
+            Err(StorageError::IdCollision(data.id()))
+        }
+    }
+
+    ///Replaces data with the same id, and marks it for update on the next synchronization.
+    fn replace(&self, data: V) -> Result<(), StorageError> {
+        let mut runtime = self.runtime.write().unwrap();
+        let id = data.id();
+        let current = runtime.get_mut(&id).ok_or(StorageError::NotFound(id))?;
+        if let Some(key) = self.key.lock().unwrap().as_mut() {
+            key.remove(id, current);
+            if let Err(other) = key.insert(id, &data) {
+                key.insert(id, current).ok();
+                return Err(StorageError::KeyCollision(other));
+            }
+        }
+        for index in self.indexes.lock().unwrap().values_mut() {
+            index.remove(id, current);
+            index.insert(id, &data);
+        }
+        *current = data;
+        self.modified.lock().unwrap().insert(id);
+        self.touch(id);
+        Ok(())
+    }
+
+    ///Returns whether the pool holds data with the given uid.
+    pub fn contains(&self, uid: Uid) -> bool {
+        self.runtime.read().unwrap().contains_key(&uid)
+    }
+
+    ///Returns the number of data held by the pool.
+    pub fn len(&self) -> usize {
+        self.runtime.read().unwrap().len()
+    }
+
+    ///Returns every data held by the pool, ordered by uid.
+    pub fn values(&self) -> Vec<V> {
+        self.runtime
+            .read()
+            .unwrap()
+            .iter()
+            .sorted_by_key(|(uid, _)| **uid)
+            .map(|(_, data)| data.clone())
+            .collect()
+    }
+
+    ///Returns whether the pool holds no data.
+    pub fn is_empty(&self) -> bool {
+        self.runtime.read().unwrap().is_empty()
+    }
+
+    ///Returns the number of data for which `predicate` returns true, without cloning any data.
+    pub fn count_where(&self, predicate: impl Fn(&V) -> bool) -> usize {
+        self.runtime
+            .read()
+            .unwrap()
+            .values()
+            .filter(|data| predicate(data))
+            .count()
+    }
+
+    fn get(&self, uid: Uid) -> Option<V> {
+        let runtime = self.runtime.read().unwrap();
+        let data = runtime.get(&uid).cloned();
+        if data.is_some() {
+            self.touch(uid);
+        }
+        data
+    }
+
+    ///Records that data was used, for [`EvictionPolicy::LeastRecentlyUsed`].
+    ///Usage is only tracked in bounded pools, so that reads of other pools never take an exclusive lock.
+    fn touch(&self, uid: Uid) {
+        if self.max_size.is_none() {
+            return;
+        }
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        self.usage.lock().unwrap().insert(uid, tick);
+    }
+
+    ///Bound the number of data held by the pool. Once it is reached, storing data evicts
+    /// other data according to `policy`. Evicted data is removed from disk on the next synchronization.
+    /// # Example
+    /// ```rust
+    /// let mut pool = DataPool::new(String::from("fingerprints"), schema);
+    /// pool.set_max_size(10_000, EvictionPolicy::LeastRecentlyUsed);
+    /// ```
+    pub fn set_max_size(&mut self, max_size: usize, policy: EvictionPolicy) {
+        self.max_size = Some((max_size, policy));
+    }
+
+    ///Synchronize the pool every `interval` in the background, instead of on every tick of
+    /// [`RuntimeStorage::start_sync`]. The interval is rounded up to a multiple of the tick.
+    /// [`RuntimeStorage::sync`] still synchronizes every pool.
+    /// # Example
+    /// ```rust
+    /// let mut audit = DataPool::new(String::from("audit"), schema);
+    /// audit.set_sync_interval(Duration::from_secs(60));
+    /// ```
+    pub fn set_sync_interval(&mut self, interval: Duration) {
+        self.sync_interval = Some(interval);
+    }
+
+    ///Set the priority of the pool during a synchronization: pools with a higher priority
+    /// are synchronized first. Defaults to 0.
+    pub fn set_sync_priority(&mut self, priority: u8) {
+        self.sync_priority = priority;
+    }
+
+    ///Returns whether the sync interval of the pool elapsed at `now`.
+    fn is_sync_due(&self, now: Instant) -> bool {
+        match (self.sync_interval, *self.last_sync.lock().unwrap()) {
+            (Some(interval), Some(last_sync)) => {
+                now.saturating_duration_since(last_sync) >= interval
+            }
+            _ => true,
+        }
+    }
+
+    ///Drops data until the pool fits its maximum size, and returns the ids of evicted data.
+    fn evict(&self) -> Vec<Uid> {
+        let Some((max_size, policy)) = self.max_size else {
+            return vec![];
+        };
+        let mut runtime = self.runtime.write().unwrap();
+        let excess = runtime.len().saturating_sub(max_size);
+        if excess == 0 {
+            return vec![];
+        }
+        //Victims are picked in order from each candidate list, sorted once
+        let expiring: Vec<Uid> = match policy {
+            EvictionPolicy::OldestExpirationFirst => self
+                .expirations
+                .lock()
+                .unwrap()
+                .iter()
+                .sorted_by_key(|(_, expiration)| **expiration)
+                .map(|(id, _)| *id)
+                .collect(),
+            EvictionPolicy::LeastRecentlyUsed => vec![],
+        };
+        let least_used: Vec<Uid> = self
+            .usage
+            .lock()
+            .unwrap()
+            .iter()
+            .sorted_by_key(|(_, tick)| **tick)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut picked = HashSet::new();
+        let evicted: Vec<Uid> = expiring
+            .into_iter()
+            .chain(least_used)
+            .filter(|id| picked.insert(*id))
+            .take(excess)
+            .collect();
+        self.remove_entries(&mut runtime, &evicted);
+        evicted
+    }
+
+    ///Drops data given its id.
+    fn delete(&self, id: &Uid) {
+        self.remove_entries(&mut self.runtime.write().unwrap(), &[*id]);
+    }
+
+    ///Create an empty pool with a given name.
+    pub fn empty(name: String) -> Self {
+        Self::new(name, String::from("(id INT)"))
+    }
+
+    pub fn new(name: String, schema: String) -> Self {
+        Self {
+            name,
+            filters: vec![],
+            validators: vec![],
+            decoder: None,
+            runtime: Arc::new(RwLock::new(HashMap::new())),
+            expirations: Arc::new(Mutex::new(HashMap::new())),
+            expiration_column: None,
+            indexes: Arc::new(Mutex::new(HashMap::new())),
+            key: Arc::new(Mutex::new(None)),
+            modified: Arc::new(Mutex::new(HashSet::new())),
+            undecodable: Arc::new(Mutex::new(HashSet::new())),
+            max_size: None,
+            sync_interval: None,
+            sync_priority: 0,
+            last_sync: Mutex::new(None),
+            usage: Arc::new(Mutex::new(HashMap::new())),
+            clock: AtomicU64::new(0),
+            schema,
+        }
+    }
+
+    ///Getter
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    ///Getter
+    pub fn schema(&self) -> String {
+        self.schema.clone()
+    }
+}
+
+///Converts an expiration to milliseconds since the UNIX epoch, as stored in an expiration column.
+fn expiration_to_millis(expiration: Instant) -> i64 {
+    let at = SystemTime::now() + expiration.saturating_duration_since(Instant::now());
+    at.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
+}
+
+///Converts milliseconds since the UNIX epoch read from an expiration column to an expiration.
+fn expiration_from_millis(millis: i64) -> Instant {
+    let at = UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64);
+    Instant::now() + at.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::{backend::BackendError, memory_backend::MemoryBackend};
+    use derive_data::Storable;
+    use std::sync::atomic::AtomicBool;
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Lease {
+        name: String,
+        address: String,
+        uid: Uid,
+    }
+
+    impl Storable for Lease {
+        fn id(&self) -> Uid {
+            self.uid
+        }
+        fn set_uid(&mut self, uid: Uid) {
+            self.uid = uid;
+        }
+        fn to_record(&self) -> Record {
+            Record::new()
+                .with("type", "lease")
+                .with("id", self.uid)
+                .with("name", self.name.clone())
+                .with("address", self.address.clone())
+        }
+    }
+
+    impl FromRecord for Lease {
+        fn from_record(record: &Record) -> Option<Self> {
+            Some(Self {
+                name: record.get("name")?.as_str()?.to_string(),
+                address: record.get("address")?.as_str()?.to_string(),
+                uid: record.id()?,
+            })
+        }
+    }
+
+    #[derive(Clone, Storable, PartialEq, Eq)]
+    pub enum Data {
+        Lease(Lease),
+        Null,
+    }
+
+    impl FromRecord for Data {
+        fn from_record(record: &Record) -> Option<Self> {
+            match record.get("type")?.as_str()? {
+                "lease" => Lease::from_record(record).map(Data::Lease),
+                _ => Some(Data::Null),
+            }
+        }
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut data = Data::Lease(Lease {
+            name: String::from("test"),
+            address: String::from("127.0.0.1"),
+            uid: Uid::default(),
+        });
+        data.set_uid(Uid::new(12));
+
+        let record = data.to_record();
+        assert_eq!(record.id(), Some(Uid::new(12)));
+        assert!(Data::from_record(&record) == Some(data));
+    }
+
+    fn lease(name: &str) -> Data {
+        Data::Lease(Lease {
+            name: String::from(name),
+            address: String::from("127.0.0.1"),
+            uid: Uid::default(),
         })
-        .await
-        .unwrap();
+    }
 
-        let ids_disk = ids.clone();
+    #[test]
+    fn test_memory_sync() {
+        let backend = MemoryBackend::new();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
 
-        //Retrieve from runtime
-        let getter = bench.clone();
-        tokio::spawn(async move {
-            let start = Instant::now();
-            let mut datas = vec![];
-            for id in ids {
-                datas.push(getter.lock().unwrap().get(id).unwrap());
+        let first = storage
+            .store(lease("first"), String::from("lease"))
+            .unwrap();
+        let second = storage
+            .store(lease("second"), String::from("lease"))
+            .unwrap();
+        assert!(
+            storage.get(first).unwrap() == {
+                let mut data = lease("first");
+                data.set_uid(first);
+                data
             }
-            println!("Retrieved from runtime in {:.2?}", start.elapsed());
-            datas
+        );
+
+        storage.sync().unwrap();
+        assert!(storage.get_from_disk(second).unwrap() == storage.get(second).unwrap());
+
+        storage.delete(first).unwrap();
+        storage.sync().unwrap();
+        let mut disk = backend.clone();
+        assert_eq!(disk.select_ids("lease").unwrap(), vec![second]);
+
+        let reloaded: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+        reloaded.load().unwrap();
+        assert!(reloaded.get(second).unwrap() == storage.get(second).unwrap());
+    }
+
+    #[test]
+    fn test_batched_load() {
+        let backend = MemoryBackend::new();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        let ids: Vec<Uid> = (0..7)
+            .map(|_| {
+                storage
+                    .store(lease("batch"), String::from("lease"))
+                    .unwrap()
+            })
+            .collect();
+        storage.sync().unwrap();
+
+        let mut reloaded: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+        reloaded.set_load_batch_size(3);
+        reloaded.load().unwrap();
+        for id in ids {
+            assert!(reloaded.get(id).unwrap() == storage.get(id).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_load_conflicts() {
+        let mut backend = MemoryBackend::new();
+        backend.create_table("lease", "").unwrap();
+        backend.create_table("backup", "").unwrap();
+        backend.insert("lease", &lease("disk").to_record()).unwrap();
+        backend
+            .insert("backup", &lease("copy").to_record())
+            .unwrap();
+
+        let runtime = |policy| {
+            let mut storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+            storage.set_conflict_policy(policy);
+            storage
+        };
+
+        let storage = runtime(ConflictPolicy::SkipWithWarning);
+        let report = storage.load().unwrap();
+        assert_eq!(report.loaded, 1);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].uid, Uid::default());
+
+        let storage = runtime(ConflictPolicy::PreferRuntime);
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        storage
+            .store_or_replace(lease("runtime"), String::from("lease"))
+            .unwrap();
+        let report = storage.load().unwrap();
+        assert_eq!(report.loaded, 0);
+        assert_eq!(report.conflicts.len(), 2);
+        assert!(storage.get(Uid::default()).unwrap() == lease("runtime"));
+
+        let storage = runtime(ConflictPolicy::PreferDisk);
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        storage
+            .store_or_replace(lease("runtime"), String::from("lease"))
+            .unwrap();
+        let report = storage.load().unwrap();
+        assert_eq!(report.loaded, 2);
+        assert!(storage.get(Uid::default()).unwrap() != lease("runtime"));
+
+        let storage = runtime(ConflictPolicy::Fail);
+        assert!(matches!(
+            storage.load(),
+            Err(StorageError::LoadConflict(LoadConflict { uid, .. })) if uid == Uid::default()
+        ));
+    }
+
+    #[test]
+    fn test_undecodable_records() {
+        let mut backend = MemoryBackend::new();
+        backend.create_table("lease", "").unwrap();
+        let broken = Uid::new(9);
+        backend
+            .insert("lease", &Record::new().with("id", broken))
+            .unwrap();
+
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        let report = storage.load().unwrap();
+        assert_eq!(report.loaded, 0);
+        assert_eq!(report.undecodable, vec![broken]);
+        assert!(!storage.contains(broken));
+
+        let uid = storage.store(lease("new"), String::from("lease")).unwrap();
+        assert_ne!(uid, broken);
+        storage.sync().unwrap();
+        storage.verify(Some(Repair::RuntimeToDisk)).unwrap();
+        let mut ids = backend.select_ids("lease").unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![broken, uid].into_iter().sorted().collect_vec());
+    }
+
+    #[test]
+    fn test_composite_key() {
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        let pool = DataPool::new(String::from("lease"), String::new());
+        pool.set_key(|data| match data {
+            Data::Lease(lease) => Some((lease.name.clone(), lease.address.clone())),
+            Data::Null => None,
         })
-        .await
         .unwrap();
+        storage.add_pool(pool).unwrap();
+
+        let uid = storage
+            .store(lease("keyed"), String::from("lease"))
+            .unwrap();
+        assert!(matches!(
+            storage.store(lease("keyed"), String::from("lease")),
+            Err(StorageError::KeyCollision(other)) if other == uid
+        ));
+        storage.store(Data::Null, String::from("lease")).unwrap();
+
+        let key = (String::from("keyed"), String::from("127.0.0.1"));
+        assert!(storage.get_by_key("lease", &key).unwrap() == storage.get(uid).ok());
+        assert!(storage.get_by_key("lease", &1u16).unwrap().is_none());
+
+        storage.delete(uid).unwrap();
+        assert!(storage.get_by_key("lease", &key).unwrap().is_none());
+        storage
+            .store(lease("keyed"), String::from("lease"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_lazy_load() {
+        let backend = MemoryBackend::new();
+        let mut disk = backend.clone();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        for pool in ["lease", "archive"] {
+            storage
+                .add_pool(DataPool::new(String::from(pool), String::new()))
+                .unwrap();
+        }
+        let lease_uid = storage
+            .store(lease("lease"), String::from("lease"))
+            .unwrap();
+        let archived = storage
+            .store(lease("archived"), String::from("archive"))
+            .unwrap();
+        storage.sync().unwrap();
+
+        let mut reloaded: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+        reloaded.set_lazy_load(true);
+        assert_eq!(reloaded.load().unwrap().loaded, 0);
+        assert_eq!(reloaded.cold.read().unwrap().len(), 2);
+
+        //Uids of cold pools are never reused, and cold pools are not synchronized
+        assert_ne!(reloaded.get_unused_id("lease"), archived);
+        reloaded.sync().unwrap();
+        assert_eq!(disk.select_ids("archive").unwrap(), vec![archived]);
+
+        assert_eq!(reloaded.warm("lease").unwrap().loaded, 1);
+        assert_eq!(reloaded.warm("lease").unwrap().loaded, 0);
+        assert!(reloaded.get(archived).unwrap() == storage.get(archived).unwrap());
+        assert!(reloaded.get(lease_uid).unwrap() == storage.get(lease_uid).unwrap());
+        assert!(reloaded.cold.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_many() {
+        let backend = MemoryBackend::new();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        let uids: Vec<Uid> = ["a", "b", "c"]
+            .iter()
+            .map(|name| storage.store(lease(name), String::from("lease")).unwrap())
+            .collect();
+        storage.sync().unwrap();
+
+        let runtime = storage.get_many(&uids).unwrap();
+        assert!(storage.get_many_from_disk(&uids).unwrap() == runtime);
+        assert!(runtime[1] == storage.get(uids[1]).unwrap());
+
+        storage.delete(uids[0]).unwrap();
+        assert!(storage.get_many(&uids).is_err());
+    }
+
+    #[test]
+    fn test_count() {
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        assert_eq!(storage.count("lease").unwrap(), 0);
+
+        let uid = storage.store(lease("a"), String::from("lease")).unwrap();
+        storage.store(lease("b"), String::from("lease")).unwrap();
+        storage.store(Data::Null, String::from("lease")).unwrap();
+        assert!(storage.contains(uid));
+        assert_eq!(storage.count("lease").unwrap(), 3);
+        assert_eq!(
+            storage
+                .count_where("lease", |data| matches!(data, Data::Lease(_)))
+                .unwrap(),
+            2
+        );
+        assert!(storage.count("missing").is_err());
+
+        storage.delete(uid).unwrap();
+        assert!(!storage.contains(uid));
+    }
+
+    #[test]
+    fn test_verify() {
+        let backend = MemoryBackend::new();
+        let mut disk = backend.clone();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        let uids: Vec<Uid> = ["a", "b", "c"]
+            .iter()
+            .map(|name| storage.store(lease(name), String::from("lease")).unwrap())
+            .collect();
+        storage.sync().unwrap();
+        assert!(storage.verify(None).unwrap().is_consistent());
+
+        let corrupt = |disk: &mut MemoryBackend| {
+            disk.delete("lease", &[uids[0]]).unwrap();
+            let mut changed = lease("changed");
+            changed.set_uid(uids[1]);
+            disk.update("lease", &changed.to_record()).unwrap();
+            let mut extra = lease("extra");
+            extra.set_uid(Uid::new(uids.iter().max().unwrap().get() + 1));
+            disk.insert("lease", &extra.to_record()).unwrap();
+            extra.id()
+        };
+
+        let extra = corrupt(&mut disk);
+        let report = storage.verify(Some(Repair::RuntimeToDisk)).unwrap();
+        assert_eq!(
+            report.missing_on_disk,
+            vec![(String::from("lease"), uids[0])]
+        );
+        assert_eq!(
+            report.missing_in_memory,
+            vec![(String::from("lease"), extra)]
+        );
+        assert_eq!(report.mismatches[0].columns, vec![String::from("name")]);
+        assert!(storage.verify(None).unwrap().is_consistent());
+        assert!(storage.get(uids[1]).unwrap() == disk_lease(&mut disk, uids[1]));
 
-        //Retrieve from disk
-        tokio::time::sleep(Duration::from_millis(10)).await;
-        let disk_getter = bench.clone();
-        tokio::spawn(async move {
+        corrupt(&mut disk);
+        storage.verify(Some(Repair::DiskToRuntime)).unwrap();
+        assert!(storage.verify(None).unwrap().is_consistent());
+        assert!(storage.get(uids[0]).is_err());
+        assert!(storage.get(extra).is_ok());
+        assert!(
+            matches!(storage.get(uids[1]).unwrap(), Data::Lease(lease) if lease.name == "changed")
+        );
+    }
+
+    ///Backend failing every write while `failing` is set
+    #[derive(Clone, Default)]
+    struct FailingBackend {
+        disk: MemoryBackend,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl FailingBackend {
+        fn check(&self) -> Result<(), BackendError> {
+            match self.failing.load(Ordering::SeqCst) {
+                true => Err(BackendError::new("Backend unavailable")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl StorageBackend for FailingBackend {
+        fn tables(&mut self) -> Result<Vec<String>, BackendError> {
+            self.disk.tables()
+        }
+
+        fn create_table(&mut self, table: &str, schema: &str) -> Result<(), BackendError> {
+            self.disk.create_table(table, schema)
+        }
+
+        fn select_all(&mut self, table: &str) -> Result<Vec<Record>, BackendError> {
+            self.disk.select_all(table)
+        }
+
+        fn select_page(
+            &mut self,
+            table: &str,
+            after: Option<Uid>,
+            limit: usize,
+        ) -> Result<Vec<Record>, BackendError> {
+            self.disk.select_page(table, after, limit)
+        }
+
+        fn select_ids(&mut self, table: &str) -> Result<Vec<Uid>, BackendError> {
+            self.disk.select_ids(table)
+        }
+
+        fn select_by_id(&mut self, table: &str, id: Uid) -> Result<Option<Record>, BackendError> {
+            self.disk.select_by_id(table, id)
+        }
+
+        fn select_by_ids(&mut self, table: &str, ids: &[Uid]) -> Result<Vec<Record>, BackendError> {
+            self.disk.select_by_ids(table, ids)
+        }
+
+        fn select_where(
+            &mut self,
+            table: &str,
+            filters: &Record,
+            limit: Option<usize>,
+        ) -> Result<Vec<Record>, BackendError> {
+            self.disk.select_where(table, filters, limit)
+        }
+
+        fn insert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+            self.check()?;
+            self.disk.insert(table, record)
+        }
+
+        fn upsert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+            self.check()?;
+            self.disk.upsert(table, record)
+        }
+
+        fn update(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+            self.check()?;
+            self.disk.update(table, record)
+        }
+
+        fn delete(&mut self, table: &str, ids: &[Uid]) -> Result<(), BackendError> {
+            self.check()?;
+            self.disk.delete(table, ids)
+        }
+
+        fn sync_table(
+            &mut self,
+            table: &str,
+            inserts: &[Record],
+            updates: &[Record],
+            deletes: &[Uid],
+        ) -> Result<(), SyncError> {
+            self.check().map_err(SyncError::Begin)?;
+            self.disk.sync_table(table, inserts, updates, deletes)
+        }
+    }
+
+    #[test]
+    fn test_write_through_failure() {
+        let backend = FailingBackend::default();
+        let mut disk = backend.disk.clone();
+        let mut storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        storage.set_write_through(true);
+        let uid = storage.store(lease("old"), String::from("lease")).unwrap();
+
+        backend.failing.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            storage.update(uid, lease("new")),
+            Err(StorageError::Backend(_))
+        ));
+        assert!(matches!(storage.get(uid).unwrap(), Data::Lease(lease) if lease.name == "old"));
+        assert!(matches!(disk_lease(&mut disk, uid), Data::Lease(lease) if lease.name == "old"));
+
+        let mut replaced = lease("replaced");
+        replaced.set_uid(uid);
+        assert!(matches!(
+            storage.store_or_replace(replaced, String::from("lease")),
+            Err(StorageError::Backend(_))
+        ));
+        assert!(matches!(storage.get(uid).unwrap(), Data::Lease(lease) if lease.name == "old"));
+        let mut added = lease("added");
+        added.set_uid(Uid::new(42));
+        assert!(matches!(
+            storage.store_or_replace(added, String::from("lease")),
+            Err(StorageError::Backend(_))
+        ));
+        assert!(!storage.contains(Uid::new(42)));
+        assert!(!storage.index.contains_key(&Uid::new(42)));
+        assert_eq!(disk.select_ids("lease").unwrap(), vec![uid]);
+    }
+
+    fn disk_lease(disk: &mut MemoryBackend, uid: Uid) -> Data {
+        Data::from_record(&disk.select_by_id("lease", uid).unwrap().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_write_through() {
+        let backend = MemoryBackend::new();
+        let mut disk = backend.clone();
+        let mut storage: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        storage.set_write_through(true);
+
+        let uid = storage
+            .store(lease("durable"), String::from("lease"))
+            .unwrap();
+        assert_eq!(disk.select_ids("lease").unwrap(), vec![uid]);
+
+        //Data already on disk is not inserted twice on sync
+        storage.sync().unwrap();
+        assert_eq!(disk.select_ids("lease").unwrap(), vec![uid]);
+
+        storage.delete(uid).unwrap();
+        assert!(disk.select_ids("lease").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_update() {
+        let backend = MemoryBackend::new();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        let pool = DataPool::new(String::from("lease"), String::new());
+        pool.add_index("name", |data| match data {
+            Data::Lease(lease) => Some(lease.name.clone()),
+            Data::Null => None,
+        });
+        storage.add_pool(pool).unwrap();
+
+        let uid = storage.store(lease("old"), String::from("lease")).unwrap();
+        storage.sync().unwrap();
+        storage.update(uid, lease("new")).unwrap();
+        assert!(storage
+            .find_by(String::from("lease"), "name", "old")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            storage
+                .find_by(String::from("lease"), "name", "new")
+                .unwrap()
+                .len(),
+            1
+        );
+
+        storage
+            .modify(uid, |data| {
+                if let Data::Lease(lease) = data {
+                    lease.address = String::from("10.0.0.1");
+                }
+            })
+            .unwrap();
+        storage.sync().unwrap();
+        let disk = storage.get_from_disk(uid).unwrap();
+        assert!(disk == storage.get(uid).unwrap());
+        assert!(
+            matches!(disk, Data::Lease(lease) if lease.name == "new" && lease.address == "10.0.0.1")
+        );
+        assert_eq!(
+            storage.update(Uid::new(uid.get().wrapping_add(1)), lease("missing")),
+            Err(StorageError::NotFound(Uid::new(uid.get().wrapping_add(1))))
+        );
+        assert_eq!(
+            storage.delete(uid).and_then(|_| storage.delete(uid)),
+            Err(StorageError::NotFound(uid))
+        );
+        assert!(!storage.contains(uid));
+    }
+
+    #[test]
+    fn test_store_or_replace() {
+        let backend = MemoryBackend::new();
+        let mut storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        storage
+            .add_pool(DataPool::new(String::from("other"), String::new()))
+            .unwrap();
+        storage.set_write_through(true);
+
+        let mut renewed = lease("renewed");
+        renewed.set_uid(Uid::new(42));
+        assert_eq!(
+            storage.store_or_replace(renewed.clone(), String::from("lease")),
+            Ok(Uid::new(42))
+        );
+        assert_eq!(
+            storage.store_or_replace(renewed.clone(), String::from("lease")),
+            Ok(Uid::new(42))
+        );
+        assert_eq!(
+            storage.store_or_replace(renewed, String::from("other")),
+            Err(StorageError::IdCollision(Uid::new(42)))
+        );
+
+        let mut disk = backend.clone();
+        assert_eq!(disk.select_ids("lease").unwrap(), vec![Uid::new(42)]);
+        storage.sync().unwrap();
+        assert_eq!(disk.select_ids("lease").unwrap(), vec![Uid::new(42)]);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let path =
+            std::env::temp_dir().join(format!("fp_core_restore_{}.json", std::process::id()));
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        let ids: Vec<Uid> = ["first", "second"]
+            .iter()
+            .map(|name| storage.store(lease(name), String::from("lease")).unwrap())
+            .collect();
+        storage.snapshot(&path).unwrap();
+
+        let restored: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        restored.restore(&path).unwrap();
+        for id in ids {
+            assert!(restored.get(id).unwrap() == storage.get(id).unwrap());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_eviction() {
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        let mut lru = DataPool::new(String::from("lru"), String::new());
+        lru.set_max_size(2, EvictionPolicy::LeastRecentlyUsed);
+        storage.add_pool(lru).unwrap();
+        let mut expiring = DataPool::new(String::from("expiring"), String::new());
+        expiring.set_max_size(2, EvictionPolicy::OldestExpirationFirst);
+        storage.add_pool(expiring).unwrap();
+
+        let first = storage.store(lease("first"), String::from("lru")).unwrap();
+        let second = storage.store(lease("second"), String::from("lru")).unwrap();
+        storage.get(first).unwrap();
+        let third = storage.store(lease("third"), String::from("lru")).unwrap();
+        assert!(storage.get(first).is_ok());
+        assert!(storage.get(third).is_ok());
+        assert!(!storage.index.contains_key(&second));
+
+        let pool = String::from("expiring");
+        let kept = storage.store(lease("kept"), pool.clone()).unwrap();
+        let soon = storage
+            .store_with_ttl(lease("soon"), pool.clone(), Duration::from_secs(60))
+            .unwrap();
+        storage
+            .store_with_ttl(lease("later"), pool.clone(), Duration::from_secs(3600))
+            .unwrap();
+        assert!(storage.get(kept).is_ok());
+        assert!(!storage.index.contains_key(&soon));
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let backend = MemoryBackend::new();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+
+        let expiring = storage
+            .store_with_ttl(
+                lease("expiring"),
+                String::from("lease"),
+                Duration::from_millis(50),
+            )
+            .unwrap();
+        let kept = storage.store(lease("kept"), String::from("lease")).unwrap();
+        storage.sync().unwrap();
+        let mut disk = backend;
+        assert_eq!(disk.select_ids("lease").unwrap().len(), 2);
+
+        std::thread::sleep(Duration::from_millis(60));
+        storage.sync().unwrap();
+        assert!(storage.get(kept).is_ok());
+        assert!(storage.get_from_disk(expiring).is_err());
+        assert_eq!(disk.select_ids("lease").unwrap(), vec![kept]);
+    }
+
+    #[test]
+    fn test_persisted_expiry() {
+        let expiring_pool = || {
+            let mut pool = DataPool::new(String::from("lease"), String::new());
+            pool.set_expiration_column("expires_at");
+            pool
+        };
+        let backend = MemoryBackend::new();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        storage.add_pool(expiring_pool()).unwrap();
+        let expiring = storage
+            .store_with_ttl(
+                lease("expiring"),
+                String::from("lease"),
+                Duration::from_millis(100),
+            )
+            .unwrap();
+        let kept = storage.store(lease("kept"), String::from("lease")).unwrap();
+        storage.sync().unwrap();
+        let mut disk = backend.clone();
+        let stored = disk.select_by_id("lease", expiring).unwrap().unwrap();
+        assert!(stored.get("expires_at").unwrap().as_int().is_some());
+        let stored = disk.select_by_id("lease", kept).unwrap().unwrap();
+        assert!(stored.get("expires_at").unwrap().is_null());
+
+        //Expirations are restored by both eager and lazy loads
+        let restarted: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        restarted.add_pool(expiring_pool()).unwrap();
+        restarted.load().unwrap();
+        let mut lazy: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        lazy.add_pool(expiring_pool()).unwrap();
+        lazy.set_lazy_load(true);
+        lazy.load().unwrap();
+        lazy.warm("lease").unwrap();
+        for storage in [&restarted, &lazy] {
+            let pool = storage.get_pool("lease").unwrap();
+            assert!(pool.time_to_live(expiring).unwrap() <= Duration::from_millis(100));
+            assert_eq!(pool.time_to_live(kept), None);
+        }
+
+        std::thread::sleep(Duration::from_millis(110));
+        lazy.sync().unwrap();
+        assert!(lazy.get(expiring).is_err());
+        restarted.sync().unwrap();
+        assert!(restarted.get(expiring).is_err());
+        assert!(restarted.get(kept).is_ok());
+        assert_eq!(disk.select_ids("lease").unwrap(), vec![kept]);
+    }
+
+    #[test]
+    fn test_filters() {
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        let mut pool = DataPool::new(String::from("lease"), String::new());
+        let dropped = String::from("stale");
+        pool.add_filter(move |_, data| match data {
+            Data::Lease(lease) => lease.name == dropped,
+            Data::Null => false,
+        });
+        storage.add_pool(pool).unwrap();
+
+        let stale = storage
+            .store(lease("stale"), String::from("lease"))
+            .unwrap();
+        let fresh = storage
+            .store(lease("fresh"), String::from("lease"))
+            .unwrap();
+        storage.sync().unwrap();
+        assert!(storage.get(stale).is_err());
+        assert!(storage.get(fresh).is_ok());
+    }
+
+    #[test]
+    fn test_validators() {
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        let mut pool = DataPool::new(String::from("lease"), String::new());
+        pool.add_validator(|data| match data {
+            Data::Lease(lease) if lease.address == "0.0.0.0" => {
+                Err(ValidationError::new("unspecified address"))
+            }
+            _ => Ok(()),
+        });
+        storage.add_pool(pool).unwrap();
+
+        let unspecified = Data::Lease(Lease {
+            name: String::from("unspecified"),
+            address: String::from("0.0.0.0"),
+            uid: Uid::default(),
+        });
+        assert!(matches!(
+            storage.store(unspecified.clone(), String::from("lease")),
+            Err(StorageError::Invalid(_))
+        ));
+        let uid = storage
+            .store(lease("valid"), String::from("lease"))
+            .unwrap();
+        assert!(storage.update(uid, unspecified).is_err());
+        assert!(matches!(storage.get(uid).unwrap(), Data::Lease(lease) if lease.name == "valid"));
+        assert_eq!(storage.index.len(), 1);
+    }
+
+    #[test]
+    fn test_sync_interval() {
+        let backend = MemoryBackend::new();
+        let mut disk = backend.clone();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+        let mut audit = DataPool::new(String::from("audit"), String::new());
+        audit.set_sync_interval(Duration::from_secs(60));
+        storage.add_pool(audit).unwrap();
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+
+        let start = Instant::now();
+        storage.sync_due(start).unwrap();
+        storage
+            .store(lease("audit"), String::from("audit"))
+            .unwrap();
+        storage
+            .store(lease("lease"), String::from("lease"))
+            .unwrap();
+
+        storage.sync_due(start + Duration::from_secs(5)).unwrap();
+        assert_eq!(disk.select_ids("audit").unwrap().len(), 0);
+        assert_eq!(disk.select_ids("lease").unwrap().len(), 1);
+
+        storage.sync_due(start + Duration::from_secs(60)).unwrap();
+        assert_eq!(disk.select_ids("audit").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_failed_sync_retry() {
+        let backend = FailingBackend::default();
+        let mut disk = backend.disk.clone();
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend.clone());
+        let mut audit = DataPool::new(String::from("audit"), String::new());
+        audit.set_sync_interval(Duration::from_secs(60));
+        storage.add_pool(audit).unwrap();
+        storage
+            .store(lease("audit"), String::from("audit"))
+            .unwrap();
+
+        let start = Instant::now();
+        backend.failing.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            storage.sync_due(start),
+            Err(StorageError::Sync(_))
+        ));
+        //The failed pool is still due on the next tick
+        backend.failing.store(false, Ordering::SeqCst);
+        storage.sync_due(start + Duration::from_secs(5)).unwrap();
+        assert_eq!(disk.select_ids("audit").unwrap().len(), 1);
+
+        storage
+            .store(lease("audit"), String::from("audit"))
+            .unwrap();
+        storage.sync_due(start + Duration::from_secs(10)).unwrap();
+        assert_eq!(disk.select_ids("audit").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_secondary_index() {
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        let pool = DataPool::new(String::from("lease"), String::new());
+        pool.add_index("name", |data| match data {
+            Data::Lease(lease) => Some(lease.name.clone()),
+            Data::Null => None,
+        });
+        storage.add_pool(pool).unwrap();
+
+        let first = storage
+            .store(lease("shared"), String::from("lease"))
+            .unwrap();
+        let second = storage
+            .store(lease("shared"), String::from("lease"))
+            .unwrap();
+        storage
+            .store(lease("other"), String::from("lease"))
+            .unwrap();
+        assert_eq!(
+            storage
+                .find_by(String::from("lease"), "name", "shared")
+                .unwrap()
+                .len(),
+            2
+        );
+
+        storage.delete(first).unwrap();
+        let found = storage
+            .find_by(String::from("lease"), "name", "shared")
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id(), second);
+        assert!(storage
+            .find_by(String::from("lease"), "missing", "shared")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    #[ignore]
+    fn concurrent_get_benchmark() {
+        let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::new()))
+            .unwrap();
+        let ids: Arc<Vec<Uid>> = Arc::new(
+            (0..1000)
+                .map(|_| {
+                    storage
+                        .store(lease("bench"), String::from("lease"))
+                        .unwrap()
+                })
+                .collect(),
+        );
+        let storage = Arc::new(storage);
+
+        for threads in [1, 8] {
             let start = Instant::now();
-            println!("Retrieving {} datas from disk...", ids_disk.len());
-            let mut datas = vec![];
-            for id in ids_disk {
-                datas.push(disk_getter.lock().unwrap().get_from_disk(id).unwrap());
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    let storage = storage.clone();
+                    let ids = ids.clone();
+                    std::thread::spawn(move || {
+                        for _ in 0..100 {
+                            for id in ids.iter() {
+                                storage.get(*id).unwrap();
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
             }
-            println!("Retrieved from disk in {:.2?}", start.elapsed());
-            datas
-        })
-        .await
-        .unwrap();
+            println!(
+                "{} threads: {} gets in {:.2?}",
+                threads,
+                threads * 100 * ids.len(),
+                start.elapsed()
+            );
+        }
     }
 }
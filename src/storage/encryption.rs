@@ -0,0 +1,317 @@
+//! Encryption at rest of sensitive columns.
+//!
+//! [`EncryptedBackend`] wraps any [`StorageBackend`] and encrypts
+//! the columns marked as sensitive (client hostnames, relay agent
+//! circuit ids...) with AES-256-GCM before they reach the database,
+//! and decrypts them when records are read back. Everything above
+//! the backend, including [`RuntimeStorage`], only sees plaintext.
+//!
+//! Encrypted values are stored as text, so sensitive columns must
+//! be text columns. Each value is encrypted with a random nonce and
+//! bound to its table, column and id, so equality filters on
+//! sensitive columns never match, and encrypted values cannot be
+//! moved to another record. Values which are not encrypted yet are
+//! read as is, so existing tables can be encrypted progressively.
+//!
+//! [`RuntimeStorage`]: super::data::RuntimeStorage
+
+use std::collections::{HashMap, HashSet};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+
+use super::{
+    backend::{BackendError, StorageBackend, SyncError},
+    sql::{Record, SqlValue},
+    uid::Uid,
+};
+
+/// Prefix of encrypted values, versioning their format
+const PREFIX: &str = "enc1:";
+
+/// Length of an AES-GCM nonce, in bytes
+const NONCE_LEN: usize = 12;
+
+/// [`StorageBackend`] encrypting sensitive columns of the records
+/// written to `B`, and decrypting them when reading
+pub struct EncryptedBackend<B> {
+    backend: B,
+    cipher: Aes256Gcm,
+    sensitive: HashMap<String, HashSet<String>>,
+}
+
+impl<B: StorageBackend> EncryptedBackend<B> {
+    /// Wraps `backend`, encrypting with the given 256-bit key
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut backend = EncryptedBackend::new(db, &config.storage_key);
+    /// backend.add_sensitive_columns("lease", &["client_hostname", "circuit_id"]);
+    /// let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+    /// ```
+    pub fn new(backend: B, key: &[u8; 32]) -> Self {
+        Self {
+            backend,
+            cipher: Aes256Gcm::new(key.into()),
+            sensitive: HashMap::new(),
+        }
+    }
+
+    /// Marks `columns` of `table` as sensitive
+    pub fn add_sensitive_columns(&mut self, table: &str, columns: &[&str]) {
+        self.sensitive
+            .entry(table.to_string())
+            .or_default()
+            .extend(columns.iter().map(|column| column.to_string()));
+    }
+
+    /// Returns whether `column` of `table` is sensitive
+    fn is_sensitive(&self, table: &str, column: &str) -> bool {
+        self.sensitive
+            .get(table)
+            .is_some_and(|columns| columns.contains(column))
+    }
+
+    /// Associated data binding an encrypted value to its location
+    ///
+    /// The id is written in decimal, and left empty for records
+    /// without one, so that the encoding never changes.
+    fn location(table: &str, column: &str, record: &Record) -> String {
+        let id = record.id().map(|id| id.get().to_string());
+        format!("{}.{}.{}", table, column, id.unwrap_or_default())
+    }
+
+    /// Returns `record` with its sensitive columns encrypted
+    fn encrypt(&self, table: &str, record: &Record) -> Result<Record, BackendError> {
+        let mut encrypted = Record::new();
+        for (column, value) in record.columns().into_iter().zip(record.values()) {
+            if !self.is_sensitive(table, column) || value.is_null() {
+                encrypted.push(column, value.clone());
+                continue;
+            }
+            let plaintext =
+                serde_json::to_vec(value).map_err(|e| BackendError::new(e.to_string()))?;
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let aad = Self::location(table, column, record);
+            let ciphertext = self
+                .cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: &plaintext,
+                        aad: aad.as_bytes(),
+                    },
+                )
+                .map_err(|_| BackendError::new(format!("Could not encrypt {}", aad)))?;
+            let mut sealed = nonce.to_vec();
+            sealed.extend(ciphertext);
+            encrypted.push(column, format!("{}{}", PREFIX, to_hex(&sealed)));
+        }
+        Ok(encrypted)
+    }
+
+    /// Returns `record` with its sensitive columns decrypted
+    fn decrypt(&self, table: &str, record: Record) -> Result<Record, BackendError> {
+        let mut decrypted = Record::new();
+        for (column, value) in record.columns().into_iter().zip(record.values()) {
+            let sealed = match value.as_str() {
+                Some(sealed) if self.is_sensitive(table, column) => sealed.strip_prefix(PREFIX),
+                _ => None,
+            };
+            let Some(sealed) = sealed else {
+                decrypted.push(column, value.clone());
+                continue;
+            };
+            let aad = Self::location(table, column, &record);
+            let invalid = || BackendError::new(format!("Could not decrypt {}", aad));
+            let sealed = from_hex(sealed).ok_or_else(invalid)?;
+            if sealed.len() < NONCE_LEN {
+                return Err(invalid());
+            }
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+            let plaintext = self
+                .cipher
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad: aad.as_bytes(),
+                    },
+                )
+                .map_err(|_| invalid())?;
+            let value: SqlValue = serde_json::from_slice(&plaintext).map_err(|_| invalid())?;
+            decrypted.push(column, value);
+        }
+        Ok(decrypted)
+    }
+
+    fn encrypt_all(&self, table: &str, records: &[Record]) -> Result<Vec<Record>, BackendError> {
+        records
+            .iter()
+            .map(|record| self.encrypt(table, record))
+            .collect()
+    }
+
+    fn decrypt_all(&self, table: &str, records: Vec<Record>) -> Result<Vec<Record>, BackendError> {
+        records
+            .into_iter()
+            .map(|record| self.decrypt(table, record))
+            .collect()
+    }
+}
+
+impl<B: StorageBackend> StorageBackend for EncryptedBackend<B> {
+    fn tables(&mut self) -> Result<Vec<String>, BackendError> {
+        self.backend.tables()
+    }
+
+    fn create_table(&mut self, table: &str, schema: &str) -> Result<(), BackendError> {
+        self.backend.create_table(table, schema)
+    }
+
+    fn select_all(&mut self, table: &str) -> Result<Vec<Record>, BackendError> {
+        let records = self.backend.select_all(table)?;
+        self.decrypt_all(table, records)
+    }
+
+    fn select_page(
+        &mut self,
+        table: &str,
+        after: Option<Uid>,
+        limit: usize,
+    ) -> Result<Vec<Record>, BackendError> {
+        let records = self.backend.select_page(table, after, limit)?;
+        self.decrypt_all(table, records)
+    }
+
+    fn select_ids(&mut self, table: &str) -> Result<Vec<Uid>, BackendError> {
+        self.backend.select_ids(table)
+    }
+
+    fn select_by_id(&mut self, table: &str, id: Uid) -> Result<Option<Record>, BackendError> {
+        self.backend
+            .select_by_id(table, id)?
+            .map(|record| self.decrypt(table, record))
+            .transpose()
+    }
+
+    fn select_by_ids(&mut self, table: &str, ids: &[Uid]) -> Result<Vec<Record>, BackendError> {
+        let records = self.backend.select_by_ids(table, ids)?;
+        self.decrypt_all(table, records)
+    }
+
+    fn select_where(
+        &mut self,
+        table: &str,
+        filters: &Record,
+        limit: Option<usize>,
+    ) -> Result<Vec<Record>, BackendError> {
+        let records = self.backend.select_where(table, filters, limit)?;
+        self.decrypt_all(table, records)
+    }
+
+    fn insert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        let record = self.encrypt(table, record)?;
+        self.backend.insert(table, &record)
+    }
+
+    fn upsert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        let record = self.encrypt(table, record)?;
+        self.backend.upsert(table, &record)
+    }
+
+    fn update(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        let record = self.encrypt(table, record)?;
+        self.backend.update(table, &record)
+    }
+
+    fn delete(&mut self, table: &str, ids: &[Uid]) -> Result<(), BackendError> {
+        self.backend.delete(table, ids)
+    }
+
+    fn sync_table(
+        &mut self,
+        table: &str,
+        inserts: &[Record],
+        updates: &[Record],
+        deletes: &[Uid],
+    ) -> Result<(), SyncError> {
+        let inserts = self.encrypt_all(table, inserts).map_err(SyncError::Begin)?;
+        let updates = self.encrypt_all(table, updates).map_err(SyncError::Begin)?;
+        self.backend.sync_table(table, &inserts, &updates, deletes)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::memory_backend::MemoryBackend;
+
+    #[test]
+    fn test_encrypted_columns() {
+        let mut disk = MemoryBackend::new();
+        let mut backend = EncryptedBackend::new(disk.clone(), &[7; 32]);
+        backend.add_sensitive_columns("lease", &["hostname"]);
+        backend.create_table("lease", "").unwrap();
+
+        let record = Record::new()
+            .with("id", 1u16)
+            .with("hostname", "printer")
+            .with("address", "10.0.0.1");
+        backend.insert("lease", &record).unwrap();
+        assert_eq!(
+            EncryptedBackend::<MemoryBackend>::location("lease", "hostname", &record),
+            "lease.hostname.1"
+        );
+        disk.insert(
+            "lease",
+            &Record::new().with("id", 2u16).with("hostname", "legacy"),
+        )
+        .unwrap();
+
+        let stored = disk.select_by_id("lease", Uid::new(1)).unwrap().unwrap();
+        let sealed = stored.get("hostname").and_then(SqlValue::as_str).unwrap();
+        assert!(sealed.starts_with(PREFIX) && !sealed.contains("printer"));
+        assert_eq!(stored.get("address"), record.get("address"));
+
+        assert_eq!(
+            backend.select_by_id("lease", Uid::new(1)).unwrap(),
+            Some(record)
+        );
+        assert_eq!(
+            backend
+                .select_by_id("lease", Uid::new(2))
+                .unwrap()
+                .unwrap()
+                .get("hostname"),
+            Some(&SqlValue::from("legacy"))
+        );
+
+        //Encrypted values are bound to their record
+        let moved = Record::new().with("id", 3u16).with("hostname", sealed);
+        disk.insert("lease", &moved).unwrap();
+        assert!(backend.select_by_id("lease", Uid::new(3)).is_err());
+
+        let mut other_key = EncryptedBackend::new(disk, &[8; 32]);
+        other_key.add_sensitive_columns("lease", &["hostname"]);
+        assert!(other_key.select_by_id("lease", Uid::new(1)).is_err());
+    }
+}
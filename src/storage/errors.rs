@@ -0,0 +1,100 @@
+use std::fmt::Display;
+
+use super::{
+    backend::{BackendError, SyncError},
+    data::LoadConflict,
+    uid::Uid,
+};
+
+/// Error returned by a validator when data is rejected
+///
+/// See [`DataPool::add_validator`].
+///
+/// [`DataPool::add_validator`]: super::data::DataPool::add_validator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError(pub String);
+
+impl ValidationError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self(message.into())
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid data: {}", self.0)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Error returned by [`RuntimeStorage`]
+///
+/// [`RuntimeStorage`]: super::data::RuntimeStorage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    /// No data with the given uid
+    NotFound(Uid),
+    /// No pool with the given name
+    PoolMissing(String),
+    /// The uid is already used by other data
+    IdCollision(Uid),
+    /// The key of the pool is already used by the data with the given uid
+    KeyCollision(Uid),
+    /// The backend failed
+    Backend(BackendError),
+    /// A pool could not be synchronized with the backend
+    Sync(SyncError),
+    /// A loaded record has the uid of existing data
+    LoadConflict(LoadConflict),
+    /// Data was rejected by a validator of its pool
+    Invalid(ValidationError),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(uid) => write!(f, "No data with uid {}", uid),
+            Self::PoolMissing(name) => write!(f, "Pool {} doesn't exist", name),
+            Self::IdCollision(uid) => write!(f, "Uid {} is already in use", uid),
+            Self::KeyCollision(uid) => write!(f, "Key is already used by data {}", uid),
+            Self::Backend(e) => write!(f, "{}", e),
+            Self::Sync(e) => write!(f, "{}", e),
+            Self::LoadConflict(conflict) => write!(
+                f,
+                "Uid {} loaded from {} is already used in pool {}",
+                conflict.uid, conflict.table, conflict.pool
+            ),
+            Self::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Backend(e) => Some(e),
+            Self::Sync(e) => Some(e),
+            Self::Invalid(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BackendError> for StorageError {
+    fn from(value: BackendError) -> Self {
+        Self::Backend(value)
+    }
+}
+
+impl From<SyncError> for StorageError {
+    fn from(value: SyncError) -> Self {
+        Self::Sync(value)
+    }
+}
+
+impl From<ValidationError> for StorageError {
+    fn from(value: ValidationError) -> Self {
+        Self::Invalid(value)
+    }
+}
@@ -0,0 +1,135 @@
+//! Export of stored data to JSON or CSV.
+//!
+//! Exports are meant for operators, e.g. to audit leases or
+//! migrate them to another server, so values are written as
+//! plain JSON values or CSV fields rather than in the
+//! format of a [`Snapshot`].
+//!
+//! [`Snapshot`]: super::snapshot::Snapshot
+
+use std::io::{self, Write};
+
+use serde_json::{Map, Value};
+
+use super::sql::{Record, SqlValue};
+
+/// Format of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// An array of objects, one per record
+    Json,
+    /// A header line followed by one line per record
+    Csv,
+}
+
+/// Writes `records` to `writer` in the given format
+///
+/// Columns are written in the order of the first record
+/// holding them. In CSV, `NULL` is written as an empty field.
+///
+/// # Errors
+///
+/// Returns an error if `writer` fails
+pub fn export_records(
+    records: &[Record],
+    format: ExportFormat,
+    writer: impl Write,
+) -> io::Result<()> {
+    match format {
+        ExportFormat::Json => {
+            let objects: Vec<Map<String, Value>> = records.iter().map(to_object).collect();
+            Ok(serde_json::to_writer_pretty(writer, &objects)?)
+        }
+        ExportFormat::Csv => {
+            let columns = columns(records);
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record(&columns)?;
+            for record in records {
+                writer.write_record(
+                    columns
+                        .iter()
+                        .map(|column| record.get(column).map(to_field).unwrap_or_default()),
+                )?;
+            }
+            writer.flush()
+        }
+    }
+}
+
+fn columns(records: &[Record]) -> Vec<&str> {
+    let mut columns: Vec<&str> = vec![];
+    for column in records.iter().flat_map(Record::columns) {
+        if !columns.contains(&column) {
+            columns.push(column);
+        }
+    }
+    columns
+}
+
+fn to_object(record: &Record) -> Map<String, Value> {
+    record
+        .columns()
+        .into_iter()
+        .zip(record.values())
+        .map(|(column, value)| (column.to_string(), to_json(value)))
+        .collect()
+}
+
+fn to_json(value: &SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Int(value) => Value::from(*value),
+        SqlValue::Float(value) => Value::from(*value),
+        SqlValue::Text(_) | SqlValue::Bytes(_) => Value::from(to_field(value)),
+    }
+}
+
+/// Text of a value, raw bytes which are not
+/// valid UTF-8 being written in hexadecimal
+fn to_field(value: &SqlValue) -> String {
+    match value {
+        SqlValue::Null => String::new(),
+        SqlValue::Int(value) => value.to_string(),
+        SqlValue::Float(value) => value.to_string(),
+        SqlValue::Text(value) => value.clone(),
+        SqlValue::Bytes(bytes) => match value.as_str() {
+            Some(value) => value.to_string(),
+            None => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let records = [
+            Record::new().with("id", 1u16).with("name", "a, \"b\""),
+            Record::new()
+                .with("id", 2u16)
+                .with("name", None::<String>)
+                .with("mac", vec![0xaa, 0xff]),
+        ];
+
+        let mut csv = vec![];
+        export_records(&records, ExportFormat::Csv, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,name,mac\n1,\"a, \"\"b\"\"\",\n2,,aaff\n"
+        );
+
+        let mut json = vec![];
+        export_records(&records, ExportFormat::Json, &mut json).unwrap();
+        let json: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                { "id": 1, "name": "a, \"b\"" },
+                { "id": 2, "name": null, "mac": "aaff" },
+            ])
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Secondary indexes over the data of a [`DataPool`].
+//!
+//! A [`SecondaryIndex`] maps a key derived from each value
+//! (e.g. its hardware address) to the uid of every value
+//! sharing that key, allowing O(1) lookups by key instead
+//! of scanning the whole pool.
+//!
+//! A [`UniqueIndex`] maps a key of any hashable type, such
+//! as a compound `(subnet, hardware address)` key, to the
+//! single value holding it.
+//!
+//! [`DataPool`]: super::data::DataPool
+
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use super::uid::Uid;
+
+pub type IndexKey<V> = dyn Fn(&V) -> Option<String> + Send + Sync;
+pub type UniqueKey<V, K> = dyn Fn(&V) -> Option<K> + Send + Sync;
+
+/// Index of the values of a pool by a derived key
+///
+/// Values for which the key function returns `None`
+/// are not indexed.
+pub struct SecondaryIndex<V> {
+    key: Box<IndexKey<V>>,
+    entries: HashMap<String, HashSet<Uid>>,
+}
+
+impl<V> SecondaryIndex<V> {
+    /// Creates an empty `SecondaryIndex` deriving keys through `key`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let index = SecondaryIndex::new(|data: &Data| match data {
+    ///     Data::Lease(lease) => Some(lease.hardware_address.to_string()),
+    ///     _ => None,
+    /// });
+    /// ```
+    pub fn new(key: impl Fn(&V) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self {
+            key: Box::new(key),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Indexes `value`, stored under `uid`
+    pub fn insert(&mut self, uid: Uid, value: &V) {
+        if let Some(key) = (self.key)(value) {
+            self.entries.entry(key).or_default().insert(uid);
+        }
+    }
+
+    /// Removes `value`, stored under `uid`, from the index
+    pub fn remove(&mut self, uid: Uid, value: &V) {
+        let Some(key) = (self.key)(value) else {
+            return;
+        };
+        if let Some(uids) = self.entries.get_mut(&key) {
+            uids.remove(&uid);
+            if uids.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    /// Returns the uid of every value indexed under `key`
+    pub fn get(&self, key: &str) -> Vec<Uid> {
+        self.entries
+            .get(key)
+            .map(|uids| uids.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Index mapping a unique key to the uid of a value, whatever the type of the key
+pub trait KeyIndex<V>: Send {
+    /// Indexes `value`, stored under `uid`, or returns the
+    /// uid of the value already holding the same key
+    fn insert(&mut self, uid: Uid, value: &V) -> Result<(), Uid>;
+
+    /// Removes `value`, stored under `uid`, from the index
+    fn remove(&mut self, uid: Uid, value: &V);
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Index of the values of a pool by a unique key of type `K`
+///
+/// Values for which the key function returns `None`
+/// are not indexed.
+pub struct UniqueIndex<V, K> {
+    key: Box<UniqueKey<V, K>>,
+    uids: HashMap<K, Uid>,
+}
+
+impl<V, K: Hash + Eq> UniqueIndex<V, K> {
+    /// Creates an empty `UniqueIndex` deriving keys through `key`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let index = UniqueIndex::new(|data: &Data| match data {
+    ///     Data::Lease(lease) => Some((lease.subnet, lease.hardware_address)),
+    ///     _ => None,
+    /// });
+    /// ```
+    pub fn new(key: impl Fn(&V) -> Option<K> + Send + Sync + 'static) -> Self {
+        Self {
+            key: Box::new(key),
+            uids: HashMap::new(),
+        }
+    }
+
+    /// Returns the uid of the value indexed under `key`, if any
+    pub fn get(&self, key: &K) -> Option<Uid> {
+        self.uids.get(key).cloned()
+    }
+}
+
+impl<V: 'static, K: Hash + Eq + Send + 'static> KeyIndex<V> for UniqueIndex<V, K> {
+    fn insert(&mut self, uid: Uid, value: &V) -> Result<(), Uid> {
+        let Some(key) = (self.key)(value) else {
+            return Ok(());
+        };
+        match self.uids.get(&key) {
+            Some(&other) if other != uid => Err(other),
+            _ => {
+                self.uids.insert(key, uid);
+                Ok(())
+            }
+        }
+    }
+
+    fn remove(&mut self, uid: Uid, value: &V) {
+        let Some(key) = (self.key)(value) else {
+            return;
+        };
+        if self.uids.get(&key) == Some(&uid) {
+            self.uids.remove(&key);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_secondary_index() {
+        let mut index = SecondaryIndex::new(|value: &(u8, &str)| match value.0 {
+            0 => None,
+            _ => Some(value.1.to_string()),
+        });
+
+        index.insert(Uid::new(1), &(1, "aa:bb"));
+        index.insert(Uid::new(2), &(1, "aa:bb"));
+        index.insert(Uid::new(3), &(0, "aa:bb"));
+        let mut uids = index.get("aa:bb");
+        uids.sort();
+        assert_eq!(uids, vec![Uid::new(1), Uid::new(2)]);
+
+        index.remove(Uid::new(1), &(1, "aa:bb"));
+        index.remove(Uid::new(2), &(1, "aa:bb"));
+        assert!(index.get("aa:bb").is_empty());
+        assert!(index.entries.is_empty());
+    }
+
+    #[test]
+    fn test_unique_index() {
+        let mut index = UniqueIndex::new(|value: &(u8, &'static str)| match value.0 {
+            0 => None,
+            _ => Some(*value),
+        });
+
+        index.insert(Uid::new(1), &(1, "aa:bb")).unwrap();
+        index.insert(Uid::new(2), &(2, "aa:bb")).unwrap();
+        assert_eq!(index.insert(Uid::new(3), &(1, "aa:bb")), Err(Uid::new(1)));
+        assert_eq!(index.get(&(1, "aa:bb")), Some(Uid::new(1)));
+        index.insert(Uid::new(4), &(0, "aa:bb")).unwrap();
+        index.insert(Uid::new(5), &(0, "aa:bb")).unwrap();
+
+        index.remove(Uid::new(3), &(1, "aa:bb"));
+        assert_eq!(index.get(&(1, "aa:bb")), Some(Uid::new(1)));
+        index.remove(Uid::new(1), &(1, "aa:bb"));
+        assert_eq!(index.get(&(1, "aa:bb")), None);
+    }
+}
@@ -0,0 +1,537 @@
+//! Parser of ISC dhcpd lease files (`dhcpd.leases`).
+//!
+//! It eases the migration of an existing ISC dhcpd deployment:
+//! leases are parsed into [`IscLease`], which callers convert
+//! into their own data before storing them, see
+//! [`RuntimeStorage::import_isc_leases`].
+//!
+//! dhcpd appends a new `lease` block every time a lease changes,
+//! so only the last block of each address is kept.
+//!
+//! Leases can also be written back in the same format by an
+//! [`IscLeaseWriter`], so that tools parsing `dhcpd.leases`
+//! keep working after the migration.
+//!
+//! [`RuntimeStorage::import_isc_leases`]: super::data::RuntimeStorage::import_isc_leases
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use mac_address::MacAddress;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+/// Error returned when a lease file cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+    line: usize,
+    message: String,
+}
+
+impl ImportError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+
+    /// Line of the lease file at which parsing failed
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid lease file at line {}: {}",
+            self.line, self.message
+        )
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// A lease, as declared in a lease file
+///
+/// Times are in UTC, `None` meaning the
+/// time is missing or set to `never`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IscLease {
+    pub address: Ipv4Addr,
+    pub starts: Option<PrimitiveDateTime>,
+    pub ends: Option<PrimitiveDateTime>,
+    pub binding_state: Option<String>,
+    pub hardware_address: Option<MacAddress>,
+    pub client_hostname: Option<String>,
+    pub uid: Option<String>,
+}
+
+impl IscLease {
+    fn new(address: Ipv4Addr) -> Self {
+        Self {
+            address,
+            starts: None,
+            ends: None,
+            binding_state: None,
+            hardware_address: None,
+            client_hostname: None,
+            uid: None,
+        }
+    }
+
+    /// Returns whether the lease is bound to a client
+    pub fn is_active(&self) -> bool {
+        self.binding_state
+            .as_deref()
+            .is_none_or(|state| state == "active")
+    }
+}
+
+impl IscLease {
+    /// Creates an active lease of `address` by `hardware_address`
+    pub fn active(
+        address: Ipv4Addr,
+        hardware_address: MacAddress,
+        starts: PrimitiveDateTime,
+        ends: Option<PrimitiveDateTime>,
+    ) -> Self {
+        Self {
+            starts: Some(starts),
+            ends,
+            binding_state: Some(String::from("active")),
+            hardware_address: Some(hardware_address),
+            ..Self::new(address)
+        }
+    }
+
+    /// Creates a lease of `address` returned to the free list at `starts`
+    pub fn free(address: Ipv4Addr, starts: PrimitiveDateTime) -> Self {
+        Self {
+            starts: Some(starts),
+            binding_state: Some(String::from("free")),
+            ..Self::new(address)
+        }
+    }
+}
+
+/// Formats the lease as a `lease` block, as written by dhcpd
+impl Display for IscLease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "lease {} {{", self.address)?;
+        writeln!(f, "  starts {};", format_time(self.starts))?;
+        writeln!(f, "  ends {};", format_time(self.ends))?;
+        if let Some(state) = &self.binding_state {
+            writeln!(f, "  binding state {};", state)?;
+        }
+        if let Some(address) = self.hardware_address {
+            writeln!(
+                f,
+                "  hardware ethernet {};",
+                address.to_string().to_lowercase()
+            )?;
+        }
+        if let Some(uid) = &self.uid {
+            writeln!(f, "  uid \"{}\";", escape(uid))?;
+        }
+        if let Some(hostname) = &self.client_hostname {
+            writeln!(f, "  client-hostname \"{}\";", escape(hostname))?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Formats a time as `<weekday> <yyyy/mm/dd> <hh:mm:ss>`, or `never`
+fn format_time(time: Option<PrimitiveDateTime>) -> String {
+    let Some(time) = time else {
+        return String::from("never");
+    };
+    format!(
+        "{} {:04}/{:02}/{:02} {:02}:{:02}:{:02}",
+        time.weekday().number_days_from_sunday(),
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Header written at the top of lease files
+const HEADER: &str =
+    "# The format of this file is documented in the dhcpd.leases(5) manual page.\n";
+
+/// Writes `leases` in the format of a lease file
+pub fn write_leases(leases: &[IscLease], mut writer: impl Write) -> io::Result<()> {
+    writer.write_all(HEADER.as_bytes())?;
+    for lease in leases {
+        write!(writer, "{}", lease)?;
+    }
+    writer.flush()
+}
+
+/// Maintains an ISC dhcpd lease file
+///
+/// Like dhcpd, changes are appended to the file as new `lease`
+/// blocks, and the file is periodically [rewritten](IscLeaseWriter::rewrite)
+/// with the current leases only, so it doesn't grow forever.
+pub struct IscLeaseWriter {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl IscLeaseWriter {
+    /// Opens the lease file at `path`, creating it if needed
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let writer = IscLeaseWriter::open("/var/lib/dhcp/dhcpd.leases")?;
+    /// writer.append(&IscLease::free(address, now))?;
+    /// ```
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(HEADER.as_bytes())?;
+        }
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends `lease` to the file, superseding the
+    /// previous blocks of its address
+    pub fn append(&self, lease: &IscLease) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.write_all(lease.to_string().as_bytes())?;
+        file.sync_data()
+    }
+
+    /// Replaces the content of the file with `leases`
+    ///
+    /// The leases are first written to a temporary file
+    /// next to the lease file, then renamed over it, so readers
+    /// always see either the previous or the new content.
+    pub fn rewrite(&self, leases: &[IscLease]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        write_leases(leases, &mut writer)?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        *file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+    End,
+}
+
+/// Parses the content of a lease file
+///
+/// Declarations other than `lease` blocks, as well as
+/// unknown statements inside them, are ignored.
+///
+/// # Errors
+///
+/// Returns an [`ImportError`] if the file is malformed
+pub fn parse_leases(input: &str) -> Result<Vec<IscLease>, ImportError> {
+    let tokens = tokenize(input)?;
+    let mut tokens = tokens.iter().peekable();
+    //Leases replaced by a later block of their address are left as None
+    let mut leases: Vec<Option<IscLease>> = vec![];
+    let mut positions: HashMap<Ipv4Addr, usize> = HashMap::new();
+
+    while let Some((line, token)) = tokens.next() {
+        match token {
+            Token::Word(word) if word == "lease" => {
+                let address = match tokens.next() {
+                    Some((_, Token::Word(address))) => Ipv4Addr::from_str(address)
+                        .map_err(|_| ImportError::new(*line, "invalid lease address"))?,
+                    _ => return Err(ImportError::new(*line, "missing lease address")),
+                };
+                if !matches!(tokens.next(), Some((_, Token::Open))) {
+                    return Err(ImportError::new(*line, "expected '{'"));
+                }
+                let mut lease = IscLease::new(address);
+                loop {
+                    let statement = statement(&mut tokens, *line)?;
+                    match statement {
+                        None => break,
+                        Some((line, words)) => apply(&mut lease, line, &words)?,
+                    }
+                }
+                if let Some(position) = positions.insert(lease.address, leases.len()) {
+                    leases[position] = None;
+                }
+                leases.push(Some(lease));
+            }
+            Token::Open => skip_block(&mut tokens, *line)?,
+            _ => {}
+        }
+    }
+    Ok(leases.into_iter().flatten().collect())
+}
+
+type Tokens<'a> = std::iter::Peekable<std::slice::Iter<'a, (usize, Token)>>;
+
+/// Reads the next statement of a block, or returns
+/// `None` once the block is closed
+fn statement(
+    tokens: &mut Tokens,
+    start: usize,
+) -> Result<Option<(usize, Vec<String>)>, ImportError> {
+    let mut words = vec![];
+    let mut first_line = None;
+    loop {
+        match tokens.next() {
+            None => return Err(ImportError::new(start, "unterminated lease block")),
+            Some((_, Token::Close)) if words.is_empty() => return Ok(None),
+            Some((line, Token::Close)) => return Err(ImportError::new(*line, "expected ';'")),
+            Some((line, Token::Open)) => {
+                skip_block(tokens, *line)?;
+                words.clear();
+            }
+            Some((line, Token::End)) => return Ok(Some((first_line.unwrap_or(*line), words))),
+            Some((line, Token::Word(word) | Token::Quoted(word))) => {
+                first_line.get_or_insert(*line);
+                words.push(word.clone());
+            }
+        }
+    }
+}
+
+fn skip_block(tokens: &mut Tokens, start: usize) -> Result<(), ImportError> {
+    let mut depth = 1;
+    while depth > 0 {
+        match tokens.next() {
+            None => return Err(ImportError::new(start, "unterminated block")),
+            Some((_, Token::Open)) => depth += 1,
+            Some((_, Token::Close)) => depth -= 1,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn apply(lease: &mut IscLease, line: usize, words: &[String]) -> Result<(), ImportError> {
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    match words.as_slice() {
+        ["starts", time @ ..] => lease.starts = parse_time(line, time)?,
+        ["ends", time @ ..] => lease.ends = parse_time(line, time)?,
+        ["binding", "state", state] => lease.binding_state = Some(state.to_string()),
+        ["hardware", _, address] => {
+            lease.hardware_address = Some(
+                MacAddress::from_str(address)
+                    .map_err(|_| ImportError::new(line, "invalid hardware address"))?,
+            )
+        }
+        ["client-hostname", hostname] => lease.client_hostname = Some(hostname.to_string()),
+        ["uid", uid] => lease.uid = Some(uid.to_string()),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Parses `<weekday> <yyyy/mm/dd> <hh:mm:ss>`,
+/// `epoch <seconds>` or `never`
+fn parse_time(line: usize, words: &[&str]) -> Result<Option<PrimitiveDateTime>, ImportError> {
+    let invalid = || ImportError::new(line, "invalid time");
+    match words {
+        ["never"] => Ok(None),
+        ["epoch", seconds] => {
+            let seconds = seconds.parse().map_err(|_| invalid())?;
+            let time = OffsetDateTime::from_unix_timestamp(seconds).map_err(|_| invalid())?;
+            Ok(Some(PrimitiveDateTime::new(time.date(), time.time())))
+        }
+        [_, date, time] => {
+            let date: Vec<u32> = parse_fields(date, '/').ok_or_else(invalid)?;
+            let time: Vec<u32> = parse_fields(time, ':').ok_or_else(invalid)?;
+            let (&[year, month, day], &[hour, minute, second]) = (date.as_slice(), time.as_slice())
+            else {
+                return Err(invalid());
+            };
+            let month = Month::try_from(month as u8).map_err(|_| invalid())?;
+            let date =
+                Date::from_calendar_date(year as i32, month, day as u8).map_err(|_| invalid())?;
+            let time =
+                Time::from_hms(hour as u8, minute as u8, second as u8).map_err(|_| invalid())?;
+            Ok(Some(PrimitiveDateTime::new(date, time)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_fields(value: &str, separator: char) -> Option<Vec<u32>> {
+    value
+        .split(separator)
+        .map(|field| field.parse().ok())
+        .collect()
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ImportError> {
+    let mut tokens = vec![];
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '#' => break,
+                '{' => tokens.push((line_number, Token::Open)),
+                '}' => tokens.push((line_number, Token::Close)),
+                ';' => tokens.push((line_number, Token::End)),
+                '"' => {
+                    let mut value = String::new();
+                    loop {
+                        match chars.next() {
+                            None => {
+                                return Err(ImportError::new(line_number, "unterminated string"))
+                            }
+                            Some('"') => break,
+                            Some('\\') => value.extend(chars.next()),
+                            Some(c) => value.push(c),
+                        }
+                    }
+                    tokens.push((line_number, Token::Quoted(value)));
+                }
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut word = String::from(c);
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || matches!(c, '{' | '}' | ';' | '"' | '#') {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    tokens.push((line_number, Token::Word(word)));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const LEASES: &str = r#"
+# The format of this file is documented in the dhcpd.leases(5) manual page.
+authoring-byte-order little-endian;
+server-duid "\000\001\000\001";
+
+lease 192.168.0.10 {
+  starts 4 2023/05/11 10:00:00;
+  ends 4 2023/05/11 22:00:00;
+  binding state active;
+  hardware ethernet aa:bb:cc:dd:ee:ff;
+  uid "\001\252\273\314\335\356\377";
+  client-hostname "laptop";
+  on expiry { set ddns-fwd-name = "laptop"; }
+}
+lease 192.168.0.11 {
+  starts epoch 1683800000;
+  ends never;
+  binding state free;
+}
+lease 192.168.0.10 {
+  starts 4 2023/05/11 12:00:00;
+  ends 5 2023/05/12 00:00:00;
+  binding state active;
+  hardware ethernet aa:bb:cc:dd:ee:ff;
+}
+"#;
+
+    #[test]
+    fn test_parse_leases() {
+        let leases = parse_leases(LEASES).unwrap();
+        assert_eq!(leases.len(), 2);
+
+        let free = &leases[0];
+        assert_eq!(free.address, Ipv4Addr::new(192, 168, 0, 11));
+        assert_eq!(free.ends, None);
+        assert_eq!(free.starts.unwrap().year(), 2023);
+        assert!(!free.is_active());
+
+        let renewed = &leases[1];
+        assert_eq!(renewed.address, Ipv4Addr::new(192, 168, 0, 10));
+        assert_eq!(renewed.starts.unwrap().hour(), 12);
+        assert_eq!(renewed.client_hostname, None);
+        assert_eq!(
+            renewed.hardware_address,
+            Some(MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]))
+        );
+        assert!(renewed.is_active());
+
+        let error =
+            parse_leases("lease 192.168.0.10 {\n  starts 4 2023/13/11 10:00:00;\n}").unwrap_err();
+        assert_eq!(error.line(), 2);
+        assert!(parse_leases("lease 192.168.0.10 {").is_err());
+    }
+
+    #[test]
+    fn test_write_leases() {
+        let dir = std::env::temp_dir().join(format!("fp_core_isc_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dhcpd.leases");
+
+        let starts = PrimitiveDateTime::new(
+            Date::from_calendar_date(2023, Month::May, 11).unwrap(),
+            Time::from_hms(10, 0, 0).unwrap(),
+        );
+        let address = Ipv4Addr::new(192, 168, 0, 10);
+        let mut lease = IscLease::active(
+            address,
+            MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]),
+            starts,
+            Some(starts + time::Duration::hours(12)),
+        );
+        lease.client_hostname = Some(String::from("the \"laptop\""));
+        assert!(lease.to_string().contains("starts 4 2023/05/11 10:00:00;"));
+
+        let writer = IscLeaseWriter::open(&path).unwrap();
+        writer.append(&lease).unwrap();
+        let other = IscLease::free(Ipv4Addr::new(192, 168, 0, 11), starts);
+        writer.append(&other).unwrap();
+        writer.append(&IscLease::free(address, starts)).unwrap();
+        let written = parse_leases(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            vec![other.clone(), IscLease::free(address, starts)]
+        );
+
+        writer.rewrite(std::slice::from_ref(&lease)).unwrap();
+        writer.append(&other).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(HEADER));
+        assert_eq!(parse_leases(&content).unwrap(), vec![lease, other]);
+        assert!(!dir.join("dhcpd.leases.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
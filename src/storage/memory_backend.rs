@@ -0,0 +1,246 @@
+//! In-memory [`StorageBackend`] implementation.
+//!
+//! It lets a [`RuntimeStorage`] run without any database,
+//! either in tests or for ephemeral deployments where
+//! data does not need to outlive the process.
+//!
+//! [`RuntimeStorage`]: super::data::RuntimeStorage
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    backend::{BackendError, StorageBackend, SyncError},
+    sql::Record,
+    uid::Uid,
+};
+
+type Tables = HashMap<String, BTreeMap<Uid, Record>>;
+
+/// `MemoryBackend` keeps every table in memory
+///
+/// Clones share the same tables, so a clone can be kept
+/// to inspect what a [`RuntimeStorage`] synchronized.
+///
+/// [`RuntimeStorage`]: super::data::RuntimeStorage
+#[derive(Debug, Default, Clone)]
+pub struct MemoryBackend {
+    tables: Arc<Mutex<Tables>>,
+}
+
+impl MemoryBackend {
+    /// Creates a new `MemoryBackend` without any table
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let storage: RuntimeStorage<Data> = RuntimeStorage::new(MemoryBackend::new());
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_table<R>(
+        &self,
+        table: &str,
+        f: impl FnOnce(&mut BTreeMap<Uid, Record>) -> R,
+    ) -> Result<R, BackendError> {
+        let mut tables = self.tables.lock().unwrap();
+        let table = tables
+            .get_mut(table)
+            .ok_or_else(|| BackendError::new(format!("Table {} doesn't exist", table)))?;
+        Ok(f(table))
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn tables(&mut self) -> Result<Vec<String>, BackendError> {
+        Ok(self.tables.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn create_table(&mut self, table: &str, _schema: &str) -> Result<(), BackendError> {
+        self.tables
+            .lock()
+            .unwrap()
+            .entry(table.to_string())
+            .or_default();
+        Ok(())
+    }
+
+    fn select_all(&mut self, table: &str) -> Result<Vec<Record>, BackendError> {
+        self.with_table(table, |table| table.values().cloned().collect())
+    }
+
+    fn select_page(
+        &mut self,
+        table: &str,
+        after: Option<Uid>,
+        limit: usize,
+    ) -> Result<Vec<Record>, BackendError> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        self.with_table(table, |table| {
+            table
+                .range((start, Bound::Unbounded))
+                .take(limit)
+                .map(|(_, record)| record.clone())
+                .collect()
+        })
+    }
+
+    fn select_ids(&mut self, table: &str) -> Result<Vec<Uid>, BackendError> {
+        self.with_table(table, |table| table.keys().cloned().collect())
+    }
+
+    fn select_by_id(&mut self, table: &str, id: Uid) -> Result<Option<Record>, BackendError> {
+        self.with_table(table, |table| table.get(&id).cloned())
+    }
+
+    fn select_by_ids(&mut self, table: &str, ids: &[Uid]) -> Result<Vec<Record>, BackendError> {
+        self.with_table(table, |table| {
+            ids.iter().filter_map(|id| table.get(id).cloned()).collect()
+        })
+    }
+
+    fn select_where(
+        &mut self,
+        table: &str,
+        filters: &Record,
+        limit: Option<usize>,
+    ) -> Result<Vec<Record>, BackendError> {
+        self.with_table(table, |table| {
+            table
+                .values()
+                .filter(|record| {
+                    filters
+                        .columns()
+                        .into_iter()
+                        .zip(filters.values())
+                        .all(|(column, value)| record.get(column) == Some(value))
+                })
+                .take(limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect()
+        })
+    }
+
+    fn insert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        let id = record
+            .id()
+            .ok_or_else(|| BackendError::new("Record has no valid id column"))?;
+        self.with_table(table, |table| match table.contains_key(&id) {
+            true => Err(BackendError::new(format!("Duplicate id {}", id))),
+            false => {
+                table.insert(id, record.clone());
+                Ok(())
+            }
+        })?
+    }
+
+    fn upsert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        let id = record
+            .id()
+            .ok_or_else(|| BackendError::new("Record has no valid id column"))?;
+        self.with_table(table, |table| {
+            table.insert(id, record.clone());
+        })
+    }
+
+    fn update(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        let id = record
+            .id()
+            .ok_or_else(|| BackendError::new("Record has no valid id column"))?;
+        self.with_table(table, |table| match table.get_mut(&id) {
+            Some(current) => {
+                *current = record.clone();
+                Ok(())
+            }
+            None => Err(BackendError::new(format!("No record with id {}", id))),
+        })?
+    }
+
+    fn delete(&mut self, table: &str, ids: &[Uid]) -> Result<(), BackendError> {
+        self.with_table(table, |table| {
+            for id in ids {
+                table.remove(id);
+            }
+        })
+    }
+
+    fn sync_table(
+        &mut self,
+        table: &str,
+        inserts: &[Record],
+        updates: &[Record],
+        deletes: &[Uid],
+    ) -> Result<(), SyncError> {
+        let mut tables = self.tables.lock().unwrap();
+        let current = tables.get(table).ok_or_else(|| {
+            SyncError::Begin(BackendError::new(format!("Table {} doesn't exist", table)))
+        })?;
+
+        let mut updated = current.clone();
+        for record in inserts {
+            let id = record.id().ok_or_else(|| {
+                SyncError::RolledBack(BackendError::new("Record has no valid id column"))
+            })?;
+            if updated.insert(id, record.clone()).is_some() {
+                return Err(SyncError::RolledBack(BackendError::new(format!(
+                    "Duplicate id {}",
+                    id
+                ))));
+            }
+        }
+        for record in updates {
+            match record.id().and_then(|id| updated.get_mut(&id)) {
+                Some(current) => *current = record.clone(),
+                None => {
+                    return Err(SyncError::RolledBack(BackendError::new(
+                        "Updated record doesn't exist",
+                    )))
+                }
+            }
+        }
+        for id in deletes {
+            updated.remove(id);
+        }
+
+        tables.insert(table.to_string(), updated);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_sync_rollback() {
+        let mut backend = MemoryBackend::new();
+        backend.create_table("lease", "").unwrap();
+        backend
+            .insert("lease", &Record::new().with("id", 1u16))
+            .unwrap();
+
+        let inserts = [
+            Record::new().with("id", 2u16),
+            Record::new().with("id", 2u16),
+        ];
+        assert!(matches!(
+            backend.sync_table("lease", &inserts, &[], &[Uid::new(1)]),
+            Err(SyncError::RolledBack(_))
+        ));
+        assert_eq!(backend.select_ids("lease").unwrap(), vec![Uid::new(1)]);
+
+        backend
+            .sync_table("lease", &inserts[..1], &[], &[Uid::new(1)])
+            .unwrap();
+        assert_eq!(backend.select_ids("lease").unwrap(), vec![Uid::new(2)]);
+    }
+}
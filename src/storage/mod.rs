@@ -1 +1,19 @@
+pub mod any;
+pub mod audit;
+pub mod backend;
 pub mod data;
+pub mod encryption;
+pub mod errors;
+pub mod export;
+pub mod index;
+pub mod isc_leases;
+pub mod memory_backend;
+pub mod mysql_backend;
+pub mod postgres_backend;
+pub mod query;
+pub mod redis_backend;
+pub mod serialized;
+pub mod snapshot;
+pub mod sql;
+pub mod synchronizer;
+pub mod uid;
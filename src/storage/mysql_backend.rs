@@ -0,0 +1,418 @@
+//! [`StorageBackend`] implementation for MySQL and MariaDB.
+//!
+//! Connectivity errors are retried with exponential backoff,
+//! following the [`RetryPolicy`] of the [`DbManager`]. Dead
+//! connections are dropped by the pool, so every retry runs
+//! on a fresh or health-checked connection.
+//!
+//! Reads can be spread over replicas, see [`DbManager::add_replica`].
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use mysql::{prelude::Queryable, Opts, Params, Pool, PooledConn, Row, TxOpts, Value};
+
+use crate::core::retry::RetryPolicy;
+
+use super::{
+    backend::{BackendError, StorageBackend, SyncError},
+    sql::{Dialect, MySqlDialect, Record, SqlValue},
+    uid::Uid,
+};
+
+///Default retry policy of a [`DbManager`]: 5 attempts, backing off from 100ms up to 5s.
+pub fn default_retry_policy() -> RetryPolicy {
+    RetryPolicy::new(5, Duration::from_millis(100)).with_max_backoff(Duration::from_secs(5))
+}
+
+///DbManager aims to manage MySql connections and interactions.
+pub struct DbManager {
+    pub db_name: String,
+    pub user: String,
+    pub password: String,
+    pub pool: Arc<Pool>,
+    replicas: Vec<Arc<Pool>>,
+    next_replica: AtomicUsize,
+    dialect: MySqlDialect,
+    retry_policy: RetryPolicy,
+}
+
+impl DbManager {
+    ///Connects to the given MySQL database, retrying with the [default policy](default_retry_policy).
+    /// # Example
+    /// ```rust
+    /// let db = DbManager::new(db_name, user, password, host)?;
+    /// ```
+    pub fn new(
+        db_name: String,
+        user: String,
+        password: String,
+        host: String,
+    ) -> Result<Self, BackendError> {
+        let retry_policy = default_retry_policy();
+        let pool = connect(&retry_policy, &db_name, &user, &password, &host)?;
+        Ok(Self {
+            db_name,
+            user,
+            password,
+            pool: Arc::new(pool),
+            replicas: vec![],
+            next_replica: AtomicUsize::new(0),
+            dialect: MySqlDialect,
+            retry_policy,
+        })
+    }
+
+    ///Add a read replica of the database, reached with the same credentials.
+    ///Reads are spread over replicas in turn, and fall back to the primary if a replica is unreachable.
+    ///Writes, and the id scans deciding what each synchronization inserts or deletes,
+    ///always go to the primary, so that a lagging replica cannot make them diverge.
+    /// # Example
+    /// ```rust
+    /// let mut db = DbManager::new(db_name, user, password, primary)?;
+    /// db.add_replica(String::from("replica-1:3306"))?;
+    /// ```
+    pub fn add_replica(&mut self, host: String) -> Result<(), BackendError> {
+        let pool = connect(
+            &self.retry_policy,
+            &self.db_name,
+            &self.user,
+            &self.password,
+            &host,
+        )?;
+        self.replicas.push(Arc::new(pool));
+        Ok(())
+    }
+
+    ///Set the policy used to retry operations failing because of a connectivity error.
+    /// # Example
+    /// ```rust
+    /// db.set_retry_policy(RetryPolicy::new(10, Duration::from_millis(50)));
+    /// ```
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    ///Returns whether the database can currently be reached.
+    pub fn is_healthy(&self) -> bool {
+        self.pool
+            .try_get_conn(Duration::from_secs(1))
+            .and_then(|mut conn| conn.as_mut().ping())
+            .is_ok()
+    }
+
+    ///Run `operation` on a pooled connection to the primary, retrying on connectivity errors
+    fn with_conn<R>(
+        &self,
+        mut operation: impl FnMut(&mut PooledConn) -> mysql::Result<R>,
+    ) -> Result<R, BackendError> {
+        retry(&self.retry_policy, || operation(&mut self.pool.get_conn()?))
+    }
+
+    ///Run the read-only `operation` on the next replica, or on the primary
+    ///if there is no replica or if the replica is unreachable
+    fn with_read_conn<R>(
+        &self,
+        mut operation: impl FnMut(&mut PooledConn) -> mysql::Result<R>,
+    ) -> Result<R, BackendError> {
+        if self.replicas.is_empty() {
+            return self.with_conn(operation);
+        }
+        let index = self.next_replica.fetch_add(1, Ordering::Relaxed) % self.replicas.len();
+        let replica = &self.replicas[index];
+        let mut connected = false;
+        let result = retry(&self.retry_policy, || {
+            let conn = replica.get_conn();
+            connected = conn.is_ok();
+            operation(&mut conn?)
+        });
+        match result {
+            Err(e) if !connected => {
+                log::warn!(
+                    "Replica {} unreachable, reading from primary : {}",
+                    index,
+                    e
+                );
+                self.with_conn(operation)
+            }
+            result => result,
+        }
+    }
+
+    ///Exec read-only statement with given values and return the resulting records
+    fn select(&self, stmt: String, values: Vec<&SqlValue>) -> Result<Vec<Record>, BackendError> {
+        let params = params(values);
+        let rows: Vec<Row> = self.with_read_conn(|conn| conn.exec(&stmt, params.clone()))?;
+        Ok(rows.into_iter().map(to_record).collect())
+    }
+
+    ///Exec statement with given values and drop the result
+    fn exec(&self, stmt: String, values: Vec<&SqlValue>) -> Result<(), BackendError> {
+        let params = params(values);
+        self.with_conn(|conn| conn.exec_drop(&stmt, params.clone()))
+    }
+}
+
+///Create a connection pool to the given database
+fn connect(
+    policy: &RetryPolicy,
+    db_name: &str,
+    user: &str,
+    password: &str,
+    host: &str,
+) -> Result<Pool, BackendError> {
+    let url = format!("mysql://{}:{}@{}/{}", user, password, host, db_name);
+    let opts = Opts::from_url(&url).map_err(|e| BackendError::new(e.to_string()))?;
+    retry(policy, || Pool::new(opts.clone()))
+}
+
+///Run `operation` until it succeeds, fails with an error which is not
+///a connectivity error, or until `policy` runs out of attempts
+fn retry<R>(
+    policy: &RetryPolicy,
+    mut operation: impl FnMut() -> mysql::Result<R>,
+) -> Result<R, BackendError> {
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Ok(res) => return Ok(res),
+            Err(e) if e.is_connectivity_error() && attempt < policy.max_attempts() => {
+                log::warn!(
+                    "Database unreachable (attempt {}/{}) : {}",
+                    attempt,
+                    policy.max_attempts(),
+                    e
+                );
+                thread::sleep(policy.backoff(attempt));
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+impl StorageBackend for DbManager {
+    fn tables(&mut self) -> Result<Vec<String>, BackendError> {
+        let stmt = self.dialect.list_tables();
+        self.with_read_conn(|conn| conn.query(&stmt))
+    }
+
+    fn create_table(&mut self, table: &str, schema: &str) -> Result<(), BackendError> {
+        self.exec(self.dialect.create_table(table, schema)?, vec![])
+    }
+
+    fn select_all(&mut self, table: &str) -> Result<Vec<Record>, BackendError> {
+        self.select(self.dialect.select_all(table)?, vec![])
+    }
+
+    fn select_page(
+        &mut self,
+        table: &str,
+        after: Option<Uid>,
+        limit: usize,
+    ) -> Result<Vec<Record>, BackendError> {
+        let after = after.map_or(SqlValue::Int(-1), SqlValue::from);
+        let limit = SqlValue::Int(limit as i64);
+        self.select(self.dialect.select_page(table)?, vec![&after, &limit])
+    }
+
+    fn select_ids(&mut self, table: &str) -> Result<Vec<Uid>, BackendError> {
+        let stmt = self.dialect.select_ids(table)?;
+        //Synchronizations insert the ids missing from this scan
+        let ids: Vec<i64> = self.with_conn(|conn| conn.query(&stmt))?;
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| Uid::try_from(id).ok())
+            .collect())
+    }
+
+    fn select_by_id(&mut self, table: &str, id: Uid) -> Result<Option<Record>, BackendError> {
+        let id = SqlValue::from(id);
+        let records = self.select(self.dialect.select_by_id(table)?, vec![&id])?;
+        Ok(records.into_iter().next())
+    }
+
+    fn select_by_ids(&mut self, table: &str, ids: &[Uid]) -> Result<Vec<Record>, BackendError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let ids: Vec<SqlValue> = ids.iter().map(|&id| SqlValue::from(id)).collect();
+        self.select(
+            self.dialect.select_by_ids(table, ids.len())?,
+            ids.iter().collect(),
+        )
+    }
+
+    fn select_where(
+        &mut self,
+        table: &str,
+        filters: &Record,
+        limit: Option<usize>,
+    ) -> Result<Vec<Record>, BackendError> {
+        let stmt = self
+            .dialect
+            .select_where(table, &filters.columns(), limit.is_some())?;
+        let limit = limit.map(|limit| SqlValue::Int(limit as i64));
+        let mut values = filters.values();
+        values.extend(limit.as_ref());
+        self.select(stmt, values)
+    }
+
+    fn insert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        self.exec(
+            self.dialect.insert(table, &record.columns())?,
+            record.values(),
+        )
+    }
+
+    fn upsert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        self.exec(
+            self.dialect.upsert(table, &record.columns())?,
+            record.values(),
+        )
+    }
+
+    fn update(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        let (columns, values) = record.update_params();
+        self.exec(self.dialect.update(table, &columns)?, values)
+    }
+
+    fn delete(&mut self, table: &str, ids: &[Uid]) -> Result<(), BackendError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<SqlValue> = ids.iter().map(|&id| SqlValue::from(id)).collect();
+        self.exec(self.dialect.delete(table, ids.len())?, ids.iter().collect())
+    }
+
+    fn sync_table(
+        &mut self,
+        table: &str,
+        inserts: &[Record],
+        updates: &[Record],
+        deletes: &[Uid],
+    ) -> Result<(), SyncError> {
+        //Nothing was written before the transaction starts, so it is safe to retry
+        let mut tx = retry(&self.retry_policy, || {
+            self.pool.start_transaction(TxOpts::default())
+        })
+        .map_err(SyncError::Begin)?;
+
+        let mut run = || -> Result<(), BackendError> {
+            for record in inserts {
+                let stmt = self.dialect.insert(table, &record.columns())?;
+                tx.exec_drop(stmt, params(record.values()))?;
+            }
+            for record in updates {
+                let (columns, values) = record.update_params();
+                let stmt = self.dialect.update(table, &columns)?;
+                tx.exec_drop(stmt, params(values))?;
+            }
+            if !deletes.is_empty() {
+                let ids: Vec<SqlValue> = deletes.iter().map(|&id| SqlValue::from(id)).collect();
+                let stmt = self.dialect.delete(table, ids.len())?;
+                tx.exec_drop(stmt, params(ids.iter().collect()))?;
+            }
+            Ok(())
+        };
+
+        match run() {
+            Ok(()) => tx.commit().map_err(|e| SyncError::CommitFailed(e.into())),
+            Err(cause) => match tx.rollback() {
+                Ok(()) => Err(SyncError::RolledBack(cause)),
+                Err(e) => Err(SyncError::RollbackFailed {
+                    cause,
+                    rollback: e.into(),
+                }),
+            },
+        }
+    }
+}
+
+fn params(values: Vec<&SqlValue>) -> Params {
+    if values.is_empty() {
+        return Params::Empty;
+    }
+    Params::Positional(
+        values
+            .into_iter()
+            .map(|value| match value {
+                SqlValue::Null => Value::NULL,
+                SqlValue::Int(value) => Value::Int(*value),
+                SqlValue::Float(value) => Value::Double(*value),
+                SqlValue::Text(value) => Value::Bytes(value.as_bytes().to_vec()),
+                SqlValue::Bytes(value) => Value::Bytes(value.clone()),
+            })
+            .collect(),
+    )
+}
+
+fn to_record(row: Row) -> Record {
+    let columns: Vec<String> = row
+        .columns_ref()
+        .iter()
+        .map(|column| column.name_str().to_string())
+        .collect();
+
+    columns
+        .iter()
+        .zip(row.unwrap())
+        .fold(Record::new(), |record, (column, value)| {
+            let value = match value {
+                Value::NULL => SqlValue::Null,
+                Value::Int(value) => SqlValue::Int(value),
+                Value::UInt(value) => SqlValue::Int(value as i64),
+                Value::Float(value) => SqlValue::Float(value as f64),
+                Value::Double(value) => SqlValue::Float(value),
+                Value::Bytes(value) => match String::from_utf8(value) {
+                    Ok(value) => SqlValue::Text(value),
+                    Err(value) => SqlValue::Bytes(value.into_bytes()),
+                },
+                value => SqlValue::Text(value.as_sql(true)),
+            };
+            record.with(column, value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_retry() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        let mut attempts = 0;
+        let result = retry(&policy, || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(mysql::Error::server_disconnected()),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result, Ok(3));
+
+        let mut attempts = 0;
+        let result: Result<(), _> = retry(&policy, || {
+            attempts += 1;
+            Err(mysql::Error::server_disconnected())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = retry(&policy, || {
+            attempts += 1;
+            Err(mysql::Error::FromValueError(Value::NULL))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
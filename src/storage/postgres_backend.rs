@@ -0,0 +1,279 @@
+//! [`StorageBackend`] implementation for PostgreSQL.
+//!
+//! The underlying client is blocking and drives its own
+//! runtime, so it must not be used from an async context:
+//! use [`tokio::task::spawn_blocking`] instead.
+
+use std::error::Error;
+
+use bytes::BytesMut;
+use postgres::{
+    types::{to_sql_checked, IsNull, ToSql, Type},
+    Client, NoTls, Row,
+};
+
+use super::{
+    backend::{BackendError, StorageBackend, SyncError},
+    sql::{Dialect, PostgresDialect, Record, SqlValue},
+    uid::Uid,
+};
+
+/// `PostgresManager` manages a PostgreSQL connection
+pub struct PostgresManager {
+    pub db_name: String,
+    pub user: String,
+    client: Client,
+    dialect: PostgresDialect,
+}
+
+impl PostgresManager {
+    /// Connects to the given PostgreSQL database
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let backend = PostgresManager::new(
+    ///     String::from("dhcp"),
+    ///     String::from("dhcp"),
+    ///     password,
+    ///     String::from("localhost"),
+    /// )?;
+    /// let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+    /// ```
+    pub fn new(
+        db_name: String,
+        user: String,
+        password: String,
+        host: String,
+    ) -> Result<Self, BackendError> {
+        let client = postgres::Config::new()
+            .host(&host)
+            .user(&user)
+            .password(password)
+            .dbname(&db_name)
+            .connect(NoTls)?;
+        Ok(Self {
+            db_name,
+            user,
+            client,
+            dialect: PostgresDialect,
+        })
+    }
+
+    fn select(
+        &mut self,
+        stmt: String,
+        values: Vec<&SqlValue>,
+    ) -> Result<Vec<Record>, BackendError> {
+        let params = to_params(values);
+        let rows = self.client.query(&stmt, &params)?;
+        Ok(rows.iter().map(to_record).collect())
+    }
+
+    fn exec(&mut self, stmt: String, values: Vec<&SqlValue>) -> Result<(), BackendError> {
+        let params = to_params(values);
+        self.client.execute(&stmt, &params)?;
+        Ok(())
+    }
+}
+
+impl StorageBackend for PostgresManager {
+    fn tables(&mut self) -> Result<Vec<String>, BackendError> {
+        let records = self.select(self.dialect.list_tables(), vec![])?;
+        Ok(records
+            .iter()
+            .filter_map(|record| Some(record.get("tablename")?.as_str()?.to_string()))
+            .collect())
+    }
+
+    fn create_table(&mut self, table: &str, schema: &str) -> Result<(), BackendError> {
+        self.exec(self.dialect.create_table(table, schema)?, vec![])
+    }
+
+    fn select_all(&mut self, table: &str) -> Result<Vec<Record>, BackendError> {
+        self.select(self.dialect.select_all(table)?, vec![])
+    }
+
+    fn select_page(
+        &mut self,
+        table: &str,
+        after: Option<Uid>,
+        limit: usize,
+    ) -> Result<Vec<Record>, BackendError> {
+        let after = after.map_or(SqlValue::Int(-1), SqlValue::from);
+        let limit = SqlValue::Int(limit as i64);
+        self.select(self.dialect.select_page(table)?, vec![&after, &limit])
+    }
+
+    fn select_ids(&mut self, table: &str) -> Result<Vec<Uid>, BackendError> {
+        let records = self.select(self.dialect.select_ids(table)?, vec![])?;
+        Ok(records.iter().filter_map(Record::id).collect())
+    }
+
+    fn select_by_id(&mut self, table: &str, id: Uid) -> Result<Option<Record>, BackendError> {
+        let id = SqlValue::from(id);
+        let records = self.select(self.dialect.select_by_id(table)?, vec![&id])?;
+        Ok(records.into_iter().next())
+    }
+
+    fn select_by_ids(&mut self, table: &str, ids: &[Uid]) -> Result<Vec<Record>, BackendError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let ids: Vec<SqlValue> = ids.iter().map(|&id| SqlValue::from(id)).collect();
+        self.select(
+            self.dialect.select_by_ids(table, ids.len())?,
+            ids.iter().collect(),
+        )
+    }
+
+    fn select_where(
+        &mut self,
+        table: &str,
+        filters: &Record,
+        limit: Option<usize>,
+    ) -> Result<Vec<Record>, BackendError> {
+        let stmt = self
+            .dialect
+            .select_where(table, &filters.columns(), limit.is_some())?;
+        let limit = limit.map(|limit| SqlValue::Int(limit as i64));
+        let mut values = filters.values();
+        values.extend(limit.as_ref());
+        self.select(stmt, values)
+    }
+
+    fn insert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        self.exec(
+            self.dialect.insert(table, &record.columns())?,
+            record.values(),
+        )
+    }
+
+    fn upsert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        self.exec(
+            self.dialect.upsert(table, &record.columns())?,
+            record.values(),
+        )
+    }
+
+    fn update(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        let (columns, values) = record.update_params();
+        self.exec(self.dialect.update(table, &columns)?, values)
+    }
+
+    fn delete(&mut self, table: &str, ids: &[Uid]) -> Result<(), BackendError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<SqlValue> = ids.iter().map(|&id| SqlValue::from(id)).collect();
+        self.exec(self.dialect.delete(table, ids.len())?, ids.iter().collect())
+    }
+
+    fn sync_table(
+        &mut self,
+        table: &str,
+        inserts: &[Record],
+        updates: &[Record],
+        deletes: &[Uid],
+    ) -> Result<(), SyncError> {
+        let dialect = self.dialect;
+        let mut tx = self
+            .client
+            .transaction()
+            .map_err(|e| SyncError::Begin(e.into()))?;
+
+        let mut run = || -> Result<(), BackendError> {
+            for record in inserts {
+                let stmt = dialect.insert(table, &record.columns())?;
+                tx.execute(&stmt, &to_params(record.values()))?;
+            }
+            for record in updates {
+                let (columns, values) = record.update_params();
+                let stmt = dialect.update(table, &columns)?;
+                tx.execute(&stmt, &to_params(values))?;
+            }
+            if !deletes.is_empty() {
+                let ids: Vec<SqlValue> = deletes.iter().map(|&id| SqlValue::from(id)).collect();
+                let stmt = dialect.delete(table, ids.len())?;
+                tx.execute(&stmt, &to_params(ids.iter().collect()))?;
+            }
+            Ok(())
+        };
+
+        match run() {
+            Ok(()) => tx.commit().map_err(|e| SyncError::CommitFailed(e.into())),
+            Err(cause) => match tx.rollback() {
+                Ok(()) => Err(SyncError::RolledBack(cause)),
+                Err(e) => Err(SyncError::RollbackFailed {
+                    cause,
+                    rollback: e.into(),
+                }),
+            },
+        }
+    }
+}
+
+fn to_params(values: Vec<&SqlValue>) -> Vec<&(dyn ToSql + Sync)> {
+    values
+        .into_iter()
+        .map(|value| value as &(dyn ToSql + Sync))
+        .collect()
+}
+
+impl ToSql for SqlValue {
+    /// Encodes the value as the type of the column
+    /// it is bound to, as PostgreSQL does not
+    /// implicitly convert parameters
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        match (self, ty) {
+            (Self::Null, _) => Ok(IsNull::Yes),
+            (Self::Int(value), &Type::BOOL) => (*value != 0).to_sql(ty, out),
+            (Self::Int(value), &Type::INT2) => i16::try_from(*value)?.to_sql(ty, out),
+            (Self::Int(value), &Type::INT4) => i32::try_from(*value)?.to_sql(ty, out),
+            (Self::Int(value), &Type::FLOAT4) => (*value as f32).to_sql(ty, out),
+            (Self::Int(value), &Type::FLOAT8) => (*value as f64).to_sql(ty, out),
+            (Self::Int(value), &Type::TEXT | &Type::VARCHAR | &Type::BPCHAR) => {
+                value.to_string().to_sql(ty, out)
+            }
+            (Self::Int(value), _) => value.to_sql(ty, out),
+            (Self::Float(value), &Type::FLOAT4) => (*value as f32).to_sql(ty, out),
+            (Self::Float(value), _) => value.to_sql(ty, out),
+            (Self::Text(value), &Type::BYTEA) => value.as_bytes().to_sql(ty, out),
+            (Self::Text(value), _) => value.as_str().to_sql(ty, out),
+            (Self::Bytes(value), _) => value.as_slice().to_sql(ty, out),
+        }
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn to_record(row: &Row) -> Record {
+    row.columns()
+        .iter()
+        .enumerate()
+        .fold(Record::new(), |record, (index, column)| {
+            let value: SqlValue = match *column.type_() {
+                Type::BOOL => row.get::<_, Option<bool>>(index).into(),
+                Type::INT2 => row.get::<_, Option<i16>>(index).into(),
+                Type::INT4 => row.get::<_, Option<i32>>(index).into(),
+                Type::INT8 => row.get::<_, Option<i64>>(index).into(),
+                Type::FLOAT4 => row.get::<_, Option<f32>>(index).into(),
+                Type::FLOAT8 => row.get::<_, Option<f64>>(index).into(),
+                Type::BYTEA => row.get::<_, Option<Vec<u8>>>(index).into(),
+                _ => row
+                    .try_get::<_, Option<String>>(index)
+                    .ok()
+                    .flatten()
+                    .into(),
+            };
+            record.with(column.name(), value)
+        })
+}
@@ -0,0 +1,115 @@
+//! Ad-hoc queries over the data stored on disk.
+//!
+//! [`RuntimeStorage::from`] starts a [`Query`] over a table,
+//! narrowed down with [`Query::filter_eq`] and [`Query::limit`],
+//! and run with [`Query::fetch`]. Values are always bound as
+//! parameters, and column names are validated by the backend,
+//! so queries never need hand-written SQL.
+//!
+//! Queries only read the database: data stored since the last
+//! synchronization is not returned.
+
+use super::{
+    data::{FromRecord, RuntimeStorage, Storable},
+    errors::StorageError,
+    sql::{Record, SqlValue},
+};
+
+/// Query over the records of a table, built by [`RuntimeStorage::from`]
+pub struct Query<'a, V: Storable + Clone + FromRecord> {
+    storage: &'a RuntimeStorage<V>,
+    table: String,
+    filters: Record,
+    limit: Option<usize>,
+}
+
+impl<'a, V: Storable + Clone + FromRecord> Query<'a, V> {
+    /// Only keeps records whose `column` equals `value`
+    pub fn filter_eq(mut self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.filters.push(column, value);
+        self
+    }
+
+    /// Returns at most `limit` records
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Runs the query, and returns the matching records, ordered by id
+    pub fn fetch_records(self) -> Result<Vec<Record>, StorageError> {
+        let records = self
+            .storage
+            .with_backend(|backend| backend.select_where(&self.table, &self.filters, self.limit))?;
+        Ok(records)
+    }
+
+    /// Runs the query, and converts the matching records into `T`,
+    /// skipping records which cannot be converted
+    pub fn fetch<T: FromRecord>(self) -> Result<Vec<T>, StorageError> {
+        Ok(self
+            .fetch_records()?
+            .iter()
+            .filter_map(T::from_record)
+            .collect())
+    }
+}
+
+impl<V: Storable + Clone + FromRecord> RuntimeStorage<V> {
+    /// Starts a query over the records of `table` stored on disk
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let leases = storage
+    ///     .from("lease")
+    ///     .filter_eq("hardware_address", mac.to_string())
+    ///     .limit(10)
+    ///     .fetch::<LeaseV4>()?;
+    /// ```
+    pub fn from(&self, table: &str) -> Query<'_, V> {
+        Query {
+            storage: self,
+            table: table.to_string(),
+            filters: Record::new(),
+            limit: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::{
+        data::DataPool,
+        memory_backend::MemoryBackend,
+        serialized::{Serialized, SCHEMA},
+    };
+
+    #[test]
+    fn test_query() {
+        let storage: RuntimeStorage<Serialized<u32>> = RuntimeStorage::new(MemoryBackend::new());
+        storage
+            .add_pool(DataPool::new(String::from("counter"), String::from(SCHEMA)))
+            .unwrap();
+        for value in [1, 2, 2] {
+            storage
+                .store(Serialized::new(value), String::from("counter"))
+                .unwrap();
+        }
+        storage.sync().unwrap();
+
+        let twos = storage
+            .from("counter")
+            .filter_eq("value", "2")
+            .fetch::<Serialized<u32>>()
+            .unwrap();
+        assert_eq!(twos.len(), 2);
+        assert!(twos.iter().all(|value| **value == 2));
+
+        let limited = storage.from("counter").limit(1).fetch_records().unwrap();
+        assert_eq!(limited.len(), 1);
+        assert!(storage.from("missing").fetch_records().is_err());
+    }
+}
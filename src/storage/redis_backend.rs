@@ -0,0 +1,457 @@
+//! [`StorageBackend`] implementation for Redis, sharing data
+//! between several server instances.
+//!
+//! Each record is stored as JSON under its own key, and the ids of
+//! every table are kept in a sorted set, so that tables can be read
+//! in order of id. Records can expire along with the data they hold:
+//! see [`RedisBackend::set_expiration_column`].
+//!
+//! Every write is published on an invalidation channel, so that the
+//! other instances can drop or reload their copy of the record, see
+//! [`RedisBackend::invalidations`].
+
+use std::collections::HashMap;
+
+use redis::{Client, Commands, Connection, Msg, Pipeline};
+
+use super::{
+    backend::{BackendError, StorageBackend, SyncError},
+    sql::Record,
+    uid::Uid,
+};
+
+/// Number of attempts of [`StorageBackend::sync_table`] when the
+/// records it writes are modified concurrently by another instance
+const SYNC_ATTEMPTS: usize = 3;
+
+/// Write to a record made by another instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalidation {
+    pub table: String,
+    pub id: Uid,
+}
+
+/// `RedisBackend` manages a Redis connection
+pub struct RedisBackend {
+    client: Client,
+    connection: Connection,
+    prefix: String,
+    instance: String,
+    expirations: HashMap<String, String>,
+}
+
+impl RedisBackend {
+    /// Connects to the Redis server at `url`, storing every
+    /// key under `prefix`
+    ///
+    /// Instances sharing data must use the same prefix.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut backend = RedisBackend::new("redis://127.0.0.1/", String::from("dhcp"))?;
+    /// backend.set_expiration_column("lease", "expiration");
+    /// let storage: RuntimeStorage<Data> = RuntimeStorage::new(backend);
+    /// ```
+    pub fn new(url: &str, prefix: String) -> Result<Self, BackendError> {
+        let client = Client::open(url)?;
+        let connection = client.get_connection()?;
+        Ok(Self {
+            client,
+            connection,
+            prefix,
+            instance: uuid::Uuid::new_v4().to_string(),
+            expirations: HashMap::new(),
+        })
+    }
+
+    /// Expires the records of `table` at the time held by `column`,
+    /// as a UNIX timestamp in seconds
+    ///
+    /// Expired records are removed from Redis, and are no longer
+    /// returned by any instance.
+    pub fn set_expiration_column(&mut self, table: &str, column: &str) {
+        self.expirations
+            .insert(table.to_string(), column.to_string());
+    }
+
+    /// Subscribes to the writes made by other instances
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut invalidations = backend.invalidations()?;
+    /// std::thread::spawn(move || {
+    ///     while let Ok(invalidation) = invalidations.recv() {
+    ///         log::debug!("Data {} was modified", invalidation.id);
+    ///     }
+    /// });
+    /// ```
+    pub fn invalidations(&self) -> Result<Invalidations, BackendError> {
+        let mut connection = self.client.get_connection()?;
+        connection.send_packed_command(
+            &redis::cmd("SUBSCRIBE")
+                .arg(self.channel())
+                .get_packed_command(),
+        )?;
+        Ok(Invalidations {
+            connection,
+            instance: self.instance.clone(),
+        })
+    }
+
+    fn tables_key(&self) -> String {
+        format!("{}:tables", self.prefix)
+    }
+
+    fn ids_key(&self, table: &str) -> String {
+        format!("{}:{}:ids", self.prefix, table)
+    }
+
+    fn record_key(&self, table: &str, id: Uid) -> String {
+        format!("{}:{}:{}", self.prefix, table, id)
+    }
+
+    fn channel(&self) -> String {
+        format!("{}:invalidate", self.prefix)
+    }
+
+    /// Returns the time at which `record` of `table` expires, if any
+    fn expiration(&self, table: &str, record: &Record) -> Option<i64> {
+        record.get(self.expirations.get(table)?)?.as_int()
+    }
+
+    /// Queues the write of `record` into `table` to `pipe`
+    fn queue_write(
+        &self,
+        pipe: &mut Pipeline,
+        table: &str,
+        record: &Record,
+    ) -> Result<(), BackendError> {
+        let id = record
+            .id()
+            .ok_or_else(|| BackendError::new("Record has no valid id column"))?;
+        let json = serde_json::to_string(record).map_err(|e| BackendError::new(e.to_string()))?;
+        let key = self.record_key(table, id);
+        pipe.set(&key, json).ignore();
+        if let Some(expiration) = self.expiration(table, record) {
+            pipe.expire_at(&key, expiration).ignore();
+        }
+        pipe.zadd(self.ids_key(table), id.get(), id.get()).ignore();
+        pipe.publish(self.channel(), invalidation(&self.instance, table, id))
+            .ignore();
+        Ok(())
+    }
+
+    /// Queues the deletion of the records of `table` with the given ids to `pipe`
+    fn queue_delete(&self, pipe: &mut Pipeline, table: &str, ids: &[Uid]) {
+        for &id in ids {
+            pipe.del(self.record_key(table, id)).ignore();
+            pipe.zrem(self.ids_key(table), id.get()).ignore();
+            pipe.publish(self.channel(), invalidation(&self.instance, table, id))
+                .ignore();
+        }
+    }
+
+    /// Returns the records of `table` with the given ids, in the same
+    /// order, dropping the ids of expired records from the table
+    fn fetch(&mut self, table: &str, ids: &[Uid]) -> Result<Vec<Record>, BackendError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let keys: Vec<String> = ids.iter().map(|&id| self.record_key(table, id)).collect();
+        let values: Vec<Option<String>> =
+            redis::cmd("MGET").arg(&keys).query(&mut self.connection)?;
+        let mut records = vec![];
+        let mut expired = vec![];
+        for (&id, value) in ids.iter().zip(values) {
+            match value {
+                Some(json) => records.push(
+                    serde_json::from_str(&json).map_err(|e| BackendError::new(e.to_string()))?,
+                ),
+                None => expired.push(id.get()),
+            }
+        }
+        if !expired.is_empty() {
+            let _: () = self.connection.zrem(self.ids_key(table), expired)?;
+        }
+        Ok(records)
+    }
+
+    /// Returns at most `limit` ids of `table` greater than `after`, in order
+    fn ids_after(
+        &mut self,
+        table: &str,
+        after: Option<Uid>,
+        limit: usize,
+    ) -> Result<Vec<Uid>, BackendError> {
+        let min = after.map_or(String::from("-inf"), |after| format!("({}", after));
+        let ids: Vec<i64> = self.connection.zrangebyscore_limit(
+            self.ids_key(table),
+            min,
+            "+inf",
+            0,
+            limit.try_into().unwrap_or(isize::MAX),
+        )?;
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| Uid::try_from(id).ok())
+            .collect())
+    }
+
+    fn ensure_table(&mut self, table: &str) -> Result<(), BackendError> {
+        let exists: bool = self.connection.sismember(self.tables_key(), table)?;
+        match exists {
+            true => Ok(()),
+            false => Err(BackendError::new(format!("Table {} doesn't exist", table))),
+        }
+    }
+
+    /// Returns the first of `keys` which exists, if any
+    fn existing<'a>(&mut self, keys: &'a [String]) -> Result<Option<&'a String>, BackendError> {
+        for key in keys {
+            if self.connection.exists(key)? {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the first of `keys` which doesn't exist, if any
+    fn missing<'a>(&mut self, keys: &'a [String]) -> Result<Option<&'a String>, BackendError> {
+        for key in keys {
+            if !self.connection.exists::<_, bool>(key)? {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the keys of `records` of `table`
+    fn keys(&self, table: &str, records: &[Record]) -> Result<Vec<String>, BackendError> {
+        records
+            .iter()
+            .map(|record| {
+                record
+                    .id()
+                    .map(|id| self.record_key(table, id))
+                    .ok_or_else(|| BackendError::new("Record has no valid id column"))
+            })
+            .collect()
+    }
+}
+
+impl StorageBackend for RedisBackend {
+    fn tables(&mut self) -> Result<Vec<String>, BackendError> {
+        Ok(self.connection.smembers(self.tables_key())?)
+    }
+
+    fn create_table(&mut self, table: &str, _schema: &str) -> Result<(), BackendError> {
+        let _: () = self.connection.sadd(self.tables_key(), table)?;
+        Ok(())
+    }
+
+    fn select_all(&mut self, table: &str) -> Result<Vec<Record>, BackendError> {
+        self.ensure_table(table)?;
+        let ids = self.ids_after(table, None, usize::MAX)?;
+        self.fetch(table, &ids)
+    }
+
+    fn select_page(
+        &mut self,
+        table: &str,
+        after: Option<Uid>,
+        limit: usize,
+    ) -> Result<Vec<Record>, BackendError> {
+        self.ensure_table(table)?;
+        let mut records = vec![];
+        let mut after = after;
+        //Expired records are skipped, so that a short page always means the end of the table
+        while records.len() < limit {
+            let ids = self.ids_after(table, after, limit - records.len())?;
+            let Some(&last) = ids.last() else {
+                break;
+            };
+            records.extend(self.fetch(table, &ids)?);
+            after = Some(last);
+        }
+        Ok(records)
+    }
+
+    fn select_ids(&mut self, table: &str) -> Result<Vec<Uid>, BackendError> {
+        Ok(self
+            .select_all(table)?
+            .iter()
+            .filter_map(Record::id)
+            .collect())
+    }
+
+    fn select_by_id(&mut self, table: &str, id: Uid) -> Result<Option<Record>, BackendError> {
+        Ok(self.fetch(table, &[id])?.pop())
+    }
+
+    fn select_by_ids(&mut self, table: &str, ids: &[Uid]) -> Result<Vec<Record>, BackendError> {
+        self.fetch(table, ids)
+    }
+
+    fn select_where(
+        &mut self,
+        table: &str,
+        filters: &Record,
+        limit: Option<usize>,
+    ) -> Result<Vec<Record>, BackendError> {
+        Ok(self
+            .select_all(table)?
+            .into_iter()
+            .filter(|record| {
+                filters
+                    .columns()
+                    .into_iter()
+                    .zip(filters.values())
+                    .all(|(column, value)| record.get(column) == Some(value))
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    fn insert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        self.sync_table(table, std::slice::from_ref(record), &[], &[])
+            .map_err(|e| BackendError::new(e.to_string()))
+    }
+
+    fn upsert(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        self.ensure_table(table)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.queue_write(&mut pipe, table, record)?;
+        pipe.query::<()>(&mut self.connection)?;
+        Ok(())
+    }
+
+    fn update(&mut self, table: &str, record: &Record) -> Result<(), BackendError> {
+        self.sync_table(table, &[], std::slice::from_ref(record), &[])
+            .map_err(|e| BackendError::new(e.to_string()))
+    }
+
+    fn delete(&mut self, table: &str, ids: &[Uid]) -> Result<(), BackendError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.queue_delete(&mut pipe, table, ids);
+        pipe.query::<()>(&mut self.connection)?;
+        Ok(())
+    }
+
+    /// Runs every write in a `MULTI` block, after checking that inserted
+    /// records don't exist and updated records do. Records are watched
+    /// meanwhile, and the transaction is retried if another instance
+    /// modifies them before it is committed.
+    fn sync_table(
+        &mut self,
+        table: &str,
+        inserts: &[Record],
+        updates: &[Record],
+        deletes: &[Uid],
+    ) -> Result<(), SyncError> {
+        self.ensure_table(table).map_err(SyncError::Begin)?;
+        let inserted = self.keys(table, inserts).map_err(SyncError::RolledBack)?;
+        let updated = self.keys(table, updates).map_err(SyncError::RolledBack)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for record in inserts.iter().chain(updates) {
+            self.queue_write(&mut pipe, table, record)
+                .map_err(SyncError::RolledBack)?;
+        }
+        self.queue_delete(&mut pipe, table, deletes);
+
+        for _ in 0..SYNC_ATTEMPTS {
+            let watched: Vec<&String> = inserted.iter().chain(&updated).collect();
+            if !watched.is_empty() {
+                redis::cmd("WATCH")
+                    .arg(&watched)
+                    .query::<()>(&mut self.connection)
+                    .map_err(|e| SyncError::Begin(e.into()))?;
+            }
+            let conflict = match self.existing(&inserted).map_err(SyncError::Begin)? {
+                Some(key) => Some(format!("Duplicate record {}", key)),
+                None => self
+                    .missing(&updated)
+                    .map_err(SyncError::Begin)?
+                    .map(|key| format!("No record {}", key)),
+            };
+            if let Some(conflict) = conflict {
+                let _ = redis::cmd("UNWATCH").query::<()>(&mut self.connection);
+                return Err(SyncError::RolledBack(BackendError::new(conflict)));
+            }
+            //EXEC returns nil when a watched key was modified
+            let committed: Option<()> = pipe
+                .query(&mut self.connection)
+                .map_err(|e| SyncError::CommitFailed(e.into()))?;
+            if committed.is_some() {
+                return Ok(());
+            }
+        }
+        Err(SyncError::RolledBack(BackendError::new(
+            "Records were concurrently modified",
+        )))
+    }
+}
+
+/// Stream of the writes made by other instances,
+/// returned by [`RedisBackend::invalidations`]
+pub struct Invalidations {
+    connection: Connection,
+    instance: String,
+}
+
+impl Invalidations {
+    /// Blocks until another instance writes a record
+    pub fn recv(&mut self) -> Result<Invalidation, BackendError> {
+        loop {
+            let Some(message) = Msg::from_owned_value(self.connection.recv_response()?) else {
+                continue;
+            };
+            let payload: String = message.get_payload()?;
+            if let Some(invalidation) = parse_invalidation(&self.instance, &payload) {
+                return Ok(invalidation);
+            }
+        }
+    }
+}
+
+/// Invalidation message of a write made by `instance`
+fn invalidation(instance: &str, table: &str, id: Uid) -> String {
+    format!("{} {} {}", instance, id, table)
+}
+
+/// Parses an invalidation message, ignoring writes made by `instance`
+fn parse_invalidation(instance: &str, payload: &str) -> Option<Invalidation> {
+    let mut parts = payload.splitn(3, ' ');
+    if parts.next()? == instance {
+        return None;
+    }
+    let id = parts.next()?.parse().ok()?;
+    Some(Invalidation {
+        table: parts.next()?.to_string(),
+        id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_invalidation_messages() {
+        let payload = invalidation("a", "lease", Uid::new(42));
+        assert_eq!(
+            parse_invalidation("b", &payload),
+            Some(Invalidation {
+                table: String::from("lease"),
+                id: Uid::new(42),
+            })
+        );
+        assert_eq!(parse_invalidation("a", &payload), None);
+        assert_eq!(parse_invalidation("b", "a lease"), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Zero-boilerplate storage of any serde-serializable type.
+//!
+//! [`Serialized`] wraps a `Serialize + DeserializeOwned` value
+//! and implements [`Storable`] and [`FromRecord`] for it, storing
+//! the value as JSON alongside its `id` and `kind` columns.
+//! Pools holding such values should be created with [`SCHEMA`].
+
+use std::{any::type_name, ops::Deref};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    data::{FromRecord, Storable},
+    sql::Record,
+    uid::Uid,
+};
+
+/// Schema of a table holding [`Serialized`] values
+pub const SCHEMA: &str = "(id INT PRIMARY KEY, kind VARCHAR(255) NOT NULL, value TEXT NOT NULL)";
+
+/// Adapter storing `T` as a JSON document
+///
+/// The `kind` column holds the type name of `T`, so that
+/// records of another type are never deserialized as `T`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Serialized<T> {
+    uid: Uid,
+    value: T,
+}
+
+impl<T: Serialize + DeserializeOwned> Serialized<T> {
+    /// Wraps `value`, its uid is set once stored
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let mut storage: RuntimeStorage<Serialized<Lease>> = RuntimeStorage::new(backend);
+    /// storage.add_pool(DataPool::new(String::from("lease"), String::from(SCHEMA)));
+    /// let uid = storage.store(Serialized::new(lease), String::from("lease"))?;
+    /// ```
+    pub fn new(value: T) -> Self {
+        Self {
+            uid: Uid::default(),
+            value,
+        }
+    }
+
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// Name stored in the `kind` column
+    pub fn kind() -> &'static str {
+        type_name::<T>()
+    }
+}
+
+impl<T> Deref for Serialized<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Storable for Serialized<T> {
+    fn to_record(&self) -> Record {
+        let value = match serde_json::to_string(&self.value) {
+            Ok(value) => value,
+            Err(e) => {
+                log::error!("Could not serialize data {} : {}", self.uid, e);
+                String::from("null")
+            }
+        };
+        Record::new()
+            .with("id", self.uid)
+            .with("kind", Self::kind())
+            .with("value", value)
+    }
+
+    fn id(&self) -> Uid {
+        self.uid
+    }
+
+    fn set_uid(&mut self, uid: Uid) {
+        self.uid = uid;
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> FromRecord for Serialized<T> {
+    fn from_record(record: &Record) -> Option<Self> {
+        if record.get("kind")?.as_str()? != Self::kind() {
+            return None;
+        }
+        let value = serde_json::from_str(record.get("value")?.as_str()?)
+            .map_err(|e| log::warn!("Could not deserialize record : {}", e))
+            .ok()?;
+        Some(Self {
+            uid: record.id()?,
+            value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::storage::{
+        data::{DataPool, RuntimeStorage},
+        memory_backend::MemoryBackend,
+    };
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Lease {
+        hardware_address: String,
+        address: [u8; 4],
+    }
+
+    #[test]
+    fn test_serialized_storage() {
+        let backend = MemoryBackend::new();
+        let storage: RuntimeStorage<Serialized<Lease>> = RuntimeStorage::new(backend.clone());
+        storage
+            .add_pool(DataPool::new(String::from("lease"), String::from(SCHEMA)))
+            .unwrap();
+
+        let lease = Lease {
+            hardware_address: String::from("aa:bb:cc:dd:ee:ff"),
+            address: [192, 168, 0, 2],
+        };
+        let uid = storage
+            .store(Serialized::new(lease.clone()), String::from("lease"))
+            .unwrap();
+        storage.sync().unwrap();
+
+        let reloaded: RuntimeStorage<Serialized<Lease>> = RuntimeStorage::new(backend);
+        reloaded.load().unwrap();
+        assert_eq!(reloaded.get(uid).unwrap().into_inner(), lease);
+        assert_eq!(
+            storage.get_from_disk(uid).unwrap().address,
+            [192, 168, 0, 2]
+        );
+
+        let other = Record::new()
+            .with("id", 1u16)
+            .with("kind", "other")
+            .with("value", "{}");
+        assert!(Serialized::<Lease>::from_record(&other).is_none());
+    }
+}
@@ -0,0 +1,93 @@
+//! On-disk snapshots of a [`RuntimeStorage`].
+//!
+//! A [`Snapshot`] holds the [`Record`] of every pool, so that
+//! a storage can be restored without any database, either for
+//! a fast cold start or from a backup.
+//!
+//! [`RuntimeStorage`]: super::data::RuntimeStorage
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::sql::Record;
+
+/// Content of a single pool
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolSnapshot {
+    pub name: String,
+    pub schema: String,
+    pub records: Vec<Record>,
+}
+
+/// Content of every pool of a storage
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub pools: Vec<PoolSnapshot>,
+}
+
+impl Snapshot {
+    /// Writes the snapshot to `path`
+    ///
+    /// The snapshot is first written to a temporary file
+    /// next to `path`, then renamed over it, so `path` always
+    /// holds either the previous or the new snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot could not be written
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Reads a snapshot previously written to `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` could not be read
+    /// or does not hold a valid snapshot
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_file() {
+        let dir = std::env::temp_dir().join(format!("fp_core_snapshot_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("storage.json");
+
+        let snapshot = Snapshot {
+            pools: vec![PoolSnapshot {
+                name: String::from("lease"),
+                schema: String::from("(id INT)"),
+                records: vec![Record::new().with("id", 1u16).with("name", "test")],
+            }],
+        };
+        snapshot.write(&path).unwrap();
+        assert_eq!(Snapshot::read(&path).unwrap(), snapshot);
+        assert!(!dir.join("storage.json.tmp").exists());
+
+        fs::write(&path, "not a snapshot").unwrap();
+        assert!(Snapshot::read(&path).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,557 @@
+//! Backend-agnostic representation of stored data, and
+//! generation of the SQL statements run by each backend.
+//!
+//! A [`Storable`] value is turned into a [`Record`], a list
+//! of named [`SqlValue`], which each backend binds to the
+//! statements generated by its [`Dialect`].
+//!
+//! Values are always bound as parameters. Table and column
+//! names cannot be, so they are validated against
+//! [`validate_identifier`] and quoted instead.
+//!
+//! [`Storable`]: super::data::Storable
+
+use serde::{Deserialize, Serialize};
+
+use super::{backend::BackendError, uid::Uid};
+
+/// Maximum length of a table or column name
+pub const MAX_IDENTIFIER_LEN: usize = 64;
+
+/// A single value, as stored in a database column
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SqlValue {
+    Null,
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl SqlValue {
+    /// Returns the value as an integer, parsing
+    /// it if it was stored as text
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(value) => Some(*value),
+            Self::Text(_) | Self::Bytes(_) => self.as_str()?.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a float, parsing
+    /// it if it was stored as text
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Self::Float(value) => Some(*value),
+            Self::Int(value) => Some(*value as f64),
+            Self::Text(_) | Self::Bytes(_) => self.as_str()?.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a string slice, if it
+    /// is made of valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Text(value) => Some(value),
+            Self::Bytes(value) => std::str::from_utf8(value).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw bytes of the value
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Text(value) => Some(value.as_bytes()),
+            Self::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the value is `NULL`
+    pub fn is_null(&self) -> bool {
+        matches!(self, Self::Null)
+    }
+}
+
+macro_rules! int_conversions {
+    ($($ty:ty),*) => {
+        $(impl From<$ty> for SqlValue {
+            fn from(value: $ty) -> Self {
+                Self::Int(value as i64)
+            }
+        })*
+    };
+}
+
+int_conversions!(u8, u16, u32, i8, i16, i32, i64);
+
+impl From<bool> for SqlValue {
+    fn from(value: bool) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<f32> for SqlValue {
+    fn from(value: f32) -> Self {
+        Self::Float(value as f64)
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<Vec<u8>> for SqlValue {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Bytes(value)
+    }
+}
+
+impl<V: Into<SqlValue>> From<Option<V>> for SqlValue {
+    fn from(value: Option<V>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+/// An ordered list of named [`SqlValue`], matching
+/// a row of a database table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    columns: Vec<(String, SqlValue)>,
+}
+
+impl Record {
+    /// Creates an empty `Record`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a column to the `Record`
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let record = Record::new()
+    ///     .with("id", lease.uid)
+    ///     .with("address", lease.address.to_string());
+    /// ```
+    pub fn with(mut self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.push(column, value);
+        self
+    }
+
+    /// Appends a column to the `Record`
+    pub fn push(&mut self, column: &str, value: impl Into<SqlValue>) {
+        self.columns.push((column.to_string(), value.into()));
+    }
+
+    /// Returns the value of the given column, if any
+    pub fn get(&self, column: &str) -> Option<&SqlValue> {
+        self.columns
+            .iter()
+            .find(|(name, _)| name == column)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the value of the `id` column, if any
+    pub fn id(&self) -> Option<Uid> {
+        self.get("id")?.as_int()?.try_into().ok()
+    }
+
+    /// Returns the name of every column
+    pub fn columns(&self) -> Vec<&str> {
+        self.columns.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Returns the value of every column
+    pub fn values(&self) -> Vec<&SqlValue> {
+        self.columns.iter().map(|(_, value)| value).collect()
+    }
+
+    /// Returns the name of every column but `id`, and their
+    /// values followed by the `id`, as bound by [`Dialect::update`]
+    pub fn update_params(&self) -> (Vec<&str>, Vec<&SqlValue>) {
+        let (columns, mut values): (Vec<&str>, Vec<&SqlValue>) = self
+            .columns
+            .iter()
+            .filter(|(name, _)| name != "id")
+            .map(|(name, value)| (name.as_str(), value))
+            .unzip();
+        values.extend(self.get("id"));
+        (columns, values)
+    }
+
+    /// Returns the number of columns
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Returns whether the `Record` has no column
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+}
+
+/// Checks that `name` can be used as a table or column name
+///
+/// Only ASCII letters, digits and underscores are accepted,
+/// and the name must not start with a digit.
+///
+/// # Errors
+///
+/// Returns a [`BackendError`] if `name` is not a valid identifier
+pub fn validate_identifier(name: &str) -> Result<&str, BackendError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_IDENTIFIER_LEN
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    match valid {
+        true => Ok(name),
+        false => Err(BackendError::new(format!("Invalid identifier {:?}", name))),
+    }
+}
+
+/// SQL flavor spoken by a database
+///
+/// Statements only differ in the way parameters are bound,
+/// identifiers are quoted and tables are listed, every other
+/// statement is derived from these.
+///
+/// Every statement taking a table or column name fails
+/// if the name is not a valid identifier.
+pub trait Dialect: Send + Sync {
+    /// Placeholder of the parameter at `index`, starting at 1
+    fn placeholder(&self, index: usize) -> String;
+
+    /// Quotes an already validated identifier
+    fn quote(&self, identifier: &str) -> String;
+
+    /// Query listing the tables of the current database
+    fn list_tables(&self) -> String;
+
+    /// Clause appended to an insert statement so that it
+    /// overwrites `columns` of a record with the same id
+    fn on_duplicate_id(&self, columns: &[String]) -> String;
+
+    /// Validates and quotes a table or column name
+    fn identifier(&self, name: &str) -> Result<String, BackendError> {
+        validate_identifier(name).map(|name| self.quote(name))
+    }
+
+    fn placeholders(&self, count: usize) -> String {
+        (1..=count)
+            .map(|index| self.placeholder(index))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Creates `table` from `schema`, which is inserted
+    /// verbatim and must therefore never come from user input
+    fn create_table(&self, table: &str, schema: &str) -> Result<String, BackendError> {
+        Ok(format!(
+            "CREATE TABLE IF NOT EXISTS {} {}",
+            self.identifier(table)?,
+            schema
+        ))
+    }
+
+    fn insert(&self, table: &str, columns: &[&str]) -> Result<String, BackendError> {
+        let columns = columns
+            .iter()
+            .map(|column| self.identifier(column))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            self.identifier(table)?,
+            columns.join(", "),
+            self.placeholders(columns.len())
+        ))
+    }
+
+    /// Updates `columns` of the record whose id
+    /// is bound after the column values
+    fn update(&self, table: &str, columns: &[&str]) -> Result<String, BackendError> {
+        let assignments = columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                Ok(format!(
+                    "{} = {}",
+                    self.identifier(column)?,
+                    self.placeholder(index + 1)
+                ))
+            })
+            .collect::<Result<Vec<_>, BackendError>>()?;
+        Ok(format!(
+            "UPDATE {} SET {} WHERE id = {}",
+            self.identifier(table)?,
+            assignments.join(", "),
+            self.placeholder(columns.len() + 1)
+        ))
+    }
+
+    /// Inserts a record, or replaces the record with the same id
+    fn upsert(&self, table: &str, columns: &[&str]) -> Result<String, BackendError> {
+        let updated = columns
+            .iter()
+            .filter(|column| **column != "id")
+            .map(|column| self.identifier(column))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!(
+            "{} {}",
+            self.insert(table, columns)?,
+            self.on_duplicate_id(&updated)
+        ))
+    }
+
+    fn select_all(&self, table: &str) -> Result<String, BackendError> {
+        Ok(format!("SELECT * FROM {}", self.identifier(table)?))
+    }
+
+    fn select_ids(&self, table: &str) -> Result<String, BackendError> {
+        Ok(format!("SELECT id FROM {}", self.identifier(table)?))
+    }
+
+    /// Selects at most as many records as the second parameter,
+    /// whose id is greater than the first one, ordered by id
+    fn select_page(&self, table: &str) -> Result<String, BackendError> {
+        Ok(format!(
+            "SELECT * FROM {} WHERE id > {} ORDER BY id LIMIT {}",
+            self.identifier(table)?,
+            self.placeholder(1),
+            self.placeholder(2)
+        ))
+    }
+
+    /// Selects the records whose `columns` equal the parameters bound in the same
+    /// order, ordered by id, followed by the maximum number of records if `limit`
+    fn select_where(
+        &self,
+        table: &str,
+        columns: &[&str],
+        limit: bool,
+    ) -> Result<String, BackendError> {
+        let mut stmt = format!("SELECT * FROM {}", self.identifier(table)?);
+        for (index, column) in columns.iter().enumerate() {
+            stmt += match index {
+                0 => " WHERE ",
+                _ => " AND ",
+            };
+            stmt += &format!(
+                "{} = {}",
+                self.identifier(column)?,
+                self.placeholder(index + 1)
+            );
+        }
+        stmt += " ORDER BY id";
+        if limit {
+            stmt += &format!(" LIMIT {}", self.placeholder(columns.len() + 1));
+        }
+        Ok(stmt)
+    }
+
+    fn select_by_id(&self, table: &str) -> Result<String, BackendError> {
+        Ok(format!(
+            "SELECT * FROM {} WHERE id = {}",
+            self.identifier(table)?,
+            self.placeholder(1)
+        ))
+    }
+
+    /// Selects the records whose id is one of `count` parameters
+    fn select_by_ids(&self, table: &str, count: usize) -> Result<String, BackendError> {
+        Ok(format!(
+            "SELECT * FROM {} WHERE id IN ({})",
+            self.identifier(table)?,
+            self.placeholders(count)
+        ))
+    }
+
+    fn delete(&self, table: &str, count: usize) -> Result<String, BackendError> {
+        Ok(format!(
+            "DELETE FROM {} WHERE id IN ({})",
+            self.identifier(table)?,
+            self.placeholders(count)
+        ))
+    }
+}
+
+/// [`Dialect`] of MySQL and MariaDB
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MySqlDialect;
+
+impl Dialect for MySqlDialect {
+    fn placeholder(&self, _: usize) -> String {
+        String::from("?")
+    }
+
+    fn quote(&self, identifier: &str) -> String {
+        format!("`{}`", identifier)
+    }
+
+    fn list_tables(&self) -> String {
+        String::from("SHOW TABLES")
+    }
+
+    fn on_duplicate_id(&self, columns: &[String]) -> String {
+        if columns.is_empty() {
+            return String::from("ON DUPLICATE KEY UPDATE id = id");
+        }
+        let assignments = columns
+            .iter()
+            .map(|column| format!("{} = VALUES({})", column, column))
+            .collect::<Vec<_>>();
+        format!("ON DUPLICATE KEY UPDATE {}", assignments.join(", "))
+    }
+}
+
+/// [`Dialect`] of PostgreSQL
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PostgresDialect;
+
+impl Dialect for PostgresDialect {
+    fn placeholder(&self, index: usize) -> String {
+        format!("${}", index)
+    }
+
+    fn quote(&self, identifier: &str) -> String {
+        format!("\"{}\"", identifier)
+    }
+
+    fn list_tables(&self) -> String {
+        String::from("SELECT tablename FROM pg_tables WHERE schemaname = current_schema()")
+    }
+
+    fn on_duplicate_id(&self, columns: &[String]) -> String {
+        if columns.is_empty() {
+            return String::from("ON CONFLICT (id) DO NOTHING");
+        }
+        let assignments = columns
+            .iter()
+            .map(|column| format!("{} = EXCLUDED.{}", column, column))
+            .collect::<Vec<_>>();
+        format!("ON CONFLICT (id) DO UPDATE SET {}", assignments.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let record = Record::new()
+            .with("id", 42u16)
+            .with("name", "test")
+            .with("address", None::<String>);
+
+        assert_eq!(record.id(), Some(Uid::new(42)));
+        assert_eq!(record.columns(), vec!["id", "name", "address"]);
+        assert_eq!(record.get("name").and_then(SqlValue::as_str), Some("test"));
+        assert!(record.get("address").unwrap().is_null());
+        assert_eq!(record.get("missing"), None);
+        assert_eq!(SqlValue::Bytes(b"12".to_vec()).as_int(), Some(12));
+
+        let (columns, values) = record.update_params();
+        assert_eq!(columns, vec!["name", "address"]);
+        assert_eq!(values.last(), Some(&&SqlValue::Int(42)));
+    }
+
+    #[test]
+    fn test_dialects() {
+        assert_eq!(
+            MySqlDialect.insert("lease", &["id", "name"]).unwrap(),
+            "INSERT INTO `lease` (`id`, `name`) VALUES (?, ?)"
+        );
+        assert_eq!(
+            PostgresDialect.insert("lease", &["id", "name"]).unwrap(),
+            "INSERT INTO \"lease\" (\"id\", \"name\") VALUES ($1, $2)"
+        );
+        assert_eq!(
+            PostgresDialect.delete("lease", 3).unwrap(),
+            "DELETE FROM \"lease\" WHERE id IN ($1, $2, $3)"
+        );
+        assert_eq!(
+            PostgresDialect
+                .update("lease", &["name", "address"])
+                .unwrap(),
+            "UPDATE \"lease\" SET \"name\" = $1, \"address\" = $2 WHERE id = $3"
+        );
+        assert_eq!(
+            MySqlDialect.upsert("lease", &["id", "name"]).unwrap(),
+            "INSERT INTO `lease` (`id`, `name`) VALUES (?, ?) ON DUPLICATE KEY UPDATE `name` = VALUES(`name`)"
+        );
+        assert_eq!(
+            PostgresDialect.upsert("lease", &["id", "name"]).unwrap(),
+            "INSERT INTO \"lease\" (\"id\", \"name\") VALUES ($1, $2) ON CONFLICT (id) DO UPDATE SET \"name\" = EXCLUDED.\"name\""
+        );
+        assert_eq!(
+            PostgresDialect.select_page("lease").unwrap(),
+            "SELECT * FROM \"lease\" WHERE id > $1 ORDER BY id LIMIT $2"
+        );
+        assert_eq!(
+            MySqlDialect.select_by_id("lease").unwrap(),
+            "SELECT * FROM `lease` WHERE id = ?"
+        );
+        assert_eq!(
+            PostgresDialect.select_by_ids("lease", 2).unwrap(),
+            "SELECT * FROM \"lease\" WHERE id IN ($1, $2)"
+        );
+        assert_eq!(
+            PostgresDialect
+                .select_where("lease", &["name", "address"], true)
+                .unwrap(),
+            "SELECT * FROM \"lease\" WHERE \"name\" = $1 AND \"address\" = $2 ORDER BY id LIMIT $3"
+        );
+        assert_eq!(
+            MySqlDialect.select_where("lease", &[], false).unwrap(),
+            "SELECT * FROM `lease` ORDER BY id"
+        );
+    }
+
+    #[test]
+    fn test_malicious_identifiers() {
+        let names = [
+            "lease; DROP TABLE lease",
+            "lease` WHERE 1=1 --",
+            "lease\" OR \"1\"=\"1",
+            "lease WHERE id = 1",
+            "1lease",
+            "",
+            "léase",
+        ];
+        for name in names {
+            assert!(
+                validate_identifier(name).is_err(),
+                "{:?} was accepted",
+                name
+            );
+            assert!(MySqlDialect.select_all(name).is_err());
+            assert!(MySqlDialect.select_where("lease", &[name], false).is_err());
+            assert!(PostgresDialect.delete(name, 1).is_err());
+            assert!(MySqlDialect.create_table(name, "(id INT)").is_err());
+            assert!(MySqlDialect.insert("lease", &["id", name]).is_err());
+            assert!(PostgresDialect.update("lease", &[name]).is_err());
+        }
+        assert!(validate_identifier(&"a".repeat(MAX_IDENTIFIER_LEN + 1)).is_err());
+        assert!(validate_identifier("lease_v4").is_ok());
+    }
+}
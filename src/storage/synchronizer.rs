@@ -0,0 +1,145 @@
+//! Background synchronization of a [`RuntimeStorage`].
+//!
+//! [`RuntimeStorage::start_sync`] spawns a task synchronizing
+//! pools periodically, on the blocking thread pool since
+//! backends are blocking. Each tick only synchronizes the pools
+//! whose own [sync interval] elapsed. The task survives
+//! a failed synchronization, and flushes the storage one
+//! last time when stopped through its [`SyncHandle`].
+//!
+//! [sync interval]: super::data::DataPool::set_sync_interval
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::Notify,
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
+};
+
+use super::data::{FromRecord, RuntimeStorage, Storable};
+
+/// Handle over the synchronization task
+/// started by [`RuntimeStorage::start_sync`]
+///
+/// Dropping the handle does not stop the task.
+pub struct SyncHandle {
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl SyncHandle {
+    /// Stops the synchronization task, and waits for
+    /// it to flush the storage one last time
+    pub async fn stop(self) {
+        self.stop.notify_one();
+        if let Err(e) = self.task.await {
+            log::error!("Storage synchronizer failed : {}", e);
+        }
+    }
+
+    /// Returns whether the synchronization task is over
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl<V> RuntimeStorage<V>
+where
+    V: Storable + Clone + FromRecord + Send + Sync + 'static,
+{
+    /// Spawns a task synchronizing the storage every `interval`
+    ///
+    /// Pools with their own [sync interval](super::data::DataPool::set_sync_interval)
+    /// are only synchronized on the ticks where it elapsed.
+    /// Must be called from within a tokio runtime.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// let runtime = Arc::new(RuntimeStorage::new(backend));
+    /// let synchronizer = runtime.start_sync(Duration::from_secs(5));
+    /// // ...
+    /// synchronizer.stop().await;
+    /// ```
+    pub fn start_sync(self: &Arc<Self>, interval: Duration) -> SyncHandle {
+        let stop = Arc::new(Notify::new());
+        let storage = self.clone();
+        let stopped = stop.clone();
+        let task = tokio::spawn(async move {
+            let mut ticks = time::interval(interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            //The first tick completes immediately
+            ticks.tick().await;
+            loop {
+                tokio::select! {
+                    tick = ticks.tick() => sync(&storage, Some(tick.into_std())).await,
+                    _ = stopped.notified() => break,
+                }
+            }
+            log::info!("Flushing storage before stopping synchronization");
+            sync(&storage, None).await;
+        });
+        SyncHandle { stop, task }
+    }
+}
+
+/// Runs a synchronization on the blocking thread pool, logging
+/// it if it failed or panicked. Only the pools due at `tick`
+/// are synchronized, or every pool if there is no tick.
+async fn sync<V>(storage: &Arc<RuntimeStorage<V>>, tick: Option<Instant>)
+where
+    V: Storable + Clone + FromRecord + Send + Sync + 'static,
+{
+    let storage = storage.clone();
+    let synced = tokio::task::spawn_blocking(move || match tick {
+        Some(tick) => storage.sync_due(tick),
+        None => storage.sync(),
+    });
+    match synced.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::error!("Storage synchronization failed : {}", e),
+        Err(e) => log::error!("Storage synchronization panicked : {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::storage::{
+        backend::StorageBackend, data::DataPool, memory_backend::MemoryBackend,
+        serialized::Serialized,
+    };
+
+    #[tokio::test]
+    async fn test_start_sync() {
+        let backend = MemoryBackend::new();
+        let mut disk = backend.clone();
+        let storage: Arc<RuntimeStorage<Serialized<u32>>> = Arc::new(RuntimeStorage::new(backend));
+        storage
+            .add_pool(DataPool::new(String::from("counter"), String::new()))
+            .unwrap();
+
+        let synchronizer = storage.start_sync(Duration::from_millis(20));
+        let first = storage
+            .store(Serialized::new(1), String::from("counter"))
+            .unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(disk.select_ids("counter").unwrap(), vec![first]);
+
+        //Data stored right before stopping is flushed
+        let second = storage
+            .store(Serialized::new(2), String::from("counter"))
+            .unwrap();
+        synchronizer.stop().await;
+        let mut ids = disk.select_ids("counter").unwrap();
+        ids.sort();
+        let mut expected = vec![first, second];
+        expected.sort();
+        assert_eq!(ids, expected);
+    }
+}
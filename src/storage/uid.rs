@@ -0,0 +1,100 @@
+//! Unique identifier of stored data.
+//!
+//! Every [`Storable`] value is identified by a [`Uid`], unique
+//! across all the pools of a [`RuntimeStorage`], and stored in
+//! the `id` column of its table.
+//!
+//! [`Storable`]: super::data::Storable
+//! [`RuntimeStorage`]: super::data::RuntimeStorage
+
+use std::{fmt::Display, num::ParseIntError, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::sql::SqlValue;
+
+/// Underlying representation of a [`Uid`]
+type Repr = u16;
+
+/// Unique identifier of data stored in a [`RuntimeStorage`]
+///
+/// [`RuntimeStorage`]: super::data::RuntimeStorage
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Uid(Repr);
+
+impl Uid {
+    /// Creates a `Uid` from its raw value
+    pub const fn new(uid: Repr) -> Self {
+        Self(uid)
+    }
+
+    /// Returns the raw value of the `Uid`
+    pub const fn get(self) -> Repr {
+        self.0
+    }
+
+    /// Returns a random `Uid`
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl From<Repr> for Uid {
+    fn from(uid: Repr) -> Self {
+        Self(uid)
+    }
+}
+
+impl From<Uid> for Repr {
+    fn from(uid: Uid) -> Self {
+        uid.0
+    }
+}
+
+impl From<Uid> for SqlValue {
+    fn from(uid: Uid) -> Self {
+        uid.0.into()
+    }
+}
+
+impl TryFrom<i64> for Uid {
+    type Error = <Repr as TryFrom<i64>>::Error;
+
+    fn try_from(uid: i64) -> Result<Self, Self::Error> {
+        Repr::try_from(uid).map(Self)
+    }
+}
+
+impl Display for Uid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Uid {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_uid() {
+        let uid = Uid::new(42);
+        assert_eq!(uid.to_string().parse::<Uid>(), Ok(uid));
+        assert!("-1".parse::<Uid>().is_err());
+        assert_eq!(Uid::try_from(42i64), Ok(uid));
+        assert!(Uid::try_from(i64::MAX).is_err());
+        assert_eq!(SqlValue::from(uid), SqlValue::Int(42));
+        assert_eq!(serde_json::to_string(&uid).unwrap(), "42");
+    }
+}
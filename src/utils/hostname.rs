@@ -0,0 +1,145 @@
+//! Sanitization of the hostnames sent by clients.
+//!
+//! Clients send arbitrary hostnames. Before they are stored
+//! in leases or registered in the DNS, [`sanitize_hostname`]
+//! turns them into valid DNS labels, and a [`HostnameRegistry`]
+//! gives each client a unique name, suffixing collisions
+//! (`host`, `host-2`, ...).
+
+use std::{collections::HashMap, sync::Mutex};
+
+use mac_address::MacAddress;
+
+/// Maximum length of a hostname, which is a single DNS label
+pub const MAX_HOSTNAME_LEN: usize = 63;
+
+/// Returns `hostname` as a valid DNS label, or None if nothing is left
+///
+/// Only the first label is kept, letters are lowercased, invalid
+/// characters are replaced by hyphens, and the label is truncated
+/// to [`MAX_HOSTNAME_LEN`].
+///
+/// # Examples:
+///
+/// ```
+/// assert_eq!(sanitize_hostname("John's iPhone.local"), Some(String::from("john-s-iphone")));
+/// ```
+pub fn sanitize_hostname(hostname: &str) -> Option<String> {
+    let label = hostname.split('.').next().unwrap_or_default();
+    let mut sanitized = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9') => sanitized.push(c),
+            //Consecutive invalid characters are merged in a single hyphen
+            _ if sanitized.ends_with('-') => (),
+            _ => sanitized.push('-'),
+        }
+    }
+    sanitized.truncate(MAX_HOSTNAME_LEN);
+    let sanitized = sanitized.trim_matches('-');
+    match sanitized.is_empty() {
+        true => None,
+        false => Some(sanitized.to_string()),
+    }
+}
+
+/// Hostnames given to clients, by name
+#[derive(Debug, Default)]
+pub struct HostnameRegistry {
+    names: Mutex<HashMap<String, MacAddress>>,
+}
+
+impl HostnameRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives `chaddr` the sanitized `hostname`, suffixed if it is
+    /// already used by another client, and returns it
+    ///
+    /// The previous name of the client is released.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// if let Some(hostname) = hostnames.claim(&requested, packet.chaddr) {
+    ///     ddns.register(&hostname, address, lease_time)?;
+    /// }
+    /// ```
+    pub fn claim(&self, hostname: &str, chaddr: MacAddress) -> Option<String> {
+        let base = sanitize_hostname(hostname)?;
+        let mut names = self.names.lock().unwrap();
+        let available = |name: &String| names.get(name).is_none_or(|owner| *owner == chaddr);
+        let name = match available(&base) {
+            true => base,
+            false => (2..)
+                .map(|n| {
+                    let suffix = format!("-{}", n);
+                    let len = base.len().min(MAX_HOSTNAME_LEN - suffix.len());
+                    format!("{}{}", base[..len].trim_end_matches('-'), suffix)
+                })
+                .find(available)
+                .unwrap(),
+        };
+        names.retain(|_, owner| *owner != chaddr);
+        names.insert(name.clone(), chaddr);
+        Some(name)
+    }
+
+    /// Releases the hostname of `chaddr`, returning it if any
+    pub fn release(&self, chaddr: MacAddress) -> Option<String> {
+        let mut names = self.names.lock().unwrap();
+        let name = names
+            .iter()
+            .find(|(_, owner)| **owner == chaddr)
+            .map(|(name, _)| name.clone())?;
+        names.remove(&name);
+        Some(name)
+    }
+
+    /// Returns the client holding `hostname`, if any
+    pub fn owner(&self, hostname: &str) -> Option<MacAddress> {
+        self.names.lock().unwrap().get(hostname).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_hostnames() {
+        assert_eq!(
+            sanitize_hostname("John's iPhone.local"),
+            Some(String::from("john-s-iphone"))
+        );
+        assert_eq!(sanitize_hostname("--_--"), None);
+        assert_eq!(sanitize_hostname(&"a".repeat(80)).unwrap().len(), 63);
+
+        let hostnames = HostnameRegistry::new();
+        let client = |n| MacAddress::new([0xaa, 0, 0, 0, 0, n]);
+        assert_eq!(
+            hostnames.claim("Host-A", client(1)),
+            Some(String::from("host-a"))
+        );
+        assert_eq!(
+            hostnames.claim("host-a", client(1)),
+            Some(String::from("host-a"))
+        );
+        assert_eq!(
+            hostnames.claim("host_a", client(2)),
+            Some(String::from("host-a-2"))
+        );
+        assert_eq!(
+            hostnames.claim("host-a", client(3)),
+            Some(String::from("host-a-3"))
+        );
+        assert_eq!(hostnames.release(client(1)), Some(String::from("host-a")));
+        assert_eq!(
+            hostnames.claim("host-a", client(3)),
+            Some(String::from("host-a"))
+        );
+        assert_eq!(hostnames.owner("host-a-3"), None);
+    }
+}
@@ -48,7 +48,7 @@ pub fn init_logger(app_name: impl AsRef<str>, verbosity: u64) -> Result<(), fern
         log_root,
         app_name.as_ref(),
         std::convert::Into::<OffsetDateTime>::into(std::time::SystemTime::now())
-            .format(&time::format_description::parse("[year]_[month]_[day]").unwrap())
+            .format(&time::format_description::parse_borrowed::<2>("[year]_[month]_[day]").unwrap())
             .unwrap()
     );
 
@@ -1 +1,2 @@
+pub mod hostname;
 pub mod logger;